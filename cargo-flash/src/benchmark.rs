@@ -0,0 +1,66 @@
+use probe_rs::coresight::{access_ports::AccessPortError, memory::MI};
+use std::time::{Duration, Instant};
+
+/// How often each transfer is repeated. The throughput is averaged over all repetitions.
+pub const REPETITIONS: u32 = 5;
+
+/// The throughput of block transfers with one access width, in bytes per second.
+#[derive(Debug, Clone, Copy)]
+pub struct Throughput {
+    /// The access width in bits.
+    pub width: u32,
+    pub read: f64,
+    pub write: f64,
+}
+
+/// Times reading and writing `length` bytes of RAM at `address` with 8 and 32 bit block transfers.
+///
+/// The memory interface has no 16 bit block transfers, so they are not measured.
+/// The RAM is overwritten with a pattern and restored afterwards, unless a transfer fails.
+/// `address` and `length` have to be word aligned.
+pub fn run(
+    mi: &mut impl MI,
+    address: u32,
+    length: u32,
+) -> Result<Vec<Throughput>, AccessPortError> {
+    let mut original = vec![0u32; length as usize / 4];
+    mi.read_block32(address, &mut original)?;
+
+    let words: Vec<u32> = (0..length / 4)
+        .map(|i| i.wrapping_mul(0x0101_0101))
+        .collect();
+    let mut read_words = vec![0u32; words.len()];
+    let bytes: Vec<u8> = (0..length).map(|i| i as u8).collect();
+    let mut read_bytes = vec![0u8; bytes.len()];
+
+    let block32 = Throughput {
+        width: 32,
+        write: throughput(length, || mi.write_block32(address, &words))?,
+        read: throughput(length, || mi.read_block32(address, &mut read_words))?,
+    };
+    let block8 = Throughput {
+        width: 8,
+        write: throughput(length, || mi.write_block8(address, &bytes))?,
+        read: throughput(length, || mi.read_block8(address, &mut read_bytes))?,
+    };
+
+    mi.write_block32(address, &original)?;
+
+    Ok(vec![block8, block32])
+}
+
+/// Runs `transfer` of `length` bytes `REPETITIONS` times and returns the average bytes per second.
+fn throughput(
+    length: u32,
+    mut transfer: impl FnMut() -> Result<(), AccessPortError>,
+) -> Result<f64, AccessPortError> {
+    let mut elapsed = Duration::from_secs(0);
+
+    for _ in 0..REPETITIONS {
+        let start = Instant::now();
+        transfer()?;
+        elapsed += start.elapsed();
+    }
+
+    Ok(f64::from(length * REPETITIONS) / elapsed.as_secs_f64())
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The name of the project-local config file, which is placed next to the `Cargo.toml` of the project.
+pub const CONFIG_FILE: &str = ".probe-rs.toml";
+
+/// The environment variable with the chip which is used if neither `--chip` nor the project selects one.
+pub const CHIP_ENV_VAR: &str = "PROBE_RS_CHIP";
+
+/// Settings which are saved per project, such that they do not have to be passed on every run.
+///
+/// They are read from the `.probe-rs.toml` of the project, or from the `[package.metadata.probe-rs]`
+/// table of its `Cargo.toml` if it has no `.probe-rs.toml`:
+///
+/// ```toml
+/// chip = "nRF52840_xxAA"
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProjectConfig {
+    /// The chip which is used if none is given with `--chip`, see `select_chip`.
+    pub chip: Option<String>,
+}
+
+impl ProjectConfig {
+    /// Reads the config of the project in `dir`, or returns an empty config if the project has none.
+    pub fn load(dir: &Path) -> Result<Self, failure::Error> {
+        let path = dir.join(CONFIG_FILE);
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)?;
+            return toml::from_str(&content)
+                .map_err(|e| failure::format_err!("failed to parse {}: {}", path.display(), e));
+        }
+
+        let path = dir.join("Cargo.toml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)?;
+        Self::from_manifest(&content)
+            .map_err(|e| failure::format_err!("failed to parse {}: {}", path.display(), e))
+    }
+
+    /// Reads the config from the `[package.metadata.probe-rs]` table of the `Cargo.toml` `manifest`.
+    fn from_manifest(manifest: &str) -> Result<Self, toml::de::Error> {
+        let manifest: toml::Value = toml::from_str(manifest)?;
+        match manifest
+            .get("package")
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("probe-rs"))
+        {
+            Some(config) => config.clone().try_into(),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Writes the config to the project in `dir` and returns the path of the written file.
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, failure::Error> {
+        let path = dir.join(CONFIG_FILE);
+        std::fs::write(&path, toml::to_string(self)?)?;
+        Ok(path)
+    }
+}
+
+/// Selects the chip from `--chip`, the project config and the `PROBE_RS_CHIP` environment variable,
+/// in this order. If none of them is set, the chip is autodetected.
+pub fn select_chip(
+    argument: Option<String>,
+    project: Option<String>,
+    environment: Option<String>,
+) -> Option<String> {
+    argument.or(project).or(environment)
+}
+
+/// Returns the directory of the `Cargo.toml` of the project in the current directory, if there is one.
+pub fn project_dir() -> Option<PathBuf> {
+    let project = cargo_project::Project::query(".").ok()?;
+    project.toml().parent().map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{select_chip, ProjectConfig};
+
+    fn chip(name: &str) -> Option<String> {
+        Some(name.to_owned())
+    }
+
+    #[test]
+    fn chip_selection_precedence() {
+        assert_eq!(
+            select_chip(chip("arg"), chip("project"), chip("env")),
+            chip("arg")
+        );
+        assert_eq!(
+            select_chip(None, chip("project"), chip("env")),
+            chip("project")
+        );
+        assert_eq!(select_chip(None, None, chip("env")), chip("env"));
+        assert_eq!(select_chip(None, None, None), None);
+    }
+
+    #[test]
+    fn chip_from_manifest_metadata() {
+        let manifest = r#"
+            [package]
+            name = "blinky"
+            version = "0.1.0"
+
+            [package.metadata.probe-rs]
+            chip = "nRF52840_xxAA"
+        "#;
+        let config = ProjectConfig::from_manifest(manifest).unwrap();
+        assert_eq!(config.chip, chip("nRF52840_xxAA"));
+
+        let config = ProjectConfig::from_manifest("[package]\nname = \"blinky\"\n").unwrap();
+        assert_eq!(config.chip, None);
+    }
+}
@@ -1,43 +1,384 @@
 extern crate structopt;
 
+mod benchmark;
+mod config;
+mod report;
+mod serve;
+mod timing;
+
 use colored::*;
 use failure::format_err;
 use std::{
+    cell::RefCell,
     env,
     error::Error,
+    ffi::OsString,
     fmt,
+    ops::Range,
     path::{Path, PathBuf},
     process::{self, Command, Stdio},
-    time::Instant,
+    rc::Rc,
+    time::{Duration, Instant},
 };
 use structopt::StructOpt;
 
+use config::ProjectConfig;
+use report::FlashResult;
+use timing::PhaseTimer;
+
 use probe_rs::{
-    config::registry::{Registry, SelectionStrategy},
-    coresight::access_ports::AccessPortError,
-    flash::download::{download_file_with_progress_reporting, Format},
-    flash::{FlashProgress, ProgressEvent},
+    config::{
+        dual_bank::Bank,
+        init_script::InitScript,
+        memory::MemoryRegion,
+        registry::{Registry, SelectionStrategy},
+    },
+    coresight::{access_ports::AccessPortError, memory::MI},
+    flash::download::{
+        download_file_with_manifest, download_file_xmodem, read_image, verify_file,
+        DownloadOptions, Format,
+    },
+    flash::{
+        endurance_test, erase_all, BuildId, ElfImage, EraseStrategy, FlashManifest, FlashProgress,
+        FlasherOptions, ProgressEvent,
+    },
     probe::{
-        daplink, stlink, DebugProbe, DebugProbeError, DebugProbeType, MasterProbe, WireProtocol,
+        self, daplink::DapInterface, DebugProbeError, DebugProbeInfo, DebugProbeType, FaultRetry,
+        MasterProbe, SelfTestOutcome, WireProtocol,
     },
+    rtt::{Rtt, RttError},
     session::Session,
     target::info::ChipInfo,
 };
 
+/// How long `--run-to` waits for the core to reach the location.
+const RUN_TO_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, StructOpt)]
 struct Opt {
-    #[structopt(name = "chip", long = "chip")]
+    #[structopt(
+        name = "chip",
+        long = "chip",
+        help = "The chip to flash. Falls back to the chip of the project, from its .probe-rs.toml or the [package.metadata.probe-rs] table of its Cargo.toml, then to the PROBE_RS_CHIP environment variable, and is read from the ROM table if none is set."
+    )]
     chip: Option<String>,
+    #[structopt(
+        name = "save-chip",
+        long = "save-chip",
+        help = "Save the chip to the .probe-rs.toml of the project, such that later runs use it without --chip."
+    )]
+    save_chip: bool,
+    #[structopt(
+        name = "expect-chip",
+        long = "expect-chip",
+        help = "Detect the chip from the ROM table and abort before anything is written if it is not this chip. The chip is also flashed as this chip if none is selected."
+    )]
+    expect_chip: Option<String>,
     #[structopt(
         name = "chip description file path",
         short = "c",
         long = "chip-description-path"
     )]
     chip_description_path: Option<String>,
+    #[structopt(
+        name = "probe-index",
+        long = "probe-index",
+        help = "The index of the probe to use, as listed when multiple probes are connected."
+    )]
+    probe_index: Option<usize>,
+    #[structopt(
+        name = "target-sel",
+        long = "target-sel",
+        parse(try_from_str = parse_number),
+        help = "The TARGETSEL value which selects the target on an SWD multidrop bus."
+    )]
+    target_sel: Option<u32>,
+    #[structopt(
+        name = "protocol",
+        long = "protocol",
+        help = "The wire protocol, 'swd', 'jtag' or 'auto', which tries SWD and then JTAG. Defaults to the protocol of the chip, or SWD."
+    )]
+    protocol: Option<ProtocolChoice>,
+    #[structopt(
+        name = "speed",
+        long = "speed",
+        help = "The clock speed of the wire protocol in kHz. Defaults to the maximum speed of the chip, if it has one."
+    )]
+    speed: Option<u32>,
+    #[structopt(
+        name = "transfer-delay-us",
+        long = "transfer-delay-us",
+        help = "Wait this many microseconds before each transfer of the probe. This makes flashing a lot slower and should normally be left at zero, it only helps with marginal signal integrity, e.g. long flying leads."
+    )]
+    transfer_delay_us: Option<u64>,
+    #[structopt(
+        name = "single-ap",
+        long = "single-ap",
+        help = "Use AP 0 to detect the chip instead of scanning for all APs. This speeds up attaching to chips which have everything behind AP 0."
+    )]
+    single_ap: bool,
     #[structopt(name = "nrf-recover", long = "nrf-recover")]
     nrf_recover: bool,
     #[structopt(name = "list-chips", long = "list-chips")]
     list_chips: bool,
+    #[structopt(
+        name = "list-probes",
+        long = "list-probes",
+        help = "List the connected probes and exit. Each probe is opened briefly to show details like its firmware version, without attaching to the target."
+    )]
+    list_probes: bool,
+    #[structopt(
+        name = "probe-present",
+        long = "probe-present",
+        help = "Exit with code 0 if a probe is connected (the one selected with --probe-index, if given) and with code 1 otherwise, without opening it."
+    )]
+    probe_present: bool,
+    #[structopt(
+        name = "probe-selftest",
+        long = "probe-selftest",
+        help = "Open the probe without attaching to the target, check its firmware, the target voltage, the clock and whether it answers commands, and exit with code 1 if a check failed."
+    )]
+    probe_selftest: bool,
+    #[structopt(
+        name = "serve",
+        long = "serve",
+        help = "Keep the probe open and flash a file for each line '<path> [<chip>]' on stdin, until 'quit' or the end of the input. The target is attached again for each line, and each line is answered with 'ok' or 'error: <reason>'."
+    )]
+    serve: bool,
+    #[structopt(
+        name = "identify",
+        long = "identify",
+        help = "Attach to the target, print its identification and the matching chips of the registry, and exit without flashing."
+    )]
+    identify: bool,
+    #[structopt(
+        name = "keep-debug-alive",
+        long = "keep-debug-alive",
+        help = "Keep the debug unit clocked in low power modes, if the target supports it."
+    )]
+    keep_debug_alive: bool,
+    #[structopt(
+        name = "dap-interface",
+        long = "dap-interface",
+        help = "The USB interface of a CMSIS-DAP probe: auto prefers the faster bulk interface of CMSIS-DAP v2 and falls back to HID, hid and bulk force one of them.",
+        default_value = "auto"
+    )]
+    dap_interface: DapInterface,
+    #[structopt(
+        name = "init-script",
+        long = "init-script",
+        parse(from_os_str),
+        help = "Run the register writes, polls and delays of a YAML init script after attaching."
+    )]
+    init_script: Option<PathBuf>,
+    #[structopt(
+        name = "fault-retries",
+        long = "fault-retries",
+        help = "Retry memory reads which fault this many times, e.g. reads of peripherals whose clock was just enabled by the init script."
+    )]
+    fault_retries: Option<usize>,
+    #[structopt(
+        name = "read-reset-reason",
+        long = "read-reset-reason",
+        help = "Print the cause of the last reset of the chip before flashing and clear it."
+    )]
+    read_reset_reason: bool,
+    #[structopt(
+        name = "disable-watchdog",
+        long = "disable-watchdog",
+        help = "Keep the watchdog from resetting the core while flashing, if the target supports it."
+    )]
+    disable_watchdog: bool,
+    #[structopt(
+        name = "resilient",
+        long = "resilient",
+        help = "Reconnect to the probe and resume flashing if the USB connection is lost."
+    )]
+    resilient: bool,
+    #[structopt(
+        name = "check-stack",
+        long = "check-stack",
+        help = "Check the stack of the flash algorithm for overflows after each routine. Useful when debugging a flash algorithm."
+    )]
+    check_stack: bool,
+    #[structopt(
+        name = "interleaved-erase",
+        long = "interleaved-erase",
+        help = "Erase each sector right before programming it, instead of erasing all sectors first."
+    )]
+    interleaved_erase: bool,
+    #[structopt(
+        name = "blank-check",
+        long = "blank-check",
+        help = "Read each sector before erasing it and skip the erase if it is blank already, e.g. right after a mass erase."
+    )]
+    blank_check: bool,
+    #[structopt(
+        name = "erase-all",
+        long = "erase-all",
+        help = "Erase the whole flash of the chip and exit, without building or flashing anything."
+    )]
+    erase_all: bool,
+    #[structopt(
+        name = "pre-erase-all",
+        long = "pre-erase-all",
+        help = "Erase the whole flash of the chip before flashing, including the regions which the image does not touch.",
+        conflicts_with = "erase-all"
+    )]
+    pre_erase_all: bool,
+    #[structopt(
+        name = "bank",
+        long = "bank",
+        help = "Program the image into bank 1 or 2 of a dual-bank flash, no matter which bank it was linked for."
+    )]
+    bank: Option<Bank>,
+    #[structopt(
+        name = "swap-banks",
+        long = "swap-banks",
+        help = "Swap the banks of a dual-bank flash after flashing, e.g. to boot an update which was programmed with --bank."
+    )]
+    swap_banks: bool,
+    #[structopt(
+        name = "validate-vectors",
+        long = "validate-vectors",
+        help = "Check that the reset vector of the flashed vector table matches the entry point of the ELF file."
+    )]
+    validate_vectors: bool,
+    #[structopt(
+        name = "check-build-id",
+        long = "check-build-id",
+        help = "Read back the build-id of the ELF file from the flash after flashing and check that it matches. This is a lot faster than a full verify."
+    )]
+    check_build_id: bool,
+    #[structopt(
+        name = "size",
+        long = "size",
+        help = "Print how much of each flash and RAM region of the chip the image uses after flashing."
+    )]
+    size: bool,
+    #[structopt(
+        name = "incremental",
+        long = "incremental",
+        help = "Only erase and program the sectors which changed since the last flash with this probe and chip. Falls back to a full flash if there is no manifest in .probe-rs/ yet."
+    )]
+    incremental: bool,
+    #[structopt(
+        name = "xmodem",
+        long = "xmodem",
+        help = "Send the image to an XMODEM serial bootloader on this already configured serial device instead of using a debug probe. Needs --chip.",
+        parse(from_os_str)
+    )]
+    xmodem: Option<PathBuf>,
+    #[structopt(
+        name = "no-progress",
+        short = "q",
+        long = "no-progress",
+        help = "Print plain status lines instead of progress bars. This is the default if stdout is not a terminal."
+    )]
+    no_progress: bool,
+    #[structopt(
+        name = "only",
+        long = "only",
+        help = "Only flash data within the address range <start>-<end>. Can be given multiple times.",
+        parse(try_from_str = parse_address_range),
+        number_of_values = 1
+    )]
+    only: Vec<Range<u32>>,
+    #[structopt(
+        name = "skip-region",
+        long = "skip-region",
+        help = "Do not flash data within the address range <start>-<end>. Can be given multiple times.",
+        parse(try_from_str = parse_address_range),
+        number_of_values = 1
+    )]
+    skip_region: Vec<Range<u32>>,
+    #[structopt(
+        name = "timing-json",
+        long = "timing-json",
+        help = "Write the time spent in each phase of flashing to this file as JSON.",
+        parse(from_os_str)
+    )]
+    timing_json: Option<PathBuf>,
+    #[structopt(
+        name = "result-json",
+        long = "result-json",
+        help = "Write the outcome of the run as a single line of JSON to this file, or to stdout for -. It is written on failure as well.",
+        parse(from_os_str)
+    )]
+    result_json: Option<PathBuf>,
+    #[structopt(
+        name = "dump-flash-algo",
+        long = "dump-flash-algo",
+        help = "Print the assembled flash algorithm of the chip without flashing."
+    )]
+    dump_flash_algo: bool,
+    #[structopt(
+        name = "flash-algo-output",
+        long = "flash-algo-output",
+        help = "Write the instruction blob of the flash algorithm to this file when using --dump-flash-algo.",
+        parse(from_os_str)
+    )]
+    flash_algo_output: Option<PathBuf>,
+    #[structopt(
+        name = "endurance",
+        long = "endurance",
+        help = "Erase, program and verify the flash sector at <address> <cycles> times instead of flashing, and report the first failing cycle.",
+        parse(try_from_str = parse_number),
+        number_of_values = 2,
+        value_names = &["address", "cycles"]
+    )]
+    endurance: Vec<u32>,
+    #[structopt(
+        name = "benchmark",
+        long = "benchmark",
+        help = "Halt the core, time reading and writing <length> bytes of RAM at <address> with 8 and 32 bit block transfers instead of flashing, and print the throughput. The RAM is restored afterwards.",
+        parse(try_from_str = parse_number),
+        number_of_values = 2,
+        value_names = &["address", "length"]
+    )]
+    benchmark: Vec<u32>,
+    #[structopt(
+        name = "save-ram",
+        long = "save-ram",
+        help = "Before flashing and resetting, save <length> bytes of RAM at <address> to <file>, e.g. a crash log which survives resets.",
+        number_of_values = 3,
+        value_names = &["address", "length", "file"]
+    )]
+    save_ram: Vec<String>,
+    #[structopt(
+        name = "verify-only",
+        long = "verify-only",
+        help = "Compare the flash with this ELF or Intel HEX file instead of building and flashing. Exits with code 2 on a mismatch.",
+        parse(from_os_str)
+    )]
+    verify_only: Option<PathBuf>,
+    #[structopt(
+        name = "expect",
+        long = "expect",
+        help = "After flashing, read the RTT output of the firmware until it contains this text. Exits with an error if it does not show up in time."
+    )]
+    expect: Option<String>,
+    #[structopt(
+        name = "expect-timeout",
+        long = "expect-timeout",
+        help = "How many milliseconds to wait for the text given with --expect.",
+        default_value = "5000"
+    )]
+    expect_timeout: u64,
+    #[structopt(
+        name = "run-to",
+        long = "run-to",
+        help = "After flashing, reset the core and run it until it reaches this symbol or address, e.g. main, and leave it halted there.",
+        conflicts_with = "expect"
+    )]
+    run_to: Option<String>,
+    #[structopt(
+        name = "reset-halt",
+        long = "reset-halt",
+        help = "After flashing, reset the core and halt it before it executes the first instruction, e.g. to debug the startup code.",
+        conflicts_with_all = &["expect", "run-to"]
+    )]
+    reset_halt: bool,
 
     // `cargo build` arguments
     #[structopt(name = "binary", long = "bin")]
@@ -62,16 +403,28 @@ struct Opt {
 
 fn main() {
     pretty_env_logger::init();
-    match main_try() {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("{}: {}", "error".red().bold(), e);
-            process::exit(1);
-        }
+
+    let mut result = FlashResult::new();
+
+    if let Err(e) = main_try(&mut result) {
+        eprintln!("{}: {}", "error".red().bold(), e);
+        result.fail(e, 1);
+    }
+
+    if let Err(e) = result.write() {
+        eprintln!(
+            "{}: failed to write the result: {}",
+            "error".red().bold(),
+            e
+        );
+    }
+
+    if result.exit_code != 0 {
+        process::exit(result.exit_code);
     }
 }
 
-fn main_try() -> Result<(), failure::Error> {
+fn main_try(result: &mut FlashResult) -> Result<(), failure::Error> {
     let mut args = std::env::args();
 
     // When called by Cargo, the first argument after the binary name will be `flash`. If that's the
@@ -80,69 +433,113 @@ fn main_try() -> Result<(), failure::Error> {
         args.next();
     }
 
-    let mut args: Vec<_> = args.collect();
+    let args: Vec<_> = args.collect();
 
     // Get commandline options.
-    let opt = Opt::from_iter(&args);
+    let mut opt = Opt::from_iter(&args);
+
+    // The project config is only read if no chip was given, so a broken one does not get in the way of --chip.
+    let project_chip = match config::project_dir() {
+        Some(dir) if opt.chip.is_none() => ProjectConfig::load(&dir)?.chip,
+        _ => None,
+    };
+    opt.chip = config::select_chip(
+        opt.chip.take(),
+        project_chip,
+        env::var(config::CHIP_ENV_VAR).ok(),
+    );
+
+    if opt.save_chip && opt.chip.is_none() {
+        return Err(format_err!(
+            "--save-chip requires a chip to be selected with --chip or PROBE_RS_CHIP"
+        ));
+    }
+
+    result.output = opt.result_json.clone();
 
     if opt.list_chips {
         print_families();
-        std::process::exit(0);
+        return Ok(());
     }
 
-    args.remove(0); // Remove executable name
+    if opt.list_probes {
+        print_probes();
+        return Ok(());
+    }
 
-    // Remove possible `--chip <chip>` arguments as cargo build does not understand it.
-    if let Some(index) = args.iter().position(|x| *x == "--chip") {
-        args.remove(index);
-        args.remove(index);
+    if opt.probe_present {
+        if !check_probe_present(opt.probe_index) {
+            result.fail("the probe is not connected", 1);
+        }
+        return Ok(());
     }
 
-    // Remove possible `--chip=<chip>` argument as cargo build does not understand it.
-    if let Some(index) = args.iter().position(|x| x.starts_with("--chip=")) {
-        args.remove(index);
+    if opt.identify {
+        return identify(&opt);
     }
 
-    // Remove possible `--chip-description-path <chip description path>` arguments as cargo build does not understand it.
-    if let Some(index) = args.iter().position(|x| *x == "--chip-description-path") {
-        args.remove(index);
-        args.remove(index);
+    if opt.probe_selftest {
+        if !probe_self_test(&opt)? {
+            result.fail("the probe failed its self-test", 1);
+        }
+        return Ok(());
     }
 
-    // Remove possible `--chip-description-path=<chip description path>` arguments as cargo build does not understand it.
-    if let Some(index) = args
-        .iter()
-        .position(|x| x.starts_with("--chip-description-path="))
-    {
-        args.remove(index);
+    if opt.serve {
+        return serve(&opt);
     }
 
-    // Remove possible `-c <chip description path>` arguments as cargo build does not understand it.
-    if let Some(index) = args.iter().position(|x| *x == "-c") {
-        args.remove(index);
-        args.remove(index);
+    if opt.dump_flash_algo {
+        return dump_flash_algorithm(&opt, opt.flash_algo_output.as_deref());
     }
 
-    // Remove possible `-c=<chip description path>` arguments as cargo build does not understand it.
-    if let Some(index) = args.iter().position(|x| x.starts_with("-c=")) {
-        args.remove(index);
+    if let [address, cycles] = opt.endurance[..] {
+        let mut session = open_session(&opt)?;
+        result.connected(&session);
+
+        if !run_endurance_test(&mut session, address, cycles as usize)? {
+            result.fail("the sector failed the endurance test", 2);
+        }
+        return Ok(());
+    }
+
+    if let [address, length] = opt.benchmark[..] {
+        let mut session = open_session(&opt)?;
+        result.connected(&session);
+        run_benchmark(&mut session, address, length)?;
+        return Ok(());
     }
 
-    // Remove possible `--nrf-recover` argument as cargo build does not understand it.
-    if let Some(index) = args.iter().position(|x| x.starts_with("--nrf-recover")) {
-        args.remove(index);
+    if opt.erase_all {
+        let mut session = open_session(&opt)?;
+        result.connected(&session);
+        erase_flash(&mut session)?;
+        return Ok(());
+    }
+
+    if let Some(path) = &opt.verify_only {
+        let mut session = open_session(&opt)?;
+        result.connected(&session);
+
+        let matches = verify_only(&mut session, &opt, path)?;
+        result.verified = Some(matches);
+        if !matches {
+            result.fail("the flash differs from the file", 2);
+        }
+        return Ok(());
     }
 
     let status = Command::new("cargo")
         .arg("build")
-        .args(args)
+        .args(cargo_build_args(&opt))
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
         .spawn()?
         .wait()?;
 
     if !status.success() {
-        handle_failed_command(status)
+        result.fail("cargo build failed", failed_command_exit_code(status));
+        return Ok(());
     }
 
     // Try and get the cargo project information.
@@ -180,128 +577,261 @@ fn main_try() -> Result<(), failure::Error> {
 
     println!("    {} {}", "Flashing".green().bold(), path_str);
 
-    let mut list = daplink::tools::list_daplink_devices();
-    list.extend(stlink::tools::list_stlink_devices());
+    if let Some(device) = &opt.xmodem {
+        return flash_xmodem(&opt, device, &path);
+    }
 
-    let device = list
-        .pop()
-        .ok_or_else(|| format_err!("no supported probe was found"))?;
+    let timer = Rc::new(RefCell::new(PhaseTimer::new()));
 
-    let mut probe = match device.probe_type {
-        DebugProbeType::DAPLink => {
-            let mut link = daplink::DAPLink::new_from_probe_info(&device)?;
+    let mut session = open_session(&opt)?;
+    result.connected(&session);
 
-            link.attach(Some(WireProtocol::Swd))?;
+    // The chip is only saved once it was found, such that a misspelled name is not saved.
+    if opt.save_chip {
+        let dir = project.toml().parent().unwrap_or_else(|| Path::new("."));
+        let config = ProjectConfig {
+            chip: Some(session.target.identifier.chip_name.clone()),
+        };
+        let path = config.save(dir)?;
+        println!(
+            "    {} the chip {} to {}",
+            "Saved".green().bold(),
+            session.target.identifier.chip_name,
+            path.display()
+        );
+    }
 
-            let mut probe = MasterProbe::from_specific_probe(link);
-            if opt.nrf_recover {
-                probe.nrf_recover()?;
-            }
-            probe
-        }
-        DebugProbeType::STLink => {
-            let mut link = stlink::STLink::new_from_probe_info(&device)?;
+    // The RAM is saved first, as flashing resets the core and overwrites RAM with the flash algorithm.
+    if let [address, length, file] = &opt.save_ram[..] {
+        save_ram(&mut session, address, length, Path::new(file))?;
+    }
+
+    session.detect_flash_size();
 
-            link.attach(Some(WireProtocol::Swd))?;
+    if opt.keep_debug_alive {
+        session.keep_debug_alive()?;
+    }
 
-            if opt.nrf_recover {
-                return Err(format_err!("It isn't possible to recover with a ST-Link"));
+    if opt.read_reset_reason {
+        match session.read_reset_reason()? {
+            Some(reason) => {
+                let causes: Vec<String> =
+                    reason.causes.iter().map(|c| format!("{:?}", c)).collect();
+                println!(
+                    "    {} {} (register value {:#010x})",
+                    "Reset reason".green().bold(),
+                    if causes.is_empty() {
+                        "unknown".to_owned()
+                    } else {
+                        causes.join(", ")
+                    },
+                    reason.register_value
+                );
+                session.clear_reset_reason()?;
             }
-            MasterProbe::from_specific_probe(link)
+            None => println!(
+                "    {} the target does not define a reset cause register",
+                "Warning".yellow().bold()
+            ),
         }
-    };
+    }
 
-    let strategy = if let Some(identifier) = opt.chip {
-        SelectionStrategy::TargetIdentifier(identifier.into())
-    } else {
-        SelectionStrategy::ChipInfo(ChipInfo::read_from_rom_table(&mut probe)?)
-    };
+    if opt.disable_watchdog && session.target.watchdog_disable.is_empty() {
+        println!(
+            "    {} the target does not define any registers to disable the watchdog",
+            "Warning".yellow().bold()
+        );
+    }
 
-    let mut registry = Registry::from_builtin_families();
-    if let Some(cdp) = opt.chip_description_path {
-        registry.add_target_from_yaml(&Path::new(&cdp))?;
+    timer.borrow_mut().connected();
+
+    let image = ElfImage::from_file(&path)?;
+
+    let build_id = image.build_id();
+    if opt.check_build_id && build_id.is_none() {
+        return Err(format_err!(
+            "{} has no build-id in flash, link it with --build-id and keep the .note.gnu.build-id section in flash",
+            path_str
+        ));
     }
 
-    let target = registry.get_target(strategy)?;
+    // The chip is only erased once the image was read, such that a broken file does not leave it blank.
+    // The manifest of an incremental flash is removed as well, as the erased flash no longer matches it.
+    if opt.pre_erase_all {
+        let _ = std::fs::remove_file(manifest_path(&session));
+        erase_flash(&mut session)?;
+    }
 
-    let mut session = Session::new(target, probe);
+    let estimate = session.estimate_flash_time(&image);
+    if estimate.as_millis() > 0 {
+        println!(
+            "    {} {}s",
+            "Estimated".green().bold(),
+            estimate.as_millis() as f32 / 1000.0
+        );
+    }
 
     // Start timer.
     let instant = Instant::now();
 
     let mm = session.target.memory_map.clone();
 
-    // Create progress bars.
-    let multi_progress = indicatif::MultiProgress::new(); //with_draw_target(indicatif::ProgressDrawTarget::stdout_nohz());
-    let style = indicatif::ProgressStyle::default_bar()
-            .tick_chars("⠁⠁⠉⠙⠚⠒⠂⠂⠒⠲⠴⠤⠄⠄⠤⠠⠠⠤⠦⠖⠒⠐⠐⠒⠓⠋⠉⠈⠈✔")
-            .progress_chars("##-")
-            .template("    {msg:.green.bold} {spinner} [{elapsed_precise}] [{wide_bar}] {bytes:>8}/{total_bytes:>8} @ {bytes_per_sec:>10} (eta {eta:3})");
-
-    // Create a new progress bar for the erase progress.
-    let erase_progress = multi_progress.add(indicatif::ProgressBar::new(0));
-    erase_progress.set_style(style.clone());
-    erase_progress.set_message("Erasing sectors  ");
-
-    // Create a new progress bar for the program progress.
-    let program_progress = multi_progress.add(indicatif::ProgressBar::new(0));
-    program_progress.set_style(style);
-    program_progress.set_message("Programming pages");
-
-    // Register callback to update the progress.
-    let progress = FlashProgress::new(move |event| {
-        use ProgressEvent::*;
-        match event {
-            Initialized {
-                total_pages,
-                total_sectors,
-                sector_size,
-                page_size,
-            } => {
-                erase_progress.set_length(total_sectors as u64 * sector_size as u64);
-                program_progress.set_length(total_pages as u64 * page_size as u64);
-            }
-            StartedFlashing => {
-                program_progress.enable_steady_tick(100);
-                program_progress.reset_elapsed();
-            }
-            StartedErasing => {
-                erase_progress.enable_steady_tick(100);
-                erase_progress.reset_elapsed();
-            }
-            PageFlashed { size, .. } => {
-                program_progress.inc(size as u64);
-            }
-            SectorErased { size, .. } => {
-                erase_progress.inc(size as u64);
-            }
-            FinishedErasing => {
-                erase_progress.finish();
-            }
-            FinishedProgramming => {
-                program_progress.finish();
+    let show_progress_bars = !opt.no_progress && console::user_attended();
+
+    let (progress, progress_thread_handle) = if show_progress_bars {
+        // Create progress bars.
+        let multi_progress = indicatif::MultiProgress::new(); //with_draw_target(indicatif::ProgressDrawTarget::stdout_nohz());
+        let style = indicatif::ProgressStyle::default_bar()
+                .tick_chars("⠁⠁⠉⠙⠚⠒⠂⠂⠒⠲⠴⠤⠄⠄⠤⠠⠠⠤⠦⠖⠒⠐⠐⠒⠓⠋⠉⠈⠈✔")
+                .progress_chars("##-")
+                .template("    {msg:.green.bold} {spinner} [{elapsed_precise}] [{wide_bar}] {bytes:>8}/{total_bytes:>8} @ {bytes_per_sec:>10} (eta {eta:3})");
+
+        // Create a new progress bar for the erase progress.
+        let erase_progress = multi_progress.add(indicatif::ProgressBar::new(0));
+        erase_progress.set_style(style.clone());
+        erase_progress.set_message("Erasing sectors  ");
+
+        // Create a new progress bar for the program progress.
+        let program_progress = multi_progress.add(indicatif::ProgressBar::new(0));
+        program_progress.set_style(style);
+        program_progress.set_message("Programming pages");
+
+        // Register callback to update the progress.
+        let progress = FlashProgress::new(move |event| {
+            use ProgressEvent::*;
+            match event {
+                Initialized {
+                    total_pages,
+                    total_sectors,
+                    sector_size,
+                    page_size,
+                } => {
+                    erase_progress.set_length(total_sectors as u64 * sector_size as u64);
+                    program_progress.set_length(total_pages as u64 * page_size as u64);
+                }
+                StartedFlashing => {
+                    program_progress.enable_steady_tick(100);
+                    program_progress.reset_elapsed();
+                }
+                StartedErasing => {
+                    erase_progress.enable_steady_tick(100);
+                    erase_progress.reset_elapsed();
+                }
+                PageFlashed { size, .. } => {
+                    program_progress.inc(size as u64);
+                }
+                SectorErased { size, .. } | SectorBlankSkipped { size } => {
+                    erase_progress.inc(size as u64);
+                }
+                FinishedErasing => {
+                    erase_progress.finish();
+                }
+                FinishedProgramming => {
+                    program_progress.finish();
+                }
+                StartedReading { .. } | ChunkRead { .. } | FinishedReading => (),
             }
+        });
+
+        // Make the multi progresses print.
+        // indicatif requires this in a separate thread as this join is a blocking op,
+        // but is required for printing multiprogress.
+        let progress_thread_handle = std::thread::spawn(move || {
+            multi_progress.join().unwrap();
+        });
+
+        (progress, Some(progress_thread_handle))
+    } else {
+        (plain_progress(), None)
+    };
+
+    // Account the phases of the download before passing the events on.
+    let progress = {
+        let timer = timer.clone();
+        FlashProgress::new(move |event| {
+            timer.borrow_mut().record(&event);
+            progress.emit(event);
+        })
+    };
+
+    let dual_bank = session.target.dual_bank.clone();
+    if (opt.bank.is_some() || opt.swap_banks) && dual_bank.is_none() {
+        return Err(format_err!(
+            "the target does not define the banks of a dual-bank flash"
+        ));
+    }
+
+    let relocations = match (opt.bank, &dual_bank) {
+        (Some(bank), Some(dual_bank)) => {
+            let swapped = dual_bank.is_swapped(&mut session.probe)?;
+            println!(
+                "    {} into bank {} at {:#010x}",
+                "Programming".green().bold(),
+                if bank == Bank::One { 1 } else { 2 },
+                dual_bank.bank_start(bank, swapped)
+            );
+            dual_bank.relocations(bank, swapped)
         }
-    });
+        _ => vec![],
+    };
+
+    // The flash stops matching the stored manifest as soon as flashing starts,
+    // so it is removed until flashing succeeded. This also drops the manifest
+    // if the chip is flashed without --incremental.
+    let manifest_path = manifest_path(&session);
+    let previous_manifest = if opt.incremental {
+        read_manifest(&manifest_path)
+    } else {
+        None
+    };
+    let _ = std::fs::remove_file(&manifest_path);
 
-    // Make the multi progresses print.
-    // indicatif requires this in a separate thread as this join is a blocking op,
-    // but is required for printing multiprogress.
-    let progress_thread_handle = std::thread::spawn(move || {
-        multi_progress.join().unwrap();
-    });
+    let options = DownloadOptions {
+        only_regions: opt.only.clone(),
+        skip_regions: opt.skip_region.clone(),
+        relocations,
+        flasher: FlasherOptions {
+            disable_watchdog: opt.disable_watchdog,
+            check_stack_overflow: opt.check_stack,
+            ..FlasherOptions::default()
+        },
+        resilient: opt.resilient,
+        erase_strategy: if opt.interleaved_erase {
+            EraseStrategy::Interleaved
+        } else {
+            EraseStrategy::AllThenProgram
+        },
+        previous_manifest,
+        // The sectors are blank after erasing the whole chip, so checking them saves erasing them again.
+        blank_check: opt.blank_check || opt.pre_erase_all,
+        ..DownloadOptions::default()
+    };
 
-    download_file_with_progress_reporting(
+    let manifest = download_file_with_manifest(
         &mut session,
         std::path::Path::new(&path_str.to_string().as_str()),
         Format::Elf,
         &mm,
         &progress,
+        &options,
     )
     .map_err(|e| format_err!("failed to flash {}: {}", path_str, e))?;
 
+    if opt.incremental {
+        write_manifest(&manifest_path, &manifest)?;
+    }
+
+    let timing = timer.borrow_mut().finish();
+    result.programmed_bytes = timing.programmed_bytes;
+    if let Some(path) = &opt.timing_json {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, &timing)?;
+    }
+
     // We don't care if we cannot join this thread.
-    let _ = progress_thread_handle.join();
+    if let Some(handle) = progress_thread_handle {
+        let _ = handle.join();
+    }
 
     // Stop timer.
     let elapsed = instant.elapsed();
@@ -311,7 +841,977 @@ fn main_try() -> Result<(), failure::Error> {
         elapsed.as_millis() as f32 / 1000.0
     );
 
-    session.target.core.reset(&mut session.probe)?;
+    if opt.size {
+        print_size_summary(&image, &session.target.memory_map);
+    }
+
+    if opt.validate_vectors {
+        validate_vectors(&mut session, &image)?;
+    }
+
+    if let (true, Some(build_id)) = (opt.check_build_id, &build_id) {
+        if !check_build_id(&mut session, build_id)? {
+            result.fail("the build-id in flash differs from the file", 2);
+        }
+    }
+
+    if let (true, Some(dual_bank)) = (opt.swap_banks, &dual_bank) {
+        dual_bank.swap(&mut session.probe)?;
+        println!("    {} the flash banks", "Swapped".green().bold());
+    }
+
+    if let Some(location) = &opt.run_to {
+        run_to(&mut session, &image, location)?;
+        return Ok(());
+    }
+
+    if opt.reset_halt {
+        let info = session.target.reset_and_halt(&mut session.probe)?;
+        println!(
+            "    {} the core at the reset vector {:#010x}",
+            "Halted".green().bold(),
+            info.pc
+        );
+        return Ok(());
+    }
+
+    session.target.reset(&mut session.probe)?;
+
+    if let Some(pattern) = &opt.expect {
+        let timeout = Duration::from_millis(opt.expect_timeout);
+        if expect_output(&mut session, &image, pattern, timeout)? {
+            println!("    {} found \"{}\"", "Passed".green().bold(), pattern);
+        } else {
+            println!(
+                "    {} \"{}\" did not show up within {} ms",
+                "Failed".red().bold(),
+                pattern,
+                opt.expect_timeout
+            );
+            result.fail(
+                format!(
+                    "\"{}\" did not show up within {} ms",
+                    pattern, opt.expect_timeout
+                ),
+                2,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints how much of each flash and RAM region the image uses, like the size summary of a linker.
+fn print_size_summary(image: &ElfImage, memory_map: &[MemoryRegion]) {
+    for usage in image.region_usage(memory_map) {
+        let kind = match usage.region {
+            MemoryRegion::Flash(_) => "flash",
+            _ => "RAM",
+        };
+        let range = usage.region.range();
+
+        println!(
+            "    {} {:5} {:#010x}..{:#010x}: {:>8} of {:>8} bytes ({:5.1}%)",
+            "Size".green().bold(),
+            kind,
+            range.start,
+            range.end,
+            usage.used,
+            usage.size(),
+            usage.percent()
+        );
+    }
+}
+
+/// Returns the path of the manifest for incremental flashing, keyed by the probe and the chip.
+///
+/// Probes without a serial number are keyed by their USB IDs.
+/// The manifests are kept in `.probe-rs` next to the `Cargo.toml` of the project,
+/// or in the current directory outside of a project.
+fn manifest_path(session: &Session) -> PathBuf {
+    let probe = match session.probe.probe_info() {
+        Some(info) => info
+            .serial_number
+            .clone()
+            .unwrap_or_else(|| format!("{:04x}-{:04x}", info.vendor_id, info.product_id)),
+        None => "unknown".to_owned(),
+    };
+    let key = format!("{}-{}", probe, session.target.identifier.chip_name);
+    let key: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    config::project_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".probe-rs")
+        .join(format!("{}.json", key))
+}
+
+/// Reads the manifest of the last flash, or returns `None` to flash everything.
+fn read_manifest(path: &Path) -> Option<FlashManifest> {
+    let file = std::fs::File::open(path).ok()?;
+
+    match serde_json::from_reader(file) {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            println!(
+                "    {} ignoring the invalid manifest {}: {}",
+                "Warning".yellow().bold(),
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+fn write_manifest(path: &Path, manifest: &FlashManifest) -> Result<(), failure::Error> {
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    let file = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(file, manifest)?;
+    Ok(())
+}
+
+/// Writes `length` bytes of memory at `address` to `path`.
+fn save_ram(
+    session: &mut Session,
+    address: &str,
+    length: &str,
+    path: &Path,
+) -> Result<(), failure::Error> {
+    let address =
+        parse_number(address).map_err(|e| format_err!("invalid address {}: {}", address, e))?;
+    let length =
+        parse_number(length).map_err(|e| format_err!("invalid length {}: {}", length, e))?;
+
+    let mut data = vec![0; length as usize];
+    session.probe.read_block8(address, &mut data)?;
+    std::fs::write(path, &data)?;
+
+    println!(
+        "    {} {} bytes at {:#010x} to {}",
+        "Saved".green().bold(),
+        length,
+        address,
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Resets the core and runs it to `location`, which is either a symbol of the image or an address.
+fn run_to(session: &mut Session, image: &ElfImage, location: &str) -> Result<(), failure::Error> {
+    let address = match image.symbol_address(location) {
+        Some(address) => address,
+        None => parse_number(location).map_err(|_| {
+            format_err!(
+                "{} is neither a symbol of the image nor an address",
+                location
+            )
+        })?,
+    };
+
+    let pc = session
+        .reset_and_run_to(address, RUN_TO_TIMEOUT)
+        .map_err(|e| format_err!("failed to run to {} at {:#010x}: {}", location, address, e))?;
+
+    if pc == address {
+        println!(
+            "    {} at {} ({:#010x})",
+            "Halted".green().bold(),
+            location,
+            pc
+        );
+    } else {
+        println!(
+            "    {} the core halted at {:#010x} before reaching {} ({:#010x})",
+            "Warning".yellow().bold(),
+            pc,
+            location,
+            address
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that the reset vector in the flashed vector table points to the entry point of the image.
+///
+/// The vector table is assumed at the lowest address of the image, its second word is the reset vector.
+/// A mismatch only prints a warning, as it usually hints at a wrong base address in the linker script.
+fn validate_vectors(session: &mut Session, image: &ElfImage) -> Result<(), failure::Error> {
+    let base_address = match image
+        .loadable_ranges()
+        .iter()
+        .map(|range| range.start)
+        .min()
+    {
+        Some(address) => address,
+        None => return Err(format_err!("the image has no loadable segments")),
+    };
+
+    let reset_vector = session.probe.read32(base_address + 4)? & !1;
+    let entry_point = image.entry_point();
+
+    if reset_vector == entry_point {
+        println!(
+            "    {} reset vector at {:#010x} matches the entry point {:#010x}",
+            "Validated".green().bold(),
+            base_address + 4,
+            entry_point
+        );
+    } else {
+        println!(
+            "    {} the reset vector at {:#010x} is {:#010x}, but the entry point of the image is {:#010x}",
+            "Warning".yellow().bold(),
+            base_address + 4,
+            reset_vector,
+            entry_point
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads the build-id back from the flash and returns whether it matches the build-id of the image.
+fn check_build_id(session: &mut Session, build_id: &BuildId) -> Result<bool, failure::Error> {
+    let mut flashed = vec![0; build_id.id.len()];
+    session.probe.read_block8(build_id.address, &mut flashed)?;
+
+    let hex = |id: &[u8]| id.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+    if flashed == build_id.id {
+        println!(
+            "    {} build-id {} at {:#010x}",
+            "Matched".green().bold(),
+            hex(&flashed),
+            build_id.address
+        );
+        Ok(true)
+    } else {
+        println!(
+            "    {} the build-id at {:#010x} is {}, but the build-id of the image is {}",
+            "Mismatch".red().bold(),
+            build_id.address,
+            hex(&flashed),
+            hex(&build_id.id)
+        );
+        Ok(false)
+    }
+}
+
+/// Prints the RTT output of the running firmware until it contains `pattern` or the `timeout` elapses.
+///
+/// The control block is located with the `_SEGGER_RTT` symbol of the image, or searched in RAM if the image has no such symbol.
+/// Returns whether the pattern was found.
+fn expect_output(
+    session: &mut Session,
+    image: &ElfImage,
+    pattern: &str,
+    timeout: Duration,
+) -> Result<bool, failure::Error> {
+    let start = Instant::now();
+    let poll_interval = Duration::from_millis(10);
+
+    let ram: Vec<Range<u32>> = session
+        .target
+        .memory_map
+        .iter()
+        .filter_map(|region| match region {
+            MemoryRegion::Ram(region) => Some(region.range.clone()),
+            _ => None,
+        })
+        .collect();
+
+    // The firmware has to initialize the control block after the reset first.
+    let rtt = loop {
+        let result = match image.symbol_address("_SEGGER_RTT") {
+            Some(address) => Rtt::attach(&mut session.probe, address),
+            None => Rtt::scan(&mut session.probe, &ram),
+        };
+
+        match result {
+            Ok(rtt) => break rtt,
+            Err(RttError::ControlBlockNotFound) if start.elapsed() < timeout => {
+                std::thread::sleep(poll_interval)
+            }
+            Err(RttError::ControlBlockNotFound) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    let mut output = vec![];
+    while start.elapsed() < timeout {
+        let offset = output.len();
+        if rtt.read(&mut session.probe, 0, &mut output)? > 0 {
+            print!("{}", String::from_utf8_lossy(&output[offset..]));
+            std::io::Write::flush(&mut std::io::stdout())?;
+            if String::from_utf8_lossy(&output).contains(pattern) {
+                return Ok(true);
+            }
+        } else {
+            std::thread::sleep(poll_interval);
+        }
+    }
+
+    Ok(false)
+}
+
+fn print_probes() {
+    let list = probe::list_all();
+    if list.is_empty() {
+        println!("No probes were found.");
+    } else {
+        println!("The following probes were found:");
+        for (index, probe) in list.iter().enumerate() {
+            match probe.details() {
+                Ok(Some(details)) => println!("    [{}]: {} {}", index, probe, details),
+                _ => println!("    [{}]: {}", index, probe),
+            }
+        }
+    }
+}
+
+/// Checks if a probe, or the probe with the given index, is connected and prints it if it is.
+fn check_probe_present(probe_index: Option<usize>) -> bool {
+    let list = probe::list_all();
+    match list.get(probe_index.unwrap_or(0)) {
+        Some(probe) => {
+            println!("{}", probe);
+            true
+        }
+        None => {
+            match probe_index {
+                Some(index) => eprintln!("There is no probe with index {}.", index),
+                None => eprintln!("No probes were found."),
+            }
+            false
+        }
+    }
+}
+
+/// Runs the self-test of the selected probe and prints the outcome of each check.
+///
+/// Returns `false` if any check failed.
+fn probe_self_test(opt: &Opt) -> Result<bool, failure::Error> {
+    let device = select_probe(opt)?;
+    println!("    {} {}", "Testing".green().bold(), device);
+
+    let checks = device.self_test()?;
+    if checks.is_empty() {
+        println!(
+            "    {} The probe has no self-test.",
+            "Warning".yellow().bold()
+        );
+    }
+
+    let mut passed = true;
+    for check in checks {
+        let (status, details) = match check.outcome {
+            SelfTestOutcome::Passed(details) => ("Passed".green().bold(), details),
+            SelfTestOutcome::Failed(details) => {
+                passed = false;
+                ("Failed".red().bold(), details)
+            }
+            SelfTestOutcome::Skipped(details) => ("Skipped".yellow().bold(), details),
+        };
+        println!("    {:>7} {}: {}", status, check.name, details);
+    }
+
+    Ok(passed)
+}
+
+/// Opens the probe once and serves flash requests from stdin until it is closed.
+fn serve(opt: &Opt) -> Result<(), failure::Error> {
+    let device = select_probe(opt)?;
+    let mut probe = open_probe(&device, opt, None)?;
+
+    if let Some(delay_us) = opt.transfer_delay_us {
+        probe.set_inter_transfer_delay(Duration::from_micros(delay_us));
+    }
+    probe.set_single_access_port(opt.single_ap);
+    if let Some(speed) = opt.speed {
+        probe.set_speed(speed)?;
+    }
+
+    let mut registry = Registry::from_builtin_families();
+    if let Some(cdp) = &opt.chip_description_path {
+        registry.add_target_from_yaml(Path::new(cdp))?;
+    }
+
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    serve::run(
+        probe,
+        &registry,
+        opt.chip.as_deref(),
+        stdin.lock(),
+        stdout.lock(),
+    )
+}
+
+/// Attaches to the target and prints its IDs and the chips of the registry which match them.
+fn identify(opt: &Opt) -> Result<(), failure::Error> {
+    let device = select_probe(opt)?;
+    let mut probe = open_probe(&device, opt, None)?;
+
+    if let Some(delay_us) = opt.transfer_delay_us {
+        probe.set_inter_transfer_delay(Duration::from_micros(delay_us));
+    }
+    probe.set_single_access_port(opt.single_ap);
+    if let Some(speed) = opt.speed {
+        probe.set_speed(speed)?;
+    }
+
+    let mut registry = Registry::from_builtin_families();
+    if let Some(cdp) = &opt.chip_description_path {
+        registry.add_target_from_yaml(Path::new(cdp))?;
+    }
+
+    let chip_info = ChipInfo::read_from_rom_table(&mut probe)?;
+    let identification = registry.identify(&chip_info, &mut probe);
+
+    println!(
+        "    {} {} (cc={:#04x}, id={:#04x})",
+        "Manufacturer".green().bold(),
+        chip_info
+            .manufacturer
+            .get()
+            .unwrap_or("<unknown manufacturer>"),
+        chip_info.manufacturer.cc,
+        chip_info.manufacturer.id
+    );
+    println!("    {} {:#06x}", "Part".green().bold(), chip_info.part);
+    for (family, address, value) in &identification.id_codes {
+        println!(
+            "    {} {:#x} (register {:#010x} of {})",
+            "ID code".green().bold(),
+            value,
+            address,
+            family
+        );
+    }
+
+    if identification.matches.is_empty() {
+        println!(
+            "    {} No chip of the registry matches these IDs. Please file them in an issue, such that the chip can be supported.",
+            "Warning".yellow().bold()
+        );
+        return Ok(());
+    }
+
+    let certain = identification.matches[0].id_code_matched;
+    for chip_match in &identification.matches {
+        if certain && !chip_match.id_code_matched {
+            break;
+        }
+        println!(
+            "    {} {} ({})",
+            "Chip".green().bold(),
+            chip_match.chip,
+            chip_match.family
+        );
+    }
+
+    Ok(())
+}
+
+/// Selects the probe given with --probe-index, or the only connected probe.
+fn select_probe(opt: &Opt) -> Result<DebugProbeInfo, failure::Error> {
+    let mut list = probe::list_all();
+
+    let mut device = match opt.probe_index {
+        Some(index) if index < list.len() => list.swap_remove(index),
+        Some(index) => {
+            return Err(format_err!(
+                "there is no probe with index {}, only {} probes were found",
+                index,
+                list.len()
+            ))
+        }
+        None => match list.len() {
+            0 => return Err(format_err!("no supported probe was found")),
+            1 => list.remove(0),
+            _ => {
+                let probes: Vec<String> = list
+                    .iter()
+                    .enumerate()
+                    .map(|(index, probe)| format!("    [{}]: {}", index, probe))
+                    .collect();
+                return Err(format_err!(
+                    "multiple probes were found:\n{}\nSelect one of them with --probe-index <n>",
+                    probes.join("\n")
+                ));
+            }
+        },
+    };
+
+    device.dap_interface = opt.dap_interface;
+
+    Ok(device)
+}
+
+/// Opens the first probe found, attaches to the target and selects the chip.
+fn open_session(opt: &Opt) -> Result<Session, failure::Error> {
+    let device = select_probe(opt)?;
+
+    if opt.nrf_recover {
+        if let DebugProbeType::STLink = device.probe_type {
+            return Err(format_err!("It isn't possible to recover with a ST-Link"));
+        }
+    }
+
+    let mut registry = Registry::from_builtin_families();
+    if let Some(cdp) = &opt.chip_description_path {
+        registry.add_target_from_yaml(Path::new(cdp))?;
+    }
+
+    // A known chip is looked up before attaching, such that its default protocol can be used.
+    let target = match opt.chip.as_ref().or(opt.expect_chip.as_ref()) {
+        Some(identifier) => Some(registry.get_target(SelectionStrategy::TargetIdentifier(
+            identifier.as_str().into(),
+        ))?),
+        None => None,
+    };
+
+    let default_protocol = target.as_ref().and_then(|target| target.default_protocol);
+    let mut probe = open_probe(&device, opt, default_protocol)?;
+
+    if let Some(delay_us) = opt.transfer_delay_us {
+        probe.set_inter_transfer_delay(Duration::from_micros(delay_us));
+    }
+    probe.set_single_access_port(opt.single_ap);
+
+    if opt.nrf_recover {
+        probe.nrf_recover()?;
+    }
+
+    if let Some(expected) = &opt.expect_chip {
+        check_expected_chip(&registry, &mut probe, expected)?;
+    }
+
+    let target = match target {
+        Some(target) => target,
+        None => registry.get_target(SelectionStrategy::ChipInfo(ChipInfo::read_from_rom_table(
+            &mut probe,
+        )?))?,
+    };
+
+    if let Some(speed) = opt.speed {
+        probe.set_speed(speed)?;
+    } else if let Some(max_speed) = target.max_speed_khz {
+        if let Err(e) = probe.set_speed(max_speed) {
+            println!(
+                "    {} failed to limit the speed to the {} kHz of the chip: {}",
+                "Warning".yellow().bold(),
+                max_speed,
+                e
+            );
+        }
+    }
+
+    if let Some(retries) = opt.fault_retries {
+        probe.set_fault_retry(Some(FaultRetry {
+            retries,
+            ..FaultRetry::default()
+        }));
+    }
+
+    let mut session = Session::new(target, probe);
+
+    if let Some(path) = &opt.init_script {
+        let script = InitScript::from_yaml_reader(std::fs::File::open(path)?)?;
+        script.run(&mut session.probe)?;
+    }
+
+    Ok(session)
+}
+
+/// Detects the connected chip and returns an error if it is not the `expected` chip.
+fn check_expected_chip(
+    registry: &Registry,
+    probe: &mut MasterProbe,
+    expected: &str,
+) -> Result<(), failure::Error> {
+    let chip_info = ChipInfo::read_from_rom_table(probe)?;
+    let identification = registry.identify(&chip_info, probe);
+
+    if !identification.matches_chip(expected) {
+        let detected = if identification.matches.is_empty() {
+            format!("an unknown chip ({})", chip_info)
+        } else {
+            identification
+                .matches
+                .iter()
+                .map(|m| m.chip.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        return Err(format_err!(
+            "Expected the chip {}, but detected {}. Nothing was written to the target.",
+            expected,
+            detected
+        ));
+    }
+
+    println!("    {} {} was detected", "Checked".green().bold(), expected);
+
+    Ok(())
+}
+
+/// Flashes the image through a serial bootloader, for boards without a debug probe.
+///
+/// The chip is only needed for its memory map, so the image can be filtered like for a probe.
+fn flash_xmodem(opt: &Opt, device: &Path, path: &Path) -> Result<(), failure::Error> {
+    let chip = opt.chip.as_ref().ok_or_else(|| {
+        format_err!("--xmodem requires a chip to be selected with --chip or PROBE_RS_CHIP")
+    })?;
+
+    let mut registry = Registry::from_builtin_families();
+    if let Some(cdp) = &opt.chip_description_path {
+        registry.add_target_from_yaml(Path::new(cdp))?;
+    }
+
+    let target = registry.get_target(SelectionStrategy::TargetIdentifier(chip.as_str().into()))?;
+
+    let mut port = std::fs::OpenOptions::new();
+    port.read(true).write(true);
+    // Reads have to return if the bootloader stops answering, such that the transfer times out.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        port.custom_flags(libc::O_NONBLOCK);
+    }
+    let port = port
+        .open(device)
+        .map_err(|e| format_err!("failed to open {}: {}", device.display(), e))?;
+
+    let options = DownloadOptions {
+        only_regions: opt.only.clone(),
+        skip_regions: opt.skip_region.clone(),
+        ..DownloadOptions::default()
+    };
+
+    let instant = Instant::now();
+
+    download_file_xmodem(
+        port,
+        path,
+        Format::Elf,
+        &target.memory_map,
+        &plain_progress(),
+        &options,
+    )
+    .map_err(|e| format_err!("failed to flash {}: {}", path.display(), e))?;
+
+    println!(
+        "    {} in {}s",
+        "Finished".green().bold(),
+        instant.elapsed().as_millis() as f32 / 1000.0
+    );
+
+    Ok(())
+}
+
+/// Erases the whole flash of the chip.
+fn erase_flash(session: &mut Session) -> Result<(), failure::Error> {
+    let mm = session.target.memory_map.clone();
+    let instant = Instant::now();
+
+    erase_all(session, &mm, &plain_progress())
+        .map_err(|e| format_err!("failed to erase the flash: {}", e))?;
+
+    println!(
+        "    {} in {}s",
+        "Finished".green().bold(),
+        instant.elapsed().as_millis() as f32 / 1000.0
+    );
+
+    Ok(())
+}
+
+/// Detects the format of the file at `path` by its contents, after decompressing it if it is
+/// compressed with gzip or zstd.
+fn file_format(path: &Path) -> Result<Format, failure::Error> {
+    let data = read_image(path)?;
+
+    Format::detect(&data)
+        .ok_or_else(|| format_err!("{} is neither an ELF nor an Intel HEX file", path.display()))
+}
+
+/// Compares the flash with the file at `path` and returns whether they match.
+fn verify_only(session: &mut Session, opt: &Opt, path: &Path) -> Result<bool, failure::Error> {
+    let format = file_format(path)?;
+    let options = DownloadOptions {
+        only_regions: opt.only.clone(),
+        skip_regions: opt.skip_region.clone(),
+        ..DownloadOptions::default()
+    };
+    let mm = session.target.memory_map.clone();
+
+    println!(
+        "    {} flash against {}",
+        "Verifying".green().bold(),
+        path.display()
+    );
+
+    match verify_file(session, path, format, &mm, &options)
+        .map_err(|e| format_err!("failed to verify {}: {}", path.display(), e))?
+    {
+        Some(address) => {
+            println!(
+                "    {} the flash differs from the file at address {:#010x}",
+                "Mismatch".red().bold(),
+                address
+            );
+            Ok(false)
+        }
+        None => {
+            println!(
+                "    {} the flash matches the file",
+                "Verified".green().bold()
+            );
+            Ok(true)
+        }
+    }
+}
+
+/// Halts the core and prints the throughput of block transfers to the RAM at `address`.
+fn run_benchmark(session: &mut Session, address: u32, length: u32) -> Result<(), failure::Error> {
+    if address & 0x3 != 0 || length & 0x3 != 0 || length == 0 {
+        return Err(format_err!(
+            "the address and the length of --benchmark have to be word aligned and the length must not be zero"
+        ));
+    }
+
+    session.target.core.halt(&mut session.probe)?;
+
+    println!(
+        "    {} {} bytes of RAM at {:#010x}, {} times per transfer",
+        "Benchmarking".green().bold(),
+        length,
+        address,
+        benchmark::REPETITIONS
+    );
+
+    for throughput in benchmark::run(&mut session.probe, address, length)? {
+        println!(
+            "    {:>6} bit: read {:.3} MB/s, write {:.3} MB/s",
+            throughput.width,
+            throughput.read / 1_000_000.0,
+            throughput.write / 1_000_000.0
+        );
+    }
+
+    Ok(())
+}
+
+/// Cycles the flash sector at `address`, prints the result of the endurance test and returns whether it passed.
+fn run_endurance_test(
+    session: &mut Session,
+    address: u32,
+    cycles: usize,
+) -> Result<bool, failure::Error> {
+    println!(
+        "    {} sector at {:#010x} for {} cycles",
+        "Cycling".green().bold(),
+        address,
+        cycles
+    );
+
+    let report = endurance_test(session, address, cycles, |cycle| {
+        if cycle % 100 == 0 {
+            println!("    {} {} cycles", "Completed".green().bold(), cycle);
+        }
+    })?;
+
+    match report.failure {
+        Some(failure) => {
+            println!(
+                "    {} sector {:#010x}..{:#010x} in cycle {}: {}",
+                "Failed".red().bold(),
+                report.sector.base_address,
+                report.sector.base_address + report.sector.size,
+                failure.cycle,
+                failure.error
+            );
+            Ok(false)
+        }
+        None => {
+            println!(
+                "    {} {} cycles of sector {:#010x}..{:#010x} without errors",
+                "Finished".green().bold(),
+                report.cycles_completed,
+                report.sector.base_address,
+                report.sector.base_address + report.sector.size
+            );
+            Ok(true)
+        }
+    }
+}
+
+/// Parses a number given in decimal or in hexadecimal with a `0x` prefix.
+fn parse_number(number: &str) -> Result<u32, std::num::ParseIntError> {
+    let number = number.trim();
+    if number.starts_with("0x") || number.starts_with("0X") {
+        u32::from_str_radix(&number[2..], 16)
+    } else {
+        number.parse()
+    }
+}
+
+/// Parses an address range in the form `<start>-<end>`, where the end is exclusive.
+///
+/// Both addresses can be given in decimal or in hexadecimal with a `0x` prefix.
+fn parse_address_range(range: &str) -> Result<Range<u32>, String> {
+    let parse = |address: &str| {
+        parse_number(address).map_err(|e| format!("invalid address '{}': {}", address.trim(), e))
+    };
+
+    let mut parts = range.splitn(2, '-');
+    let start = parse(parts.next().unwrap_or(""))?;
+    let end = parse(
+        parts
+            .next()
+            .ok_or_else(|| format!("'{}' is not of the form <start>-<end>", range))?,
+    )?;
+
+    if start >= end {
+        return Err(format!("the range '{}' is empty", range));
+    }
+
+    Ok(start..end)
+}
+
+/// The value of `--protocol`.
+#[derive(Debug, Clone, Copy)]
+enum ProtocolChoice {
+    /// Tries SWD first and JTAG second, see `DebugProbeInfo::open_autodetect`.
+    Auto,
+    Fixed(WireProtocol),
+}
+
+impl std::str::FromStr for ProtocolChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(ProtocolChoice::Auto);
+        }
+
+        s.parse().map(ProtocolChoice::Fixed).map_err(|_| {
+            format!(
+                "'{}' is not a wire protocol, use 'swd', 'jtag' or 'auto'.",
+                s
+            )
+        })
+    }
+}
+
+/// Opens the probe with the protocol of `--protocol`, or with `default` and SWD otherwise.
+fn open_probe(
+    device: &DebugProbeInfo,
+    opt: &Opt,
+    default: Option<WireProtocol>,
+) -> Result<MasterProbe, DebugProbeError> {
+    match opt.protocol {
+        Some(ProtocolChoice::Auto) => device.open_autodetect(opt.target_sel),
+        Some(ProtocolChoice::Fixed(protocol)) => {
+            device.open_with_target_sel(Some(protocol), opt.target_sel)
+        }
+        None => {
+            device.open_with_target_sel(Some(default.unwrap_or(WireProtocol::Swd)), opt.target_sel)
+        }
+    }
+}
+
+/// Creates a progress callback which prints a line at the start and end of each phase
+/// instead of drawing progress bars.
+fn plain_progress() -> FlashProgress {
+    FlashProgress::new(|event| {
+        use ProgressEvent::*;
+        match event {
+            StartedErasing => println!("    {} sectors", "Erasing".green().bold()),
+            StartedFlashing => println!("    {} pages", "Programming".green().bold()),
+            FinishedErasing => println!("    {} erasing", "Done".green().bold()),
+            FinishedProgramming => println!("    {} programming", "Done".green().bold()),
+            Initialized { .. }
+            | PageFlashed { .. }
+            | SectorErased { .. }
+            | SectorBlankSkipped { .. }
+            | StartedReading { .. }
+            | ChunkRead { .. }
+            | FinishedReading => (),
+        }
+    })
+}
+
+/// Prints the flash algorithm which would be loaded into RAM for the selected chip.
+///
+/// If `output` is given, the assembled instruction blob is written to that file.
+fn dump_flash_algorithm(opt: &Opt, output: Option<&Path>) -> Result<(), failure::Error> {
+    let chip = opt.chip.as_ref().ok_or_else(|| {
+        format_err!("--dump-flash-algo requires a chip to be selected with --chip or PROBE_RS_CHIP")
+    })?;
+
+    let mut registry = Registry::from_builtin_families();
+    if let Some(cdp) = &opt.chip_description_path {
+        registry.add_target_from_yaml(Path::new(cdp))?;
+    }
+
+    let target = registry.get_target(SelectionStrategy::TargetIdentifier(chip.as_str().into()))?;
+    let algo = target
+        .flash_algorithm
+        .ok_or_else(|| format_err!("no flash algorithm is available for {}", chip))?;
+
+    let optional = |address: Option<u32>| {
+        address.map_or_else(|| "none".to_owned(), |a| format!("{:#010x}", a))
+    };
+    let page_buffers: Vec<String> = algo
+        .page_buffers
+        .iter()
+        .map(|a| format!("{:#010x}", a))
+        .collect();
+
+    println!("Flash algorithm {}", algo.name);
+    println!("    load_address:    {:#010x}", algo.load_address);
+    println!("    instructions:    {} words", algo.instructions.len());
+    println!("    pc_init:         {}", optional(algo.pc_init));
+    println!("    pc_uninit:       {}", optional(algo.pc_uninit));
+    println!("    pc_program_page: {:#010x}", algo.pc_program_page);
+    println!("    pc_erase_sector: {:#010x}", algo.pc_erase_sector);
+    println!("    pc_erase_all:    {}", optional(algo.pc_erase_all));
+    println!("    static_base:     {:#010x}", algo.static_base);
+    println!("    begin_stack:     {:#010x}", algo.begin_stack);
+    println!("    begin_data:      {:#010x}", algo.begin_data);
+    println!("    page_buffers:    [{}]", page_buffers.join(", "));
+
+    println!("Instructions:");
+    for (i, chunk) in algo.instructions.chunks(4).enumerate() {
+        let address = algo.load_address + (i * 16) as u32;
+        let words: Vec<String> = chunk.iter().map(|w| format!("{:08x}", w)).collect();
+        println!("    {:#010x}: {}", address, words.join(" "));
+    }
+
+    if let Some(output) = output {
+        let blob: Vec<u8> = algo
+            .instructions
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        std::fs::write(output, blob)?;
+        println!("Wrote instruction blob to {}", output.display());
+    }
 
     Ok(())
 }
@@ -333,17 +1833,59 @@ fn print_families() {
     }
 }
 
+/// Returns the arguments for `cargo build`, which are the `cargo build` arguments of `opt`.
+///
+/// `--no-progress` is passed on as `--quiet` on purpose, such that cargo build is quiet as well.
+fn cargo_build_args(opt: &Opt) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec![];
+
+    let options = [
+        ("--bin", &opt.bin),
+        ("--example", &opt.example),
+        ("--package", &opt.package),
+        ("--target", &opt.target),
+    ];
+    for (name, value) in options.iter() {
+        if let Some(value) = value {
+            args.push(name.into());
+            args.push(value.into());
+        }
+    }
+    if let Some(manifest_path) = &opt.manifest_path {
+        args.push("--manifest-path".into());
+        args.push(manifest_path.into());
+    }
+    for features in &opt.features {
+        args.push("--features".into());
+        args.push(features.into());
+    }
+
+    let flags = [
+        ("--release", opt.release),
+        ("--no-default-features", opt.no_default_features),
+        ("--all-features", opt.all_features),
+        ("--quiet", opt.no_progress),
+    ];
+    for (name, set) in flags.iter() {
+        if *set {
+            args.push(name.into());
+        }
+    }
+
+    args
+}
+
+/// Returns the exit code with which cargo-flash exits if the command with `status` failed.
 #[cfg(unix)]
-fn handle_failed_command(status: std::process::ExitStatus) -> ! {
+fn failed_command_exit_code(status: std::process::ExitStatus) -> i32 {
     use std::os::unix::process::ExitStatusExt;
-    let status = status.code().or_else(|| status.signal()).unwrap_or(1);
-    std::process::exit(status)
+    status.code().or_else(|| status.signal()).unwrap_or(1)
 }
 
+/// Returns the exit code with which cargo-flash exits if the command with `status` failed.
 #[cfg(not(unix))]
-fn handle_failed_command(status: std::process::ExitStatus) -> ! {
-    let status = status.code().unwrap_or(1);
-    std::process::exit(status)
+fn failed_command_exit_code(status: std::process::ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
 }
 
 #[derive(Debug)]
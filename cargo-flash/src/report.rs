@@ -0,0 +1,85 @@
+use probe_rs::session::Session;
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// The outcome of a run, which is written as a single line of JSON with `--result-json`.
+#[derive(Debug, Serialize)]
+pub struct FlashResult {
+    pub success: bool,
+    /// The error which ended the run, if it failed.
+    pub error: Option<String>,
+    /// The serial number of the probe, if it has one.
+    pub probe_serial: Option<String>,
+    /// The name of the chip.
+    pub target: Option<String>,
+    /// The number of bytes which were programmed.
+    pub programmed_bytes: u64,
+    /// The time from the start of the run until the result was written.
+    pub duration_ms: u64,
+    /// Whether the flash matched the file, if it was verified.
+    pub verified: Option<bool>,
+    /// Where the result is written to, `-` for stdout. Nothing is written if this is `None`.
+    #[serde(skip)]
+    pub output: Option<PathBuf>,
+    /// The exit code of the run.
+    #[serde(skip)]
+    pub exit_code: i32,
+    #[serde(skip)]
+    start: Instant,
+}
+
+impl FlashResult {
+    pub fn new() -> Self {
+        Self {
+            success: true,
+            error: None,
+            probe_serial: None,
+            target: None,
+            programmed_bytes: 0,
+            duration_ms: 0,
+            verified: None,
+            output: None,
+            exit_code: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Records the probe and the chip of the session.
+    pub fn connected(&mut self, session: &Session) {
+        self.probe_serial = session
+            .probe
+            .probe_info()
+            .and_then(|info| info.serial_number.clone());
+        self.target = Some(session.target.identifier.chip_name.clone());
+    }
+
+    /// Marks the run as failed with the given error and exit code.
+    pub fn fail(&mut self, error: impl ToString, exit_code: i32) {
+        self.success = false;
+        self.error = Some(error.to_string());
+        self.exit_code = exit_code;
+    }
+
+    /// Writes the result as one line of JSON, if an output was requested.
+    pub fn write(&mut self) -> Result<(), failure::Error> {
+        self.duration_ms = self.start.elapsed().as_millis() as u64;
+
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+
+        match self.output.as_deref() {
+            Some(path) if path == Path::new("-") => {
+                let stdout = std::io::stdout();
+                let mut stdout = stdout.lock();
+                stdout.write_all(line.as_bytes())?;
+                stdout.flush()?;
+            }
+            Some(path) => std::fs::write(path, line)?,
+            None => (),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use failure::format_err;
+use probe_rs::{
+    config::{
+        registry::{Registry, SelectionStrategy},
+        target::Target,
+    },
+    flash::download::download_file,
+    probe::MasterProbe,
+    session::Session,
+    target::info::ChipInfo,
+};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// A request of `--serve`, one per line.
+#[derive(Debug)]
+pub enum Request {
+    /// `<path> [<chip>]` flashes the file at `path` to the chip which is connected now.
+    Flash { path: PathBuf, chip: Option<String> },
+    /// `quit` closes the probe and exits.
+    Quit,
+}
+
+impl std::str::FromStr for Request {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+
+        match words[..] {
+            ["quit"] => Ok(Request::Quit),
+            [path] => Ok(Request::Flash {
+                path: path.into(),
+                chip: None,
+            }),
+            [path, chip] => Ok(Request::Flash {
+                path: path.into(),
+                chip: Some(chip.to_owned()),
+            }),
+            _ => Err(format!(
+                "'{}' is not a request, use '<path> [<chip>]' or 'quit'.",
+                s
+            )),
+        }
+    }
+}
+
+/// Reads requests from `input` and flashes each one with the already opened `probe`.
+///
+/// The target is attached again for each request, such that the boards can be swapped in between.
+/// Each request is answered with a line of `ok` or `error: <reason>` on `output`.
+/// Requests without a chip are flashed to `default_chip`, or to the chip read from the ROM table.
+pub fn run(
+    mut probe: MasterProbe,
+    registry: &Registry,
+    default_chip: Option<&str>,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> Result<(), failure::Error> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = match line.parse::<Request>() {
+            Ok(Request::Quit) => break,
+            Ok(Request::Flash { path, chip }) => {
+                let (returned, result) =
+                    flash(probe, registry, &path, chip.as_deref().or(default_chip));
+                probe = returned;
+                result
+            }
+            Err(e) => Err(format_err!("{}", e)),
+        };
+
+        match result {
+            Ok(()) => writeln!(output, "ok")?,
+            Err(e) => writeln!(output, "error: {}", e)?,
+        }
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Attaches to the target, flashes the file at `path` and resets the core.
+///
+/// The probe is handed back in any case, so it can serve the next request.
+fn flash(
+    mut probe: MasterProbe,
+    registry: &Registry,
+    path: &Path,
+    chip: Option<&str>,
+) -> (MasterProbe, Result<(), failure::Error>) {
+    let target = match attach(&mut probe, registry, chip) {
+        Ok(target) => target,
+        Err(e) => return (probe, Err(e)),
+    };
+
+    let mut session = Session::new(target, probe);
+    let result = download(&mut session, path);
+
+    (session.probe, result)
+}
+
+/// Attaches to the board which is connected now and returns its target.
+fn attach(
+    probe: &mut MasterProbe,
+    registry: &Registry,
+    chip: Option<&str>,
+) -> Result<Target, failure::Error> {
+    probe.reset_probe()?;
+
+    let target = match chip {
+        Some(chip) => registry.get_target(SelectionStrategy::TargetIdentifier(chip.into()))?,
+        None => registry.get_target(SelectionStrategy::ChipInfo(ChipInfo::read_from_rom_table(
+            probe,
+        )?))?,
+    };
+
+    Ok(target)
+}
+
+fn download(session: &mut Session, path: &Path) -> Result<(), failure::Error> {
+    let format = crate::file_format(path)?;
+    let mm = session.target.memory_map.clone();
+
+    download_file(session, path, format, &mm)
+        .map_err(|e| format_err!("failed to flash {}: {}", path.display(), e))?;
+    session.target.reset(&mut session.probe)?;
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use probe_rs::flash::ProgressEvent;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// The time spent in the phases of a flash run.
+#[derive(Debug, Default, Serialize)]
+pub struct FlashTiming {
+    /// Opening the probe and attaching to the target.
+    pub connect_ms: u64,
+    /// Loading the flash algorithm into RAM and initializing it.
+    pub algorithm_load_ms: u64,
+    /// Erasing sectors.
+    pub erase_ms: u64,
+    /// Programming pages.
+    pub program_ms: u64,
+    /// Everything after the last page was programmed until the download is done,
+    /// e.g. verifying and uninitializing the flash algorithm.
+    pub verify_ms: u64,
+    /// The complete run from opening the probe until the download is done.
+    pub total_ms: u64,
+    /// The number of bytes which were erased.
+    pub erased_bytes: u64,
+    /// The number of bytes which were not erased, as they were blank already.
+    pub blank_skipped_bytes: u64,
+    /// The number of bytes which were programmed.
+    pub programmed_bytes: u64,
+}
+
+/// Accumulates a `FlashTiming` from the phase boundaries of a flash run.
+pub struct PhaseTimer {
+    timing: FlashTiming,
+    start: Instant,
+    phase_start: Instant,
+    algorithm_loaded: bool,
+}
+
+impl PhaseTimer {
+    /// Creates a new timer. The connect phase starts right away.
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            timing: FlashTiming::default(),
+            start: now,
+            phase_start: now,
+            algorithm_loaded: false,
+        }
+    }
+
+    /// Marks the end of the connect phase and the start of the download.
+    pub fn connected(&mut self) {
+        self.timing.connect_ms = millis(self.end_phase());
+    }
+
+    /// Accounts a progress event of the download.
+    pub fn record(&mut self, event: &ProgressEvent) {
+        use ProgressEvent::*;
+        match event {
+            StartedErasing | StartedFlashing => {
+                let elapsed = millis(self.end_phase());
+                // The flash algorithm is loaded before the first phase starts.
+                // Time spent between two phases belongs to the previous phase.
+                if !self.algorithm_loaded {
+                    self.timing.algorithm_load_ms += elapsed;
+                    self.algorithm_loaded = true;
+                } else {
+                    self.timing.verify_ms += elapsed;
+                }
+            }
+            FinishedErasing => self.timing.erase_ms += millis(self.end_phase()),
+            FinishedProgramming => self.timing.program_ms += millis(self.end_phase()),
+            SectorErased { size, .. } => self.timing.erased_bytes += u64::from(*size),
+            PageFlashed { size, .. } => self.timing.programmed_bytes += u64::from(*size),
+            SectorBlankSkipped { size } => self.timing.blank_skipped_bytes += u64::from(*size),
+            Initialized { .. } | StartedReading { .. } | ChunkRead { .. } | FinishedReading => (),
+        }
+    }
+
+    /// Marks the end of the download and returns the collected timing.
+    pub fn finish(&mut self) -> FlashTiming {
+        self.timing.verify_ms += millis(self.end_phase());
+        self.timing.total_ms = millis(self.start.elapsed());
+        std::mem::take(&mut self.timing)
+    }
+
+    /// Returns the duration of the current phase and starts the next one.
+    fn end_phase(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now - self.phase_start;
+        self.phase_start = now;
+        elapsed
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
@@ -1,14 +1,14 @@
 use crate::SharedOptions;
 
 use probe_rs::{
-    config::registry::{Registry, RegistryError, SelectionStrategy},
+    config::{
+        init_script::{InitScript, InitScriptError},
+        registry::{Registry, RegistryError, SelectionStrategy},
+    },
     cores::m0::FakeM0,
     coresight::access_ports::AccessPortError,
-    flash::download::FileDownloadError,
-    probe::{
-        daplink, stlink, DebugProbe, DebugProbeError, DebugProbeType, FakeProbe, MasterProbe,
-        WireProtocol,
-    },
+    flash::{download::FileDownloadError, FlashLoaderError, FlasherError, MemoryDumpError},
+    probe::{self, DebugProbeError, DebugProbeInfo, FakeProbe, MasterProbe, WireProtocol},
     session::Session,
     target::info::{self, ChipInfo},
 };
@@ -27,9 +27,15 @@ pub enum CliError {
     AccessPort(AccessPortError),
     StdIO(std::io::Error),
     FileDownload(FileDownloadError),
+    Flasher(FlasherError),
+    FlashLoader(FlashLoaderError),
+    MemoryDump(MemoryDumpError),
     RegistryError(RegistryError),
+    InitScript(InitScriptError),
     MissingArgument,
-    UnableToOpenProbe,
+    NoProbeFound,
+    MultipleProbesFound(Vec<DebugProbeInfo>),
+    ProbeIndexOutOfRange(usize, usize), // Contains the requested index and the number of probes.
 }
 
 impl Error for CliError {
@@ -42,9 +48,15 @@ impl Error for CliError {
             AccessPort(ref e) => Some(e),
             StdIO(ref e) => Some(e),
             RegistryError(ref e) => Some(e),
+            InitScript(ref e) => Some(e),
             MissingArgument => None,
-            UnableToOpenProbe => None,
+            NoProbeFound => None,
+            MultipleProbesFound(_) => None,
+            ProbeIndexOutOfRange(_, _) => None,
             FileDownload(ref e) => Some(e),
+            Flasher(ref e) => Some(e),
+            FlashLoader(ref e) => Some(e),
+            MemoryDump(ref e) => Some(e),
         }
     }
 }
@@ -59,9 +71,25 @@ impl fmt::Display for CliError {
             AccessPort(ref e) => e.fmt(f),
             StdIO(ref e) => e.fmt(f),
             FileDownload(ref e) => e.fmt(f),
+            Flasher(ref e) => e.fmt(f),
+            FlashLoader(ref e) => e.fmt(f),
+            MemoryDump(ref e) => e.fmt(f),
             RegistryError(ref e) => e.fmt(f),
+            InitScript(ref e) => e.fmt(f),
             MissingArgument => write!(f, "Command expected more arguments."),
-            UnableToOpenProbe => write!(f, "Unable to open probe."),
+            NoProbeFound => write!(f, "No probe found."),
+            MultipleProbesFound(list) => {
+                writeln!(f, "Multiple probes found:")?;
+                for (index, probe) in list.iter().enumerate() {
+                    writeln!(f, "[{}]: {}", index, probe)?;
+                }
+                write!(f, "Select one of them with --probe-index <n>.")
+            }
+            ProbeIndexOutOfRange(index, count) => write!(
+                f,
+                "There is no probe with index {}, only {} probes were found.",
+                index, count
+            ),
         }
     }
 }
@@ -96,44 +124,57 @@ impl From<RegistryError> for CliError {
     }
 }
 
+impl From<InitScriptError> for CliError {
+    fn from(error: InitScriptError) -> Self {
+        CliError::InitScript(error)
+    }
+}
+
+impl From<FlasherError> for CliError {
+    fn from(error: FlasherError) -> Self {
+        CliError::Flasher(error)
+    }
+}
+
+impl From<FlashLoaderError> for CliError {
+    fn from(error: FlashLoaderError) -> Self {
+        CliError::FlashLoader(error)
+    }
+}
+
 impl From<FileDownloadError> for CliError {
     fn from(error: FileDownloadError) -> Self {
         CliError::FileDownload(error)
     }
 }
 
-pub(crate) fn open_probe(index: Option<usize>) -> Result<MasterProbe, CliError> {
-    let mut list = daplink::tools::list_daplink_devices();
-    list.extend(stlink::tools::list_stlink_devices());
+impl From<MemoryDumpError> for CliError {
+    fn from(error: MemoryDumpError) -> Self {
+        CliError::MemoryDump(error)
+    }
+}
+
+pub(crate) fn open_probe(
+    index: Option<usize>,
+    target_sel: Option<u32>,
+) -> Result<MasterProbe, CliError> {
+    let list = probe::list_all();
 
     let device = match index {
-        Some(index) => list.get(index).ok_or(CliError::UnableToOpenProbe)?,
+        Some(index) => list
+            .get(index)
+            .ok_or(CliError::ProbeIndexOutOfRange(index, list.len()))?,
         None => {
             // open the default probe, if only one probe was found
-            if list.len() == 1 {
-                &list[0]
-            } else {
-                return Err(CliError::UnableToOpenProbe);
+            match list.len() {
+                0 => return Err(CliError::NoProbeFound),
+                1 => &list[0],
+                _ => return Err(CliError::MultipleProbesFound(list)),
             }
         }
     };
 
-    let probe = match device.probe_type {
-        DebugProbeType::DAPLink => {
-            let mut link = daplink::DAPLink::new_from_probe_info(&device)?;
-
-            link.attach(Some(WireProtocol::Swd))?;
-
-            MasterProbe::from_specific_probe(link)
-        }
-        DebugProbeType::STLink => {
-            let mut link = stlink::STLink::new_from_probe_info(&device)?;
-
-            link.attach(Some(WireProtocol::Swd))?;
-
-            MasterProbe::from_specific_probe(link)
-        }
-    };
+    let probe = device.open_with_target_sel(Some(WireProtocol::Swd), target_sel)?;
 
     Ok(probe)
 }
@@ -145,7 +186,7 @@ pub(crate) fn with_device<F>(shared_options: &SharedOptions, f: F) -> Result<(),
 where
     for<'a> F: FnOnce(Session) -> Result<(), CliError>,
 {
-    let mut probe = open_probe(shared_options.n)?;
+    let mut probe = open_probe(shared_options.n, shared_options.target_sel)?;
 
     let strategy = if let Some(identifier) = &shared_options.target {
         SelectionStrategy::TargetIdentifier(identifier.into())
@@ -157,7 +198,18 @@ where
 
     let target = registry.get_target(strategy)?;
 
-    let session = Session::new(target, probe);
+    let mut session = Session::new(target, probe);
+
+    session.detect_flash_size();
+
+    if shared_options.keep_debug_alive {
+        session.keep_debug_alive()?;
+    }
+
+    if let Some(path) = &shared_options.init_script {
+        let script = InitScript::from_yaml_reader(File::open(path)?)?;
+        script.run(&mut session.probe)?;
+    }
 
     f(session)
 }
@@ -1,6 +1,9 @@
 use crate::common::CliError;
 
-use probe_rs::{cores::CortexDump, coresight::memory::MI, debug::DebugInfo, session::Session};
+use probe_rs::{
+    cores::CortexDump, coresight::memory::MI, debug::DebugInfo, session::Session,
+    target::CoreRegisterAddress,
+};
 
 use capstone::Capstone;
 
@@ -19,14 +22,19 @@ impl DebugCli {
 
         cli.add_command(Command {
             name: "step",
-            help_text: "Step a single instruction",
+            help_text: "Step a single instruction, or the given number of instructions",
+
+            function: |cli_data, args| {
+                let count = args
+                    .first()
+                    .map(|c| c.parse::<usize>().unwrap())
+                    .unwrap_or(1);
 
-            function: |cli_data, _args| {
                 let cpu_info = cli_data
                     .session
                     .target
                     .core
-                    .step(&mut cli_data.session.probe)?;
+                    .step_n(&mut cli_data.session.probe, count)?;
                 println!("Core stopped at address 0x{:08x}", cpu_info.pc);
 
                 Ok(CliState::Continue)
@@ -175,15 +183,13 @@ impl DebugCli {
             help_text: "Show CPU register values",
 
             function: |cli_data, _args| {
-                let mut regs = [0u32; 15];
-
-                for i in 0..15 {
-                    regs[i as usize] = cli_data
-                        .session
-                        .target
-                        .core
-                        .read_core_reg(&mut cli_data.session.probe, i.into())?;
-                }
+                let addresses: Vec<CoreRegisterAddress> =
+                    (0..15).map(CoreRegisterAddress).collect();
+                let regs = cli_data
+                    .session
+                    .target
+                    .core
+                    .read_core_registers(&mut cli_data.session.probe, &addresses)?;
 
                 for (i, val) in regs.iter().enumerate() {
                     println!("Register {}: {:#08x}", i, val);
@@ -226,13 +232,14 @@ impl DebugCli {
 
                 let mut dump = CortexDump::new(stack_bot, stack);
 
-                for i in 0..12 {
-                    dump.regs[i as usize] = cli_data
-                        .session
-                        .target
-                        .core
-                        .read_core_reg(&mut cli_data.session.probe, i.into())?;
-                }
+                let addresses: Vec<CoreRegisterAddress> =
+                    (0..12).map(CoreRegisterAddress).collect();
+                let general = cli_data
+                    .session
+                    .target
+                    .core
+                    .read_core_registers(&mut cli_data.session.probe, &addresses)?;
+                dump.regs[..12].copy_from_slice(&general);
 
                 dump.regs[13] = stack_bot;
                 dump.regs[14] = cli_data
@@ -266,13 +273,13 @@ impl DebugCli {
                     .core
                     .halt(&mut cli_data.session.probe)?;
 
-                // Enable vector catch after reset (set bit 1 in DEMCR register)
-                cli_data.session.probe.write32(0xE000_EDFC, 1)?;
+                // Halt the core again before it executes the first instruction after the reset.
                 cli_data
                     .session
                     .target
                     .core
-                    .reset(&mut cli_data.session.probe)?;
+                    .reset_catch_set(&mut cli_data.session.probe)?;
+                cli_data.session.target.reset(&mut cli_data.session.probe)?;
 
                 Ok(CliState::Continue)
             },
@@ -11,7 +11,7 @@ use probe_rs::coresight::{
 };
 
 pub(crate) fn show_info_of_device(shared_options: &SharedOptions) -> Result<(), CliError> {
-    let mut probe = open_probe(shared_options.n)?;
+    let mut probe = open_probe(shared_options.n, shared_options.target_sel)?;
 
     /*
         The following code only works with debug port v2,
@@ -8,8 +8,11 @@ use debugger::CliState;
 use probe_rs::{
     coresight::memory::MI,
     debug::DebugInfo,
-    flash::download::{download_file, Format},
-    probe::{daplink, stlink, DebugProbeInfo},
+    flash::{
+        download::{download_file, Format},
+        dump_segments, elf_header, erase_all, read_memory_to_writer, FlashProgress, ProgressEvent,
+    },
+    probe,
 };
 
 use capstone::{arch::arm::ArchMode, prelude::*, Capstone, Endian};
@@ -18,14 +21,25 @@ use rustyline::Editor;
 use structopt::StructOpt;
 
 use std::fs;
+use std::io::{BufWriter, Write};
 use std::num::ParseIntError;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 fn parse_hex(src: &str) -> Result<u32, ParseIntError> {
     u32::from_str_radix(src, 16)
 }
 
+/// Parses a number given in decimal or in hexadecimal with a `0x` prefix.
+fn parse_number(number: &str) -> Result<u32, ParseIntError> {
+    let number = number.trim();
+    if number.starts_with("0x") || number.starts_with("0X") {
+        u32::from_str_radix(&number[2..], 16)
+    } else {
+        number.parse()
+    }
+}
+
 #[derive(StructOpt)]
 #[structopt(
     name = "Probe-rs CLI",
@@ -64,7 +78,32 @@ enum CLI {
         /// Binary to debug
         exe: Option<PathBuf>,
     },
-    /// Dump memory from attached target
+    /// Read memory from attached target and print it
+    #[structopt(name = "read")]
+    Read {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The address of the memory to read from the target (in hexadecimal without 0x prefix)
+        #[structopt(parse(try_from_str = parse_hex))]
+        loc: u32,
+        /// The amount of memory (in words) to read
+        words: u32,
+    },
+    /// Write a word to the memory of the attached target
+    #[structopt(name = "write")]
+    Write {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The address of the word to write (in hexadecimal without 0x prefix)
+        #[structopt(parse(try_from_str = parse_hex))]
+        loc: u32,
+        /// The value to write (in hexadecimal without 0x prefix)
+        #[structopt(parse(try_from_str = parse_hex))]
+        value: u32,
+    },
+    /// Dump memory from attached target to a file
     #[structopt(name = "dump")]
     Dump {
         #[structopt(flatten)]
@@ -75,6 +114,19 @@ enum CLI {
         loc: u32,
         /// The amount of memory (in words) to dump
         words: u32,
+        /// The file to write the memory to
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// The format of the file: raw little endian data, or an ELF file with one loadable
+        /// segment per memory region at its address
+        #[structopt(long = "dump-format", default_value = "raw")]
+        format: DumpFormat,
+    },
+    /// Erase the complete flash of the attached target
+    #[structopt(name = "erase")]
+    Erase {
+        #[structopt(flatten)]
+        shared: SharedOptions,
     },
     /// Download memory to attached target
     #[structopt(name = "download")]
@@ -96,6 +148,25 @@ enum CLI {
     },
 }
 
+/// The file format of `dump`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DumpFormat {
+    Raw,
+    Elf,
+}
+
+impl std::str::FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "raw" | "bin" => Ok(DumpFormat::Raw),
+            "elf" => Ok(DumpFormat::Elf),
+            _ => Err(format!("'{}' is not a dump format, use 'raw' or 'elf'.", s)),
+        }
+    }
+}
+
 /// Shared options for all commands which use a specific probe
 #[derive(StructOpt)]
 struct SharedOptions {
@@ -106,6 +177,18 @@ struct SharedOptions {
     /// The target to be selected.
     #[structopt(short, long)]
     target: Option<String>,
+
+    /// The TARGETSEL value of the target on an SWD multidrop bus (in decimal or in hexadecimal with a 0x prefix)
+    #[structopt(long = "target-sel", parse(try_from_str = parse_number))]
+    target_sel: Option<u32>,
+
+    /// Keep the debug unit clocked in low power modes, if the target supports it.
+    #[structopt(long = "keep-debug-alive")]
+    keep_debug_alive: bool,
+
+    /// A YAML script of register writes, polls and delays which is run after attaching
+    #[structopt(long = "init-script", parse(from_os_str))]
+    init_script: Option<PathBuf>,
 }
 
 fn main() {
@@ -119,7 +202,16 @@ fn main() {
         CLI::Info { shared } => crate::info::show_info_of_device(&shared),
         CLI::Reset { shared, assert } => reset_target_of_device(&shared, assert),
         CLI::Debug { shared, exe, dump } => debug(&shared, exe, dump),
-        CLI::Dump { shared, loc, words } => dump_memory(&shared, loc, words),
+        CLI::Read { shared, loc, words } => read_memory(&shared, loc, words),
+        CLI::Write { shared, loc, value } => write_memory(&shared, loc, value),
+        CLI::Dump {
+            shared,
+            loc,
+            words,
+            file,
+            format,
+        } => dump_memory(&shared, loc, words, &file, format),
+        CLI::Erase { shared } => erase_flash(&shared),
         CLI::Download { shared, path } => download_program_fast(&shared, &path),
         CLI::Trace { shared, loc } => trace_u32_on_target(&shared, loc),
     };
@@ -131,14 +223,14 @@ fn main() {
 }
 
 fn list_connected_devices() -> Result<(), CliError> {
-    let links = get_connected_devices();
+    let links = probe::list_all();
 
     if !links.is_empty() {
         println!("The following devices were found:");
         links
             .iter()
             .enumerate()
-            .for_each(|(num, link)| println!("[{}]: {:?}", num, link));
+            .for_each(|(num, link)| println!("[{}]: {}", num, link));
     } else {
         println!("No devices were found.");
     }
@@ -146,7 +238,7 @@ fn list_connected_devices() -> Result<(), CliError> {
     Ok(())
 }
 
-fn dump_memory(shared_options: &SharedOptions, loc: u32, words: u32) -> Result<(), CliError> {
+fn read_memory(shared_options: &SharedOptions, loc: u32, words: u32) -> Result<(), CliError> {
     with_device(shared_options, |mut session| {
         let mut data = vec![0 as u32; words as usize];
 
@@ -174,6 +266,82 @@ fn dump_memory(shared_options: &SharedOptions, loc: u32, words: u32) -> Result<(
     })
 }
 
+fn write_memory(shared_options: &SharedOptions, loc: u32, value: u32) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        session.probe.write32(loc, value)?;
+
+        println!("Wrote 0x{:08x} to 0x{:08x}", value, loc);
+
+        Ok(())
+    })
+}
+
+fn dump_memory(
+    shared_options: &SharedOptions,
+    loc: u32,
+    words: u32,
+    file: &Path,
+    format: DumpFormat,
+) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let length = words * 4;
+        let mut output = BufWriter::new(fs::File::create(file)?);
+
+        if format == DumpFormat::Elf {
+            let segments = dump_segments(&session.target.memory_map, loc..loc + length);
+            output.write_all(&elf_header(&segments))?;
+        }
+
+        let progress_bar = indicatif::ProgressBar::new(u64::from(length));
+        progress_bar.set_style(indicatif::ProgressStyle::default_bar().template(
+            "{msg} [{elapsed_precise}] [{wide_bar}] {bytes:>8}/{total_bytes:>8} @ {bytes_per_sec:>10} (eta {eta:3})",
+        ));
+        progress_bar.set_message("Dumping");
+        let progress = {
+            let progress_bar = progress_bar.clone();
+            FlashProgress::new(move |event| {
+                if let ProgressEvent::ChunkRead { size, .. } = event {
+                    progress_bar.inc(u64::from(size));
+                }
+            })
+        };
+
+        // Start timer.
+        let instant = Instant::now();
+
+        read_memory_to_writer(&mut session.probe, loc, length, &mut output, &progress)?;
+
+        // Stop timer.
+        let elapsed = instant.elapsed();
+        progress_bar.finish_and_clear();
+
+        println!(
+            "Dumped {} words from 0x{:08x} to {} in {:?}",
+            words,
+            loc,
+            file.display(),
+            elapsed
+        );
+
+        Ok(())
+    })
+}
+
+fn erase_flash(shared_options: &SharedOptions) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let mm = session.target.memory_map.clone();
+
+        // Start timer.
+        let instant = Instant::now();
+
+        erase_all(&mut session, &mm, &FlashProgress::new(|_| {}))?;
+
+        println!("Erased the flash in {:?}", instant.elapsed());
+
+        Ok(())
+    })
+}
+
 fn download_program_fast(shared_options: &SharedOptions, path: &str) -> Result<(), CliError> {
     with_device(shared_options, |mut session| {
         // Start timer.
@@ -240,12 +408,6 @@ fn trace_u32_on_target(shared_options: &SharedOptions, loc: u32) -> Result<(), C
     })
 }
 
-fn get_connected_devices() -> Vec<DebugProbeInfo> {
-    let mut links = daplink::tools::list_daplink_devices();
-    links.extend(stlink::tools::list_stlink_devices());
-    links
-}
-
 fn debug(
     shared_options: &SharedOptions,
     exe: Option<PathBuf>,
@@ -0,0 +1,726 @@
+use crate::protocol::{Request, Sender};
+
+use probe_rs::{
+    config::registry::{Registry, RegistryError, SelectionStrategy},
+    coresight::{access_ports::AccessPortError, memory::MI},
+    debug::DebugInfo,
+    probe::{self, DebugProbeError, MasterProbe, WireProtocol},
+    session::Session,
+    target::{
+        info::{self, ChipInfo},
+        CoreRegisterAddress, HaltReason,
+    },
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// There is only one core, which is reported as the only thread.
+const THREAD_ID: i64 = 1;
+
+/// How long to wait for the core to resume after `run`.
+const RESUME_TIMEOUT: Duration = Duration::from_millis(100);
+
+const REGISTERS_REFERENCE: i64 = 1;
+const STACK_REFERENCE: i64 = 2;
+
+/// The number of words shown in the stack scope.
+const STACK_WORDS: usize = 16;
+
+#[derive(Debug)]
+pub enum DebuggerError {
+    InfoReadError(info::ReadError),
+    DebugProbe(DebugProbeError),
+    AccessPort(AccessPortError),
+    StdIO(io::Error),
+    RegistryError(RegistryError),
+    InvalidArguments(serde_json::Error),
+    NotConnected,
+    NoProbeFound,
+    MultipleProbesFound(usize),
+    ProbeIndexOutOfRange(usize, usize), // Contains the requested index and the number of probes.
+    UnknownVariablesReference(i64),
+}
+
+impl Error for DebuggerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use DebuggerError::*;
+
+        match self {
+            InfoReadError(ref e) => Some(e),
+            DebugProbe(ref e) => Some(e),
+            AccessPort(ref e) => Some(e),
+            StdIO(ref e) => Some(e),
+            RegistryError(ref e) => Some(e),
+            InvalidArguments(ref e) => Some(e),
+            NotConnected => None,
+            NoProbeFound => None,
+            MultipleProbesFound(_) => None,
+            ProbeIndexOutOfRange(_, _) => None,
+            UnknownVariablesReference(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for DebuggerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use DebuggerError::*;
+
+        match self {
+            InfoReadError(ref e) => e.fmt(f),
+            DebugProbe(ref e) => e.fmt(f),
+            AccessPort(ref e) => e.fmt(f),
+            StdIO(ref e) => e.fmt(f),
+            RegistryError(ref e) => e.fmt(f),
+            InvalidArguments(ref e) => write!(f, "Invalid arguments: {}", e),
+            NotConnected => write!(f, "Not connected to a target, launch or attach first."),
+            NoProbeFound => write!(f, "No probe found."),
+            MultipleProbesFound(count) => write!(
+                f,
+                "{} probes were found. Select one of them with the probeIndex argument.",
+                count
+            ),
+            ProbeIndexOutOfRange(index, count) => write!(
+                f,
+                "There is no probe with index {}, only {} probes were found.",
+                index, count
+            ),
+            UnknownVariablesReference(reference) => {
+                write!(f, "Unknown variables reference {}.", reference)
+            }
+        }
+    }
+}
+
+impl From<info::ReadError> for DebuggerError {
+    fn from(error: info::ReadError) -> Self {
+        DebuggerError::InfoReadError(error)
+    }
+}
+
+impl From<DebugProbeError> for DebuggerError {
+    fn from(error: DebugProbeError) -> Self {
+        DebuggerError::DebugProbe(error)
+    }
+}
+
+impl From<AccessPortError> for DebuggerError {
+    fn from(error: AccessPortError) -> Self {
+        DebuggerError::AccessPort(error)
+    }
+}
+
+impl From<io::Error> for DebuggerError {
+    fn from(error: io::Error) -> Self {
+        DebuggerError::StdIO(error)
+    }
+}
+
+impl From<RegistryError> for DebuggerError {
+    fn from(error: RegistryError) -> Self {
+        DebuggerError::RegistryError(error)
+    }
+}
+
+impl From<serde_json::Error> for DebuggerError {
+    fn from(error: serde_json::Error) -> Self {
+        DebuggerError::InvalidArguments(error)
+    }
+}
+
+/// The arguments of the `launch` and `attach` requests, as set in the launch configuration of the IDE.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectArguments {
+    /// The name of the chip, it is read from the ROM table if it is not given.
+    chip: Option<String>,
+    /// The index of the probe to use if several probes are connected.
+    probe_index: Option<usize>,
+    /// The ELF file which is running on the target, used to resolve source locations.
+    program: Option<PathBuf>,
+    /// Stay halted after connecting instead of running the program.
+    #[serde(default)]
+    stop_on_entry: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Source {
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SourceBreakpoint {
+    line: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetBreakpointsArguments {
+    source: Source,
+    #[serde(default)]
+    breakpoints: Vec<SourceBreakpoint>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VariablesArguments {
+    variables_reference: i64,
+}
+
+/// Handles the requests of a debug adapter protocol client for a single core.
+pub struct Debugger<W: Write> {
+    sender: Sender<W>,
+    session: Option<Session>,
+    debug_info: Option<DebugInfo>,
+    /// The addresses of the breakpoints which are set, per source file.
+    breakpoints: HashMap<PathBuf, Vec<u32>>,
+    stop_on_entry: bool,
+    launched: bool,
+    running: bool,
+    /// A stopped event which is sent after the response of the current request.
+    pending_stop: Option<&'static str>,
+}
+
+impl<W: Write> Debugger<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            sender: Sender::new(writer),
+            session: None,
+            debug_info: None,
+            breakpoints: HashMap::new(),
+            stop_on_entry: false,
+            launched: false,
+            running: false,
+            pending_stop: None,
+        }
+    }
+
+    /// Handles a request and sends the response.
+    ///
+    /// Returns `false` if the client disconnected and the server should exit.
+    pub fn handle(&mut self, request: Request) -> io::Result<bool> {
+        log::debug!("Received request {:?}", request);
+
+        let result = match request.command.as_str() {
+            "initialize" => Ok(Some(json!({
+                "supportsConfigurationDoneRequest": true,
+            }))),
+            "launch" => self.connect(&request.arguments, true),
+            "attach" => self.connect(&request.arguments, false),
+            "setBreakpoints" => self.set_breakpoints(&request.arguments),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(Some(json!({
+                "threads": [{ "id": THREAD_ID, "name": "Core 0" }],
+            }))),
+            "continue" => self.resume().map(|_| {
+                Some(json!({
+                    "allThreadsContinued": true,
+                }))
+            }),
+            // Both step one instruction, as there is no stepping by source lines yet.
+            "next" | "stepIn" => self.step().map(|_| None),
+            "pause" => self.pause().map(|_| None),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(Some(json!({
+                "scopes": [
+                    {
+                        "name": "Registers",
+                        "variablesReference": REGISTERS_REFERENCE,
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Stack",
+                        "variablesReference": STACK_REFERENCE,
+                        "expensive": false,
+                    },
+                ],
+            }))),
+            "variables" => self.variables(&request.arguments),
+            "disconnect" => {
+                self.disconnect();
+                self.sender.respond(&request, None)?;
+                return Ok(false);
+            }
+            command => {
+                log::warn!("Unsupported request {}", command);
+                self.sender
+                    .respond_error(&request, &format!("Unsupported request {}.", command))?;
+                return Ok(true);
+            }
+        };
+
+        match result {
+            Ok(body) => self.sender.respond(&request, body)?,
+            Err(e) => {
+                log::warn!("Request {} failed: {}", request.command, e);
+                self.pending_stop = None;
+                self.sender.respond_error(&request, &e.to_string())?;
+            }
+        }
+
+        if request.command == "launch" || request.command == "attach" {
+            // The client can send the breakpoints now.
+            if self.session.is_some() {
+                self.sender.event("initialized", None)?;
+            }
+        }
+
+        if let Some(reason) = self.pending_stop.take() {
+            self.send_stopped(reason)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Checks whether a running core halted, e.g. on a breakpoint, and notifies the client.
+    pub fn poll(&mut self) -> Result<(), DebuggerError> {
+        if !self.running {
+            return Ok(());
+        }
+
+        let session = self.session.as_mut().ok_or(DebuggerError::NotConnected)?;
+
+        if session.target.core.core_halted(&mut session.probe)? {
+            self.running = false;
+
+            let reason = match read_halt_reason(session)? {
+                HaltReason::Breakpoint => "breakpoint",
+                HaltReason::Watchpoint => "data breakpoint",
+                HaltReason::VectorCatch => "exception",
+                _ => "pause",
+            };
+
+            self.send_stopped(reason)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_stopped(&mut self, reason: &str) -> io::Result<()> {
+        self.sender.event(
+            "stopped",
+            Some(json!({
+                "reason": reason,
+                "threadId": THREAD_ID,
+                "allThreadsStopped": true,
+            })),
+        )
+    }
+
+    fn session(&mut self) -> Result<&mut Session, DebuggerError> {
+        self.session.as_mut().ok_or(DebuggerError::NotConnected)
+    }
+
+    fn connect(&mut self, arguments: &Value, launch: bool) -> Result<Option<Value>, DebuggerError> {
+        let arguments: ConnectArguments = serde_json::from_value(arguments.clone())?;
+
+        let mut probe = open_probe(arguments.probe_index)?;
+
+        let strategy = if let Some(chip) = &arguments.chip {
+            SelectionStrategy::TargetIdentifier(chip.into())
+        } else {
+            SelectionStrategy::ChipInfo(ChipInfo::read_from_rom_table(&mut probe)?)
+        };
+
+        let target = Registry::from_builtin_families().get_target(strategy)?;
+
+        let mut session = Session::new(target, probe);
+
+        if launch {
+            session.target.reset_and_halt(&mut session.probe)?;
+        }
+
+        self.running = !session.target.core.core_halted(&mut session.probe)?;
+
+        if let Some(program) = &arguments.program {
+            let data = std::fs::read(program)?;
+            self.debug_info = Some(DebugInfo::from_raw(&data));
+        }
+
+        self.session = Some(session);
+        self.stop_on_entry = arguments.stop_on_entry;
+        self.launched = launch;
+
+        Ok(None)
+    }
+
+    fn configuration_done(&mut self) -> Result<Option<Value>, DebuggerError> {
+        if self.stop_on_entry {
+            if self.running {
+                self.pause()?;
+            }
+            self.pending_stop = Some("entry");
+        } else if self.launched {
+            self.resume()?;
+        }
+
+        Ok(None)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Option<Value>, DebuggerError> {
+        let arguments: SetBreakpointsArguments = serde_json::from_value(arguments.clone())?;
+
+        let path = arguments.source.path.unwrap_or_default();
+
+        let session = self.session.as_mut().ok_or(DebuggerError::NotConnected)?;
+
+        // The request replaces all breakpoints of the source file.
+        for address in self.breakpoints.remove(&path).unwrap_or_default() {
+            session.clear_hw_breakpoint(address)?;
+        }
+
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+
+        for breakpoint in arguments.breakpoints {
+            let location = self
+                .debug_info
+                .as_ref()
+                .and_then(|debug_info| debug_info.get_breakpoint_location(&path, breakpoint.line));
+
+            let response = match location {
+                Some(address) => match session.set_hw_breakpoint(address as u32) {
+                    Ok(()) => {
+                        addresses.push(address as u32);
+                        json!({ "verified": true, "line": breakpoint.line })
+                    }
+                    Err(e) => json!({ "verified": false, "message": e.to_string() }),
+                },
+                None => json!({
+                    "verified": false,
+                    "message": "No code was found for this line.",
+                }),
+            };
+
+            breakpoints.push(response);
+        }
+
+        self.breakpoints.insert(path, addresses);
+
+        Ok(Some(json!({ "breakpoints": breakpoints })))
+    }
+
+    /// Executes the instruction at the current PC if a breakpoint is set on it,
+    /// because the core would halt on the breakpoint again right away otherwise.
+    fn step_over_breakpoint(&mut self) -> Result<(), DebuggerError> {
+        let session = self.session.as_mut().ok_or(DebuggerError::NotConnected)?;
+
+        let pc = session
+            .target
+            .core
+            .read_core_reg(&mut session.probe, session.target.core.registers().PC)?;
+
+        if self
+            .breakpoints
+            .values()
+            .flatten()
+            .any(|&address| address == pc)
+        {
+            session.clear_hw_breakpoint(pc)?;
+            session.target.core.step(&mut session.probe)?;
+            read_halt_reason(session)?;
+            session.set_hw_breakpoint(pc)?;
+        }
+
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), DebuggerError> {
+        self.step_over_breakpoint()?;
+
+        let session = self.session()?;
+        session.target.core.run(&mut session.probe)?;
+
+        // Make sure the core resumed before it is polled, or it could be reported as halted right away.
+        // It is never seen running if it hits a breakpoint right away, which the next poll reports.
+        match session
+            .target
+            .core
+            .wait_for_core_running(&mut session.probe, RESUME_TIMEOUT)
+        {
+            Ok(()) | Err(DebugProbeError::Timeout) => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        self.running = true;
+
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<(), DebuggerError> {
+        let session = self.session.as_mut().ok_or(DebuggerError::NotConnected)?;
+
+        let pc = session
+            .target
+            .core
+            .read_core_reg(&mut session.probe, session.target.core.registers().PC)?;
+
+        if self
+            .breakpoints
+            .values()
+            .flatten()
+            .any(|&address| address == pc)
+        {
+            self.step_over_breakpoint()?;
+        } else {
+            session.target.core.step(&mut session.probe)?;
+            read_halt_reason(session)?;
+        }
+
+        self.pending_stop = Some("step");
+
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), DebuggerError> {
+        let session = self.session()?;
+
+        session.target.core.halt(&mut session.probe)?;
+        read_halt_reason(session)?;
+
+        self.running = false;
+        self.pending_stop = Some("pause");
+
+        Ok(())
+    }
+
+    /// Reports the frame at the PC and, if it is not an exception return value, the caller at the LR.
+    fn stack_trace(&mut self) -> Result<Option<Value>, DebuggerError> {
+        let session = self.session.as_mut().ok_or(DebuggerError::NotConnected)?;
+
+        let registers = session.target.core.registers();
+        let pc = session
+            .target
+            .core
+            .read_core_reg(&mut session.probe, registers.PC)?;
+        let lr = session
+            .target
+            .core
+            .read_core_reg(&mut session.probe, registers.LR)?;
+
+        let mut addresses = vec![pc];
+
+        // EXC_RETURN values start with 0xFFFF_FF.., the LR is all ones after a reset.
+        if lr & 0xFFFF_FF00 != 0xFFFF_FF00 {
+            // Clear the thumb bit.
+            addresses.push(lr & !1);
+        }
+
+        let frames: Vec<Value> = addresses
+            .iter()
+            .enumerate()
+            .map(|(id, &address)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": format!("{:#010x}", address),
+                    "instructionPointerReference": format!("{:#010x}", address),
+                    "line": 0,
+                    "column": 0,
+                });
+
+                let location = self
+                    .debug_info
+                    .as_ref()
+                    .and_then(|debug_info| debug_info.get_source_location(u64::from(address)));
+
+                if let Some(location) = location {
+                    if let Some(file) = &location.file {
+                        let path = match &location.directory {
+                            Some(directory) => directory.join(file),
+                            None => PathBuf::from(file),
+                        };
+
+                        frame["source"] = json!({
+                            "name": file,
+                            "path": path,
+                        });
+                    }
+
+                    frame["line"] = json!(location.line.unwrap_or(0));
+                }
+
+                frame
+            })
+            .collect();
+
+        Ok(Some(json!({
+            "stackFrames": frames,
+            "totalFrames": frames.len(),
+        })))
+    }
+
+    fn variables(&mut self, arguments: &Value) -> Result<Option<Value>, DebuggerError> {
+        let arguments: VariablesArguments = serde_json::from_value(arguments.clone())?;
+
+        let session = self.session()?;
+
+        let variables: Vec<Value> = match arguments.variables_reference {
+            REGISTERS_REFERENCE => {
+                let registers = session.target.core.registers();
+
+                // R0 to R12 are numbered like the registers themselves.
+                let mut list: Vec<(String, CoreRegisterAddress)> = (0..=12)
+                    .map(|n| (format!("R{}", n), CoreRegisterAddress(n)))
+                    .collect();
+                list.push(("SP".to_owned(), registers.SP));
+                list.push(("LR".to_owned(), registers.LR));
+                list.push(("PC".to_owned(), registers.PC));
+                list.push(("XPSR".to_owned(), registers.XPSR));
+
+                let mut variables = Vec::new();
+                for (name, address) in list {
+                    let value = session
+                        .target
+                        .core
+                        .read_core_reg(&mut session.probe, address)?;
+                    variables.push(variable(&name, value));
+                }
+                variables
+            }
+            STACK_REFERENCE => {
+                let sp = session
+                    .target
+                    .core
+                    .read_core_reg(&mut session.probe, session.target.core.registers().SP)?;
+
+                let mut words = [0u32; STACK_WORDS];
+                session.probe.read_block32(sp, &mut words)?;
+
+                words
+                    .iter()
+                    .enumerate()
+                    .map(|(index, &value)| variable(&format!("[SP + {:#04x}]", index * 4), value))
+                    .collect()
+            }
+            reference => return Err(DebuggerError::UnknownVariablesReference(reference)),
+        };
+
+        Ok(Some(json!({ "variables": variables })))
+    }
+
+    /// Removes all breakpoints and lets the core run, so the target keeps working without the debugger.
+    fn disconnect(&mut self) {
+        if let Some(session) = self.session.as_mut() {
+            for address in self.breakpoints.values().flatten() {
+                if let Err(e) = session.clear_hw_breakpoint(*address) {
+                    log::warn!("Failed to clear breakpoint at {:#010x}: {}", address, e);
+                }
+            }
+
+            if let Err(e) = session.target.core.run(&mut session.probe) {
+                log::warn!("Failed to resume the core: {}", e);
+            }
+        }
+
+        self.breakpoints.clear();
+        self.session = None;
+        self.running = false;
+    }
+}
+
+fn variable(name: &str, value: u32) -> Value {
+    json!({
+        "name": name,
+        "value": format!("{:#010x}", value),
+        "variablesReference": 0,
+    })
+}
+
+/// Reads the reason of the last halt from the DFSR and clears it,
+/// so the next halt is reported correctly.
+fn read_halt_reason(session: &mut Session) -> Result<HaltReason, DebuggerError> {
+    let dfsr = session.probe.read32(HaltReason::DFSR_ADDRESS)?;
+    session.probe.write32(HaltReason::DFSR_ADDRESS, dfsr)?;
+
+    Ok(HaltReason::from_dfsr(dfsr))
+}
+
+fn open_probe(index: Option<usize>) -> Result<MasterProbe, DebuggerError> {
+    let list = probe::list_all();
+
+    let device = match index {
+        Some(index) => list
+            .get(index)
+            .ok_or(DebuggerError::ProbeIndexOutOfRange(index, list.len()))?,
+        None => match list.len() {
+            0 => return Err(DebuggerError::NoProbeFound),
+            1 => &list[0],
+            count => return Err(DebuggerError::MultipleProbesFound(count)),
+        },
+    };
+
+    Ok(device.open(Some(WireProtocol::Swd))?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::read_message;
+
+    fn request(command: &str, arguments: Value) -> Request {
+        Request {
+            seq: 1,
+            command: command.to_owned(),
+            arguments,
+        }
+    }
+
+    fn messages(output: &[u8]) -> Vec<Value> {
+        let mut reader = io::Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn requests_without_session_fail() {
+        let mut output = Vec::new();
+
+        {
+            let mut debugger = Debugger::new(&mut output);
+
+            assert!(debugger.handle(request("initialize", Value::Null)).unwrap());
+            assert!(debugger.handle(request("continue", Value::Null)).unwrap());
+            assert!(debugger
+                .handle(request("variables", json!({ "variablesReference": 1 })))
+                .unwrap());
+            assert!(!debugger.handle(request("disconnect", Value::Null)).unwrap());
+        }
+
+        let messages = messages(&output);
+
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0]["success"], true);
+        assert_eq!(
+            messages[0]["body"]["supportsConfigurationDoneRequest"],
+            true
+        );
+        assert_eq!(messages[1]["success"], false);
+        assert_eq!(messages[2]["success"], false);
+        assert_eq!(messages[3]["command"], "disconnect");
+        assert_eq!(messages[3]["success"], true);
+    }
+
+    #[test]
+    fn unsupported_request() {
+        let mut output = Vec::new();
+
+        assert!(Debugger::new(&mut output)
+            .handle(request("goto", Value::Null))
+            .unwrap());
+
+        let messages = messages(&output);
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["success"], false);
+        assert_eq!(messages[0]["message"], "Unsupported request goto.");
+    }
+}
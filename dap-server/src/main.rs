@@ -0,0 +1,102 @@
+mod debugger;
+mod protocol;
+
+use debugger::Debugger;
+use protocol::{read_message, Request};
+
+use structopt::StructOpt;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// How often a running core is checked for halting, e.g. on a breakpoint.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "dap-server",
+    about = "A Debug Adapter Protocol server on top of probe-rs. It talks to the client on stdin and stdout by default."
+)]
+struct Opt {
+    /// Listen for a single client on this TCP port instead of using stdin and stdout
+    #[structopt(long = "port")]
+    port: Option<u16>,
+}
+
+fn main() {
+    pretty_env_logger::init();
+
+    let opt = Opt::from_args();
+
+    let result = match opt.port {
+        Some(port) => serve_tcp(port),
+        None => run(BufReader::new(io::stdin()), io::stdout()),
+    };
+
+    if let Err(e) = result {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn serve_tcp(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+
+    log::info!("Waiting for a client on port {}", port);
+
+    let (stream, address) = listener.accept()?;
+
+    log::info!("Client {} connected", address);
+
+    run(BufReader::new(stream.try_clone()?), stream)
+}
+
+/// Handles requests from `reader` until the client disconnects.
+fn run(mut reader: impl BufRead + Send + 'static, writer: impl Write) -> io::Result<()> {
+    let (sender, receiver) = channel();
+
+    // The messages are read on their own thread, so a running core can be polled in between.
+    thread::spawn(move || loop {
+        match read_message(&mut reader) {
+            Ok(Some(message)) => {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                log::error!("Failed to read a message: {}", e);
+                break;
+            }
+        }
+    });
+
+    let mut debugger = Debugger::new(writer);
+
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(message) => {
+                let request: Request = match serde_json::from_value(message) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        log::warn!("Ignoring invalid message: {}", e);
+                        continue;
+                    }
+                };
+
+                if !debugger.handle(request)? {
+                    return Ok(());
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = debugger.poll() {
+                    log::warn!("Failed to poll the core state: {}", e);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
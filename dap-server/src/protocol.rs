@@ -0,0 +1,178 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use std::io::{self, BufRead, Write};
+
+/// A request sent by the client, e.g. an IDE.
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    pub seq: i64,
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// Reads one message framed with a `Content-Length` header.
+///
+/// Returns `None` if the input was closed.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+
+        // An empty line terminates the header.
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            let length = value.trim().parse::<usize>().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "Invalid Content-Length header.")
+            })?;
+            content_length = Some(length);
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header.")
+    })?;
+
+    let mut content = vec![0; content_length];
+    reader.read_exact(&mut content)?;
+
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes the protocol messages to the client and numbers them.
+pub struct Sender<W: Write> {
+    writer: W,
+    seq: i64,
+}
+
+impl<W: Write> Sender<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, seq: 1 }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+
+        let content = message.to_string();
+
+        log::trace!("Sending {}", content);
+
+        write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )?;
+        self.writer.flush()
+    }
+
+    /// Sends a successful response to `request`.
+    pub fn respond(&mut self, request: &Request, body: Option<Value>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "success": true,
+            "command": request.command,
+        });
+
+        if let Some(body) = body {
+            response["body"] = body;
+        }
+
+        self.send(response)
+    }
+
+    /// Sends a failed response to `request`, which the client shows to the user.
+    pub fn respond_error(&mut self, request: &Request, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "success": false,
+            "command": request.command,
+            "message": message,
+        }))
+    }
+
+    /// Sends an event.
+    pub fn event(&mut self, event: &str, body: Option<Value>) -> io::Result<()> {
+        let mut message = json!({
+            "type": "event",
+            "event": event,
+        });
+
+        if let Some(body) = body {
+            message["body"] = body;
+        }
+
+        self.send(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_framed_messages() {
+        let input = "Content-Length: 9\r\n\r\n{\"seq\":1}Content-Length: 9\r\n\r\n{\"seq\":2}";
+        let mut reader = io::Cursor::new(input.as_bytes());
+
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(json!({ "seq": 1 }))
+        );
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(json!({ "seq": 2 }))
+        );
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn read_message_without_length() {
+        let mut reader = io::Cursor::new(&b"Content-Type: foo\r\n\r\n{}"[..]);
+
+        assert!(read_message(&mut reader).is_err());
+    }
+
+    #[test]
+    fn send_numbers_messages() {
+        let mut sender = Sender::new(Vec::new());
+
+        let request = Request {
+            seq: 7,
+            command: "threads".to_owned(),
+            arguments: Value::Null,
+        };
+
+        sender.respond(&request, None).unwrap();
+        sender.event("initialized", None).unwrap();
+
+        let output = String::from_utf8(sender.writer).unwrap();
+        let mut reader = io::Cursor::new(output.as_bytes());
+
+        let response = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(response["seq"], 1);
+        assert_eq!(response["request_seq"], 7);
+        assert_eq!(response["success"], true);
+        assert_eq!(response["command"], "threads");
+
+        let event = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(event["seq"], 2);
+        assert_eq!(event["event"], "initialized");
+    }
+}
@@ -131,6 +131,18 @@ fn extract_algorithms(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                 .unwrap()
                 .as_u64()
                 .unwrap() as u32;
+            let init_extra_argument = quote_option(
+                algorithm
+                    .get("init_extra_argument")
+                    .and_then(|v| v.as_u64())
+                    .map(|v| v as u32),
+            );
+            let program_page_extra_argument = quote_option(
+                algorithm
+                    .get("program_page_extra_argument")
+                    .and_then(|v| v.as_u64())
+                    .map(|v| v as u32),
+            );
 
             // Quote the algorithm struct.
             let algorithm = quote::quote! {
@@ -147,6 +159,8 @@ fn extract_algorithms(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                     pc_erase_sector: #pc_erase_sector,
                     pc_erase_all: #pc_erase_all,
                     data_section_offset: #data_section_offset,
+                    init_extra_argument: #init_extra_argument,
+                    program_page_extra_argument: #program_page_extra_argument,
                 }
             };
 
@@ -195,6 +209,21 @@ fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                         let page_size = region.get("page_size").unwrap().as_u64().unwrap() as u32;
                         let erased_byte_value =
                             region.get("erased_byte_value").unwrap().as_u64().unwrap() as u8;
+                        let erase_sector_time_ms = quote_option(
+                            region
+                                .get("erase_sector_time_ms")
+                                .and_then(|v| v.as_u64().map(|v| v as u32)),
+                        );
+                        let program_page_time_ms = quote_option(
+                            region
+                                .get("program_page_time_ms")
+                                .and_then(|v| v.as_u64().map(|v| v as u32)),
+                        );
+                        let erase_page_size = quote_option(
+                            region
+                                .get("erase_page_size")
+                                .and_then(|v| v.as_u64().map(|v| v as u32)),
+                        );
 
                         quote::quote! {
                             MemoryRegion::Flash(FlashRegion {
@@ -203,6 +232,9 @@ fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                                 sector_size: #sector_size,
                                 page_size: #page_size,
                                 erased_byte_value: #erased_byte_value,
+                                erase_sector_time_ms: #erase_sector_time_ms,
+                                program_page_time_ms: #program_page_time_ms,
+                                erase_page_size: #erase_page_size,
                             })
                         }
                     })
@@ -229,6 +261,36 @@ fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenSt
                     .get("part")
                     .and_then(|v| v.as_u64().map(|v| v as u16)),
             );
+            let id_code = quote_option(
+                variant
+                    .get("id_code")
+                    .and_then(|v| v.as_u64().map(|v| v as u32)),
+            );
+            let flash_size_register = quote_option(
+                variant
+                    .get("flash_size_register")
+                    .and_then(|v| v.as_u64().map(|v| v as u32)),
+            );
+
+            let default_protocol = quote_option(
+                variant
+                    .get("default_protocol")
+                    .and_then(|v| v.as_str())
+                    .map(|protocol| match protocol {
+                        "swd" => quote::quote! { crate::probe::WireProtocol::Swd },
+                        "jtag" => quote::quote! { crate::probe::WireProtocol::Jtag },
+                        _ => panic!("Unknown default protocol {} of chip {}", protocol, name),
+                    }),
+            );
+            let max_speed_khz = quote_option(
+                variant
+                    .get("max_speed_khz")
+                    .and_then(|v| v.as_u64().map(|v| v as u32)),
+            );
+
+            let has_fpu = quote_option(variant.get("has_fpu").and_then(|v| v.as_bool()));
+
+            let dual_bank = quote_option(extract_dual_bank(variant));
 
             // Extract all the memory regions into a Vec of TookenStreams.
             let memory_map = extract_memory_map(&variant);
@@ -237,6 +299,12 @@ fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenSt
                 Chip {
                     name: #name.to_owned(),
                     part: #part,
+                    id_code: #id_code,
+                    flash_size_register: #flash_size_register,
+                    default_protocol: #default_protocol,
+                    max_speed_khz: #max_speed_khz,
+                    has_fpu: #has_fpu,
+                    dual_bank: #dual_bank,
                     memory_map: vec![
                         #(#memory_map,)*
                     ],
@@ -246,6 +314,109 @@ fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenSt
         .collect()
 }
 
+/// Extracts the dual-bank flash token stream from a yaml value.
+fn extract_dual_bank(variant: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    variant.get("dual_bank").map(|dual_bank| {
+        let bank1_start = dual_bank.get("bank1_start").unwrap().as_u64().unwrap() as u32;
+        let bank2_start = dual_bank.get("bank2_start").unwrap().as_u64().unwrap() as u32;
+        let bank_size = dual_bank.get("bank_size").unwrap().as_u64().unwrap() as u32;
+        let swapped = quote_option(dual_bank.get("swapped").map(|swapped| {
+            let address = swapped.get("address").unwrap().as_u64().unwrap() as u32;
+            let bit = swapped.get("bit").unwrap().as_u64().unwrap() as u8;
+
+            quote::quote! {
+                crate::config::dual_bank::RegisterBit {
+                    address: #address,
+                    bit: #bit,
+                }
+            }
+        }));
+        let swap_sequence = extract_init_steps(dual_bank, "swap_sequence");
+
+        quote::quote! {
+            crate::config::dual_bank::DualBank {
+                bank1_start: #bank1_start,
+                bank2_start: #bank2_start,
+                bank_size: #bank_size,
+                swapped: #swapped,
+                swap_sequence: vec![
+                    #(#swap_sequence,)*
+                ],
+            }
+        }
+    })
+}
+
+/// Extracts a list of init script step token streams from the sequence `key` of a yaml value.
+///
+/// Returns an empty list if the key is not present.
+fn extract_init_steps(value: &serde_yaml::Value, key: &str) -> Vec<proc_macro2::TokenStream> {
+    let number = |step: &serde_yaml::Value, key: &str| step.get(key).unwrap().as_u64().unwrap();
+
+    value
+        .get(key)
+        .and_then(|steps| steps.as_sequence())
+        .map(|steps| {
+            steps
+                .iter()
+                .map(|step| {
+                    if let Some(write) = step.get("write") {
+                        let address = number(write, "address") as u32;
+                        let value = number(write, "value") as u32;
+
+                        quote::quote! {
+                            crate::config::init_script::InitStep::Write {
+                                address: #address,
+                                value: #value,
+                            }
+                        }
+                    } else if let Some(modify) = step.get("modify") {
+                        let address = number(modify, "address") as u32;
+                        let optional = |key: &str| {
+                            modify.get(key).map_or(0, |value| value.as_u64().unwrap()) as u32
+                        };
+                        let clear = optional("clear");
+                        let set = optional("set");
+                        let toggle = optional("toggle");
+
+                        quote::quote! {
+                            crate::config::init_script::InitStep::Modify {
+                                address: #address,
+                                clear: #clear,
+                                set: #set,
+                                toggle: #toggle,
+                            }
+                        }
+                    } else if let Some(read) = step.get("read") {
+                        let address = number(read, "address") as u32;
+                        let mask = number(read, "mask") as u32;
+                        let expected = number(read, "expected") as u32;
+                        let timeout_ms =
+                            quote_option(read.get("timeout_ms").and_then(|v| v.as_u64()));
+
+                        quote::quote! {
+                            crate::config::init_script::InitStep::Read {
+                                address: #address,
+                                mask: #mask,
+                                expected: #expected,
+                                timeout_ms: #timeout_ms,
+                            }
+                        }
+                    } else if let Some(sleep) = step.get("sleep") {
+                        let ms = sleep.as_u64().unwrap();
+
+                        quote::quote! {
+                            crate::config::init_script::InitStep::Sleep(#ms)
+                        }
+                    } else {
+                        panic!("Unknown init script step {:?}", step)
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 /// Extracts a chip family token stream from a yaml value.
 fn extract_chip_family(chip_family: &serde_yaml::Value) -> proc_macro2::TokenStream {
     // Extract all the algorithms into a Vec of TokenStreams.
@@ -267,6 +438,15 @@ fn extract_chip_family(chip_family: &serde_yaml::Value) -> proc_macro2::TokenStr
         .unwrap()
         .to_ascii_lowercase();
     let manufacturer = quote_option(extract_manufacturer(&chip_family));
+    let debug_keep_alive = extract_register_writes(chip_family, "debug_keep_alive");
+    let watchdog_disable = extract_register_writes(chip_family, "watchdog_disable");
+    let reset_cause = quote_option(extract_reset_cause(chip_family));
+    let reset_sequence = quote_option(extract_reset_sequence(chip_family));
+    let id_code_register = quote_option(extract_id_code_register(chip_family));
+    let has_fpu = chip_family
+        .get("has_fpu")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
 
     // Quote the chip.
     let chip_family = quote::quote! {
@@ -280,6 +460,16 @@ fn extract_chip_family(chip_family: &serde_yaml::Value) -> proc_macro2::TokenStr
                 #(#variants,)*
             ],
             core: #core.to_owned(),
+            has_fpu: #has_fpu,
+            debug_keep_alive: vec![
+                #(#debug_keep_alive,)*
+            ],
+            watchdog_disable: vec![
+                #(#watchdog_disable,)*
+            ],
+            reset_cause: #reset_cause,
+            reset_sequence: #reset_sequence,
+            id_code_register: #id_code_register,
         }
     };
 
@@ -300,3 +490,134 @@ fn extract_manufacturer(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenSt
         }
     })
 }
+
+/// Extracts a list of register write token streams from the sequence `key` of a yaml value.
+///
+/// Returns an empty list if the key is not present.
+fn extract_register_writes(chip: &serde_yaml::Value, key: &str) -> Vec<proc_macro2::TokenStream> {
+    chip.get(key)
+        .and_then(|writes| writes.as_sequence())
+        .map(|writes| {
+            writes
+                .iter()
+                .map(|write| {
+                    let address = write.get("address").unwrap().as_u64().unwrap() as u32;
+                    let value = write.get("value").unwrap().as_u64().unwrap() as u32;
+                    let overwrite = write
+                        .get("overwrite")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false);
+
+                    quote::quote! {
+                        RegisterWrite {
+                            address: #address,
+                            value: #value,
+                            overwrite: #overwrite,
+                        }
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Extracts the reset cause register token stream from a yaml value.
+fn extract_reset_cause(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("reset_cause").map(|register| {
+        let address = register.get("address").unwrap().as_u64().unwrap() as u32;
+        let clear_value = register
+            .get("clear_value")
+            .map_or(0, |v| v.as_u64().unwrap() as u32);
+        let flags = register
+            .get("flags")
+            .unwrap()
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|flag| {
+                let bit = flag.get("bit").unwrap().as_u64().unwrap() as u8;
+                let cause = proc_macro2::Ident::new(
+                    flag.get("cause").unwrap().as_str().unwrap(),
+                    proc_macro2::Span::call_site(),
+                );
+
+                quote::quote! {
+                    ResetCauseFlag {
+                        bit: #bit,
+                        cause: ResetCause::#cause,
+                    }
+                }
+            });
+
+        quote::quote! {
+            ResetCauseRegister {
+                address: #address,
+                clear_value: #clear_value,
+                flags: vec![
+                    #(#flags,)*
+                ],
+            }
+        }
+    })
+}
+
+/// Extracts the reset sequence token stream from a yaml value.
+fn extract_reset_sequence(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("reset_sequence").map(|steps| {
+        let steps = steps.as_sequence().unwrap().iter().map(|step| {
+            if let Some(write) = step.get("write") {
+                let address = write.get("address").unwrap().as_u64().unwrap() as u32;
+                let value = write.get("value").unwrap().as_u64().unwrap() as u32;
+
+                quote::quote! {
+                    crate::config::reset_sequence::ResetStep::Write {
+                        address: #address,
+                        value: #value,
+                    }
+                }
+            } else if let Some(sleep) = step.get("sleep") {
+                let ms = sleep.as_u64().unwrap();
+
+                quote::quote! {
+                    crate::config::reset_sequence::ResetStep::Sleep(#ms)
+                }
+            } else {
+                match step.as_str() {
+                    Some("system_reset") => {
+                        quote::quote! { crate::config::reset_sequence::ResetStep::SystemReset }
+                    }
+                    Some("hardware_reset") => {
+                        quote::quote! { crate::config::reset_sequence::ResetStep::HardwareReset }
+                    }
+                    Some("reconnect") => {
+                        quote::quote! { crate::config::reset_sequence::ResetStep::Reconnect }
+                    }
+                    _ => panic!("Unknown reset sequence step {:?}", step),
+                }
+            }
+        });
+
+        quote::quote! {
+            crate::config::reset_sequence::ResetSequence {
+                steps: vec![
+                    #(#steps,)*
+                ],
+            }
+        }
+    })
+}
+
+/// Extracts the ID code register token stream from a yaml value.
+fn extract_id_code_register(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("id_code_register").map(|register| {
+        let address = register.get("address").unwrap().as_u64().unwrap() as u32;
+        let mask = register.get("mask").unwrap().as_u64().unwrap() as u32;
+
+        quote::quote! {
+            IdCodeRegister {
+                address: #address,
+                mask: #mask,
+            }
+        }
+    })
+}
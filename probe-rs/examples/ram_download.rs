@@ -1,7 +1,7 @@
 use probe_rs::{
     config::registry::{Registry, SelectionStrategy},
     coresight::memory::MI,
-    probe::{daplink, stlink, DebugProbe, DebugProbeType, MasterProbe, WireProtocol},
+    probe::{self, daplink, stlink, DebugProbe, DebugProbeType, MasterProbe, WireProtocol},
     session::Session,
     target::info::ChipInfo,
 };
@@ -109,8 +109,7 @@ fn main() -> Result<(), &'static str> {
 }
 
 fn open_probe(index: Option<usize>) -> Result<MasterProbe, &'static str> {
-    let mut list = daplink::tools::list_daplink_devices();
-    list.extend(stlink::tools::list_stlink_devices());
+    let list = probe::list_all();
 
     let device = match index {
         Some(index) => list
@@ -1,4 +1,6 @@
+use super::dual_bank::DualBank;
 use super::memory::MemoryRegion;
+use crate::probe::WireProtocol;
 
 /// This describes a single chip model.
 /// It can come in different configurations (memory, peripherals).
@@ -11,6 +13,27 @@ pub struct Chip {
     /// The `PART` register of the chip.
     /// This value can be determined via the `cli info` command.
     pub part: Option<u16>,
+    /// The value of the `id_code_register` of the family, if the family has one.
+    /// This tells chips apart which share the `PART` register.
+    #[serde(default)]
+    pub id_code: Option<u32>,
+    /// The address of a register holding the actual flash size of the chip in KiB.
+    /// This is used to detect variants which have less flash than the definition claims.
+    #[serde(default)]
+    pub flash_size_register: Option<u32>,
+    /// The wire protocol used to attach to the chip if none is given, e.g. for chips which only support SWD.
+    #[serde(default)]
+    pub default_protocol: Option<WireProtocol>,
+    /// The fastest clock speed in kHz at which the chip can be debugged reliably.
+    #[serde(default)]
+    pub max_speed_khz: Option<u32>,
+    /// Whether the chip has an FPU, if it differs between the chips of the family.
+    /// Overrides `has_fpu` of the family, e.g. for the nRF52810 which has none.
+    #[serde(default)]
+    pub has_fpu: Option<bool>,
+    /// The two banks of the flash, if the chip has a dual-bank flash which can swap the banks.
+    #[serde(default)]
+    pub dual_bank: Option<DualBank>,
     /// The memory regions available on the chip.
     pub memory_map: Vec<MemoryRegion>,
 }
@@ -1,6 +1,9 @@
 use super::chip::Chip;
 use super::flash_algorithm::RawFlashAlgorithm;
+use super::reset_cause::ResetCauseRegister;
+use super::reset_sequence::ResetSequence;
 use crate::config::target::TargetParseError;
+use crate::coresight::{access_ports::AccessPortError, memory::MI};
 use jep106::JEP106Code;
 
 use serde::{Deserialize, Serialize};
@@ -20,6 +23,71 @@ pub struct ChipFamily {
     /// The name of the core type.
     /// E.g. `M0` or `M4`.
     pub core: String,
+    /// Whether the cores of the family have an FPU, which has to be enabled before
+    /// running code that was compiled with hardware float.
+    #[serde(default)]
+    pub has_fpu: bool,
+    /// Register writes which keep the debug unit clocked in low power modes.
+    /// E.g. the `DBG_SLEEP`, `DBG_STOP` and `DBG_STANDBY` bits of the STM32 `DBGMCU_CR` register.
+    #[serde(default)]
+    pub debug_keep_alive: Vec<RegisterWrite>,
+    /// Register writes which stop or slow down watchdogs that are enabled early after reset,
+    /// such that they do not reset the core while flashing.
+    #[serde(default)]
+    pub watchdog_disable: Vec<RegisterWrite>,
+    /// The register which holds the cause of the last reset, if the family has one.
+    #[serde(default)]
+    pub reset_cause: Option<ResetCauseRegister>,
+    /// The steps which reset the chip, if the standard reset of the core does not work for it.
+    #[serde(default)]
+    pub reset_sequence: Option<ResetSequence>,
+    /// The vendor specific register which identifies the chip, if the family has one.
+    /// The `id_code` of the variants is compared to its value.
+    #[serde(default)]
+    pub id_code_register: Option<IdCodeRegister>,
+}
+
+/// A vendor specific register which identifies the chip.
+/// E.g. the `DEV_ID` field of the STM32 `DBGMCU_IDCODE` register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdCodeRegister {
+    /// The address of the register.
+    pub address: u32,
+    /// The bits of the register which identify the chip, e.g. without the revision.
+    pub mask: u32,
+}
+
+impl IdCodeRegister {
+    /// Reads the register and returns the bits of the mask.
+    pub fn read(&self, mi: &mut impl MI) -> Result<u32, AccessPortError> {
+        Ok(mi.read32(self.address)? & self.mask)
+    }
+}
+
+/// A write of a value to a memory mapped register of the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisterWrite {
+    /// The address of the register.
+    pub address: u32,
+    /// The bits which are set in the register. All other bits are left untouched.
+    pub value: u32,
+    /// Write `value` as is instead of setting its bits.
+    /// This is needed for key registers which can not be read back.
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+impl RegisterWrite {
+    /// Performs the write on the target.
+    pub fn apply(&self, mi: &mut impl MI) -> Result<(), AccessPortError> {
+        let value = if self.overwrite {
+            self.value
+        } else {
+            mi.read32(self.address)? | self.value
+        };
+        log::debug!("Writing {:#010x} to register {:#010x}", value, self.address);
+        mi.write32(self.address, value)
+    }
 }
 
 impl ChipFamily {
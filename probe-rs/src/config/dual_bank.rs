@@ -0,0 +1,168 @@
+use super::init_script::{InitScript, InitScriptError, InitStep};
+use crate::coresight::{access_ports::AccessPortError, memory::MI};
+
+use serde::{Deserialize, Serialize};
+
+use std::ops::Range;
+
+/// One of the two banks of a dual-bank flash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bank {
+    One,
+    Two,
+}
+
+impl std::str::FromStr for Bank {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" => Ok(Bank::One),
+            "2" => Ok(Bank::Two),
+            _ => Err(format!("'{}' is not a flash bank, use '1' or '2'.", s)),
+        }
+    }
+}
+
+/// A single bit of a memory mapped register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisterBit {
+    /// The address of the register.
+    pub address: u32,
+    /// The number of the bit, starting at 0 for the least significant bit.
+    pub bit: u8,
+}
+
+/// The two flash banks of a chip with dual-bank flash, e.g. the STM32F76x or the STM32L47x.
+///
+/// Such chips can swap the banks, such that the second bank shows up at the address of the first one.
+/// This allows to program an update into the inactive bank and boot it by swapping the banks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DualBank {
+    /// The address of bank 1 while the banks are not swapped.
+    pub bank1_start: u32,
+    /// The address of bank 2 while the banks are not swapped.
+    pub bank2_start: u32,
+    /// The size of each bank in bytes.
+    pub bank_size: u32,
+    /// The bit which is set while the banks are swapped, e.g. `FB_MODE` of `SYSCFG_MEMRMP` on the STM32L4.
+    /// The banks are assumed to be never swapped if this is missing.
+    #[serde(default)]
+    pub swapped: Option<RegisterBit>,
+    /// The steps which swap the banks, e.g. unlocking and programming the option bytes.
+    /// On most chips this resets the chip.
+    #[serde(default)]
+    pub swap_sequence: Vec<InitStep>,
+}
+
+impl DualBank {
+    /// Returns `true` if the banks are currently swapped.
+    pub fn is_swapped(&self, mi: &mut impl MI) -> Result<bool, AccessPortError> {
+        match self.swapped {
+            Some(RegisterBit { address, bit }) => Ok(mi.read32(address)? & (1 << bit) != 0),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the address at which `bank` is currently mapped.
+    pub fn bank_start(&self, bank: Bank, swapped: bool) -> u32 {
+        match (bank, swapped) {
+            (Bank::One, false) | (Bank::Two, true) => self.bank1_start,
+            (Bank::Two, false) | (Bank::One, true) => self.bank2_start,
+        }
+    }
+
+    /// Returns relocations which move data that was linked for either bank into `bank`.
+    ///
+    /// Each relocation is a range of addresses and the address the start of the range is moved to.
+    pub fn relocations(&self, bank: Bank, swapped: bool) -> Vec<(Range<u32>, u32)> {
+        let target = self.bank_start(bank, swapped);
+
+        vec![
+            (self.bank1_start..self.bank1_start + self.bank_size, target),
+            (self.bank2_start..self.bank2_start + self.bank_size, target),
+        ]
+    }
+
+    /// Runs the steps which swap the banks.
+    pub fn swap(&self, mi: &mut impl MI) -> Result<(), InitScriptError> {
+        InitScript {
+            steps: self.swap_sequence.clone(),
+        }
+        .run(mi)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Bank, DualBank, RegisterBit};
+    use crate::coresight::memory::{mock::MockMemory, MI};
+
+    fn dual_bank() -> DualBank {
+        DualBank {
+            bank1_start: 0x0800_0000,
+            bank2_start: 0x0808_0000,
+            bank_size: 0x8_0000,
+            swapped: None,
+            swap_sequence: vec![],
+        }
+    }
+
+    #[test]
+    fn relocations_follow_the_swap() {
+        let dual_bank = dual_bank();
+
+        assert_eq!(
+            dual_bank.relocations(Bank::Two, false),
+            vec![
+                (0x0800_0000..0x0808_0000, 0x0808_0000),
+                (0x0808_0000..0x0810_0000, 0x0808_0000)
+            ]
+        );
+        assert_eq!(dual_bank.bank_start(Bank::Two, true), 0x0800_0000);
+        assert_eq!(dual_bank.bank_start(Bank::One, true), 0x0808_0000);
+    }
+
+    #[test]
+    fn swap_sequence_toggles_the_boot_bank() {
+        // The example of the cargo-flash README, for the STM32L476xG.
+        let dual_bank: DualBank = serde_yaml::from_str(
+            "
+bank1_start: 0x08000000
+bank2_start: 0x08080000
+bank_size: 0x80000
+swapped: { address: 0x40010000, bit: 8 }
+swap_sequence:
+  - write: { address: 0x40022008, value: 0x45670123 }
+  - write: { address: 0x40022008, value: 0xCDEF89AB }
+  - write: { address: 0x4002200C, value: 0x08192A3B }
+  - write: { address: 0x4002200C, value: 0x4C5D6E7F }
+  - modify: { address: 0x40022020, toggle: 0x00100000 }
+  - modify: { address: 0x40022014, set: 0x00020000 }
+  - read: { address: 0x40022010, mask: 0x00010000, expected: 0 }
+  - modify: { address: 0x40022014, set: 0x08000000 }
+",
+        )
+        .unwrap();
+        assert_eq!(
+            dual_bank.swapped,
+            Some(RegisterBit {
+                address: 0x4001_0000,
+                bit: 8
+            })
+        );
+
+        // The reset values of FLASH_OPTR and FLASH_CR.
+        let mut mock = MockMemory::default();
+        mock.write32(0x4002_2020, 0xFFEF_F8AA).unwrap();
+        mock.write32(0x4002_2014, 0xC000_0000).unwrap();
+
+        dual_bank.swap(&mut mock).unwrap();
+        assert_eq!(mock.read32(0x4002_2020).unwrap(), 0xFFFF_F8AA);
+        assert_eq!(mock.read32(0x4002_2014).unwrap(), 0xC802_0000);
+        assert!(!dual_bank.is_swapped(&mut mock).unwrap());
+
+        dual_bank.swap(&mut mock).unwrap();
+        assert_eq!(mock.read32(0x4002_2020).unwrap(), 0xFFEF_F8AA);
+    }
+}
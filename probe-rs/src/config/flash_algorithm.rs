@@ -25,12 +25,18 @@ pub struct FlashAlgorithm {
     pub static_base: u32,
     /// Initial value of the stack pointer when calling any flash algo API.
     pub begin_stack: u32,
+    /// Size of the stack in bytes, which grows down from `begin_stack`.
+    pub stack_size: u32,
     /// Base address of the page buffer. Used if `page_buffers` is not provided.
     pub begin_data: u32,
     /// An optional list of base addresses for page buffers. The buffers must be at
     /// least as large as the region's `page_size` attribute. If at least 2 buffers are included in
     /// the list, then double buffered programming will be enabled.
     pub page_buffers: Vec<u32>,
+    /// A constant passed in R3 to `Init()`, for algorithms which take an additional argument.
+    pub init_extra_argument: Option<u32>,
+    /// A constant passed in R3 to `ProgramPage()`, for algorithms which take an additional argument.
+    pub program_page_extra_argument: Option<u32>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -55,6 +61,14 @@ pub struct RawFlashAlgorithm {
     pub pc_erase_all: Option<u32>,
     /// The offset from the start of RAM to the data section.
     pub data_section_offset: u32,
+    /// A constant passed in R3 to `Init()`, e.g. a clock frequency.
+    /// R0 to R2 hold the address, clock and operation as usual.
+    #[serde(default)]
+    pub init_extra_argument: Option<u32>,
+    /// A constant passed in R3 to `ProgramPage()`, e.g. a flag to verify the page.
+    /// R0 to R2 hold the address, size and buffer as usual.
+    #[serde(default)]
+    pub program_page_extra_argument: Option<u32>,
 }
 
 impl RawFlashAlgorithm {
@@ -126,8 +140,11 @@ impl RawFlashAlgorithm {
             pc_erase_all: self.pc_erase_all.map(|v| code_start + v),
             static_base: code_start + self.data_section_offset,
             begin_stack: addr_stack,
+            stack_size: addr_stack - ram_region.range.start,
             begin_data: page_buffers[0],
             page_buffers: page_buffers.clone(),
+            init_extra_argument: self.init_extra_argument,
+            program_page_extra_argument: self.program_page_extra_argument,
         }
     }
 }
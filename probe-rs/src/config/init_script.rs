@@ -0,0 +1,262 @@
+use crate::coresight::{access_ports::AccessPortError, memory::MI};
+
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How long a `read` step polls if the script does not give a timeout.
+const DEFAULT_READ_TIMEOUT_MS: u64 = 1000;
+
+/// A single step of an init script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitStep {
+    /// Writes `value` to the word at `address`.
+    Write { address: u32, value: u32 },
+    /// Reads the word at `address`, clears the bits in `clear`, sets the bits in `set`,
+    /// toggles the bits in `toggle` and writes it back.
+    Modify {
+        address: u32,
+        #[serde(default)]
+        clear: u32,
+        #[serde(default)]
+        set: u32,
+        #[serde(default)]
+        toggle: u32,
+    },
+    /// Polls the word at `address` until the bits in `mask` equal `expected`.
+    Read {
+        address: u32,
+        mask: u32,
+        expected: u32,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    /// Waits for the given number of milliseconds.
+    Sleep(u64),
+}
+
+/// An ordered list of register writes, polls and delays which sets up a board after attaching,
+/// e.g. clocks or pin muxing which are needed before flashing or tracing.
+///
+/// Scripts are written in YAML:
+///
+/// ```yaml
+/// - write: { address: 0x40021018, value: 0x4 }
+/// - modify: { address: 0x40021004, clear: 0x3, set: 0x2 }
+/// - read: { address: 0x40021000, mask: 0x02000000, expected: 0x02000000, timeout_ms: 100 }
+/// - sleep: 10
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InitScript {
+    pub steps: Vec<InitStep>,
+}
+
+#[derive(Debug)]
+pub enum InitScriptError {
+    Parse(serde_yaml::Error),
+    AccessPort(AccessPortError),
+    /// A `read` step timed out, contains the step and the last value which was read.
+    Timeout(InitStep, u32),
+}
+
+impl Error for InitScriptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use InitScriptError::*;
+
+        match self {
+            Parse(ref e) => Some(e),
+            AccessPort(ref e) => Some(e),
+            Timeout(_, _) => None,
+        }
+    }
+}
+
+impl fmt::Display for InitScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InitScriptError::*;
+
+        match self {
+            Parse(ref e) => write!(f, "Failed to parse the init script: {}", e),
+            AccessPort(ref e) => e.fmt(f),
+            Timeout(
+                InitStep::Read {
+                    address,
+                    mask,
+                    expected,
+                    ..
+                },
+                value,
+            ) => write!(
+                f,
+                "Timeout while waiting for {:#010x} & {:#010x} to be {:#010x}, the last value read was {:#010x}.",
+                address, mask, expected, value
+            ),
+            Timeout(step, value) => write!(
+                f,
+                "Timeout in step {:?}, the last value read was {:#010x}.",
+                step, value
+            ),
+        }
+    }
+}
+
+impl From<AccessPortError> for InitScriptError {
+    fn from(error: AccessPortError) -> Self {
+        InitScriptError::AccessPort(error)
+    }
+}
+
+impl From<serde_yaml::Error> for InitScriptError {
+    fn from(error: serde_yaml::Error) -> Self {
+        InitScriptError::Parse(error)
+    }
+}
+
+impl InitScript {
+    pub fn from_yaml_reader<R: std::io::Read>(reader: R) -> Result<Self, InitScriptError> {
+        Ok(serde_yaml::from_reader(reader)?)
+    }
+
+    /// Runs the steps of the script in order and stops at the first failing step.
+    pub fn run(&self, mi: &mut impl MI) -> Result<(), InitScriptError> {
+        for step in &self.steps {
+            log::debug!("Running init script step {:?}", step);
+
+            match *step {
+                InitStep::Write { address, value } => mi.write32(address, value)?,
+                InitStep::Modify {
+                    address,
+                    clear,
+                    set,
+                    toggle,
+                } => {
+                    let value = mi.read32(address)?;
+                    mi.write32(address, ((value & !clear) | set) ^ toggle)?;
+                }
+                InitStep::Read {
+                    address,
+                    mask,
+                    expected,
+                    timeout_ms,
+                } => {
+                    let timeout =
+                        Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_READ_TIMEOUT_MS));
+                    let start = Instant::now();
+
+                    loop {
+                        let value = mi.read32(address)?;
+
+                        if value & mask == expected {
+                            break;
+                        }
+
+                        if start.elapsed() > timeout {
+                            return Err(InitScriptError::Timeout(*step, value));
+                        }
+
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                }
+                InitStep::Sleep(ms) => std::thread::sleep(Duration::from_millis(ms)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{InitScript, InitScriptError, InitStep};
+    use crate::coresight::memory::{mock::MockMemory, MI};
+
+    #[test]
+    fn parse_script() {
+        let script = InitScript::from_yaml_reader(
+            &b"
+- write: { address: 0x40021018, value: 4 }
+- modify: { address: 0x40021004, clear: 3, set: 2 }
+- read: { address: 0x40021000, mask: 0x02000000, expected: 0x02000000, timeout_ms: 100 }
+- read: { address: 0x40021004, mask: 3, expected: 2 }
+- sleep: 10
+"[..],
+        )
+        .unwrap();
+
+        assert_eq!(
+            script.steps,
+            vec![
+                InitStep::Write {
+                    address: 0x4002_1018,
+                    value: 4
+                },
+                InitStep::Modify {
+                    address: 0x4002_1004,
+                    clear: 3,
+                    set: 2,
+                    toggle: 0
+                },
+                InitStep::Read {
+                    address: 0x4002_1000,
+                    mask: 0x0200_0000,
+                    expected: 0x0200_0000,
+                    timeout_ms: Some(100)
+                },
+                InitStep::Read {
+                    address: 0x4002_1004,
+                    mask: 3,
+                    expected: 2,
+                    timeout_ms: None
+                },
+                InitStep::Sleep(10),
+            ]
+        );
+    }
+
+    #[test]
+    fn run_script() {
+        let mut mock = MockMemory::default();
+
+        let script = InitScript {
+            steps: vec![
+                InitStep::Write {
+                    address: 0x10,
+                    value: 0x1234_5678,
+                },
+                InitStep::Modify {
+                    address: 0x10,
+                    clear: 0xF0,
+                    set: 0x0F00_0000,
+                    toggle: 0x1,
+                },
+                InitStep::Read {
+                    address: 0x10,
+                    mask: 0xFF,
+                    expected: 0x09,
+                    timeout_ms: None,
+                },
+            ],
+        };
+
+        script.run(&mut mock).unwrap();
+        assert_eq!(mock.read32(0x10).unwrap(), 0x1F34_5609);
+
+        let script = InitScript {
+            steps: vec![InitStep::Read {
+                address: 0x10,
+                mask: 0xFF,
+                expected: 0x00,
+                timeout_ms: Some(0),
+            }],
+        };
+
+        match script.run(&mut mock) {
+            Err(InitScriptError::Timeout(_, value)) => assert_eq!(value, 0x1F34_5609),
+            result => panic!("Expected a timeout, got {:?}", result),
+        }
+    }
+}
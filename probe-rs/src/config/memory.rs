@@ -1,4 +1,6 @@
 use core::ops::Range;
+use std::collections::BTreeSet;
+use std::time::Duration;
 
 /// Represents a region in flash.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -8,20 +10,52 @@ pub struct FlashRegion {
     pub sector_size: u32,
     pub page_size: u32,
     pub erased_byte_value: u8,
+    /// How long erasing one sector takes at most, used to estimate flashing times.
+    /// If `erase_page_size` is set, this is the time to erase one erase page.
+    #[serde(default)]
+    pub erase_sector_time_ms: Option<u32>,
+    /// How long programming one page takes at most, used to estimate flashing times.
+    #[serde(default)]
+    pub program_page_time_ms: Option<u32>,
+    /// The smallest unit the flash algorithm can erase, if it is smaller than a sector.
+    ///
+    /// It has to be a multiple of the page size and divide the sector size,
+    /// otherwise whole sectors are erased.
+    #[serde(default)]
+    pub erase_page_size: Option<u32>,
 }
 
 impl FlashRegion {
-    /// Returns the necessary information about the sector which `address` resides in
+    /// Returns the size of the units the flash is erased in.
+    ///
+    /// This is the `erase_page_size` if it is valid and the `sector_size` otherwise.
+    pub fn erase_size(&self) -> u32 {
+        match self.erase_page_size {
+            Some(size)
+                if size.checked_rem(self.page_size) == Some(0)
+                    && self.sector_size.checked_rem(size) == Some(0) =>
+            {
+                size
+            }
+            _ => self.sector_size,
+        }
+    }
+
+    /// Returns the necessary information about the erase unit which `address` resides in
     /// if the address is inside the flash region.
+    ///
+    /// This is a whole sector, or an erase page if the region has an `erase_page_size`.
     pub fn sector_info(&self, address: u32) -> Option<SectorInfo> {
         if !self.range.contains(&address) {
             return None;
         }
 
+        let size = self.erase_size();
+
         Some(SectorInfo {
-            base_address: address - (address % self.sector_size),
+            base_address: address - (address % size),
             page_size: self.page_size,
-            size: self.sector_size,
+            size,
         })
     }
 
@@ -45,6 +79,41 @@ impl FlashRegion {
         }
     }
 
+    /// Estimates how long erasing and programming the parts of `ranges` within this region takes,
+    /// based on the timing hints of the region.
+    ///
+    /// Returns a zero duration if the region has no timing hints.
+    pub fn estimate_time(&self, ranges: &[Range<u32>]) -> Duration {
+        let erase_size = self.erase_size();
+        let mut sectors = BTreeSet::new();
+        let mut pages = BTreeSet::new();
+
+        for range in ranges {
+            let start = range.start.max(self.range.start);
+            let end = range.end.min(self.range.end);
+            if start >= end {
+                continue;
+            }
+
+            let mut address = start - (start % erase_size);
+            while address < end {
+                sectors.insert(address);
+                address += erase_size;
+            }
+
+            let mut address = start - (start % self.page_size);
+            while address < end {
+                pages.insert(address);
+                address += self.page_size;
+            }
+        }
+
+        let erase = u64::from(self.erase_sector_time_ms.unwrap_or(0)) * sectors.len() as u64;
+        let program = u64::from(self.program_page_time_ms.unwrap_or(0)) * pages.len() as u64;
+
+        Duration::from_millis(erase + program)
+    }
+
     /// Returns true if the entire contents of the argument array equal the erased byte value.
     pub fn is_erased(&self, data: &[u8]) -> bool {
         for b in data {
@@ -98,16 +167,15 @@ pub trait MemoryRange {
 
 impl MemoryRange for Range<u32> {
     /// Returns true if `self` contains `range` fully.
+    ///
+    /// An empty `range` is contained if it starts within `self`.
     fn contains_range(&self, range: &Range<u32>) -> bool {
-        self.contains(&range.start) && self.contains(&(range.end - 1))
+        self.contains(&range.start) && range.end <= self.end
     }
 
-    /// Returns true if `self` intersects `range` partially.
+    /// Returns true if `self` and `range` have at least one address in common.
     fn intersects_range(&self, range: &Range<u32>) -> bool {
-        self.contains(&range.start) && !self.contains(&(range.end - 1))
-            || !self.contains(&range.start) && self.contains(&(range.end - 1))
-            || self.contains_range(range)
-            || range.contains_range(self)
+        self.start < range.end && range.start < self.end
     }
 }
 
@@ -119,6 +187,39 @@ pub enum MemoryRegion {
     Flash(FlashRegion),
 }
 
+impl MemoryRegion {
+    /// Returns the address range of the region.
+    pub fn range(&self) -> &Range<u32> {
+        match self {
+            MemoryRegion::Ram(region) => &region.range,
+            MemoryRegion::Generic(region) => &region.range,
+            MemoryRegion::Flash(region) => &region.range,
+        }
+    }
+
+    /// Returns true if `address` is within the region.
+    ///
+    /// The end of the range is exclusive, so the address at `range.end` is not contained.
+    pub fn contains(&self, address: u32) -> bool {
+        self.range().contains(&address)
+    }
+
+    /// Returns true if the `len` bytes starting at `start` are completely within the region.
+    ///
+    /// Spans which would wrap around the end of the address space are never contained.
+    pub fn contains_range(&self, start: u32, len: u32) -> bool {
+        match start.checked_add(len) {
+            Some(end) => self.range().contains_range(&(start..end)),
+            None => false,
+        }
+    }
+
+    /// Returns true if the region and `other` have at least one address in common.
+    pub fn overlaps(&self, other: &MemoryRegion) -> bool {
+        self.range().intersects_range(other.range())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -227,4 +328,98 @@ mod test {
         let range2 = 6..8;
         assert!(!range1.intersects_range(&range2));
     }
+
+    #[test]
+    fn contains_range_empty() {
+        let range1 = 4..8;
+        assert!(range1.contains_range(&(4..4)));
+        assert!(!range1.contains_range(&(8..8)));
+    }
+
+    fn region(range: Range<u32>) -> MemoryRegion {
+        MemoryRegion::Generic(GenericRegion { range })
+    }
+
+    #[test]
+    fn region_contains() {
+        let region = region(0x1000..0x2000);
+        assert!(!region.contains(0x0fff));
+        assert!(region.contains(0x1000));
+        assert!(region.contains(0x1fff));
+        assert!(!region.contains(0x2000));
+    }
+
+    #[test]
+    fn region_contains_range() {
+        let region = region(0x1000..0x2000);
+        assert!(region.contains_range(0x1000, 0x1000));
+        assert!(region.contains_range(0x1ffc, 4));
+        assert!(!region.contains_range(0x1ffc, 5));
+        assert!(!region.contains_range(0x0fff, 2));
+        assert!(region.contains_range(0x1fff, 0));
+        assert!(!region.contains_range(0x2000, 0));
+        assert!(!region.contains_range(0x1000, u32::MAX));
+    }
+
+    #[test]
+    fn region_overlaps() {
+        let region1 = region(0x1000..0x2000);
+        assert!(region1.overlaps(&region(0x1fff..0x3000)));
+        assert!(region1.overlaps(&region(0x0000..0x1001)));
+        assert!(region1.overlaps(&region(0x1200..0x1300)));
+        assert!(!region1.overlaps(&region(0x2000..0x3000)));
+        assert!(!region1.overlaps(&region(0x0000..0x1000)));
+    }
+
+    #[test]
+    fn estimate_time() {
+        let region = FlashRegion {
+            range: 0x1000..0x5000,
+            is_boot_memory: true,
+            sector_size: 0x1000,
+            page_size: 0x400,
+            erased_byte_value: 0xFF,
+            erase_sector_time_ms: Some(100),
+            program_page_time_ms: Some(10),
+            erase_page_size: None,
+        };
+
+        // Two sectors and three pages, the range outside of the region is ignored.
+        let ranges = [0x1f00..0x2100, 0x2400..0x2500, 0x8000..0x9000];
+        assert_eq!(region.estimate_time(&ranges), Duration::from_millis(230));
+
+        let region = FlashRegion {
+            erase_sector_time_ms: None,
+            program_page_time_ms: None,
+            ..region
+        };
+        assert_eq!(region.estimate_time(&ranges), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn sector_info_uses_erase_page_size() {
+        let region = FlashRegion {
+            range: 0x0000..0x4_0000,
+            is_boot_memory: true,
+            sector_size: 0x2_0000,
+            page_size: 0x100,
+            erased_byte_value: 0xFF,
+            erase_sector_time_ms: None,
+            program_page_time_ms: None,
+            erase_page_size: Some(0x1000),
+        };
+
+        let sector = region.sector_info(0x2_1234).unwrap();
+        assert_eq!(sector.base_address, 0x2_1000);
+        assert_eq!(sector.size, 0x1000);
+
+        // An erase page size which does not divide the sector size is ignored.
+        let region = FlashRegion {
+            erase_page_size: Some(0x1100),
+            ..region
+        };
+        let sector = region.sector_info(0x2_1234).unwrap();
+        assert_eq!(sector.base_address, 0x2_0000);
+        assert_eq!(sector.size, 0x2_0000);
+    }
 }
@@ -1,6 +1,10 @@
 pub mod chip;
 pub mod chip_family;
+pub mod dual_bank;
 pub mod flash_algorithm;
+pub mod init_script;
 pub mod memory;
 pub mod registry;
+pub mod reset_cause;
+pub mod reset_sequence;
 pub mod target;
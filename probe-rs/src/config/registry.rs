@@ -1,9 +1,11 @@
 use crate::config::{
     chip::Chip,
-    chip_family::ChipFamily,
+    chip_family::{ChipFamily, IdCodeRegister, RegisterWrite},
     flash_algorithm::RawFlashAlgorithm,
     memory::{FlashRegion, MemoryRegion, RamRegion},
+    reset_cause::{ResetCause, ResetCauseFlag, ResetCauseRegister},
 };
+use crate::coresight::memory::MI;
 use crate::target::info::ChipInfo;
 use jep106::JEP106Code;
 use std::error::Error;
@@ -13,10 +15,26 @@ use std::path::Path;
 use super::target::Target;
 use crate::cores::get_core;
 
+/// How many chips are suggested if a chip name is not found.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The largest edit distance at which a chip name is suggested.
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
 #[derive(Debug)]
 pub enum RegistryError {
-    ChipNotFound,
-    ChipAutodetectFailed,
+    /// No chip starts with the given name, contains the most similar chip names.
+    ChipNotFound {
+        name: String,
+        suggestions: Vec<String>,
+    },
+    /// The given name or the IDs of the target match chips of more than one family, contains their names.
+    ChipAmbiguous {
+        name: String,
+        candidates: Vec<String>,
+    },
+    /// No chip matches the IDs read from the ROM table of the target.
+    ChipAutodetectFailed(ChipInfo),
     AlgorithmNotFound,
     CoreNotFound,
     RamMissing,
@@ -30,8 +48,9 @@ impl Error for RegistryError {
         use RegistryError::*;
 
         match self {
-            ChipNotFound => None,
-            ChipAutodetectFailed => None,
+            ChipNotFound { .. } => None,
+            ChipAmbiguous { .. } => None,
+            ChipAutodetectFailed(_) => None,
             AlgorithmNotFound => None,
             CoreNotFound => None,
             RamMissing => None,
@@ -47,10 +66,28 @@ impl std::fmt::Display for RegistryError {
         use RegistryError::*;
 
         match self {
-            ChipNotFound => write!(f, "The requested chip was not found."),
-            ChipAutodetectFailed => write!(
+            ChipNotFound { name, suggestions } => {
+                write!(f, "No chip named '{}' was found.", name)?;
+                if !suggestions.is_empty() {
+                    write!(f, " Did you mean {}?", suggestions.join(", "))?;
+                }
+                Ok(())
+            }
+            ChipAmbiguous { name, candidates } => write!(
                 f,
-                "The connected chip could not automatically be determined."
+                "'{}' matches chips of more than one family: {}. Please specify the full name.",
+                name,
+                candidates.join(", ")
+            ),
+            ChipAutodetectFailed(chip_info) => write!(
+                f,
+                "The connected chip could not automatically be determined. The ROM table identifies \
+                 designer {} (JEP106 cc={:#04x}, id={:#04x}) and part {:#05x}, which matches no chip definition. \
+                 Please select the chip explicitly or add a definition for it.",
+                chip_info.manufacturer.get().unwrap_or("<unknown>"),
+                chip_info.manufacturer.cc,
+                chip_info.manufacturer.id,
+                chip_info.part
             ),
             AlgorithmNotFound => write!(f, "The requested algorithm was not found."),
             CoreNotFound => write!(f, "The requested core was not found."),
@@ -80,6 +117,38 @@ pub enum SelectionStrategy {
     ChipInfo(ChipInfo),
 }
 
+/// A chip of the registry which matches the IDs read from a target, see `Registry::identify`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChipMatch {
+    pub family: String,
+    pub chip: String,
+    /// Whether the vendor specific ID code matched as well, which makes the match certain.
+    pub id_code_matched: bool,
+}
+
+/// The IDs read from a target and the chips which match them.
+#[derive(Debug, Clone)]
+pub struct Identification {
+    /// The value of each ID code register which could be read, with the name of its family and its address.
+    pub id_codes: Vec<(String, u32, u32)>,
+    /// The matching chips, those whose ID code matched first.
+    pub matches: Vec<ChipMatch>,
+}
+
+impl Identification {
+    /// Whether one of the matching chips is the chip `name`, compared case-insensitively.
+    ///
+    /// `name` may also be the start of the chip name or the name of the family,
+    /// as chips which only differ in their package or flash size often can not be told apart.
+    pub fn matches_chip(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+
+        self.matches.iter().any(|m| {
+            m.chip.to_ascii_lowercase().starts_with(&name) || m.family.to_ascii_lowercase() == name
+        })
+    }
+}
+
 pub struct Registry {
     /// All the available chips.
     families: Vec<ChipFamily>,
@@ -101,28 +170,40 @@ impl Registry {
         let (family, chip, flash_algorithm) = match strategy {
             SelectionStrategy::TargetIdentifier(identifier) => {
                 // Try get the corresponding chip.
-                let mut selected_family_and_chip = None;
-                for family in &self.families {
-                    for variant in &family.variants {
-                        if variant
-                            .name
-                            .to_ascii_lowercase()
-                            .starts_with(&identifier.chip_name.to_ascii_lowercase())
-                        {
-                            if variant.name.to_ascii_lowercase()
-                                != identifier.chip_name.to_ascii_lowercase()
-                            {
-                                log::warn!(
-                                    "Found chip {} which matches given partial name {}. Consider specifying it's full name.",
-                                    variant.name,
-                                    identifier.chip_name,
-                                )
-                            }
-                            selected_family_and_chip = Some((family, variant));
-                        }
+                let name = identifier.chip_name.to_ascii_lowercase();
+                let variants = self.families.iter().flat_map(|family| {
+                    family.variants.iter().map(move |variant| (family, variant))
+                });
+
+                // An exact match is never ambiguous.
+                let matches: Vec<_> = match variants
+                    .clone()
+                    .find(|(_, variant)| variant.name.to_ascii_lowercase() == name)
+                {
+                    Some(exact) => vec![exact],
+                    None => variants
+                        .filter(|(_, variant)| variant.name.to_ascii_lowercase().starts_with(&name))
+                        .collect(),
+                };
+
+                let (family, chip) = match matches.last() {
+                    Some(selected) => *selected,
+                    None => {
+                        return Err(RegistryError::ChipNotFound {
+                            name: identifier.chip_name.clone(),
+                            suggestions: self.similar_chips(&identifier.chip_name),
+                        })
                     }
+                };
+                check_single_family(&identifier.chip_name, &matches)?;
+
+                if chip.name.to_ascii_lowercase() != name {
+                    log::warn!(
+                        "Found chip {} which matches given partial name {}. Consider specifying it's full name.",
+                        chip.name,
+                        identifier.chip_name,
+                    )
                 }
-                let (family, chip) = selected_family_and_chip.ok_or(RegistryError::ChipNotFound)?;
 
                 // Try get the correspnding flash algorithm.
                 let flash_algorithm = family
@@ -143,7 +224,7 @@ impl Registry {
             }
             SelectionStrategy::ChipInfo(chip_info) => {
                 // Try get the corresponding chip.
-                let mut selected_family_and_chip = None;
+                let mut matches = vec![];
                 for family in &self.families {
                     if family
                         .manufacturer
@@ -152,13 +233,17 @@ impl Registry {
                     {
                         for variant in &family.variants {
                             if variant.part.map(|p| p == chip_info.part).unwrap_or(false) {
-                                selected_family_and_chip = Some((family, variant));
+                                matches.push((family, variant));
                             }
                         }
                     }
                 }
-                let (family, chip) =
-                    selected_family_and_chip.ok_or(RegistryError::ChipAutodetectFailed)?;
+
+                let (family, chip) = match matches.last() {
+                    Some(selected) => *selected,
+                    None => return Err(RegistryError::ChipAutodetectFailed(chip_info)),
+                };
+                check_single_family(&chip_info.to_string(), &matches)?;
 
                 // Try get the correspnding flash algorithm.
                 let flash_algorithm = family
@@ -187,13 +272,106 @@ impl Registry {
             };
         }
 
-        Ok(Target::new(
+        let mut target = Target::new(
             chip,
             ram.ok_or(RegistryError::RamMissing)?,
             flash.ok_or(RegistryError::FlashMissing)?,
             flash_algorithm,
             core,
-        ))
+        );
+        target.has_fpu = chip.has_fpu.unwrap_or(family.has_fpu);
+        target.debug_keep_alive = family.debug_keep_alive.clone();
+        target.watchdog_disable = family.watchdog_disable.clone();
+        target.reset_cause = family.reset_cause.clone();
+        target.reset_sequence = family.reset_sequence.clone();
+
+        Ok(target)
+    }
+
+    /// Finds the chips which match the ROM table identification of a target.
+    ///
+    /// For families of the manufacturer which have an ID code register, the register is read with `mi`.
+    /// Variants with an ID code then only match if it equals the value of the register,
+    /// and they also match if their `PART` is unknown.
+    pub fn identify(&self, chip_info: &ChipInfo, mi: &mut impl MI) -> Identification {
+        let mut id_codes = vec![];
+        let mut matches = vec![];
+
+        for family in &self.families {
+            if family.manufacturer != Some(chip_info.manufacturer) {
+                continue;
+            }
+
+            let id_code = family
+                .id_code_register
+                .and_then(|register| match register.read(mi) {
+                    Ok(value) => {
+                        id_codes.push((family.name.clone(), register.address, value));
+                        Some(value)
+                    }
+                    Err(e) => {
+                        log::debug!(
+                            "Reading the ID code register of {} failed: {}",
+                            family.name,
+                            e
+                        );
+                        None
+                    }
+                });
+
+            for variant in &family.variants {
+                let id_code_matched = id_code.is_some() && variant.id_code == id_code;
+                let part_matched = variant.part == Some(chip_info.part);
+
+                if id_code_matched
+                    || (part_matched && (id_code.is_none() || variant.id_code.is_none()))
+                {
+                    matches.push(ChipMatch {
+                        family: family.name.clone(),
+                        chip: variant.name.clone(),
+                        id_code_matched,
+                    });
+                }
+            }
+        }
+
+        // The sort is stable, so the order of the registry is kept otherwise.
+        matches.sort_by_key(|m| !m.id_code_matched);
+
+        Identification { id_codes, matches }
+    }
+
+    /// Returns the names of the chips which are most similar to `name`, the most similar first.
+    ///
+    /// Chip names are compared up to the length of `name`, such that a chip is found by the
+    /// start of its name as well, e.g. by a misspelled part number without the package suffix.
+    fn similar_chips(&self, name: &str) -> Vec<String> {
+        let name = name.to_ascii_lowercase();
+
+        let mut candidates: Vec<(usize, &str)> = self
+            .families
+            .iter()
+            .flat_map(|family| &family.variants)
+            .map(|variant| {
+                let prefix: String = variant
+                    .name
+                    .to_ascii_lowercase()
+                    .chars()
+                    .take(name.chars().count())
+                    .collect();
+                (edit_distance(&name, &prefix), variant.name.as_str())
+            })
+            .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+            .collect();
+
+        // The sort is stable, so chips with the same distance keep the order of the registry.
+        candidates.sort_by_key(|(distance, _)| *distance);
+
+        candidates
+            .into_iter()
+            .map(|(_, name)| name.to_owned())
+            .take(MAX_SUGGESTIONS)
+            .collect()
     }
 
     pub fn add_target_from_yaml(&mut self, path_to_yaml: &Path) -> Result<(), RegistryError> {
@@ -213,6 +391,46 @@ impl Registry {
     }
 }
 
+/// Returns an error if `matches` contains chips of more than one family.
+///
+/// Variants of a family share the core and the flash algorithms, so picking any of them is fine.
+fn check_single_family(name: &str, matches: &[(&ChipFamily, &Chip)]) -> Result<(), RegistryError> {
+    let first_family = &matches[0].0.name;
+
+    if matches
+        .iter()
+        .all(|(family, _)| &family.name == first_family)
+    {
+        return Ok(());
+    }
+
+    Err(RegistryError::ChipAmbiguous {
+        name: name.to_owned(),
+        candidates: matches
+            .iter()
+            .map(|(_, variant)| variant.name.clone())
+            .collect(),
+    })
+}
+
+/// The Levenshtein distance between `a` and `b`, i.e. the number of inserted, removed
+/// and replaced characters which turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replace = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TargetIdentifier {
     pub chip_name: String,
@@ -258,6 +476,120 @@ mod tests {
             .is_ok());
     }
 
+    #[test]
+    fn has_fpu_of_chip_overrides_family() {
+        let registry = Registry::from_builtin_families();
+        let has_fpu = |name: &str| {
+            registry
+                .get_target(SelectionStrategy::TargetIdentifier(name.into()))
+                .unwrap()
+                .has_fpu
+        };
+
+        assert!(has_fpu("nRF52840_xxAA"));
+        assert!(!has_fpu("nRF52810_xxAA"));
+        assert!(!has_fpu("nRF52811_xxAA"));
+    }
+
+    #[test]
+    fn identify_by_id_code() {
+        use crate::coresight::memory::mock::MockMemory;
+
+        // The DBGMCU_IDCODE register of an STM32F407.
+        let mut stm32f407 = MockMemory::default();
+        stm32f407.write32(0xE004_2000, 0x1000_6413).unwrap();
+
+        let registry = Registry::from_builtin_families();
+        let chip_info = ChipInfo {
+            manufacturer: JEP106Code::new(0x00, 0x20),
+            part: 0x413,
+        };
+
+        let identification = registry.identify(&chip_info, &mut stm32f407);
+
+        assert_eq!(identification.id_codes.len(), 1);
+        assert_eq!(identification.id_codes[0].2, 0x413);
+        assert!(!identification.matches.is_empty());
+        assert!(identification.matches.iter().all(|m| m.id_code_matched));
+        assert!(identification
+            .matches
+            .iter()
+            .any(|m| m.chip == "STM32F407VGTx"));
+        assert!(!identification
+            .matches
+            .iter()
+            .any(|m| m.chip == "STM32F411RCTx"));
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("nrf52840", "nrf52840"), 0);
+        assert_eq!(edit_distance("nrf52480", "nrf52840"), 2);
+        assert_eq!(edit_distance("stm32f07", "stm32f407"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn unknown_chip_is_suggested() {
+        let registry = Registry::from_builtin_families();
+
+        match registry.get_target(SelectionStrategy::TargetIdentifier("nrf51282".into())) {
+            Err(RegistryError::ChipNotFound { suggestions, .. }) => {
+                assert!(!suggestions.is_empty());
+                assert!(suggestions.len() <= MAX_SUGGESTIONS);
+                assert!(suggestions.iter().all(|s| s.starts_with("nRF5")));
+            }
+            other => panic!("Expected ChipNotFound, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn ambiguous_partial_name() {
+        let registry = Registry::from_builtin_families();
+
+        match registry.get_target(SelectionStrategy::TargetIdentifier("stm32f".into())) {
+            Err(RegistryError::ChipAmbiguous { candidates, .. }) => {
+                assert!(candidates.iter().any(|c| c.starts_with("STM32F1")));
+                assert!(candidates.iter().any(|c| c.starts_with("STM32F4")));
+            }
+            other => panic!("Expected ChipAmbiguous, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn unknown_rom_table_ids() {
+        let registry = Registry::from_builtin_families();
+        let chip_info = ChipInfo {
+            manufacturer: JEP106Code::new(0x00, 0x20),
+            part: 0xFFF,
+        };
+
+        match registry.get_target(SelectionStrategy::ChipInfo(chip_info)) {
+            Err(error @ RegistryError::ChipAutodetectFailed(_)) => {
+                assert!(error.to_string().contains("0xfff"));
+            }
+            other => panic!("Expected ChipAutodetectFailed, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn identification_matches_chip() {
+        let identification = Identification {
+            id_codes: vec![],
+            matches: vec![ChipMatch {
+                family: "nRF52".into(),
+                chip: "nRF52832_xxAA".into(),
+                id_code_matched: false,
+            }],
+        };
+
+        assert!(identification.matches_chip("nrf52832_xxaa"));
+        assert!(identification.matches_chip("nRF52832"));
+        assert!(identification.matches_chip("nrf52"));
+        assert!(!identification.matches_chip("nRF52840_xxAA"));
+        assert!(!identification.matches_chip("STM32F103C8"));
+    }
+
     #[test]
     fn try_fetch4() {
         let registry = Registry::from_builtin_families();
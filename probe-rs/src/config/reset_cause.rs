@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+/// The cause of a reset as reported by the reset cause register of a chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResetCause {
+    /// The chip was powered on.
+    PowerOn,
+    /// The reset pin was asserted.
+    Pin,
+    /// The firmware requested a reset, e.g. via `SYSRESETREQ`.
+    Software,
+    /// The independent watchdog expired.
+    Watchdog,
+    /// The window watchdog expired.
+    WindowWatchdog,
+    /// The supply voltage dropped below the brown out threshold.
+    Brownout,
+    /// The chip was reset when entering or leaving a low power mode.
+    LowPower,
+    /// The core locked up.
+    Lockup,
+    /// The chip woke up from a power off mode.
+    WakeUp,
+}
+
+/// A single flag of a reset cause register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResetCauseFlag {
+    /// The bit of the flag in the register.
+    pub bit: u8,
+    /// The cause which is signaled by the flag.
+    pub cause: ResetCause,
+}
+
+/// The description of the register which holds the cause of the last reset.
+/// E.g. `RCC_CSR` on the STM32 or `RESETREAS` on the nRF52.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResetCauseRegister {
+    /// The address of the register.
+    pub address: u32,
+    /// The bits which are set, in addition to the current value of the register, to clear all flags.
+    ///
+    /// The register value is always written back, such that flags which are cleared by writing a one
+    /// are cleared as well.
+    #[serde(default)]
+    pub clear_value: u32,
+    /// The flags of the register.
+    pub flags: Vec<ResetCauseFlag>,
+}
+
+impl ResetCauseRegister {
+    /// Decodes a value read from the register.
+    pub fn decode(&self, value: u32) -> ResetReason {
+        ResetReason {
+            register_value: value,
+            causes: self
+                .flags
+                .iter()
+                .filter(|flag| value & (1 << flag.bit) != 0)
+                .map(|flag| flag.cause)
+                .collect(),
+        }
+    }
+}
+
+/// The decoded content of a reset cause register.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResetReason {
+    /// The raw value of the register.
+    pub register_value: u32,
+    /// All causes which are flagged in the register.
+    ///
+    /// More than one flag can be set, e.g. a power on reset usually also flags a pin reset.
+    pub causes: Vec<ResetCause>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ResetCause, ResetCauseFlag, ResetCauseRegister};
+
+    fn rcc_csr() -> ResetCauseRegister {
+        ResetCauseRegister {
+            address: 0x4002_1024,
+            clear_value: 1 << 24,
+            flags: vec![
+                ResetCauseFlag {
+                    bit: 29,
+                    cause: ResetCause::Watchdog,
+                },
+                ResetCauseFlag {
+                    bit: 27,
+                    cause: ResetCause::PowerOn,
+                },
+                ResetCauseFlag {
+                    bit: 26,
+                    cause: ResetCause::Pin,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn decode_multiple_causes() {
+        let reason = rcc_csr().decode(0x0C00_0000);
+
+        assert_eq!(reason.register_value, 0x0C00_0000);
+        assert_eq!(reason.causes, vec![ResetCause::PowerOn, ResetCause::Pin]);
+    }
+
+    #[test]
+    fn decode_without_flags() {
+        let reason = rcc_csr().decode(0x0000_0003);
+
+        assert!(reason.causes.is_empty());
+    }
+}
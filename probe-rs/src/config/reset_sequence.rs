@@ -0,0 +1,94 @@
+use crate::coresight::memory::MI;
+use crate::probe::{DebugProbeError, MasterProbe};
+use crate::target::Core;
+
+use serde::{Deserialize, Serialize};
+
+use std::time::Duration;
+
+/// A single step of a reset sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetStep {
+    /// Writes `value` to the word at `address`, e.g. a magic value which selects the boot mode.
+    Write { address: u32, value: u32 },
+    /// Requests a system reset through `AIRCR.SYSRESETREQ`, which is the standard reset of the core.
+    SystemReset,
+    /// Pulses the nRESET pin of the target.
+    HardwareReset,
+    /// Waits for the given number of milliseconds.
+    Sleep(u64),
+    /// Attaches to the debug port again, for chips which drop the debug connection while resetting.
+    Reconnect,
+}
+
+/// An ordered list of steps which resets a chip that does not reset properly with the standard reset of its core.
+///
+/// Sequences are declared in the chip family:
+///
+/// ```yaml
+/// reset_sequence:
+///   - write: { address: 0x40000500, value: 0x5AFE }
+///   - hardware_reset
+///   - sleep: 10
+///   - reconnect
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ResetSequence {
+    pub steps: Vec<ResetStep>,
+}
+
+impl ResetSequence {
+    /// Runs the steps of the sequence in order and stops at the first failing step.
+    pub fn run(&self, core: &dyn Core, probe: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        probe.flush()?;
+
+        for step in &self.steps {
+            log::debug!("Running reset sequence step {:?}", step);
+
+            match *step {
+                ResetStep::Write { address, value } => probe.write32(address, value)?,
+                ResetStep::SystemReset => core.reset(probe)?,
+                ResetStep::HardwareReset => probe.target_reset()?,
+                ResetStep::Sleep(ms) => std::thread::sleep(Duration::from_millis(ms)),
+                ResetStep::Reconnect => probe.reattach()?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ResetSequence, ResetStep};
+
+    #[test]
+    fn parse_sequence() {
+        let sequence: ResetSequence = serde_yaml::from_str(
+            "
+- write: { address: 0x40000500, value: 0x5AFE }
+- hardware_reset
+- sleep: 10
+- reconnect
+- system_reset
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            sequence.steps,
+            vec![
+                ResetStep::Write {
+                    address: 0x4000_0500,
+                    value: 0x5AFE
+                },
+                ResetStep::HardwareReset,
+                ResetStep::Sleep(10),
+                ResetStep::Reconnect,
+                ResetStep::SystemReset,
+            ]
+        );
+    }
+}
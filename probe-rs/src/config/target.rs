@@ -1,8 +1,13 @@
 use super::chip::Chip;
+use super::chip_family::RegisterWrite;
+use super::dual_bank::DualBank;
 use super::flash_algorithm::{FlashAlgorithm, RawFlashAlgorithm};
 use super::memory::{FlashRegion, MemoryRegion, RamRegion};
 use super::registry::TargetIdentifier;
-use crate::target::Core;
+use super::reset_cause::ResetCauseRegister;
+use super::reset_sequence::ResetSequence;
+use crate::probe::{DebugProbeError, MasterProbe, WireProtocol};
+use crate::target::{Core, CoreInformation};
 
 /// This describes a complete target with a fixed chip model and variant.
 #[derive(Debug, Clone)]
@@ -13,8 +18,26 @@ pub struct Target {
     pub flash_algorithm: Option<FlashAlgorithm>,
     /// The core type.
     pub core: Box<dyn Core>,
+    /// Whether the core has an FPU.
+    pub has_fpu: bool,
     /// The memory map of the target.
     pub memory_map: Vec<MemoryRegion>,
+    /// The address of the register holding the flash size in KiB, if the chip has one.
+    pub flash_size_register: Option<u32>,
+    /// The wire protocol used to attach to the target if none is given.
+    pub default_protocol: Option<WireProtocol>,
+    /// The fastest clock speed in kHz at which the target can be debugged reliably.
+    pub max_speed_khz: Option<u32>,
+    /// The two banks of the flash, if the target has a dual-bank flash.
+    pub dual_bank: Option<DualBank>,
+    /// Register writes which keep the debug unit alive in low power modes.
+    pub debug_keep_alive: Vec<RegisterWrite>,
+    /// Register writes which keep watchdogs from resetting the core while flashing.
+    pub watchdog_disable: Vec<RegisterWrite>,
+    /// The register which holds the cause of the last reset.
+    pub reset_cause: Option<ResetCauseRegister>,
+    /// The steps which reset the target instead of the standard reset of the core.
+    pub reset_sequence: Option<ResetSequence>,
 }
 
 pub type TargetParseError = serde_yaml::Error;
@@ -34,7 +57,45 @@ impl Target {
             },
             flash_algorithm: Some(flash_algorithm.assemble(ram, flash)),
             core,
+            has_fpu: false,
             memory_map: chip.memory_map.clone(),
+            flash_size_register: chip.flash_size_register,
+            default_protocol: chip.default_protocol,
+            max_speed_khz: chip.max_speed_khz,
+            dual_bank: chip.dual_bank.clone(),
+            debug_keep_alive: vec![],
+            watchdog_disable: vec![],
+            reset_cause: None,
+            reset_sequence: None,
         }
     }
+
+    /// Resets the target with its reset sequence, or with the standard reset of the core if it has none.
+    pub fn reset(&self, probe: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        match &self.reset_sequence {
+            Some(sequence) => sequence.run(self.core.as_ref(), probe),
+            None => self.core.reset(probe),
+        }
+    }
+
+    /// Resets the target like `reset` and halts the core before it executes the first instruction.
+    pub fn reset_and_halt(
+        &self,
+        probe: &mut MasterProbe,
+    ) -> Result<CoreInformation, DebugProbeError> {
+        let sequence = match &self.reset_sequence {
+            Some(sequence) => sequence,
+            None => return self.core.reset_and_halt(probe),
+        };
+
+        self.core.reset_catch_set(probe)?;
+        sequence.run(self.core.as_ref(), probe)?;
+        self.core.wait_for_core_halted(probe)?;
+        self.core.reset_catch_clear(probe)?;
+
+        let pc = self.core.registers().PC;
+        Ok(CoreInformation {
+            pc: self.core.read_core_reg(probe, pc)?,
+        })
+    }
 }
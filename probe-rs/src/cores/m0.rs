@@ -286,6 +286,11 @@ impl Core for M0 {
         Err(DebugProbeError::Timeout)
     }
 
+    fn core_halted(&self, mi: &mut MasterProbe) -> Result<bool, DebugProbeError> {
+        let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
+        Ok(dhcsr_val.s_halt())
+    }
+
     fn read_core_reg(
         &self,
         mi: &mut MasterProbe,
@@ -303,6 +308,22 @@ impl Core for M0 {
         mi.read32(Dcrdr::ADDRESS).map_err(From::from)
     }
 
+    fn read_core_registers(
+        &self,
+        mi: &mut MasterProbe,
+        addresses: &[CoreRegisterAddress],
+    ) -> Result<Vec<u32>, DebugProbeError> {
+        super::read_core_regs_banked(mi, addresses)
+    }
+
+    fn write_core_registers(
+        &self,
+        mi: &mut MasterProbe,
+        registers: &[(CoreRegisterAddress, u32)],
+    ) -> Result<(), DebugProbeError> {
+        super::write_core_regs_banked(mi, registers)
+    }
+
     fn write_core_reg(
         &self,
         mi: &mut MasterProbe,
@@ -343,6 +364,8 @@ impl Core for M0 {
     }
 
     fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.flush()?;
+
         let mut value = Dhcsr(0);
         value.set_c_halt(false);
         value.set_c_debugen(true);
@@ -373,6 +396,8 @@ impl Core for M0 {
     }
 
     fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.flush()?;
+
         // Set THE AIRCR.SYSRESETREQ control bit to 1 to request a reset. (ARM V6 ARM, B1.5.16)
 
         let mut value = Aircr(0);
@@ -385,23 +410,10 @@ impl Core for M0 {
     }
 
     fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError> {
-        // Ensure debug mode is enabled
-        let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
-        if !dhcsr_val.c_debugen() {
-            let mut dhcsr = Dhcsr(0);
-            dhcsr.set_c_debugen(true);
-            dhcsr.enable_write();
-            mi.write32(Dhcsr::ADDRESS, dhcsr.into())?;
-        }
+        mi.flush()?;
 
-        // Set the vc_corereset bit in the DEMCR register.
-        // This will halt the core after reset.
-        let demcr_val = Demcr(mi.read32(Demcr::ADDRESS)?);
-        if !demcr_val.vc_corereset() {
-            let mut demcr_enabled = demcr_val;
-            demcr_enabled.set_vc_corereset(true);
-            mi.write32(Demcr::ADDRESS, demcr_enabled.into())?;
-        }
+        // The vector catch halts the core before it executes the first instruction after the reset.
+        self.reset_catch_set(mi)?;
 
         self.reset(mi)?;
 
@@ -413,7 +425,7 @@ impl Core for M0 {
             self.write_core_reg(mi, REGISTERS.XPSR, xpsr_value | XPSR_THUMB)?;
         }
 
-        mi.write32(Demcr::ADDRESS, demcr_val.into())?;
+        self.reset_catch_clear(mi)?;
 
         // try to read the program counter
         let pc_value = self.read_core_reg(mi, REGISTERS.PC)?;
@@ -422,6 +434,31 @@ impl Core for M0 {
         Ok(CoreInformation { pc: pc_value })
     }
 
+    fn reset_catch_set(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        // The vector catch only halts the core if debug mode is enabled.
+        let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
+        if !dhcsr_val.c_debugen() {
+            let mut dhcsr = Dhcsr(0);
+            dhcsr.set_c_debugen(true);
+            dhcsr.enable_write();
+            mi.write32(Dhcsr::ADDRESS, dhcsr.into())?;
+        }
+
+        let mut demcr = Demcr(mi.read32(Demcr::ADDRESS)?);
+        demcr.set_vc_corereset(true);
+        mi.write32(Demcr::ADDRESS, demcr.into())?;
+
+        Ok(())
+    }
+
+    fn reset_catch_clear(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        let mut demcr = Demcr(mi.read32(Demcr::ADDRESS)?);
+        demcr.set_vc_corereset(false);
+        mi.write32(Demcr::ADDRESS, demcr.into())?;
+
+        Ok(())
+    }
+
     fn get_available_breakpoint_units(&self, mi: &mut MasterProbe) -> Result<u32, DebugProbeError> {
         let result = mi.read32(BpCtrl::ADDRESS)?;
 
@@ -448,9 +485,13 @@ impl Core for M0 {
         addr: u32,
     ) -> Result<(), DebugProbeError> {
         debug!("Setting breakpoint on address 0x{:08x}", addr);
+        // The thumb bit of a function address is not part of the instruction address.
+        let addr = addr & !1;
+
         let mut value = BpCompx(0);
-        value.set_bp_match(0b11);
-        value.set_comp((addr >> 2) & 0x00FF_FFFF);
+        // Only match the halfword of the instruction, not the other one of the word.
+        value.set_bp_match(if addr & 0x2 == 0 { 0b01 } else { 0b10 });
+        value.set_comp((addr >> 2) & 0x07FF_FFFF);
         value.set_enable(true);
 
         let register_addr = BpCompx::ADDRESS + (bp_register_index * size_of::<u32>()) as u32;
@@ -504,6 +545,10 @@ impl Core for FakeM0 {
         unimplemented!();
     }
 
+    fn core_halted(&self, _mi: &mut MasterProbe) -> Result<bool, DebugProbeError> {
+        unimplemented!();
+    }
+
     fn halt(&self, _mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError> {
         unimplemented!()
     }
@@ -525,6 +570,14 @@ impl Core for FakeM0 {
         unimplemented!()
     }
 
+    fn reset_catch_set(&self, _mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        unimplemented!()
+    }
+
+    fn reset_catch_clear(&self, _mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        unimplemented!()
+    }
+
     fn read_core_reg(
         &self,
         _mi: &mut MasterProbe,
@@ -41,6 +41,11 @@ impl Core for M33 {
         Err(DebugProbeError::Timeout)
     }
 
+    fn core_halted(&self, mi: &mut MasterProbe) -> Result<bool, DebugProbeError> {
+        let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
+        Ok(dhcsr_val.s_halt())
+    }
+
     fn halt(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError> {
         let mut value = Dhcsr(0);
         value.set_c_halt(true);
@@ -58,6 +63,8 @@ impl Core for M33 {
         Ok(CoreInformation { pc: pc_value })
     }
     fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.flush()?;
+
         let mut value = Dhcsr(0);
         value.set_c_halt(false);
         value.set_c_debugen(true);
@@ -66,6 +73,8 @@ impl Core for M33 {
         mi.write32(Dhcsr::ADDRESS, value.into()).map_err(Into::into)
     }
     fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        mi.flush()?;
+
         // Set THE AIRCR.SYSRESETREQ control bit to 1 to request a reset. (ARM V6 ARM, B1.5.16)
 
         let mut value = Aircr(0);
@@ -78,23 +87,10 @@ impl Core for M33 {
     }
 
     fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError> {
-        // Ensure debug mode is enabled
-        let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
-        if !dhcsr_val.c_debugen() {
-            let mut dhcsr = Dhcsr(0);
-            dhcsr.set_c_debugen(true);
-            dhcsr.enable_write();
-            mi.write32(Dhcsr::ADDRESS, dhcsr.into())?;
-        }
+        mi.flush()?;
 
-        // Set the vc_corereset bit in the DEMCR register.
-        // This will halt the core after reset.
-        let demcr_val = Demcr(mi.read32(Demcr::ADDRESS)?);
-        if !demcr_val.vc_corereset() {
-            let mut demcr_enabled = demcr_val;
-            demcr_enabled.set_vc_corereset(true);
-            mi.write32(Demcr::ADDRESS, demcr_enabled.into())?;
-        }
+        // The vector catch halts the core before it executes the first instruction after the reset.
+        self.reset_catch_set(mi)?;
 
         self.reset(mi)?;
 
@@ -106,7 +102,7 @@ impl Core for M33 {
             self.write_core_reg(mi, REGISTERS.XPSR, xpsr_value | XPSR_THUMB)?;
         }
 
-        mi.write32(Demcr::ADDRESS, demcr_val.into())?;
+        self.reset_catch_clear(mi)?;
 
         // try to read the program counter
         let pc_value = self.read_core_reg(mi, REGISTERS.PC)?;
@@ -115,6 +111,31 @@ impl Core for M33 {
         Ok(CoreInformation { pc: pc_value })
     }
 
+    fn reset_catch_set(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        // The vector catch only halts the core if debug mode is enabled.
+        let dhcsr_val = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
+        if !dhcsr_val.c_debugen() {
+            let mut dhcsr = Dhcsr(0);
+            dhcsr.set_c_debugen(true);
+            dhcsr.enable_write();
+            mi.write32(Dhcsr::ADDRESS, dhcsr.into())?;
+        }
+
+        let mut demcr = Demcr(mi.read32(Demcr::ADDRESS)?);
+        demcr.set_vc_corereset(true);
+        mi.write32(Demcr::ADDRESS, demcr.into())?;
+
+        Ok(())
+    }
+
+    fn reset_catch_clear(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        let mut demcr = Demcr(mi.read32(Demcr::ADDRESS)?);
+        demcr.set_vc_corereset(false);
+        mi.write32(Demcr::ADDRESS, demcr.into())?;
+
+        Ok(())
+    }
+
     fn step(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError> {
         let mut value = Dhcsr(0);
         // Leave halted state.
@@ -152,6 +173,22 @@ impl Core for M33 {
 
         mi.read32(Dcrdr::ADDRESS).map_err(From::from)
     }
+    fn read_core_registers(
+        &self,
+        mi: &mut MasterProbe,
+        addresses: &[CoreRegisterAddress],
+    ) -> Result<Vec<u32>, DebugProbeError> {
+        super::read_core_regs_banked(mi, addresses)
+    }
+
+    fn write_core_registers(
+        &self,
+        mi: &mut MasterProbe,
+        registers: &[(CoreRegisterAddress, u32)],
+    ) -> Result<(), DebugProbeError> {
+        super::write_core_regs_banked(mi, registers)
+    }
+
     fn write_core_reg(
         &self,
         mi: &mut MasterProbe,
@@ -197,10 +234,8 @@ impl Core for M33 {
     ) -> Result<(), DebugProbeError> {
         let mut val = FpCompX::from(0);
 
-        // clear bits which cannot be set
-        let comp_val = addr & 0xff_ff_ff_fe;
-
-        val.set_bp_addr(comp_val);
+        // The thumb bit of a function address is not part of the instruction address.
+        val.set_bp_addr(addr >> 1);
         val.set_enable(true);
 
         let reg_addr = FpCompX::ADDRESS + (bp_unit_index * size_of::<u32>()) as u32;
@@ -491,8 +526,8 @@ bitfield! {
 }
 
 impl CoreRegister for FpCompX {
-    const ADDRESS: u32 = 0xE000_2000;
-    const NAME: &'static str = "FP_CTRL";
+    const ADDRESS: u32 = 0xE000_2008;
+    const NAME: &'static str = "FP_COMP0";
 }
 
 impl From<u32> for FpCompX {
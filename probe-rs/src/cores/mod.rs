@@ -1,4 +1,8 @@
-use crate::target::Core;
+use crate::coresight::access_ports::memory_ap::{DataSize, MemoryAP, BD0, BD1, BD2, TAR};
+use crate::coresight::ap_access::APAccess;
+use crate::coresight::memory::adi_v5_memory_interface::ADIMemoryInterface;
+use crate::probe::{DebugProbeError, MasterProbe};
+use crate::target::{Core, CoreRegisterAddress};
 use std::collections::HashMap;
 
 pub mod m0;
@@ -31,3 +35,98 @@ pub fn get_core(name: impl AsRef<str>) -> Option<Box<dyn Core>> {
 
     map.get(&name.as_ref().to_ascii_lowercase()[..]).cloned()
 }
+
+/// Reads multiple core registers of a Cortex-M core in as few transfers as possible.
+///
+/// DHCSR, DCRSR and DCRDR are located in the same 16 byte block on all Cortex-M cores,
+/// so they can be accessed through the banked data registers of the memory AP.
+/// This way TAR only has to be written once, and each register read only takes
+/// a DCRSR write followed by a DHCSR and a DCRDR read.
+pub(crate) fn read_core_regs_banked(
+    mi: &mut MasterProbe,
+    addresses: &[CoreRegisterAddress],
+) -> Result<Vec<u32>, DebugProbeError> {
+    let ap = select_debug_register_bank(mi)?;
+
+    let mut values = Vec::with_capacity(addresses.len());
+
+    for address in addresses {
+        // BD1 maps to DCRSR. Leaving REGWnR cleared selects a read.
+        mi.write_ap_register(
+            ap,
+            BD1 {
+                data: u32::from(*address),
+            },
+        )?;
+
+        wait_for_regrdy_banked(mi, ap)?;
+
+        // BD2 maps to DCRDR.
+        let dcrdr: BD2 = mi.read_ap_register(ap, BD2::default())?;
+        values.push(dcrdr.data);
+    }
+
+    Ok(values)
+}
+
+/// Writes multiple core registers of a Cortex-M core in as few transfers as possible.
+///
+/// This works like `read_core_regs_banked`, each register write takes a DCRDR write,
+/// a DCRSR write and a DHCSR read.
+pub(crate) fn write_core_regs_banked(
+    mi: &mut MasterProbe,
+    registers: &[(CoreRegisterAddress, u32)],
+) -> Result<(), DebugProbeError> {
+    /// The `REGWnR` bit of DCRSR, which selects a write.
+    const REGWNR: u32 = 1 << 16;
+
+    let ap = select_debug_register_bank(mi)?;
+
+    for (address, value) in registers {
+        mi.write_ap_register(ap, BD2 { data: *value })?;
+        mi.write_ap_register(
+            ap,
+            BD1 {
+                data: u32::from(*address) | REGWNR,
+            },
+        )?;
+
+        wait_for_regrdy_banked(mi, ap)?;
+    }
+
+    Ok(())
+}
+
+/// Points TAR of the memory AP of the probe to the debug register block, such that BD0 to BD2 map to DHCSR, DCRSR and DCRDR.
+fn select_debug_register_bank(mi: &mut MasterProbe) -> Result<MemoryAP, DebugProbeError> {
+    /// Base address of the debug register block, which holds DHCSR at offset 0.
+    const DEBUG_REGISTER_BASE: u32 = 0xE000_EDF0;
+
+    let port = mi.memory_access_port();
+    let ap = MemoryAP::new(port);
+    let csw = ADIMemoryInterface::new(port).build_csw_register(DataSize::U32);
+    mi.write_ap_register(ap, csw)?;
+    mi.write_ap_register(
+        ap,
+        TAR {
+            address: DEBUG_REGISTER_BASE,
+        },
+    )?;
+
+    Ok(ap)
+}
+
+/// Polls DHCSR through BD0 until the core register transfer has finished.
+fn wait_for_regrdy_banked(mi: &mut MasterProbe, ap: MemoryAP) -> Result<(), DebugProbeError> {
+    /// The `S_REGRDY` bit of DHCSR.
+    const S_REGRDY: u32 = 1 << 16;
+
+    for _ in 0..100 {
+        let dhcsr: BD0 = mi.read_ap_register(ap, BD0::default())?;
+        if dhcsr.data & S_REGRDY != 0 {
+            return Ok(());
+        }
+    }
+
+    Err(DebugProbeError::Timeout)
+}
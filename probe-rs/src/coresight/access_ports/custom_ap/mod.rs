@@ -2,10 +2,43 @@
 
 use crate::coresight::common::Register;
 
-use crate::coresight::access_ports::generic_ap::GenericAP;
+use crate::coresight::access_ports::generic_ap::{APClass, APType, GenericAP, IDR};
 use crate::coresight::access_ports::APRegister;
 use crate::coresight::ap_access::AccessPort;
 
+/// A vendor specific access port which controls the chip even while the memory APs are locked,
+/// e.g. to erase and unlock it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlApKind {
+    /// The CTRL-AP of the nRF52 and nRF91, see `CtrlAP`.
+    NordicCtrlAp,
+    /// The debug mailbox of the LPC55xx, see `DebugMailboxAP`.
+    NxpDebugMailbox,
+}
+
+impl ControlApKind {
+    /// All known kinds of control APs.
+    pub const ALL: [ControlApKind; 2] =
+        [ControlApKind::NordicCtrlAp, ControlApKind::NxpDebugMailbox];
+
+    /// Returns the IDR of the access port, which identifies it.
+    pub fn idr(self) -> IDR {
+        let designer = match self {
+            ControlApKind::NordicCtrlAp => 0x0144,
+            ControlApKind::NxpDebugMailbox => 0x0015,
+        };
+
+        IDR {
+            REVISION: 0,
+            DESIGNER: designer,
+            CLASS: APClass::Undefined,
+            _RES0: 0,
+            VARIANT: 0,
+            TYPE: APType::JTAG_COM_AP,
+        }
+    }
+}
+
 // Ctrl-Ap
 // The Control Access Port (CTRL-AP) is a Nordic's custom access port that enables control of the
 // device even if the other access ports in the DAP are being disabled by the access port protection.
@@ -68,3 +101,71 @@ define_ap_register!(
     },
     if value.APPROTECTSTATUS { 1 } else { 0 }
 );
+
+// Debug mailbox
+// The debug mailbox access port (DM-AP) of the NXP LPC55xx passes requests to the boot ROM,
+// e.g. to start a debug session while the debug access is disabled.
+define_ap!(DebugMailboxAP);
+
+/// The debug mailbox command which makes the boot ROM enable the debug access and wait for the debugger.
+pub const DEBUG_MAILBOX_START_DEBUG_SESSION: u16 = 7;
+
+impl From<GenericAP> for DebugMailboxAP {
+    fn from(other: GenericAP) -> Self {
+        DebugMailboxAP {
+            port_number: other.get_port_number(),
+        }
+    }
+}
+
+define_ap_register!(
+    /// Control and status of the debug mailbox
+    DebugMailboxAP,
+    DMCSW,
+    0x000,
+    [
+        (RESYNCH_REQ: bool),
+        (REQ_PENDING: bool),
+        (DBG_OR_ERR: bool),
+        (AHB_OR_ERR: bool),
+        (SOFT_RESET: bool),
+        (CHIP_RESET_REQ: bool),
+    ],
+    value,
+    DMCSW {
+        RESYNCH_REQ: value & (1 << 0) != 0,
+        REQ_PENDING: value & (1 << 1) != 0,
+        DBG_OR_ERR: value & (1 << 2) != 0,
+        AHB_OR_ERR: value & (1 << 3) != 0,
+        SOFT_RESET: value & (1 << 4) != 0,
+        CHIP_RESET_REQ: value & (1 << 5) != 0,
+    },
+    (value.RESYNCH_REQ as u32)
+        | (value.REQ_PENDING as u32) << 1
+        | (value.DBG_OR_ERR as u32) << 2
+        | (value.AHB_OR_ERR as u32) << 3
+        | (value.SOFT_RESET as u32) << 4
+        | (value.CHIP_RESET_REQ as u32) << 5
+);
+
+define_ap_register!(
+    /// The request to the boot ROM, the command in the lower 16 bits and the number of data words in the upper 16 bits
+    DebugMailboxAP,
+    REQUEST,
+    0x004,
+    [(REQUEST: u32),],
+    value,
+    REQUEST { REQUEST: value },
+    value.REQUEST
+);
+
+define_ap_register!(
+    /// The response of the boot ROM, the status in the lower 16 bits
+    DebugMailboxAP,
+    RETURN,
+    0x008,
+    [(RETURN: u32),],
+    value,
+    RETURN { RETURN: value },
+    value.RETURN
+);
@@ -5,6 +5,8 @@ use std::{collections::HashMap, error::Error, fmt};
 
 pub struct MockMemoryAP {
     pub data: Vec<u8>,
+    /// Emulates an AP which only supports 32 bit accesses and ignores other sizes written to the CSW.
+    pub only_32bit_data_size: bool,
     store: HashMap<(u8, u8), u32>,
 }
 
@@ -33,11 +35,18 @@ impl Default for MockMemoryAP {
         store.insert((DRW::ADDRESS, DRW::APBANKSEL), 0);
         Self {
             data: vec![0; 256],
+            only_32bit_data_size: false,
             store,
         }
     }
 }
 
+/// Increments the TAR like the smallest auto-increment a MEM-AP may implement,
+/// which only changes the lowest 10 bits and wraps at 1 KiB boundaries.
+fn auto_increment(address: u32, increment: u32) -> u32 {
+    (address & !0x3FF) | (address.wrapping_add(increment) & 0x3FF)
+}
+
 impl<REGISTER> APAccess<MemoryAP, REGISTER> for MockMemoryAP
 where
     REGISTER: APRegister<MemoryAP>,
@@ -66,23 +75,28 @@ where
                             | (u32::from(self.data[address as usize + 2]) << 16)
                             | (u32::from(self.data[address as usize + 3]) << 24),
                     )),
+                    // Smaller accesses transfer the data on the byte lanes of the address.
                     DataSize::U16 => Ok(REGISTER::from(
-                        u32::from(self.data[address as usize])
-                            | (u32::from(self.data[address as usize + 1]) << 8),
+                        (u32::from(self.data[address as usize])
+                            | (u32::from(self.data[address as usize + 1]) << 8))
+                            << ((address & 0x2) * 8),
+                    )),
+                    DataSize::U8 => Ok(REGISTER::from(
+                        u32::from(self.data[address as usize]) << ((address & 0x3) * 8),
                     )),
-                    DataSize::U8 => Ok(REGISTER::from(u32::from(self.data[address as usize]))),
                     _ => Err(MockMemoryError::UnknownWidth),
                 };
 
                 if data.is_ok() {
                     match csw.AddrInc {
                         AddressIncrement::Single => {
-                            let new_address = match csw.SIZE {
-                                DataSize::U32 => address + 4,
-                                DataSize::U16 => address + 2,
-                                DataSize::U8 => address + 1,
+                            let increment = match csw.SIZE {
+                                DataSize::U32 => 4,
+                                DataSize::U16 => 2,
+                                DataSize::U8 => 1,
                                 _ => unimplemented!(),
                             };
+                            let new_address = auto_increment(address, increment);
 
                             self.store
                                 .insert((TAR::ADDRESS, TAR::APBANKSEL), new_address);
@@ -130,12 +144,13 @@ where
                         Ok(())
                     }
                     DataSize::U16 => {
+                        let value = value >> ((address & 0x2) * 8);
                         self.data[address as usize] = value as u8;
                         self.data[address as usize + 1] = (value >> 8) as u8;
                         Ok(())
                     }
                     DataSize::U8 => {
-                        self.data[address as usize] = value as u8;
+                        self.data[address as usize] = (value >> ((address & 0x3) * 8)) as u8;
                         Ok(())
                     }
                     _ => Err(MockMemoryError::UnknownWidth),
@@ -145,12 +160,13 @@ where
                     let csw = CSW::from(csw);
                     match csw.AddrInc {
                         AddressIncrement::Single => {
-                            let new_address = match csw.SIZE {
-                                DataSize::U32 => address + 4,
-                                DataSize::U16 => address + 2,
-                                DataSize::U8 => address + 1,
+                            let increment = match csw.SIZE {
+                                DataSize::U32 => 4,
+                                DataSize::U16 => 2,
+                                DataSize::U8 => 1,
                                 _ => unimplemented!(),
                             };
+                            let new_address = auto_increment(address, increment);
                             self.store
                                 .insert((TAR::ADDRESS, TAR::APBANKSEL), new_address);
                         }
@@ -164,6 +180,11 @@ where
                 result
             }
             (CSW::ADDRESS, CSW::APBANKSEL) => {
+                let value = if self.only_32bit_data_size {
+                    (value & !0x7) | DataSize::U32 as u32
+                } else {
+                    value
+                };
                 self.store.insert((CSW::ADDRESS, CSW::APBANKSEL), value);
                 Ok(())
             }
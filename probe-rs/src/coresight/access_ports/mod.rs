@@ -41,7 +41,10 @@ impl fmt::Display for AccessPortError {
                 name, addr
             ),
             OutOfBoundsError => write!(f, "Out of bounds access"),
-            CtrlAPNotFound => write!(f, "Could not find Nordic's CTRL-AP"),
+            CtrlAPNotFound => write!(
+                f,
+                "Could not find a control AP, e.g. Nordic's CTRL-AP or NXP's debug mailbox"
+            ),
         }
     }
 }
@@ -3,12 +3,22 @@ use crate::coresight::access_ports::{
     memory_ap::{AddressIncrement, DataSize, MemoryAP, CSW, DRW, TAR},
     APRegister, AccessPortError,
 };
-use crate::coresight::ap_access::APAccess;
+use crate::coresight::ap_access::{APAccess, AccessPort};
 use scroll::Pread;
 
+/// The number of bytes after which the TAR auto-increment may wrap around.
+///
+/// The ADIv5 specification only guarantees that the lowest 10 bits of the TAR are incremented,
+/// the size is implementation defined and can not be read from the AP.
+/// Block transfers write the TAR again at every boundary of this size.
+const TAR_AUTO_INCREMENT_SIZE: usize = 0x400;
+
 /// A struct to give access to a targets memory using a certain DAP.
 pub struct ADIMemoryInterface {
     access_port: MemoryAP,
+    max_transfer_size: usize,
+    only_32bit_data_size: bool,
+    byte_access: bool,
 }
 
 pub fn bytes_to_transfer_size(bytes: u8) -> DataSize {
@@ -34,13 +44,92 @@ impl ADIMemoryInterface {
     pub fn new(access_port_number: u8) -> Self {
         Self {
             access_port: MemoryAP::new(access_port_number),
+            max_transfer_size: TAR_AUTO_INCREMENT_SIZE,
+            only_32bit_data_size: true,
+            byte_access: false,
         }
     }
 
+    /// Sets whether the AP only supports 32 bit accesses, as determined by `detect_data_sizes`.
+    ///
+    /// If it does, 8 bit accesses are done as 32 bit accesses to the containing word.
+    /// This is the default, as it works with every AP.
+    pub fn with_only_32bit_data_size(mut self, only_32bit_data_size: bool) -> Self {
+        self.only_32bit_data_size = only_32bit_data_size;
+        self
+    }
+
+    /// Returns `true` if the AP only supports 32 bit accesses.
+    pub fn only_32bit_data_size(&self) -> bool {
+        self.only_32bit_data_size
+    }
+
+    /// Sets whether `read8` and `write8` use real 8 bit accesses.
+    ///
+    /// By default they access the containing word, which works on every AP and for every memory.
+    /// Real 8 bit accesses are only needed for memory which does not tolerate accesses to its
+    /// neighbouring bytes, such as byte wide peripheral registers. They are only done if the AP supports them.
+    pub fn with_byte_access(mut self, byte_access: bool) -> Self {
+        self.byte_access = byte_access;
+        self
+    }
+
+    /// Returns `true` if `read8` and `write8` use real 8 bit accesses.
+    fn uses_byte_access(&self) -> bool {
+        self.byte_access && !self.only_32bit_data_size
+    }
+
+    /// Detects which access sizes the AP supports.
+    ///
+    /// A MEM-AP which does not support accesses smaller than a word ignores writes of
+    /// other sizes to the SIZE field of the CSW, so the CSW is written with an 8 bit size and read back.
+    /// If the detection fails, only 32 bit accesses are used.
+    pub fn detect_data_sizes<AP>(&mut self, debug_port: &mut AP) -> Result<(), AccessPortError>
+    where
+        AP: APAccess<MemoryAP, CSW>,
+    {
+        self.only_32bit_data_size = true;
+
+        let csw = self.build_csw_register(DataSize::U8);
+        self.write_ap_register(debug_port, csw)?;
+        let csw: CSW = self.read_ap_register(debug_port, CSW::default())?;
+
+        self.only_32bit_data_size = csw.SIZE != DataSize::U8;
+        log::debug!(
+            "AP {} supports 8 bit accesses: {}",
+            self.access_port.get_port_number(),
+            !self.only_32bit_data_size
+        );
+
+        Ok(())
+    }
+
+    /// Limits block transfers to chunks of at most `max_transfer_size` bytes,
+    /// e.g. to the [`DebugProbe::max_memory_transfer_size`] of the probe.
+    ///
+    /// Chunks never cross a boundary of the TAR auto-increment, so sizes above 1 KiB have no effect.
+    ///
+    /// [`DebugProbe::max_memory_transfer_size`]: ../../probe/trait.DebugProbe.html#method.max_memory_transfer_size
+    pub fn with_max_transfer_size(mut self, max_transfer_size: usize) -> Self {
+        // Transfers are done in whole words, and at least one word has to fit into a transfer.
+        self.max_transfer_size = (max_transfer_size.min(TAR_AUTO_INCREMENT_SIZE) & !0x3).max(4);
+        self
+    }
+
+    /// Returns how many words of the `remaining_words` can be transferred at `address` in one chunk,
+    /// without crossing a TAR auto-increment boundary or exceeding the maximum transfer size.
+    fn chunk_size_words(&self, address: u32, remaining_words: usize) -> usize {
+        let to_boundary = TAR_AUTO_INCREMENT_SIZE - (address as usize % TAR_AUTO_INCREMENT_SIZE);
+        let chunk_size_bytes = to_boundary
+            .min(self.max_transfer_size)
+            .min(remaining_words * 4);
+        chunk_size_bytes / 4
+    }
+
     /// Build the correct CSW register for a memory access
     ///
     /// Currently, only AMBA AHB Access is supported.
-    fn build_csw_register(&self, data_size: DataSize) -> CSW {
+    pub(crate) fn build_csw_register(&self, data_size: DataSize) -> CSW {
         // The CSW Register is set for an AMBA AHB Acccess, according to
         // the ARM Debug Interface Architecture Specification.
         //
@@ -152,26 +241,25 @@ impl ADIMemoryInterface {
 
     /// Read an 8bit word at `addr`.
     ///
-    /// The address where the read should be performed at has to be word aligned.
-    /// Returns `AccessPortError::MemoryNotAligned` if this does not hold true.
+    /// The containing word is read, unless byte access was enabled with `with_byte_access`.
     pub fn read8<AP>(&self, debug_port: &mut AP, address: u32) -> Result<u8, AccessPortError>
     where
         AP: APAccess<MemoryAP, CSW> + APAccess<MemoryAP, TAR> + APAccess<MemoryAP, DRW>,
     {
-        let pre_bytes = ((4 - (address % 4)) % 4) as usize;
-        let aligned_addr = address - (address % 4);
+        // The byte is transferred on the byte lane of its address in both cases.
+        let byte_lane = address % 4;
 
-        let result = self.read32(debug_port, aligned_addr)?;
-
-        dbg!(pre_bytes);
+        let result = if !self.uses_byte_access() {
+            self.read32(debug_port, address - byte_lane)?
+        } else {
+            let csw = self.build_csw_register(DataSize::U8);
+            let tar = TAR { address };
+            self.write_ap_register(debug_port, csw)?;
+            self.write_ap_register(debug_port, tar)?;
+            self.read_ap_register(debug_port, DRW::default())?.data
+        };
 
-        Ok(match pre_bytes {
-            3 => ((result >> 8) & 0xff) as u8,
-            2 => ((result >> 16) & 0xff) as u8,
-            1 => ((result >> 24) & 0xff) as u8,
-            0 => (result & 0xff) as u8,
-            _ => panic!("This case cannot happen ever. This must be a bug. Please report it."),
-        })
+        Ok(((result >> (byte_lane * 8)) & 0xff) as u8)
     }
 
     /// Read a block of words of the size defined by S at `addr`.
@@ -196,68 +284,31 @@ impl ADIMemoryInterface {
         let csw = self.build_csw_register(DataSize::U32);
         self.write_ap_register(debug_port, csw)?;
 
-        let mut address = start_address;
-        let tar = TAR { address };
-        self.write_ap_register(debug_port, tar)?;
-
-        // figure out how many words we can write before the
-        // data overflows
-
-        // maximum chunk size
-        let max_chunk_size_bytes = 0x400;
-
-        let mut remaining_data_len = data.len();
-
-        let first_chunk_size_bytes = std::cmp::min(
-            max_chunk_size_bytes - (address as usize % max_chunk_size_bytes),
-            data.len() * 4,
-        );
-
         let mut data_offset = 0;
 
-        log::debug!(
-            "Read first block with len {} at address {:#08x}",
-            first_chunk_size_bytes,
-            address
-        );
-
-        let first_chunk_size_words = first_chunk_size_bytes / 4;
-
-        self.read_ap_register_repeated(
-            debug_port,
-            DRW { data: 0 },
-            &mut data[data_offset..first_chunk_size_words],
-        )?;
-
-        remaining_data_len -= first_chunk_size_words;
-        address += (4 * first_chunk_size_words) as u32;
-        data_offset += first_chunk_size_words;
+        while data_offset < data.len() {
+            let address = start_address + (4 * data_offset) as u32;
 
-        while remaining_data_len > 0 {
             // the autoincrement is limited to the 10 lowest bits so we need to write the address
-            // every time it overflows
+            // every time it overflows, and the probe might limit the size of a single transfer
             let tar = TAR { address };
             self.write_ap_register(debug_port, tar)?;
 
-            let next_chunk_size_bytes = std::cmp::min(max_chunk_size_bytes, remaining_data_len * 4);
+            let chunk_size_words = self.chunk_size_words(address, data.len() - data_offset);
 
             log::debug!(
                 "Reading chunk with len {} at address {:#08x}",
-                next_chunk_size_bytes,
+                chunk_size_words * 4,
                 address
             );
 
-            let next_chunk_size_words = next_chunk_size_bytes / 4;
-
             self.read_ap_register_repeated(
                 debug_port,
                 DRW { data: 0 },
-                &mut data[data_offset..(data_offset + next_chunk_size_words)],
+                &mut data[data_offset..(data_offset + chunk_size_words)],
             )?;
 
-            remaining_data_len -= next_chunk_size_words;
-            address += (4 * next_chunk_size_words) as u32;
-            data_offset += next_chunk_size_words;
+            data_offset += chunk_size_words;
         }
 
         log::debug!("Finished reading block");
@@ -390,8 +441,7 @@ impl ADIMemoryInterface {
 
     /// Write an 8bit word at `addr`.
     ///
-    /// The address where the write should be performed at has to be word aligned.
-    /// Returns `AccessPortError::MemoryNotAligned` if this does not hold true.
+    /// The containing word is read, modified and written back, unless byte access was enabled with `with_byte_access`.
     pub fn write8<AP>(
         &self,
         debug_port: &mut AP,
@@ -401,22 +451,28 @@ impl ADIMemoryInterface {
     where
         AP: APAccess<MemoryAP, CSW> + APAccess<MemoryAP, TAR> + APAccess<MemoryAP, DRW>,
     {
-        let pre_bytes = (address % 4) as usize;
-        let aligned_addr = address - (address % 4);
+        // The byte is transferred on the byte lane of its address in both cases.
+        let byte_lane = address % 4;
 
-        let before = self.read32(debug_port, aligned_addr)?;
-        let data_t = before & !(0xFF << (pre_bytes * 8));
-        let data = data_t | (u32::from(data) << (pre_bytes * 8));
+        if !self.uses_byte_access() {
+            let aligned_addr = address - byte_lane;
 
-        let csw = self.build_csw_register(DataSize::U32);
-        let drw = DRW { data };
-        let tar = TAR {
-            address: aligned_addr,
-        };
-        self.write_ap_register(debug_port, csw)?;
-        self.write_ap_register(debug_port, tar)?;
-        self.write_ap_register(debug_port, drw)?;
-        Ok(())
+            let before = self.read32(debug_port, aligned_addr)?;
+            let data_t = before & !(0xFF << (byte_lane * 8));
+            let data = data_t | (u32::from(data) << (byte_lane * 8));
+
+            self.write32(debug_port, aligned_addr, data)
+        } else {
+            let csw = self.build_csw_register(DataSize::U8);
+            let drw = DRW {
+                data: u32::from(data) << (byte_lane * 8),
+            };
+            let tar = TAR { address };
+            self.write_ap_register(debug_port, csw)?;
+            self.write_ap_register(debug_port, tar)?;
+            self.write_ap_register(debug_port, drw)?;
+            Ok(())
+        }
     }
 
     /// Write a block of 32bit words at `addr`.
@@ -445,71 +501,33 @@ impl ADIMemoryInterface {
 
         // Second we write in 32 bit reads until we have less than 32 bits left to write.
         let csw = self.build_csw_register(DataSize::U32);
-
         self.write_ap_register(debug_port, csw)?;
 
-        let mut address = start_address;
-        let tar = TAR { address };
-        self.write_ap_register(debug_port, tar)?;
-
-        // figure out how many words we can write before the
-        // data overflows
-
-        // maximum chunk size
-        let max_chunk_size_bytes = 0x400_usize;
-
-        let mut remaining_data_len = data.len();
-
-        let first_chunk_size_bytes = std::cmp::min(
-            max_chunk_size_bytes - (address as usize % max_chunk_size_bytes),
-            data.len() * 4,
-        );
-
         let mut data_offset = 0;
 
-        log::debug!(
-            "Write first block with len {} at address {:#08x}",
-            first_chunk_size_bytes,
-            address
-        );
-
-        let first_chunk_size_words = first_chunk_size_bytes / 4;
-
-        self.write_ap_register_repeated(
-            debug_port,
-            DRW { data: 0 },
-            &data[data_offset..first_chunk_size_words],
-        )?;
+        while data_offset < data.len() {
+            let address = start_address + (4 * data_offset) as u32;
 
-        remaining_data_len -= first_chunk_size_words;
-        address += (4 * first_chunk_size_words) as u32;
-        data_offset += first_chunk_size_words;
-
-        while remaining_data_len > 0 {
             // the autoincrement is limited to the 10 lowest bits so we need to write the address
-            // every time it overflows
+            // every time it overflows, and the probe might limit the size of a single transfer
             let tar = TAR { address };
             self.write_ap_register(debug_port, tar)?;
 
-            let next_chunk_size_bytes = std::cmp::min(max_chunk_size_bytes, remaining_data_len * 4);
+            let chunk_size_words = self.chunk_size_words(address, data.len() - data_offset);
 
             log::debug!(
                 "Writing chunk with len {} at address {:#08x}",
-                next_chunk_size_bytes,
+                chunk_size_words * 4,
                 address
             );
 
-            let next_chunk_size_words = next_chunk_size_bytes / 4;
-
             self.write_ap_register_repeated(
                 debug_port,
                 DRW { data: 0 },
-                &data[data_offset..(data_offset + next_chunk_size_words)],
+                &data[data_offset..(data_offset + chunk_size_words)],
             )?;
 
-            remaining_data_len -= next_chunk_size_words;
-            address += (4 * next_chunk_size_words) as u32;
-            data_offset += next_chunk_size_words;
+            data_offset += chunk_size_words;
         }
 
         log::debug!("Finished writing block");
@@ -531,35 +549,39 @@ impl ADIMemoryInterface {
     where
         AP: APAccess<MemoryAP, CSW> + APAccess<MemoryAP, TAR> + APAccess<MemoryAP, DRW>,
     {
-        let pre_bytes = ((4 - (address % 4)) % 4) as usize;
-        let pre_address = address / 4;
+        let offset = (address % 4) as usize;
+        // The bytes up to the next word boundary, which might be all of the data.
+        let pre_bytes = ((4 - offset) % 4).min(data.len());
+        let pre_address = address - offset as u32;
         let aligned_address = address + pre_bytes as u32;
         let post_bytes = (data.len() - pre_bytes) % 4;
         let post_address = address + (data.len() - post_bytes) as u32;
 
         if pre_bytes != 0 {
             let mut pre_data = self.read32(debug_port, pre_address)?;
-            for (i, shift) in (4 - pre_bytes..4).enumerate() {
+            for (i, byte) in data[..pre_bytes].iter().enumerate() {
+                let shift = offset + i;
                 pre_data &= !(0xFF << (shift * 8));
-                pre_data |= u32::from(data[i]) << (shift * 8);
+                pre_data |= u32::from(*byte) << (shift * 8);
             }
 
             self.write32(debug_port, pre_address, pre_data)?;
         }
 
-        self.write_block32(
-            debug_port,
-            aligned_address,
-            data[pre_bytes..data.len() - post_bytes]
-                .chunks(4)
-                .map(|c| c.pread::<u32>(0).expect("This is a bug. Please report it."))
-                .collect::<Vec<_>>()
-                .as_slice(),
-        )?;
+        if data.len() - pre_bytes - post_bytes != 0 {
+            self.write_block32(
+                debug_port,
+                aligned_address,
+                data[pre_bytes..data.len() - post_bytes]
+                    .chunks(4)
+                    .map(|c| c.pread::<u32>(0).expect("This is a bug. Please report it."))
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+            )?;
+        }
 
         if post_bytes != 0 {
             let mut post_data = self.read32(debug_port, post_address)?;
-            dbg!(post_bytes);
             for shift in 0..post_bytes {
                 post_data &= !(0xFF << (shift * 8));
                 post_data |= u32::from(data[data.len() - post_bytes + shift]) << (shift * 8);
@@ -575,7 +597,8 @@ impl ADIMemoryInterface {
 #[cfg(test)]
 mod tests {
     use super::ADIMemoryInterface;
-    use crate::coresight::access_ports::memory_ap::mock::MockMemoryAP;
+    use crate::coresight::access_ports::memory_ap::{mock::MockMemoryAP, DataSize, MemoryAP, CSW};
+    use crate::coresight::ap_access::APAccess;
 
     #[test]
     fn read_u32() {
@@ -625,6 +648,64 @@ mod tests {
         debug_assert_eq!(read4.unwrap(), 0xDE);
     }
 
+    #[test]
+    fn read_u8_with_8bit_access() {
+        let mut mock = MockMemoryAP::default();
+        mock.data[0..4].copy_from_slice(&[0xEF, 0xBE, 0xAD, 0xDE]);
+        let mut mi = ADIMemoryInterface::new(0x0).with_byte_access(true);
+        debug_assert!(mi.detect_data_sizes(&mut mock).is_ok());
+        debug_assert!(!mi.only_32bit_data_size());
+        for (address, byte) in [0xEF, 0xBE, 0xAD, 0xDE].iter().enumerate() {
+            debug_assert_eq!(mi.read8(&mut mock, address as u32).unwrap(), *byte);
+        }
+    }
+
+    #[test]
+    fn write_u8_on_ap_with_only_32bit_access() {
+        let mut mock = MockMemoryAP::default();
+        mock.only_32bit_data_size = true;
+        let mut mi = ADIMemoryInterface::new(0x0)
+            .with_only_32bit_data_size(false)
+            .with_byte_access(true);
+        debug_assert!(mi.detect_data_sizes(&mut mock).is_ok());
+        debug_assert!(mi.only_32bit_data_size());
+        debug_assert!(mi.write8(&mut mock, 1, 0xBE).is_ok());
+        debug_assert!(mi.write8(&mut mock, 3, 0xDE).is_ok());
+        debug_assert_eq!(mock.data[0..4], [0x00, 0xBE, 0x00, 0xDE]);
+        debug_assert_eq!(mi.read8(&mut mock, 3).unwrap(), 0xDE);
+    }
+
+    #[test]
+    fn write_u8_with_8bit_access() {
+        let mut mock = MockMemoryAP::default();
+        mock.data[0..4].copy_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        let mi = ADIMemoryInterface::new(0x0)
+            .with_only_32bit_data_size(false)
+            .with_byte_access(true);
+        debug_assert!(mi.write8(&mut mock, 2, 0xAD).is_ok());
+        debug_assert_eq!(mock.data[0..4], [0x11, 0x22, 0xAD, 0x44]);
+        let csw: CSW = mock
+            .read_ap_register(MemoryAP::new(0), CSW::default())
+            .unwrap();
+        debug_assert_eq!(csw.SIZE, DataSize::U8);
+    }
+
+    #[test]
+    fn u8_accesses_use_words_without_byte_access() {
+        let mut mock = MockMemoryAP::default();
+        mock.data[0..4].copy_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        let mut mi = ADIMemoryInterface::new(0x0);
+        debug_assert!(mi.detect_data_sizes(&mut mock).is_ok());
+        debug_assert!(!mi.only_32bit_data_size());
+        debug_assert!(mi.write8(&mut mock, 2, 0xAD).is_ok());
+        debug_assert_eq!(mi.read8(&mut mock, 1).unwrap(), 0x22);
+        debug_assert_eq!(mock.data[0..4], [0x11, 0x22, 0xAD, 0x44]);
+        let csw: CSW = mock
+            .read_ap_register(MemoryAP::new(0), CSW::default())
+            .unwrap();
+        debug_assert_eq!(csw.SIZE, DataSize::U32);
+    }
+
     #[test]
     fn write_u32() {
         let mut mock = MockMemoryAP::default();
@@ -672,6 +753,56 @@ mod tests {
         debug_assert_eq!(data, [0xDEAD_BEEF, 0xABBA_BABE]);
     }
 
+    #[test]
+    fn read_block_u32_across_1k_boundary() {
+        let mut mock = MockMemoryAP::default();
+        mock.data = (0..0xC00).map(|i| (i / 4) as u8).collect();
+        let mi = ADIMemoryInterface::new(0x0);
+
+        // Start shortly before the first boundary and span the next one completely.
+        let mut data = [0u32; 0x180];
+        let read = mi.read_block32(&mut mock, 0x3F0, &mut data);
+        debug_assert!(read.is_ok());
+        for (i, word) in data.iter().enumerate() {
+            let byte = ((0x3F0 / 4 + i) & 0xFF) as u32;
+            debug_assert_eq!(*word, byte * 0x0101_0101, "word {}", i);
+        }
+    }
+
+    #[test]
+    fn read_block_u32_with_small_max_transfer_size() {
+        let mut mock = MockMemoryAP::default();
+        mock.data = (0..0x800).map(|i| (i / 4) as u8).collect();
+        // Not a multiple of 4, so it has to be rounded down to 12 bytes.
+        let mi = ADIMemoryInterface::new(0x0).with_max_transfer_size(14);
+
+        let mut data = [0u32; 0x20];
+        let read = mi.read_block32(&mut mock, 0x3F8, &mut data);
+        debug_assert!(read.is_ok());
+        for (i, word) in data.iter().enumerate() {
+            let byte = ((0x3F8 / 4 + i) & 0xFF) as u32;
+            debug_assert_eq!(*word, byte * 0x0101_0101, "word {}", i);
+        }
+    }
+
+    #[test]
+    fn write_block_u32_across_1k_boundary() {
+        let mut mock = MockMemoryAP::default();
+        mock.data = vec![0; 0xC00];
+        let mi = ADIMemoryInterface::new(0x0);
+
+        let data: Vec<u32> = (0..0x180).collect();
+        let write = mi.write_block32(&mut mock, 0x3F0, &data);
+        debug_assert!(write.is_ok());
+        for (i, word) in data.iter().enumerate() {
+            let address = 0x3F0 + 4 * i;
+            debug_assert_eq!(mock.data[address], *word as u8, "word {}", i);
+            debug_assert_eq!(mock.data[address + 1], (*word >> 8) as u8, "word {}", i);
+        }
+        // Nothing is written below the start address, as would happen if the TAR wrapped.
+        debug_assert!(mock.data[..0x3F0].iter().all(|b| *b == 0));
+    }
+
     #[test]
     fn read_block_u32_only_1_word() {
         let mut mock = MockMemoryAP::default();
@@ -900,6 +1031,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn write_block_u8_within_word() {
+        let mut mock = MockMemoryAP::default();
+        mock.data[4..8].copy_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        let mi = ADIMemoryInterface::new(0x0);
+        debug_assert!(mi
+            .write_block8(&mut mock, 5, &([0xAD, 0xDE] as [u8; 2]))
+            .is_ok());
+        debug_assert_eq!(
+            mock.data[0..8],
+            [0x00, 0x00, 0x00, 0x00, 0x11, 0xAD, 0xDE, 0x44]
+        );
+    }
+
     #[test]
     fn write_block_u8_unaligned2() {
         let mut mock = MockMemoryAP::default();
@@ -0,0 +1,86 @@
+use super::MI;
+use crate::coresight::access_ports::AccessPortError;
+use std::collections::HashMap;
+
+/// Target memory for tests of code which accesses the target through `MI`.
+///
+/// Every address can be read and written. Bytes which were never written read as the fill value,
+/// which is zero by default.
+#[derive(Debug, Default)]
+pub(crate) struct MockMemory {
+    bytes: HashMap<u32, u8>,
+    fill: u8,
+}
+
+impl MockMemory {
+    /// Creates memory whose unwritten bytes read as `fill`, e.g. `0xFF` for erased flash.
+    pub fn filled_with(fill: u8) -> Self {
+        Self {
+            bytes: HashMap::new(),
+            fill,
+        }
+    }
+
+    fn check_alignment(address: u32) -> Result<(), AccessPortError> {
+        if address & 0x3 == 0 {
+            Ok(())
+        } else {
+            Err(AccessPortError::MemoryNotAligned)
+        }
+    }
+}
+
+impl MI for MockMemory {
+    fn read32(&mut self, address: u32) -> Result<u32, AccessPortError> {
+        let mut data = [0u32; 1];
+        self.read_block32(address, &mut data)?;
+        Ok(data[0])
+    }
+
+    fn read8(&mut self, address: u32) -> Result<u8, AccessPortError> {
+        Ok(*self.bytes.get(&address).unwrap_or(&self.fill))
+    }
+
+    fn read_block32(&mut self, address: u32, data: &mut [u32]) -> Result<(), AccessPortError> {
+        Self::check_alignment(address)?;
+
+        for (i, word) in data.iter_mut().enumerate() {
+            let mut bytes = [0u8; 4];
+            self.read_block8(address + 4 * i as u32, &mut bytes)?;
+            *word = u32::from_le_bytes(bytes);
+        }
+        Ok(())
+    }
+
+    fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), AccessPortError> {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.read8(address + i as u32)?;
+        }
+        Ok(())
+    }
+
+    fn write32(&mut self, address: u32, data: u32) -> Result<(), AccessPortError> {
+        self.write_block32(address, &[data])
+    }
+
+    fn write8(&mut self, address: u32, data: u8) -> Result<(), AccessPortError> {
+        self.bytes.insert(address, data);
+        Ok(())
+    }
+
+    fn write_block32(&mut self, address: u32, data: &[u32]) -> Result<(), AccessPortError> {
+        Self::check_alignment(address)?;
+
+        for (i, word) in data.iter().enumerate() {
+            self.write_block8(address + 4 * i as u32, &word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_block8(&mut self, address: u32, data: &[u8]) -> Result<(), AccessPortError> {
+        for (i, byte) in data.iter().enumerate() {
+            self.write8(address + i as u32, *byte)?;
+        }
+        Ok(())
+    }
+}
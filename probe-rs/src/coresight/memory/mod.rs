@@ -1,4 +1,6 @@
 pub mod adi_v5_memory_interface;
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod romtable;
 
 use crate::coresight::access_ports::AccessPortError;
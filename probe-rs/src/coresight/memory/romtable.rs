@@ -226,6 +226,8 @@ pub struct CSComponentId {
     base_address: u64,
     class: CSComponentClass,
     pub peripheral_id: PeripheralID,
+    /// The architecture of the component, if it is a class 0x9 component which has a DEVARCH register.
+    pub device_architecture: Option<DeviceArchitecture>,
 }
 
 /// A reader to extract infromation from a CoreSight component table.
@@ -297,12 +299,41 @@ impl<'p, P: MI> ComponentInformationReader<'p, P> {
         Ok(PeripheralID::from_raw(&data))
     }
 
+    /// Reads the DEVARCH and DEVTYPE registers of a class 0x9 component.
+    ///
+    /// Returns `None` if the DEVARCH register is not present.
+    pub fn device_architecture(&mut self) -> Result<Option<DeviceArchitecture>, RomTableError> {
+        let mut probe = self.probe.borrow_mut();
+
+        let devarch = probe.read32(self.base_address as u32 + 0xFBC)?;
+        debug!("DEVARCH: {:08x}", devarch);
+
+        if devarch & (1 << 20) == 0 {
+            return Ok(None);
+        }
+
+        let devtype = probe.read32(self.base_address as u32 + 0xFCC)?;
+        debug!("DEVTYPE: {:08x}", devtype);
+
+        Ok(Some(DeviceArchitecture::from_raw(devarch, devtype)))
+    }
+
     /// Reads all component properties from a component info table
     pub fn read_all(&mut self) -> Result<CSComponentId, RomTableError> {
+        let class = self.component_class()?;
+        let peripheral_id = self.peripheral_id()?;
+
+        // Only class 0x9 components have the DEVARCH and DEVTYPE registers.
+        let device_architecture = match class {
+            CSComponentClass::CoreSightComponent => self.device_architecture()?,
+            _ => None,
+        };
+
         Ok(CSComponentId {
             base_address: self.base_address,
-            class: self.component_class()?,
-            peripheral_id: self.peripheral_id()?,
+            class,
+            peripheral_id,
+            device_architecture,
         })
     }
 }
@@ -459,3 +490,89 @@ impl PeripheralID {
         }
     }
 }
+
+/// The architecture of a CoreSight component, from its DEVARCH and DEVTYPE registers.
+///
+/// Newer components, e.g. those of ARMv8-M, are identified precisely by this instead of their part number.
+/// Described in section B2.3 of the CoreSight Architecture Specification v3.0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceArchitecture {
+    /// The designer of the architecture, which is not necessarily the designer of the component.
+    pub architect: jep106::JEP106Code,
+    /// The revision of the architecture.
+    pub revision: u8,
+    /// The architecture ID, which is unique for each architecture of the `architect`.
+    pub arch_id: u16,
+    /// The major type of the component, e.g. 3 for a trace source.
+    pub major_type: u8,
+    /// The sub type of the component, its meaning depends on `major_type`.
+    pub sub_type: u8,
+}
+
+impl DeviceArchitecture {
+    /// The JEP106 code of ARM, the architect of all the architectures named by `name`.
+    const ARM: jep106::JEP106Code = jep106::JEP106Code { cc: 0x04, id: 0x3B };
+
+    /// Extracts the architecture from the values of the DEVARCH and DEVTYPE registers.
+    fn from_raw(devarch: u32, devtype: u32) -> Self {
+        DeviceArchitecture {
+            architect: jep106::JEP106Code::new(
+                ((devarch >> 28) & 0x0F) as u8,
+                ((devarch >> 21) & 0x7F) as u8,
+            ),
+            revision: ((devarch >> 16) & 0x0F) as u8,
+            arch_id: (devarch & 0xFFFF) as u16,
+            major_type: (devtype & 0x0F) as u8,
+            sub_type: ((devtype >> 4) & 0x0F) as u8,
+        }
+    }
+
+    /// Returns the name of the architecture, if it is one of the known ARM architectures.
+    pub fn name(&self) -> Option<&'static str> {
+        if self.architect != Self::ARM {
+            return None;
+        }
+
+        match self.arch_id {
+            0x1A01 => Some("ITM"),
+            0x1A02 => Some("DWT"),
+            0x1A03 => Some("FPB"),
+            0x1A14 => Some("CTI"),
+            0x2A04 => Some("ARMv8-M debug"),
+            0x4A13 => Some("ETMv4"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DeviceArchitecture;
+
+    #[test]
+    fn parse_device_architecture() {
+        // The DEVARCH and DEVTYPE of the debug control block of an ARMv8-M core.
+        let architecture = DeviceArchitecture::from_raw(0x4770_2A04, 0x0000_0000);
+
+        assert_eq!(architecture.architect.get(), Some("ARM Ltd"));
+        assert_eq!(architecture.revision, 0);
+        assert_eq!(architecture.arch_id, 0x2A04);
+        assert_eq!(architecture.name(), Some("ARMv8-M debug"));
+
+        // The ITM, a trace source of the stimulus type.
+        let architecture = DeviceArchitecture::from_raw(0x4770_1A01, 0x0000_0043);
+
+        assert_eq!(architecture.name(), Some("ITM"));
+        assert_eq!(architecture.major_type, 3);
+        assert_eq!(architecture.sub_type, 4);
+    }
+
+    #[test]
+    fn unknown_architect_has_no_name() {
+        // A vendor architecture which reuses an ARM architecture ID.
+        let architecture = DeviceArchitecture::from_raw(0x0030_1A01, 0);
+
+        assert_eq!(architecture.arch_id, 0x1A01);
+        assert_eq!(architecture.name(), None);
+    }
+}
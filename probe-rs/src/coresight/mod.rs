@@ -4,3 +4,4 @@ pub mod common;
 pub mod debug_port;
 pub mod dp_access;
 pub mod memory;
+pub mod tpiu;
@@ -0,0 +1,112 @@
+//! Configuration of the Trace Port Interface Unit (TPIU) of ARMv7-M and ARMv8-M cores.
+//!
+//! The TPIU serializes the trace data of the ITM, DWT and ETM either onto the
+//! single SWO pin or onto a parallel trace port of up to four data pins.
+//! Capturing the data is up to the probe, see `MasterProbe::start_trace`.
+
+use crate::coresight::{access_ports::AccessPortError, memory::MI};
+
+/// Debug Exception and Monitor Control Register.
+const DEMCR: u32 = 0xE000_EDFC;
+/// Enables the DWT, ITM, ETM and TPIU.
+const DEMCR_TRCENA: u32 = 1 << 24;
+
+/// Current Parallel Port Size Register.
+const TPIU_CSPSR: u32 = 0xE004_0004;
+/// Asynchronous Clock Prescaler Register.
+const TPIU_ACPR: u32 = 0xE004_0010;
+/// Selected Pin Protocol Register.
+const TPIU_SPPR: u32 = 0xE004_00F0;
+/// Formatter and Flush Control Register.
+const TPIU_FFCR: u32 = 0xE004_0304;
+
+/// Enables continuous formatting of the trace data.
+const FFCR_ENFCONT: u32 = 1 << 1;
+/// Indicates a trigger on a trigger event. This is the reset value of the register.
+const FFCR_TRIGIN: u32 = 1 << 8;
+
+/// The number of data pins of a parallel trace port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TracePortWidth {
+    One = 1,
+    Two = 2,
+    Three = 3,
+    Four = 4,
+}
+
+/// The way the TPIU outputs the trace data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceProtocol {
+    /// Synchronous output on the TRACECLK and TRACEDATA pins.
+    ///
+    /// The trace data is wrapped by the formatter, such that sources can be told apart.
+    Parallel(TracePortWidth),
+    /// Asynchronous output on the SWO pin using Manchester encoding.
+    SwoManchester,
+    /// Asynchronous output on the SWO pin using NRZ (UART) encoding.
+    SwoNrz,
+}
+
+/// The configuration of a trace capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceConfig {
+    /// The protocol used to output the trace data.
+    pub protocol: TraceProtocol,
+    /// The frequency of the trace clock input of the TPIU in Hz, which is usually the core clock.
+    pub trace_clock_hz: u32,
+    /// The bit rate on the SWO pin in Hz. This is ignored for parallel trace.
+    pub swo_baud_rate: u32,
+}
+
+impl TraceConfig {
+    /// Returns the value of the asynchronous clock prescaler for the configured SWO baud rate.
+    fn prescaler(&self) -> u32 {
+        (self.trace_clock_hz / self.swo_baud_rate.max(1)).max(1) - 1
+    }
+}
+
+/// Enables the trace components and configures the TPIU for the given protocol.
+///
+/// This only configures the output of the target.
+/// The trace sources, e.g. the ITM stimulus ports or the ETM, have to be enabled separately.
+pub fn configure_tpiu(mi: &mut impl MI, config: &TraceConfig) -> Result<(), AccessPortError> {
+    let demcr = mi.read32(DEMCR)?;
+    mi.write32(DEMCR, demcr | DEMCR_TRCENA)?;
+
+    match config.protocol {
+        TraceProtocol::Parallel(width) => {
+            mi.write32(TPIU_CSPSR, 1 << (width as u32 - 1))?;
+            mi.write32(TPIU_SPPR, 0)?;
+            mi.write32(TPIU_FFCR, FFCR_TRIGIN | FFCR_ENFCONT)?;
+        }
+        TraceProtocol::SwoManchester | TraceProtocol::SwoNrz => {
+            let protocol = if config.protocol == TraceProtocol::SwoManchester {
+                1
+            } else {
+                2
+            };
+            mi.write32(TPIU_ACPR, config.prescaler())?;
+            mi.write32(TPIU_SPPR, protocol)?;
+            // The formatter is bypassed, such that the ITM packets are output directly.
+            mi.write32(TPIU_FFCR, FFCR_TRIGIN)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TraceConfig, TraceProtocol};
+
+    #[test]
+    fn swo_prescaler() {
+        let config = TraceConfig {
+            protocol: TraceProtocol::SwoNrz,
+            trace_clock_hz: 72_000_000,
+            swo_baud_rate: 2_000_000,
+        };
+
+        assert_eq!(config.prescaler(), 35);
+    }
+}
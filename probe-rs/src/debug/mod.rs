@@ -5,7 +5,7 @@ pub use typ::*;
 pub use variable::*;
 
 use std::borrow;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use crate::coresight::memory::MI;
@@ -295,7 +295,9 @@ impl DebugInfo {
         }
     }
 
-    fn get_source_location(&self, address: u64) -> Option<SourceLocation> {
+    /// Returns the source location of the instruction at `address`, if it is described by the
+    /// line programs of the debug information.
+    pub fn get_source_location(&self, address: u64) -> Option<SourceLocation> {
         let mut units = self.dwarf.units();
 
         while let Some(header) = units.next().unwrap() {
@@ -390,6 +392,60 @@ impl DebugInfo {
         None
     }
 
+    /// Returns the address of the first statement generated for `line` of the source file at `path`.
+    ///
+    /// The file names of the line programs are matched against the end of `path`,
+    /// so `path` can be absolute while the debug information only contains relative paths.
+    pub fn get_breakpoint_location(&self, path: &Path, line: u64) -> Option<u64> {
+        let mut units = self.dwarf.units();
+
+        let mut location: Option<u64> = None;
+
+        while let Ok(Some(header)) = units.next() {
+            let unit = match self.dwarf.unit(header) {
+                Ok(unit) => unit,
+                Err(_) => continue,
+            };
+
+            let ilnp = match unit.line_program.as_ref() {
+                Some(ilnp) => ilnp,
+                None => continue,
+            };
+
+            let mut rows = ilnp.clone().rows();
+
+            while let Ok(Some((header, row))) = rows.next_row() {
+                if row.end_sequence() || !row.is_stmt() || row.line() != Some(line) {
+                    continue;
+                }
+
+                let file = match row.file(header) {
+                    Some(file) => file,
+                    None => continue,
+                };
+
+                let file_name = match self.dwarf.attr_string(&unit, file.path_name()) {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+
+                let file_name = match std::str::from_utf8(&file_name) {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+
+                if path.ends_with(file_name) {
+                    location = Some(match location {
+                        Some(address) => address.min(row.address()),
+                        None => row.address(),
+                    });
+                }
+            }
+        }
+
+        location
+    }
+
     fn get_units(&self) -> UnitIter {
         self.dwarf.units()
     }
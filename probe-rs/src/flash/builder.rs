@@ -1,6 +1,13 @@
-use super::flasher::{Flasher, FlasherError};
+use super::flasher::{ActiveFlasher, Flasher, FlasherError, Program};
+use super::manifest::{hash_sector, FlashManifest};
+use super::session::FlashSession;
 use super::FlashProgress;
-use crate::config::memory::{PageInfo, SectorInfo};
+use crate::config::memory::{FlashRegion, MemoryRange, PageInfo, SectorInfo};
+use crate::coresight::memory::MI;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 
 /// A struct to hold all the information about one page of flash.
 #[derive(Derivative, Clone)]
@@ -95,11 +102,61 @@ impl<'a> FlashWriteData<'a> {
     }
 }
 
+/// The order in which sectors are erased and programmed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EraseStrategy {
+    /// Erase all sectors first, then program all pages.
+    AllThenProgram,
+    /// Erase each sector right before programming its pages, one sector after the other.
+    ///
+    /// This avoids a long erase phase before programming starts, but switches the flash
+    /// algorithm between erasing and programming for every sector.
+    Interleaved,
+}
+
+/// Erases and programs single sectors and pages of a flash region, for `EraseStrategy::Interleaved`.
+///
+/// Implemented by `FlashSession`, which keeps the flash algorithm loaded for all sectors
+/// and only runs its init routine again when switching between erasing and programming.
+pub(crate) trait SectorFlash {
+    fn region(&self) -> &FlashRegion;
+    fn erase_sector(&mut self, address: u32) -> Result<(), FlasherError>;
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), FlasherError>;
+    fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), FlasherError>;
+}
+
+impl SectorFlash for FlashSession<'_> {
+    fn region(&self) -> &FlashRegion {
+        FlashSession::region(self)
+    }
+
+    fn erase_sector(&mut self, address: u32) -> Result<(), FlasherError> {
+        FlashSession::erase_sector(self, address)
+    }
+
+    fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), FlasherError> {
+        FlashSession::program_page(self, address, data)
+    }
+
+    fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), FlasherError> {
+        FlashSession::read_block8(self, address, data)
+    }
+}
+
 #[derive(Default)]
 pub struct FlashBuilder<'a> {
     flash_write_data: Vec<FlashWriteData<'a>>,
     buffered_data_size: usize,
     enable_double_buffering: bool,
+    /// The number of sectors which were completely programmed by an interrupted `program` call.
+    /// The next call resumes after them.
+    completed_sectors: Cell<usize>,
+    /// The manifest of the last flash operation, sectors which did not change since are skipped.
+    previous_manifest: Option<FlashManifest>,
+    /// The hashes of all sectors of the last `program` call.
+    manifest: RefCell<FlashManifest>,
+    /// Whether sectors are read before erasing them, to skip the ones which are blank already.
+    blank_check: bool,
 }
 
 #[derive(Debug)]
@@ -111,9 +168,64 @@ pub enum FlashBuilderError {
     PageSizeDoesNotMatch(u32, u32), // The flash sector size is not a multiple of the flash page size.
     MaxPageCountExceeded(usize),
     ProgramPage(u32, u32),
+    PageRetriesExhausted(u32, FlasherError), // Contains faulty address and the last error.
     Flasher(FlasherError),
 }
 
+impl Error for FlashBuilderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use FlashBuilderError::*;
+
+        match self {
+            PageRetriesExhausted(_, ref e) => Some(e),
+            Flasher(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FlashBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use FlashBuilderError::*;
+
+        match self {
+            AddressBeforeFlashStart(addr) => write!(
+                f,
+                "Address {:#010x} is located before the start of the flash.",
+                addr
+            ),
+            DataOverlap(addr) => write!(
+                f,
+                "The data at address {:#010x} overlaps with other data.",
+                addr
+            ),
+            InvalidFlashAddress(addr) => {
+                write!(f, "Address {:#010x} is not a valid flash address.", addr)
+            }
+            DuplicateDataEntry(addr) => write!(
+                f,
+                "There are multiple chunks of data for address {:#010x}.",
+                addr
+            ),
+            PageSizeDoesNotMatch(page_size, sector_page_size) => write!(
+                f,
+                "The page size {} does not match the page size {} of the sector.",
+                page_size, sector_page_size
+            ),
+            MaxPageCountExceeded(count) => write!(f, "A sector can hold at most {} pages.", count),
+            ProgramPage(addr, _) => {
+                write!(f, "Programming the page at address {:#010x} failed.", addr)
+            }
+            PageRetriesExhausted(addr, e) => write!(
+                f,
+                "Programming the page at address {:#010x} failed after all retries: {}",
+                addr, e
+            ),
+            Flasher(ref e) => e.fmt(f),
+        }
+    }
+}
+
 impl From<FlasherError> for FlashBuilderError {
     fn from(error: FlasherError) -> Self {
         FlashBuilderError::Flasher(error)
@@ -129,14 +241,53 @@ impl<'a> FlashBuilder<'a> {
             flash_write_data: vec![],
             buffered_data_size: 0,
             enable_double_buffering: false,
+            completed_sectors: Cell::new(0),
+            previous_manifest: None,
+            manifest: RefCell::new(FlashManifest::new()),
+            blank_check: false,
         }
     }
 
+    /// Reads each sector before erasing it and skips the erase if the sector is blank already,
+    /// i.e. if all of its bytes have the erased value of the flash region.
+    ///
+    /// This saves time on a freshly erased chip, but costs a read of every sector otherwise.
+    /// Chip erase is not affected.
+    pub fn set_blank_check(&mut self, blank_check: bool) {
+        self.blank_check = blank_check;
+    }
+
+    /// Makes the next `program` call start at the first sector again,
+    /// instead of resuming after the sectors which an interrupted call completed.
+    ///
+    /// Call this when a failed `program` call is not going to be retried.
+    pub fn forget_completed_sectors(&self) {
+        self.completed_sectors.set(0);
+    }
+
+    /// Skips all sectors whose contents match `manifest` when programming.
+    pub fn set_previous_manifest(&mut self, manifest: FlashManifest) {
+        self.previous_manifest = Some(manifest);
+    }
+
+    /// Returns the hashes of all sectors of the last `program` call, including skipped ones.
+    pub fn manifest(&self) -> FlashManifest {
+        self.manifest.borrow().clone()
+    }
+
     /// Iterate over all pages in an array of `FlashSector`s.
     pub fn pages(sectors: &[FlashSector]) -> Vec<&FlashPage> {
         sectors.iter().map(|s| &s.pages).flatten().collect()
     }
 
+    /// Returns the chunks of data which were added, with their addresses and ordered by address.
+    pub fn data(&self) -> Vec<(u32, &'a [u8])> {
+        self.flash_write_data
+            .iter()
+            .map(|d| (d.address, d.data))
+            .collect()
+    }
+
     /// Add a block of data to be programmed.
     ///
     /// Programming does not start until the `program` method is called.
@@ -161,7 +312,10 @@ impl<'a> FlashBuilder<'a> {
         let mut previous_operation: Option<&FlashWriteData> = None;
         for operation in &self.flash_write_data {
             if let Some(previous) = previous_operation {
-                if previous.address + previous.data.len() as u32 > operation.address {
+                let previous_range =
+                    previous.address..previous.address + previous.data.len() as u32;
+                let range = operation.address..operation.address + operation.data.len() as u32;
+                if previous_range.intersects_range(&range) {
                     return Err(FlashBuilderError::DataOverlap(operation.address));
                 }
             }
@@ -175,11 +329,29 @@ impl<'a> FlashBuilder<'a> {
     /// If `restore_unwritten_bytes` is `true`, all bytes of a sector,
     /// that are not to be written during flashing will be read from the flash first
     /// and written again once the sector is erased.
+    /// Otherwise the unwritten bytes of partially written pages are set to `pad_value`,
+    /// or to the erased byte value of the region if it is `None`.
+    ///
+    /// If programming a page fails, its sector is erased and reprogrammed up to `page_retries` times.
+    ///
+    /// If a previous call failed, programming resumes after the last sector which was
+    /// completely programmed, unless `forget_completed_sectors` was called since.
+    /// In that case chip erase is never used.
+    ///
+    /// With `EraseStrategy::Interleaved`, each sector is erased right before it is programmed.
+    /// This is not possible with chip erase, which always erases everything first.
+    ///
+    /// If a previous manifest was set, sectors which did not change are neither erased nor programmed,
+    /// and chip erase is not used when any sector is skipped.
+    #[allow(clippy::too_many_arguments)]
     pub fn program(
         &self,
         mut flash: Flasher,
         mut do_chip_erase: bool,
         restore_unwritten_bytes: bool,
+        pad_value: Option<u8>,
+        page_retries: usize,
+        erase_strategy: EraseStrategy,
         progress: &FlashProgress,
     ) -> Result<(), FlashBuilderError> {
         if self.flash_write_data.is_empty() {
@@ -190,7 +362,25 @@ impl<'a> FlashBuilder<'a> {
         let mut sectors = vec![];
 
         // Convert the list of flash operations into flash sectors and pages.
-        self.build_sectors_and_pages(&mut flash, &mut sectors, restore_unwritten_bytes)?;
+        let region = flash.region().clone();
+        let pad_value = Self::pad_value(&region, pad_value);
+        self.build_sectors_and_pages(&region, &mut sectors, |page, len| {
+            Self::fill_page(&mut flash, page, len, restore_unwritten_bytes, pad_value)
+        })?;
+
+        // Skip the sectors which are unchanged since the last flash operation.
+        self.skip_unchanged_sectors(&mut sectors, &mut do_chip_erase);
+
+        // Skip the sectors which were programmed by an interrupted previous call.
+        let completed_sectors = self.completed_sectors.get().min(sectors.len());
+        if completed_sectors > 0 {
+            log::info!(
+                "Resuming after {} already programmed sectors.",
+                completed_sectors
+            );
+            sectors.drain(..completed_sectors);
+            do_chip_erase = false;
+        }
 
         let num_pages = sectors.iter().map(|s| s.pages.len()).sum();
         let sizes = sectors.first().map(|s| (s.size, s.page_size));
@@ -214,6 +404,22 @@ impl<'a> FlashBuilder<'a> {
             self.enable_double_buffering
         );
 
+        if erase_strategy == EraseStrategy::Interleaved && !do_chip_erase {
+            progress.started_erasing();
+            progress.started_flashing();
+
+            // The algorithm stays loaded for all sectors, only its init routine runs again
+            // when switching between erasing and programming.
+            let mut session = flash.start_session()?;
+            let result = self.program_interleaved(&mut session, &sectors, page_retries, progress);
+            let finished = session.finish();
+            result?;
+            finished?;
+
+            self.completed_sectors.set(0);
+            return Ok(());
+        }
+
         // Erase all necessary sectors.
         progress.started_erasing();
 
@@ -229,22 +435,72 @@ impl<'a> FlashBuilder<'a> {
         if flash.double_buffering_supported() && self.enable_double_buffering {
             self.program_double_buffer(&mut flash, &sectors, progress)?;
         } else {
-            self.program_simple(&mut flash, &sectors, progress)?;
+            self.program_simple(&mut flash, &sectors, page_retries, progress)?;
         };
 
+        self.completed_sectors.set(0);
+
         Ok(())
     }
 
+    /// Records the hash of every sector and removes the sectors whose hash matches the previous manifest.
+    fn skip_unchanged_sectors(&self, sectors: &mut Vec<FlashSector>, do_chip_erase: &mut bool) {
+        let mut manifest = FlashManifest::new();
+        let mut unchanged = vec![];
+
+        for sector in sectors.iter() {
+            let hash = hash_sector(
+                sector.size,
+                sector.pages.iter().map(|p| (p.address, p.data.as_slice())),
+            );
+            manifest.sectors.insert(sector.address, hash);
+
+            if let Some(previous) = &self.previous_manifest {
+                if previous.contains(sector.address, hash) {
+                    unchanged.push(sector.address);
+                }
+            }
+        }
+
+        *self.manifest.borrow_mut() = manifest;
+
+        if !unchanged.is_empty() {
+            log::info!("Skipping {} unchanged sectors.", unchanged.len());
+            sectors.retain(|s| !unchanged.contains(&s.address));
+            *do_chip_erase = false;
+        }
+    }
+
+    /// Reads back all data of the builder and compares it with the data that would be programmed.
+    ///
+    /// Returns the lowest address which differs, or `None` if all data matches.
+    pub fn verify(&self, mi: &mut impl MI) -> Result<Option<u32>, FlashBuilderError> {
+        for operation in &self.flash_write_data {
+            let mut read_back = vec![0; operation.data.len()];
+            mi.read_block8(operation.address, &mut read_back)
+                .map_err(FlasherError::AccessPort)?;
+
+            if let Some(offset) = read_back
+                .iter()
+                .zip(operation.data.iter())
+                .position(|(read, expected)| read != expected)
+            {
+                return Ok(Some(operation.address + offset as u32));
+            }
+        }
+
+        Ok(None)
+    }
+
     /// Layouts an entire flash memory.
     ///
-    /// If `restore_unwritten_bytes` is `true`, all bytes of a sector,
-    /// that are not to be written during flashing will be read from the flash first
-    /// and written again once the sector is erased.
+    /// `fill_page` is called whenever bytes of a page are not covered by any data.
+    /// It has to fill the page data up to the given length.
     fn build_sectors_and_pages(
         &self,
-        flash: &mut Flasher,
+        region: &FlashRegion,
         sectors: &mut Vec<FlashSector>,
-        restore_unwritten_bytes: bool,
+        mut fill_page: impl FnMut(&mut FlashPage, usize) -> Result<(), FlashBuilderError>,
     ) -> Result<(), FlashBuilderError> {
         for op in &self.flash_write_data {
             let mut pos = 0;
@@ -254,7 +510,7 @@ impl<'a> FlashBuilder<'a> {
                 if let Some(sector) = sectors.last_mut() {
                     // If the address is not in the sector, add a new sector.
                     if flash_address >= sector.address + sector.size {
-                        let sector_info = flash.region().sector_info(flash_address);
+                        let sector_info = region.sector_info(flash_address);
                         if let Some(sector_info) = sector_info {
                             let new_sector = FlashSector::new(&sector_info);
                             sectors.push(new_sector);
@@ -271,9 +527,9 @@ impl<'a> FlashBuilder<'a> {
                         // If the current page does not contain the address.
                         if flash_address >= page.address + page.size {
                             // Fill any gap at the end of the current page before switching to a new page.
-                            Self::fill_page(flash, page, restore_unwritten_bytes)?;
+                            fill_page(page, page.size as usize)?;
 
-                            let page_info = flash.region().page_info(flash_address);
+                            let page_info = region.page_info(flash_address);
                             if let Some(page_info) = page_info {
                                 let new_page = FlashPage::new(&page_info);
                                 sector.add_page(new_page)?;
@@ -287,6 +543,12 @@ impl<'a> FlashBuilder<'a> {
                             }
                             continue;
                         } else {
+                            // Fill any gap between the previous data and this data.
+                            let offset_in_page = (flash_address - page.address) as usize;
+                            if offset_in_page > page.data.len() {
+                                fill_page(page, offset_in_page)?;
+                            }
+
                             let space_left_in_page = page.size - page.data.len() as u32;
                             let space_left_in_data = op.data.len() - pos;
                             let amount =
@@ -298,7 +560,7 @@ impl<'a> FlashBuilder<'a> {
                         }
                     } else {
                         // If no page is on the sector yet.
-                        let page_info = flash.region().page_info(flash_address);
+                        let page_info = region.page_info(flash_address);
                         if let Some(page_info) = page_info {
                             let new_page = FlashPage::new(&page_info);
                             sector.add_page(new_page.clone())?;
@@ -314,7 +576,7 @@ impl<'a> FlashBuilder<'a> {
                     }
                 } else {
                     // If no sector exists, create a new one.
-                    let sector_info = flash.region().sector_info(flash_address);
+                    let sector_info = region.sector_info(flash_address);
                     if let Some(sector_info) = sector_info {
                         let new_sector = FlashSector::new(&sector_info);
                         sectors.push(new_sector);
@@ -334,7 +596,7 @@ impl<'a> FlashBuilder<'a> {
         // Fill the page gap if there is one.
         if let Some(sector) = sectors.last_mut() {
             if let Some(page) = sector.pages.last_mut() {
-                Self::fill_page(flash, page, restore_unwritten_bytes)?;
+                fill_page(page, page.size as usize)?;
             }
         }
 
@@ -346,19 +608,30 @@ impl<'a> FlashBuilder<'a> {
         Ok(())
     }
 
-    /// Fills all the bytes of `current_page`.
+    /// Returns the value which pads the unwritten bytes of pages in `region`.
+    ///
+    /// This is `pad_value` if one is given, and the erased byte value of the region otherwise,
+    /// such that padding does not program bytes which would read as erased.
+    fn pad_value(region: &FlashRegion, pad_value: Option<u8>) -> u8 {
+        pad_value.unwrap_or(region.erased_byte_value)
+    }
+
+    /// Fills the bytes of `current_page` until its data is `len` bytes long.
     ///
     /// If `restore_unwritten_bytes` is `true`, all bytes of the page,
     /// that are not to be written during flashing will be read from the flash first
     /// and written again once the page is programmed.
+    /// Otherwise the bytes are set to `pad_value`.
     fn fill_page(
         flash: &mut Flasher,
         current_page: &mut FlashPage,
+        len: usize,
         restore_unwritten_bytes: bool,
+        pad_value: u8,
     ) -> Result<(), FlashBuilderError> {
-        // The remaining bytes to be filled in at the end of the page.
-        let remaining_bytes = current_page.size as usize - current_page.data.len();
-        if current_page.data.len() != current_page.size as usize {
+        // The remaining bytes to be filled in.
+        let remaining_bytes = len.saturating_sub(current_page.data.len());
+        if remaining_bytes > 0 {
             let address_remaining_start = current_page.address + current_page.data.len() as u32;
 
             // Fill up the page with current page bytes until it's full.
@@ -370,8 +643,8 @@ impl<'a> FlashBuilder<'a> {
                 })?;
                 data
             } else {
-                // Set all the remaining bytes to their default erased value.
-                vec![flash.region().erased_byte_value; remaining_bytes]
+                // Pad all the remaining bytes.
+                vec![pad_value; remaining_bytes]
             };
             current_page.data.extend(old_data);
         }
@@ -397,26 +670,194 @@ impl<'a> FlashBuilder<'a> {
         result
     }
 
-    /// Program all sectors in `sectors` by first performing a chip erase.
+    /// Program all pages in `sectors`.
+    ///
+    /// If `page_retries` is not zero, every page is read back after programming.
+    /// If programming or the read back fails, the enclosing sector is erased again
+    /// and all of its pages are reprogrammed, up to `page_retries` times per page.
     fn program_simple(
         &self,
         flash: &mut Flasher,
         sectors: &[FlashSector],
+        page_retries: usize,
         progress: &FlashProgress,
     ) -> Result<(), FlashBuilderError> {
         let mut t = std::time::Instant::now();
-        let result = flash.run_program(|active| {
-            for page in Self::pages(sectors) {
-                active.program_page(page.address, page.data.as_slice())?;
-                progress.page_programmed(page.size, t.elapsed().as_millis());
-                t = std::time::Instant::now();
+        let mut attempts: HashMap<u32, usize> = HashMap::new();
+        let mut programmed_pages = HashSet::new();
+        let mut first_sector = 0;
+
+        let result = loop {
+            let failure = flash.run_program(|active| {
+                for (index, sector) in sectors.iter().enumerate().skip(first_sector) {
+                    for page in &sector.pages {
+                        if let Err(error) = Self::program_page(active, page, page_retries > 0) {
+                            return Ok(Some((index, page.address, error)));
+                        }
+                        // Pages which are programmed again after a retry were reported already.
+                        if programmed_pages.insert(page.address) {
+                            progress.page_programmed(page.size, t.elapsed().as_millis());
+                        }
+                        t = std::time::Instant::now();
+                    }
+                    self.completed_sectors.set(self.completed_sectors.get() + 1);
+                }
+                Ok::<_, FlashBuilderError>(None)
+            });
+
+            match failure {
+                Ok(None) => break Ok(()),
+                Ok(Some((index, address, error))) => {
+                    let count = attempts.entry(address).or_insert(0);
+                    if *count >= page_retries {
+                        break Err(FlashBuilderError::PageRetriesExhausted(address, error));
+                    }
+                    *count += 1;
+
+                    log::warn!(
+                        "Programming the page at address {:#010x} failed: {}. Erasing the sector and retrying ({}/{}).",
+                        address,
+                        error,
+                        count,
+                        page_retries
+                    );
+
+                    let sector_address = sectors[index].address;
+                    if let Err(error) =
+                        flash.run_erase(|active| active.erase_sector(sector_address))
+                    {
+                        break Err(FlashBuilderError::from(error));
+                    }
+                    first_sector = index;
+                }
+                Err(error) => break Err(error),
             }
-            Ok(())
-        });
+        };
+
         progress.finished_programming();
         result
     }
 
+    /// Erases and programs the sectors in `sectors` one after the other.
+    ///
+    /// Pages are retried like in `program_simple`, but only the current sector
+    /// has to be erased and reprogrammed.
+    fn program_interleaved(
+        &self,
+        flash: &mut impl SectorFlash,
+        sectors: &[FlashSector],
+        page_retries: usize,
+        progress: &FlashProgress,
+    ) -> Result<(), FlashBuilderError> {
+        let mut t = std::time::Instant::now();
+        let mut attempts: HashMap<u32, usize> = HashMap::new();
+        let mut programmed_pages = HashSet::new();
+        let erased_byte_value = flash.region().erased_byte_value;
+
+        for sector in sectors {
+            // A retry has to erase the sector in any case.
+            let mut blank_check = self.blank_check;
+            let mut retry = false;
+
+            while !sector.pages.is_empty() {
+                let skipped = blank_check
+                    && Self::sector_is_blank(sector, erased_byte_value, |address, data| {
+                        flash.read_block8(address, data)
+                    })?;
+                if !skipped {
+                    flash.erase_sector(sector.address)?;
+                }
+                blank_check = false;
+
+                // A sector which is erased again for a retry was reported already.
+                if skipped {
+                    progress.sector_blank_skipped(sector.page_size);
+                } else if !retry {
+                    progress.sector_erased(sector.page_size, t.elapsed().as_millis());
+                }
+                t = std::time::Instant::now();
+
+                let mut failure = None;
+                for page in &sector.pages {
+                    let result = flash
+                        .program_page(page.address, page.data.as_slice())
+                        .and_then(|()| {
+                            if page_retries > 0 {
+                                Self::check_page(page, |address, data| {
+                                    flash.read_block8(address, data)
+                                })
+                            } else {
+                                Ok(())
+                            }
+                        });
+                    if let Err(error) = result {
+                        failure = Some((page.address, error));
+                        break;
+                    }
+                    if programmed_pages.insert(page.address) {
+                        progress.page_programmed(page.size, t.elapsed().as_millis());
+                    }
+                    t = std::time::Instant::now();
+                }
+
+                match failure {
+                    None => break,
+                    Some((address, error)) => {
+                        let count = attempts.entry(address).or_insert(0);
+                        if *count >= page_retries {
+                            return Err(FlashBuilderError::PageRetriesExhausted(address, error));
+                        }
+                        *count += 1;
+
+                        log::warn!(
+                            "Programming the page at address {:#010x} failed: {}. Erasing the sector and retrying ({}/{}).",
+                            address,
+                            error,
+                            count,
+                            page_retries
+                        );
+                        retry = true;
+                    }
+                }
+            }
+            self.completed_sectors.set(self.completed_sectors.get() + 1);
+        }
+
+        progress.finished_erasing();
+        progress.finished_programming();
+        Ok(())
+    }
+
+    /// Programs a single page and optionally checks that it contains the right data afterwards.
+    fn program_page(
+        active: &mut ActiveFlasher<Program>,
+        page: &FlashPage,
+        check: bool,
+    ) -> Result<(), FlasherError> {
+        active.program_page(page.address, page.data.as_slice())?;
+
+        if check {
+            Self::check_page(page, |address, data| active.read_block8(address, data))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads `page` back with `read_block8` and checks that it contains the data of the page.
+    fn check_page(
+        page: &FlashPage,
+        read_block8: impl FnOnce(u32, &mut [u8]) -> Result<(), FlasherError>,
+    ) -> Result<(), FlasherError> {
+        let mut read_back = vec![0; page.data.len()];
+        read_block8(page.address, &mut read_back)?;
+
+        if read_back == page.data {
+            Ok(())
+        } else {
+            Err(FlasherError::VerifyPage(page.address))
+        }
+    }
+
     /// Perform an erase of all sectors given in `sectors` which contain pages.
     fn sector_erase(
         &self,
@@ -425,11 +866,20 @@ impl<'a> FlashBuilder<'a> {
         progress: &FlashProgress,
     ) -> Result<(), FlashBuilderError> {
         let mut t = std::time::Instant::now();
+        let erased_byte_value = flash.region().erased_byte_value;
         let r: R = flash.run_erase(|active| {
             for sector in sectors {
                 if !sector.pages.is_empty() {
-                    active.erase_sector(sector.address)?;
-                    progress.sector_erased(sector.page_size, t.elapsed().as_millis());
+                    if self.blank_check
+                        && Self::sector_is_blank(sector, erased_byte_value, |address, data| {
+                            active.read_block8(address, data)
+                        })?
+                    {
+                        progress.sector_blank_skipped(sector.page_size);
+                    } else {
+                        active.erase_sector(sector.address)?;
+                        progress.sector_erased(sector.page_size, t.elapsed().as_millis());
+                    }
                     t = std::time::Instant::now();
                 }
             }
@@ -440,6 +890,26 @@ impl<'a> FlashBuilder<'a> {
         Ok(())
     }
 
+    /// Returns `true` if all bytes of `sector`, read with `read_block8`, have the value `erased_byte_value`.
+    fn sector_is_blank(
+        sector: &FlashSector,
+        erased_byte_value: u8,
+        read_block8: impl FnOnce(u32, &mut [u8]) -> Result<(), FlasherError>,
+    ) -> Result<bool, FlasherError> {
+        let mut data = vec![0; sector.size as usize];
+        read_block8(sector.address, &mut data)?;
+
+        let blank = data.iter().all(|byte| *byte == erased_byte_value);
+        if blank {
+            log::debug!(
+                "Not erasing the sector at {:#010x}, it is blank already.",
+                sector.address
+            );
+        }
+
+        Ok(blank)
+    }
+
     /// Flash a program using double buffering.
     ///
     /// UNTESTED
@@ -483,3 +953,275 @@ impl<'a> FlashBuilder<'a> {
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::coresight::memory::mock::MockMemory;
+
+    fn fill_with(
+        pad_value: u8,
+    ) -> impl FnMut(&mut FlashPage, usize) -> Result<(), FlashBuilderError> {
+        move |page, len| {
+            page.data.resize(len, pad_value);
+            Ok(())
+        }
+    }
+
+    fn test_region() -> FlashRegion {
+        FlashRegion {
+            range: 0x0000..0x1000,
+            is_boot_memory: true,
+            sector_size: 0x400,
+            page_size: 0x100,
+            erased_byte_value: 0xFF,
+            erase_sector_time_ms: None,
+            program_page_time_ms: None,
+            erase_page_size: None,
+        }
+    }
+
+    #[test]
+    fn short_last_page_is_padded() {
+        let data: Vec<u8> = (0..0x101).map(|i| (i % 0x7F) as u8).collect();
+        let mut builder = FlashBuilder::new();
+        builder.add_data(0x0000, &data).unwrap();
+
+        let mut sectors = vec![];
+        builder
+            .build_sectors_and_pages(&test_region(), &mut sectors, fill_with(0xA5))
+            .unwrap();
+
+        let pages = FlashBuilder::pages(&sectors);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].data.as_slice(), &data[..0x100]);
+        assert_eq!(pages[1].address, 0x100);
+        assert_eq!(pages[1].data.len(), 0x100);
+        assert_eq!(pages[1].data[0], data[0x100]);
+        assert!(pages[1].data[1..].iter().all(|b| *b == 0xA5));
+    }
+
+    #[test]
+    fn pages_are_padded_with_the_erased_byte_value() {
+        let region = FlashRegion {
+            erased_byte_value: 0x00,
+            ..test_region()
+        };
+        let data = [0x11u8; 0x80];
+        let mut builder = FlashBuilder::new();
+        builder.add_data(0x0000, &data).unwrap();
+
+        let mut sectors = vec![];
+        let pad_value = FlashBuilder::pad_value(&region, None);
+        builder
+            .build_sectors_and_pages(&region, &mut sectors, fill_with(pad_value))
+            .unwrap();
+
+        let pages = FlashBuilder::pages(&sectors);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].data.len(), 0x100);
+        assert!(pages[0].data[0x80..].iter().all(|b| *b == 0x00));
+
+        // A pad value which is given explicitly takes precedence.
+        assert_eq!(FlashBuilder::pad_value(&region, Some(0xFF)), 0xFF);
+    }
+
+    #[test]
+    fn verify_finds_first_mismatch() {
+        let first = [0x11u8; 4];
+        let second = [0x22u8; 4];
+        let mut builder = FlashBuilder::new();
+        builder.add_data(0x0010, &second).unwrap();
+        builder.add_data(0x0000, &first).unwrap();
+
+        let mut flash = MockMemory::filled_with(0xFF);
+        flash.write_block8(0x00, &first).unwrap();
+        flash.write_block8(0x10, &second).unwrap();
+        assert_eq!(builder.verify(&mut flash).unwrap(), None);
+
+        flash.write8(0x12, 0x00).unwrap();
+        assert_eq!(builder.verify(&mut flash).unwrap(), Some(0x12));
+
+        flash.write8(0x01, 0x00).unwrap();
+        assert_eq!(builder.verify(&mut flash).unwrap(), Some(0x01));
+    }
+
+    #[test]
+    fn gap_in_page_is_padded() {
+        let first = [0x11u8; 4];
+        let second = [0x22u8; 4];
+        let mut builder = FlashBuilder::new();
+        builder.add_data(0x0000, &first).unwrap();
+        builder.add_data(0x0008, &second).unwrap();
+
+        let mut sectors = vec![];
+        builder
+            .build_sectors_and_pages(&test_region(), &mut sectors, fill_with(0xFF))
+            .unwrap();
+
+        let pages = FlashBuilder::pages(&sectors);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(&pages[0].data[0..4], &first);
+        assert_eq!(&pages[0].data[4..8], &[0xFF; 4]);
+        assert_eq!(&pages[0].data[8..12], &second);
+        assert!(pages[0].data[12..].iter().all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn unchanged_sectors_are_skipped() {
+        let data = vec![0x11u8; 0x800];
+        let mut builder = FlashBuilder::new();
+        builder.add_data(0x0000, &data).unwrap();
+
+        let build = |builder: &FlashBuilder| {
+            let mut sectors = vec![];
+            builder
+                .build_sectors_and_pages(&test_region(), &mut sectors, fill_with(0xFF))
+                .unwrap();
+            sectors
+        };
+
+        let mut sectors = build(&builder);
+        let mut do_chip_erase = true;
+        builder.skip_unchanged_sectors(&mut sectors, &mut do_chip_erase);
+        assert_eq!(sectors.len(), 2);
+        assert!(do_chip_erase);
+
+        let mut manifest = builder.manifest();
+        assert_eq!(manifest.sectors.len(), 2);
+
+        // Pretend the second sector was written with other contents.
+        *manifest.sectors.get_mut(&0x400).unwrap() ^= 1;
+        builder.set_previous_manifest(manifest);
+
+        let mut sectors = build(&builder);
+        builder.skip_unchanged_sectors(&mut sectors, &mut do_chip_erase);
+        assert_eq!(sectors.len(), 1);
+        assert_eq!(sectors[0].address, 0x400);
+        assert!(!do_chip_erase);
+    }
+
+    /// An erase or program operation of `MockFlash`, with the address of the sector or page.
+    #[derive(Debug, PartialEq)]
+    enum Access {
+        Erase(u32),
+        Program(u32),
+    }
+
+    /// Flash of `test_region()` on top of the shared memory mock, which records all erase
+    /// and program operations.
+    struct MockFlash {
+        memory: MockMemory,
+        region: FlashRegion,
+        accesses: Vec<Access>,
+    }
+
+    impl MockFlash {
+        fn erased() -> Self {
+            Self {
+                memory: MockMemory::filled_with(0xFF),
+                region: test_region(),
+                accesses: vec![],
+            }
+        }
+    }
+
+    impl SectorFlash for MockFlash {
+        fn region(&self) -> &FlashRegion {
+            &self.region
+        }
+
+        fn erase_sector(&mut self, address: u32) -> Result<(), FlasherError> {
+            self.accesses.push(Access::Erase(address));
+            let erased = vec![self.region.erased_byte_value; self.region.sector_size as usize];
+            Ok(self.memory.write_block8(address, &erased)?)
+        }
+
+        fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), FlasherError> {
+            self.accesses.push(Access::Program(address));
+            Ok(self.memory.write_block8(address, data)?)
+        }
+
+        fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), FlasherError> {
+            Ok(self.memory.read_block8(address, data)?)
+        }
+    }
+
+    #[test]
+    fn interleaved_erases_each_sector_right_before_programming_it() {
+        let data = vec![0x11u8; 0x500];
+        let mut builder = FlashBuilder::new();
+        builder.add_data(0x0000, &data).unwrap();
+
+        let mut sectors = vec![];
+        builder
+            .build_sectors_and_pages(&test_region(), &mut sectors, fill_with(0xFF))
+            .unwrap();
+
+        let mut flash = MockFlash::erased();
+        flash.memory.write_block8(0x0000, &[0x00; 0x800]).unwrap();
+        builder
+            .program_interleaved(&mut flash, &sectors, 1, &FlashProgress::new(|_| ()))
+            .unwrap();
+
+        assert_eq!(
+            flash.accesses,
+            vec![
+                Access::Erase(0x000),
+                Access::Program(0x000),
+                Access::Program(0x100),
+                Access::Program(0x200),
+                Access::Program(0x300),
+                Access::Erase(0x400),
+                Access::Program(0x400),
+            ]
+        );
+
+        let mut contents = vec![0; 0x800];
+        flash.memory.read_block8(0x0000, &mut contents).unwrap();
+        assert_eq!(&contents[..0x500], data.as_slice());
+        assert!(contents[0x500..].iter().all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn blank_sectors_are_not_erased() {
+        use crate::flash::ProgressEvent;
+        use std::rc::Rc;
+
+        let data = vec![0x11u8; 0x800];
+        let mut builder = FlashBuilder::new();
+        builder.add_data(0x0000, &data).unwrap();
+        builder.set_blank_check(true);
+
+        let mut sectors = vec![];
+        builder
+            .build_sectors_and_pages(&test_region(), &mut sectors, fill_with(0xFF))
+            .unwrap();
+
+        // The first sector is blank, the second one has a single programmed byte.
+        let mut flash = MockFlash::erased();
+        flash.memory.write8(0x0480, 0x00).unwrap();
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let progress = {
+            let events = events.clone();
+            FlashProgress::new(move |event| match event {
+                ProgressEvent::SectorBlankSkipped { .. } => events.borrow_mut().push("skipped"),
+                ProgressEvent::SectorErased { .. } => events.borrow_mut().push("erased"),
+                _ => (),
+            })
+        };
+
+        builder
+            .program_interleaved(&mut flash, &sectors, 0, &progress)
+            .unwrap();
+
+        assert_eq!(*events.borrow(), vec!["skipped", "erased"]);
+        assert!(!flash.accesses.contains(&Access::Erase(0x000)));
+        assert!(flash.accesses.contains(&Access::Erase(0x400)));
+
+        let mut contents = vec![0; 0x800];
+        flash.memory.read_block8(0x0000, &mut contents).unwrap();
+        assert_eq!(contents, data);
+    }
+}
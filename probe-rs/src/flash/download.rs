@@ -3,9 +3,10 @@ use ihex;
 use ihex::record::Record::*;
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::path::Path;
+use std::time::Instant;
 
 use super::*;
 use crate::config::memory::{MemoryRange, MemoryRegion};
@@ -17,18 +18,159 @@ pub struct BinOptions {
     skip: u32,
 }
 
+/// Options for downloading a file into flash.
+pub struct DownloadOptions {
+    /// The value used to pad partially written pages, the erased byte value of the flash region if it is `None`.
+    pub pad_value: Option<u8>,
+    /// How many times programming a page is retried before giving up.
+    pub page_retries: usize,
+    /// If not empty, only data within these address ranges is written.
+    pub only_regions: Vec<Range<u32>>,
+    /// Data within these address ranges is never written.
+    pub skip_regions: Vec<Range<u32>>,
+    /// Data within each range is written to the address next to it instead, relative to the start
+    /// of the range, e.g. to program an image into the second bank of a dual-bank flash.
+    /// The region filters apply to the addresses before the data is moved.
+    pub relocations: Vec<(Range<u32>, u32)>,
+    /// How the target is prepared and the flash algorithm is run.
+    pub flasher: FlasherOptions,
+    /// Reconnect to the probe and resume if the connection is lost while flashing.
+    pub resilient: bool,
+    /// Whether all sectors are erased before programming, or each sector right before it is programmed.
+    pub erase_strategy: EraseStrategy,
+    /// The manifest of the last download, sectors whose contents did not change since are skipped.
+    /// It must describe the current contents of the flash, see `FlashManifest`.
+    pub previous_manifest: Option<FlashManifest>,
+    /// Do not erase sectors which are blank already, see `FlashLoader::set_blank_check`.
+    pub blank_check: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            pad_value: None,
+            page_retries: 1,
+            only_regions: vec![],
+            skip_regions: vec![],
+            relocations: vec![],
+            flasher: FlasherOptions::default(),
+            resilient: false,
+            erase_strategy: EraseStrategy::AllThenProgram,
+            previous_manifest: None,
+            blank_check: false,
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// Returns the parts of `range` which may be written according to the region filters.
+    fn allowed_ranges(&self, range: Range<u32>) -> Vec<Range<u32>> {
+        let mut allowed = if self.only_regions.is_empty() {
+            vec![range]
+        } else {
+            self.only_regions
+                .iter()
+                .map(|only| range.start.max(only.start)..range.end.min(only.end))
+                .filter(|r| r.start < r.end)
+                .collect()
+        };
+
+        for skip in &self.skip_regions {
+            allowed = allowed
+                .into_iter()
+                .flat_map(|r| vec![r.start..r.end.min(skip.start), r.start.max(skip.end)..r.end])
+                .filter(|r| r.start < r.end)
+                .collect();
+        }
+
+        allowed.sort_by_key(|r| r.start);
+        allowed
+    }
+
+    /// Splits `range` at the boundaries of the relocations,
+    /// and returns each part with the address it is written to.
+    fn relocated_ranges(&self, range: Range<u32>) -> Vec<(Range<u32>, u32)> {
+        let mut boundaries = vec![range.start, range.end];
+        for (from, _) in &self.relocations {
+            boundaries.extend(
+                [from.start, from.end]
+                    .iter()
+                    .filter(|&&b| range.start < b && b < range.end),
+            );
+        }
+        boundaries.sort();
+        boundaries.dedup();
+
+        boundaries
+            .windows(2)
+            .map(|part| {
+                let destination = self
+                    .relocations
+                    .iter()
+                    .find(|(from, _)| from.contains(&part[0]))
+                    .map_or(part[0], |(from, to)| to + (part[0] - from.start));
+
+                (part[0]..part[1], destination)
+            })
+            .collect()
+    }
+}
+
 pub enum Format {
     Bin(BinOptions),
     Hex,
     Elf,
 }
 
+impl Format {
+    /// Detects the format of an image by its contents, ELF by its magic bytes and Intel HEX by
+    /// the colon which starts the first record.
+    ///
+    /// Returns `None` for anything else, e.g. a raw binary, which needs a base address.
+    pub fn detect(data: &[u8]) -> Option<Format> {
+        if data.starts_with(b"\x7fELF") {
+            Some(Format::Elf)
+        } else if data.starts_with(b":") {
+            Some(Format::Hex)
+        } else {
+            None
+        }
+    }
+}
+
+/// The magic bytes at the start of a gzip file.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// The magic bytes at the start of a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Reads the image at `path`, decompressing it in memory if it is compressed with gzip or zstd.
+///
+/// The compression is detected by the contents of the file, not by its extension.
+/// All download functions read their files with this.
+pub fn read_image(path: &Path) -> Result<Vec<u8>, std::io::Error> {
+    decompress(std::fs::read(path)?)
+}
+
+/// Decompresses `data` if it is compressed with gzip or zstd, and returns it unchanged otherwise.
+fn decompress(data: Vec<u8>) -> Result<Vec<u8>, std::io::Error> {
+    if data.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    } else if data.starts_with(&ZSTD_MAGIC) {
+        zstd::stream::decode_all(&data[..])
+    } else {
+        Ok(data)
+    }
+}
+
 #[derive(Debug)]
 pub enum FileDownloadError {
     FlashLoader(FlashLoaderError),
     IhexRead(ihex::reader::ReaderError),
     IO(std::io::Error),
     Object(&'static str),
+    Xmodem(XmodemError),
 }
 
 impl Error for FileDownloadError {}
@@ -42,6 +184,7 @@ impl fmt::Display for FileDownloadError {
             IhexRead(ref e) => e.fmt(f),
             IO(ref e) => e.fmt(f),
             Object(ref s) => write!(f, "Object Error: {}.", s),
+            Xmodem(ref e) => e.fmt(f),
         }
     }
 }
@@ -52,6 +195,12 @@ impl From<FlashLoaderError> for FileDownloadError {
     }
 }
 
+impl From<XmodemError> for FileDownloadError {
+    fn from(error: XmodemError) -> FileDownloadError {
+        FileDownloadError::Xmodem(error)
+    }
+}
+
 impl From<ihex::reader::ReaderError> for FileDownloadError {
     fn from(error: ihex::reader::ReaderError) -> FileDownloadError {
         FileDownloadError::IhexRead(error)
@@ -78,7 +227,92 @@ pub fn download_file_with_progress_reporting(
     memory_map: &[MemoryRegion],
     progress: &FlashProgress,
 ) -> Result<(), FileDownloadError> {
-    download_file_internal(session, path, format, memory_map, progress)
+    download_file_internal(
+        session,
+        path,
+        format,
+        memory_map,
+        progress,
+        &DownloadOptions::default(),
+    )
+}
+
+/// Downloads a file at `path` into flash using the given `options`.
+pub fn download_file_with_options(
+    session: &mut Session,
+    path: &Path,
+    format: Format,
+    memory_map: &[MemoryRegion],
+    progress: &FlashProgress,
+    options: &DownloadOptions,
+) -> Result<(), FileDownloadError> {
+    download_file_internal(session, path, format, memory_map, progress, options)
+}
+
+/// Downloads a file at `path` into flash using the given `options` and returns the manifest
+/// of the written sectors.
+///
+/// Passing the manifest as `previous_manifest` to the next download skips all unchanged sectors.
+pub fn download_file_with_manifest(
+    session: &mut Session,
+    path: &Path,
+    format: Format,
+    memory_map: &[MemoryRegion],
+    progress: &FlashProgress,
+    options: &DownloadOptions,
+) -> Result<FlashManifest, FileDownloadError> {
+    with_loader(path, format, memory_map, options, |loader| {
+        loader
+            .commit(session, progress, false)
+            .map_err(FileDownloadError::FlashLoader)?;
+
+        Ok(loader.manifest())
+    })
+}
+
+/// Sends a file at `path` to a serial bootloader on `port` with XMODEM, for boards without a debug probe.
+///
+/// The data is staged and filtered like for the other downloads, then sent as one image which starts at
+/// the lowest address of the data. The gaps are filled with the pad value. The bootloader erases and
+/// programs the flash itself, at its application start address, which has to match the start of the image.
+/// The progress reports each block as a programmed page, no sectors are erased.
+pub fn download_file_xmodem(
+    port: impl Read + Write,
+    path: &Path,
+    format: Format,
+    memory_map: &[MemoryRegion],
+    progress: &FlashProgress,
+    options: &DownloadOptions,
+) -> Result<(), FileDownloadError> {
+    with_loader(path, format, memory_map, options, |loader| {
+        let (start, image) = match loader.contiguous_image()? {
+            Some(image) => image,
+            None => return Ok(()),
+        };
+
+        log::info!(
+            "Sending {} bytes for address {:#010x} to the bootloader.",
+            image.len(),
+            start
+        );
+
+        let blocks = image.chunks(BLOCK_SIZE).count();
+        progress.initialized(0, blocks, 0, BLOCK_SIZE as u32);
+        progress.started_erasing();
+        progress.finished_erasing();
+        progress.started_flashing();
+
+        // The last block is padded like the end of the image.
+        let pad_value = loader.pad_value_at(start + image.len() as u32 - 1);
+        let mut block_start = Instant::now();
+        let result = Xmodem::new(port).send(&image, pad_value, |size| {
+            progress.page_programmed(size as u32, block_start.elapsed().as_millis());
+            block_start = Instant::now();
+        });
+
+        progress.finished_programming();
+        Ok(result?)
+    })
 }
 
 /// Downloads a file at `path` into flash.
@@ -94,9 +328,29 @@ pub fn download_file(
         format,
         memory_map,
         &FlashProgress::new(|_| {}),
+        &DownloadOptions::default(),
     )
 }
 
+/// Compares the flash contents with the file at `path`, without erasing or programming anything.
+///
+/// The flash is read directly through the memory interface, so the flash algorithm is not run.
+/// Only data which passes the region filters of `options` is compared.
+/// Returns the first address which differs, or `None` if the flash matches the file.
+pub fn verify_file(
+    session: &mut Session,
+    path: &Path,
+    format: Format,
+    memory_map: &[MemoryRegion],
+    options: &DownloadOptions,
+) -> Result<Option<u32>, FileDownloadError> {
+    with_loader(path, format, memory_map, options, |loader| {
+        loader
+            .verify(session)
+            .map_err(FileDownloadError::FlashLoader)
+    })
+}
+
 /// Downloads a file at `path` into flash.
 fn download_file_internal(
     session: &mut Session,
@@ -104,26 +358,50 @@ fn download_file_internal(
     format: Format,
     memory_map: &[MemoryRegion],
     progress: &FlashProgress,
+    options: &DownloadOptions,
 ) -> Result<(), FileDownloadError> {
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(e) => return Err(FileDownloadError::IO(e)),
-    };
+    with_loader(path, format, memory_map, options, |loader| {
+        loader
+            // TODO: hand out chip erase flag
+            .commit(session, progress, false)
+            .map_err(FileDownloadError::FlashLoader)
+    })
+}
+
+/// Stages the contents of the file at `path` in a `FlashLoader` set up with `options` and passes it to `f`.
+fn with_loader<T>(
+    path: &Path,
+    format: Format,
+    memory_map: &[MemoryRegion],
+    options: &DownloadOptions,
+    f: impl FnOnce(&mut FlashLoader<'_, '_>) -> Result<T, FileDownloadError>,
+) -> Result<T, FileDownloadError> {
+    let mut file = Cursor::new(read_image(path)?);
     let mut buffer = vec![];
     let mut buffer_vec = vec![];
     // IMPORTANT: Change this to an actual memory map of a real chip
     let mut loader = FlashLoader::new(memory_map, false);
+    if let Some(pad_value) = options.pad_value {
+        loader.set_pad_value(pad_value);
+    }
+    loader.set_page_retries(options.page_retries);
+    loader.set_flasher_options(options.flasher);
+    loader.set_resilient(options.resilient);
+    loader.set_erase_strategy(options.erase_strategy);
+    loader.set_blank_check(options.blank_check);
+    if let Some(manifest) = &options.previous_manifest {
+        loader.set_previous_manifest(manifest.clone());
+    }
 
     match format {
-        Format::Bin(options) => download_bin(&mut buffer, &mut file, &mut loader, options),
-        Format::Elf => download_elf(&mut buffer, &mut file, &mut loader),
-        Format::Hex => download_hex(&mut buffer_vec, &mut file, &mut loader),
+        Format::Bin(bin_options) => {
+            download_bin(&mut buffer, &mut file, &mut loader, bin_options, options)
+        }
+        Format::Elf => download_elf(&mut buffer, &mut file, &mut loader, options),
+        Format::Hex => download_hex(&mut buffer_vec, &mut file, &mut loader, options),
     }?;
 
-    loader
-        // TODO: hand out chip erase flag
-        .commit(session, progress, false)
-        .map_err(FileDownloadError::FlashLoader)
+    f(&mut loader)
 }
 
 /// Starts the download of a binary file.
@@ -132,13 +410,15 @@ fn download_bin<'b, T: Read + Seek>(
     file: &'b mut T,
     loader: &mut FlashLoader<'_, 'b>,
     options: BinOptions,
+    download_options: &DownloadOptions,
 ) -> Result<(), FileDownloadError> {
     // Skip the specified bytes.
     file.seek(SeekFrom::Start(u64::from(options.skip)))?;
 
     file.read_to_end(buffer)?;
 
-    loader.add_data(
+    add_filtered_data(
+        loader,
         if let Some(address) = options.base_address {
             address
         } else {
@@ -147,6 +427,7 @@ fn download_bin<'b, T: Read + Seek>(
             0
         },
         buffer.as_slice(),
+        download_options,
     )?;
 
     Ok(())
@@ -157,6 +438,7 @@ fn download_hex<'b, T: Read + Seek>(
     buffer: &'b mut Vec<(u32, Vec<u8>)>,
     file: &mut T,
     loader: &mut FlashLoader<'_, 'b>,
+    options: &DownloadOptions,
 ) -> Result<(), FileDownloadError> {
     let mut _extended_segment_address = 0;
     let mut extended_linear_address = 0;
@@ -183,7 +465,7 @@ fn download_hex<'b, T: Read + Seek>(
         };
     }
     for (offset, data) in buffer {
-        loader.add_data(*offset, data.as_slice())?;
+        add_filtered_data(loader, *offset, data.as_slice(), options)?;
     }
     Ok(())
 }
@@ -193,24 +475,27 @@ fn download_elf<'b, T: Read + Seek>(
     buffer: &'b mut Vec<u8>,
     file: &'b mut T,
     loader: &mut FlashLoader<'_, 'b>,
+    options: &DownloadOptions,
 ) -> Result<(), FileDownloadError> {
     file.read_to_end(buffer)?;
 
     use goblin::elf::program_header::*;
 
     if let Ok(binary) = goblin::elf::Elf::parse(&buffer.as_slice()) {
-        for ph in &binary.program_headers {
+        for (index, ph) in binary.program_headers.iter().enumerate() {
             if ph.p_type == PT_LOAD && ph.p_filesz > 0 {
                 log::debug!("Found loadable segment containing:");
 
                 let sector: core::ops::Range<u32> =
                     ph.p_offset as u32..ph.p_offset as u32 + ph.p_filesz as u32;
+                let mut section_names = vec![];
 
                 for sh in &binary.section_headers {
                     if sector.contains_range(
                         &(sh.sh_offset as u32..sh.sh_offset as u32 + sh.sh_size as u32),
                     ) {
                         log::debug!("{:?}", &binary.shdr_strtab[sh.sh_name]);
+                        section_names.push(&binary.shdr_strtab[sh.sh_name]);
                         for line in hexdump::hexdump_iter(
                             &buffer[sh.sh_offset as usize..][..sh.sh_size as usize],
                         ) {
@@ -219,12 +504,163 @@ fn download_elf<'b, T: Read + Seek>(
                     }
                 }
 
-                loader.add_data(
+                add_filtered_data(
+                    loader,
                     ph.p_paddr as u32,
                     &buffer[ph.p_offset as usize..][..ph.p_filesz as usize],
-                )?;
+                    options,
+                )
+                .map_err(|e| match e {
+                    // Name the segment, such that it is clear which part of the image is misplaced.
+                    FileDownloadError::FlashLoader(FlashLoaderError::NoSuitableFlash(address))
+                    | FileDownloadError::FlashLoader(FlashLoaderError::MemoryRegionNotFlash(
+                        address,
+                    )) => {
+                        FileDownloadError::FlashLoader(FlashLoaderError::AddressNotInFlashRegion {
+                            address,
+                            segment: format!("#{} ({})", index, section_names.join(", ")),
+                        })
+                    }
+                    e => e,
+                })?;
             }
         }
     }
     Ok(())
 }
+
+/// Adds `data` at `address` to the loader, leaving out everything
+/// which is excluded by the region filters of `options`.
+fn add_filtered_data<'b>(
+    loader: &mut FlashLoader<'_, 'b>,
+    address: u32,
+    data: &'b [u8],
+    options: &DownloadOptions,
+) -> Result<(), FileDownloadError> {
+    let range = address..address + data.len() as u32;
+    let allowed = options.allowed_ranges(range.clone());
+
+    if allowed.is_empty() {
+        log::info!(
+            "Excluding data at {:#010x}..{:#010x}",
+            range.start,
+            range.end
+        );
+    } else if allowed == [range.clone()] {
+        log::info!(
+            "Including data at {:#010x}..{:#010x}",
+            range.start,
+            range.end
+        );
+    } else {
+        log::info!(
+            "Including data at {:#010x}..{:#010x} partially:",
+            range.start,
+            range.end
+        );
+    }
+
+    for r in allowed {
+        if r != range {
+            log::info!("    {:#010x}..{:#010x}", r.start, r.end);
+        }
+        for (part, destination) in options.relocated_ranges(r) {
+            if destination != part.start {
+                log::info!(
+                    "Moving data at {:#010x}..{:#010x} to {:#010x}",
+                    part.start,
+                    part.end,
+                    destination
+                );
+            }
+            let start = (part.start - address) as usize;
+            let end = (part.end - address) as usize;
+            loader.add_data(destination, &data[start..end])?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decompress, DownloadOptions, Format};
+    use std::io::Write;
+
+    #[test]
+    fn allowed_ranges_without_filters() {
+        let options = DownloadOptions::default();
+
+        assert_eq!(options.allowed_ranges(0x100..0x200), vec![0x100..0x200]);
+    }
+
+    #[test]
+    fn allowed_ranges_only() {
+        let options = DownloadOptions {
+            only_regions: vec![0x180..0x300, 0x0..0x110],
+            ..DownloadOptions::default()
+        };
+
+        assert_eq!(
+            options.allowed_ranges(0x100..0x200),
+            vec![0x100..0x110, 0x180..0x200]
+        );
+        assert!(options.allowed_ranges(0x300..0x400).is_empty());
+    }
+
+    #[test]
+    fn allowed_ranges_skip() {
+        let options = DownloadOptions {
+            only_regions: vec![0x0..0x1000, 0x2000..0x3000],
+            skip_regions: vec![0x140..0x160, 0x1f0..0x1f8],
+            ..DownloadOptions::default()
+        };
+
+        assert_eq!(
+            options.allowed_ranges(0x100..0x200),
+            vec![0x100..0x140, 0x160..0x1f0, 0x1f8..0x200]
+        );
+        assert!(options.allowed_ranges(0x140..0x160).is_empty());
+    }
+
+    #[test]
+    fn relocated_ranges() {
+        let options = DownloadOptions {
+            relocations: vec![(0x1000..0x2000, 0x8000)],
+            ..DownloadOptions::default()
+        };
+
+        assert_eq!(
+            options.relocated_ranges(0x0f00..0x1100),
+            vec![(0x0f00..0x1000, 0x0f00), (0x1000..0x1100, 0x8000)]
+        );
+        assert_eq!(
+            options.relocated_ranges(0x1800..0x1900),
+            vec![(0x1800..0x1900, 0x8800)]
+        );
+    }
+
+    #[test]
+    fn compressed_images_are_decompressed() {
+        let image = b"\x7fELF and some more data".to_vec();
+
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(&image).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::stream::encode_all(&image[..], 0).unwrap();
+
+        assert_eq!(decompress(gzip).unwrap(), image);
+        assert_eq!(decompress(zstd).unwrap(), image);
+        assert_eq!(decompress(image.clone()).unwrap(), image);
+    }
+
+    #[test]
+    fn format_is_detected_by_contents() {
+        assert!(matches!(Format::detect(b"\x7fELF\x01"), Some(Format::Elf)));
+        assert!(matches!(
+            Format::detect(b":020000040800F2"),
+            Some(Format::Hex)
+        ));
+        assert!(Format::detect(&[0x00, 0x20, 0x00, 0x20]).is_none());
+    }
+}
@@ -0,0 +1,120 @@
+use super::FlashProgress;
+use crate::coresight::{access_ports::AccessPortError, memory::MI};
+use crate::probe::MasterProbe;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// The largest chunk which is read at once, even if the probe could transfer more.
+/// Smaller chunks keep single USB transfers from timing out and let the progress move.
+const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Debug)]
+pub enum MemoryDumpError {
+    Io(io::Error),
+    AccessPort(AccessPortError),
+}
+
+impl Error for MemoryDumpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use MemoryDumpError::*;
+
+        match self {
+            Io(ref e) => Some(e),
+            AccessPort(ref e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for MemoryDumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use MemoryDumpError::*;
+
+        match self {
+            Io(ref e) => e.fmt(f),
+            AccessPort(ref e) => write!(f, "Failed to read the memory: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for MemoryDumpError {
+    fn from(error: io::Error) -> Self {
+        MemoryDumpError::Io(error)
+    }
+}
+
+impl From<AccessPortError> for MemoryDumpError {
+    fn from(error: AccessPortError) -> Self {
+        MemoryDumpError::AccessPort(error)
+    }
+}
+
+/// Reads `length` bytes of memory at `address` and writes them to `writer`.
+///
+/// The memory is read in chunks of at most the maximum transfer size of the probe, and each
+/// chunk is written right away, so the whole dump is never held in memory.
+/// `progress` gets a `StartedReading`, a `ChunkRead` per chunk and a `FinishedReading` event.
+pub fn read_memory_to_writer(
+    probe: &mut MasterProbe,
+    address: u32,
+    length: u32,
+    writer: &mut impl Write,
+    progress: &FlashProgress,
+) -> Result<(), MemoryDumpError> {
+    let chunk_size = chunk_size(probe.max_memory_transfer_size());
+    let mut buffer = vec![0; chunk_size];
+
+    progress.started_reading(length);
+
+    let end = address as u64 + length as u64;
+    let mut chunk_address = address as u64;
+    while chunk_address < end {
+        let size = chunk_size.min((end - chunk_address) as usize);
+        let chunk = &mut buffer[..size];
+
+        let start = Instant::now();
+        probe.read_block8(chunk_address as u32, chunk)?;
+        writer.write_all(chunk)?;
+        progress.chunk_read(size as u32, start.elapsed().as_millis());
+
+        chunk_address += size as u64;
+    }
+
+    writer.flush()?;
+    progress.finished_reading();
+
+    Ok(())
+}
+
+/// Reads `length` bytes of memory at `address` into the file at `path`, see `read_memory_to_writer`.
+pub fn read_memory_to_file(
+    probe: &mut MasterProbe,
+    address: u32,
+    length: u32,
+    path: &Path,
+    progress: &FlashProgress,
+) -> Result<(), MemoryDumpError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    read_memory_to_writer(probe, address, length, &mut file, progress)
+}
+
+/// Returns the size of the chunks a dump is read in, a multiple of the word size.
+fn chunk_size(max_transfer_size: usize) -> usize {
+    (max_transfer_size.min(MAX_CHUNK_SIZE) & !0x3).max(4)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chunk_size, MAX_CHUNK_SIZE};
+
+    #[test]
+    fn chunks_are_limited_and_word_aligned() {
+        assert_eq!(chunk_size(usize::MAX), MAX_CHUNK_SIZE);
+        assert_eq!(chunk_size(1022), 1020);
+        assert_eq!(chunk_size(2), 4);
+    }
+}
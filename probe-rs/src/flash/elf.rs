@@ -0,0 +1,445 @@
+use crate::config::memory::{MemoryRange, MemoryRegion};
+use goblin::elf::{
+    header::{EM_ARM, ET_EXEC},
+    program_header::{PF_R, PF_W, PF_X, PT_LOAD},
+    section_header::SHF_ALLOC,
+    sym::STT_FUNC,
+    Elf,
+};
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ElfImageError {
+    IO(std::io::Error),
+    Parse(goblin::error::Error),
+}
+
+impl Error for ElfImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use ElfImageError::*;
+
+        match self {
+            IO(ref e) => Some(e),
+            Parse(ref e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for ElfImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ElfImageError::*;
+
+        match self {
+            IO(ref e) => e.fmt(f),
+            Parse(ref e) => write!(f, "Failed to parse the ELF file: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ElfImageError {
+    fn from(error: std::io::Error) -> Self {
+        ElfImageError::IO(error)
+    }
+}
+
+impl From<goblin::error::Error> for ElfImageError {
+    fn from(error: goblin::error::Error) -> Self {
+        ElfImageError::Parse(error)
+    }
+}
+
+/// How many bytes of a memory region an image occupies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionUsage {
+    pub region: MemoryRegion,
+    pub used: u32,
+}
+
+impl RegionUsage {
+    /// Returns the size of the region in bytes.
+    pub fn size(&self) -> u32 {
+        let range = self.region.range();
+        range.end - range.start
+    }
+
+    /// Returns the used part of the region in percent.
+    pub fn percent(&self) -> f32 {
+        if self.size() == 0 {
+            0.0
+        } else {
+            self.used as f32 * 100.0 / self.size() as f32
+        }
+    }
+}
+
+/// Returns how many bytes of `region` are covered by `ranges`, which must not overlap.
+fn used_bytes(region: &Range<u32>, ranges: &[Range<u32>]) -> u32 {
+    ranges
+        .iter()
+        .filter(|range| region.intersects_range(range))
+        .map(|range| range.end.min(region.end) - range.start.max(region.start))
+        .sum()
+}
+
+/// A loadable segment of an ELF file created with `elf_header`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadSegment {
+    /// The addresses the segment is loaded to.
+    pub range: Range<u32>,
+    /// The `PF_*` permission flags of the segment.
+    pub flags: u32,
+}
+
+/// Splits the memory `range` into one segment per region of `memory_map`, in the order of their addresses.
+///
+/// Flash segments are readable and executable, RAM segments readable and writable.
+/// Memory outside of the memory map ends up in read-only segments.
+pub fn dump_segments(memory_map: &[MemoryRegion], range: Range<u32>) -> Vec<LoadSegment> {
+    let Range {
+        start: address,
+        end,
+    } = range;
+
+    let mut boundaries: Vec<u32> = memory_map
+        .iter()
+        .flat_map(|region| vec![region.range().start, region.range().end])
+        .filter(|boundary| *boundary > address && *boundary < end)
+        .collect();
+    boundaries.push(address);
+    boundaries.push(end);
+    boundaries.sort();
+    boundaries.dedup();
+
+    boundaries
+        .windows(2)
+        .map(|window| {
+            let flags = match memory_map
+                .iter()
+                .find(|region| region.range().contains(&window[0]))
+            {
+                Some(MemoryRegion::Flash(_)) => PF_R | PF_X,
+                Some(MemoryRegion::Ram(_)) => PF_R | PF_W,
+                _ => PF_R,
+            };
+
+            LoadSegment {
+                range: window[0]..window[1],
+                flags,
+            }
+        })
+        .collect()
+}
+
+/// Creates the headers of a minimal 32 bit little endian ARM ELF file with one `PT_LOAD` program header per segment.
+///
+/// The data of the segments has to follow the headers, in the order of the segments and without gaps.
+/// This allows to write the data of large segments as it is read.
+///
+/// The file has no sections and no symbols, which is enough for tools that only look at
+/// the loadable segments, e.g. `objdump -D` or `arm-none-eabi-gdb`.
+pub fn elf_header(segments: &[LoadSegment]) -> Vec<u8> {
+    const HEADER_SIZE: u32 = 52;
+    const PROGRAM_HEADER_SIZE: u32 = 32;
+    /// The flags of executables for version 5 of the ARM EABI.
+    const EF_ARM_EABI_VER5: u32 = 0x0500_0000;
+
+    let mut elf = Vec::new();
+    let word = |elf: &mut Vec<u8>, value: u32| elf.extend_from_slice(&value.to_le_bytes());
+    let half = |elf: &mut Vec<u8>, value: u16| elf.extend_from_slice(&value.to_le_bytes());
+
+    // e_ident: magic, 32 bit, little endian, version 1, System V ABI, padding.
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    half(&mut elf, ET_EXEC);
+    half(&mut elf, EM_ARM);
+    word(&mut elf, 1);
+    // The entry point is unknown.
+    word(&mut elf, 0);
+    word(&mut elf, HEADER_SIZE);
+    // There is no section header table.
+    word(&mut elf, 0);
+    word(&mut elf, EF_ARM_EABI_VER5);
+    half(&mut elf, HEADER_SIZE as u16);
+    half(&mut elf, PROGRAM_HEADER_SIZE as u16);
+    half(&mut elf, segments.len() as u16);
+    half(&mut elf, 0);
+    half(&mut elf, 0);
+    half(&mut elf, 0);
+
+    let mut offset = HEADER_SIZE + PROGRAM_HEADER_SIZE * segments.len() as u32;
+    for segment in segments {
+        let size = segment.range.end - segment.range.start;
+
+        word(&mut elf, PT_LOAD);
+        word(&mut elf, offset);
+        word(&mut elf, segment.range.start);
+        word(&mut elf, segment.range.start);
+        word(&mut elf, size);
+        word(&mut elf, size);
+        word(&mut elf, segment.flags);
+        word(&mut elf, 1);
+
+        offset += size;
+    }
+
+    elf
+}
+
+/// The type of the GNU note which holds the build-id.
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// The build-id of an image, which the linker writes to the `.note.gnu.build-id` section
+/// with `--build-id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildId {
+    /// The load address of the build-id, i.e. where it ends up in flash.
+    pub address: u32,
+    /// The build-id itself, usually a SHA1 hash of the image.
+    pub id: Vec<u8>,
+}
+
+/// Returns the offset and the contents of the descriptor of a GNU build-id note.
+fn parse_build_id_note(note: &[u8]) -> Option<(usize, &[u8])> {
+    let word = |offset: usize| -> Option<u32> {
+        let bytes = note.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    let name_size = word(0)? as usize;
+    let desc_size = word(4)? as usize;
+    if word(8)? != NT_GNU_BUILD_ID || note.get(12..12 + name_size)? != b"GNU\0" {
+        return None;
+    }
+
+    // The name is padded to a multiple of 4 bytes.
+    let desc_offset = 12 + ((name_size + 3) & !3);
+    let desc = note.get(desc_offset..desc_offset + desc_size)?;
+
+    Some((desc_offset, desc))
+}
+
+/// An ELF file which is flashed to or run on the target.
+///
+/// This gives access to the information of the ELF file which is needed besides the
+/// loadable segments, e.g. to resolve the address of a function by name.
+pub struct ElfImage {
+    data: Vec<u8>,
+}
+
+impl ElfImage {
+    /// Creates an image from the contents of an ELF file.
+    pub fn new(data: Vec<u8>) -> Result<Self, ElfImageError> {
+        // Make sure the data is a valid ELF file, so later accesses can not fail.
+        Elf::parse(&data)?;
+        Ok(Self { data })
+    }
+
+    /// Reads the ELF file at `path`.
+    pub fn from_file(path: &Path) -> Result<Self, ElfImageError> {
+        Self::new(std::fs::read(path)?)
+    }
+
+    /// Returns the raw contents of the ELF file.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the entry point of the image, which usually is the reset handler.
+    ///
+    /// The thumb bit is cleared.
+    pub fn entry_point(&self) -> u32 {
+        self.elf().entry as u32 & !1
+    }
+
+    /// Returns the address of the symbol `name`, or `None` if the image has no such symbol.
+    ///
+    /// For functions the thumb bit is cleared, such that the returned address is
+    /// the address of the first instruction.
+    pub fn symbol_address(&self, name: &str) -> Option<u32> {
+        let elf = self.elf();
+        elf.syms
+            .iter()
+            .find(|sym| {
+                sym.st_name != 0 && elf.strtab.get(sym.st_name).and_then(Result::ok) == Some(name)
+            })
+            .map(|sym| {
+                if sym.st_type() == STT_FUNC {
+                    sym.st_value as u32 & !1
+                } else {
+                    sym.st_value as u32
+                }
+            })
+    }
+
+    /// Returns the build-id of the image, or `None` if it has none or if the note is not loaded to the target.
+    ///
+    /// The note is only flashed if the linker script places it in an allocated section,
+    /// e.g. `KEEP(*(.note.gnu.build-id))` in the section after the vector table.
+    pub fn build_id(&self) -> Option<BuildId> {
+        let elf = self.elf();
+
+        let section = elf.section_headers.iter().find(|sh| {
+            sh.sh_flags & u64::from(SHF_ALLOC) != 0
+                && elf.shdr_strtab.get(sh.sh_name).and_then(Result::ok)
+                    == Some(".note.gnu.build-id")
+        })?;
+        let offset = section.sh_offset as usize;
+        let note = self.data.get(offset..offset + section.sh_size as usize)?;
+        let (desc_offset, id) = parse_build_id_note(note)?;
+
+        // The section address is the run time address, the segment tells where it is loaded.
+        let address = section.sh_addr as u32 + desc_offset as u32;
+        let segment = elf.program_headers.iter().find(|ph| {
+            ph.p_type == PT_LOAD
+                && ph.p_vaddr as u32 <= address
+                && address + id.len() as u32 <= (ph.p_vaddr + ph.p_filesz) as u32
+        })?;
+
+        Some(BuildId {
+            address: address - segment.p_vaddr as u32 + segment.p_paddr as u32,
+            id: id.to_vec(),
+        })
+    }
+
+    /// Returns the address ranges of all loadable segments with data, at their load addresses.
+    pub fn loadable_ranges(&self) -> Vec<Range<u32>> {
+        self.elf()
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD && ph.p_filesz > 0)
+            .map(|ph| ph.p_paddr as u32..ph.p_paddr as u32 + ph.p_filesz as u32)
+            .collect()
+    }
+
+    /// Returns the address ranges of all loadable segments at run time, including
+    /// zero initialized data like `.bss`.
+    pub fn allocated_ranges(&self) -> Vec<Range<u32>> {
+        self.elf()
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD && ph.p_memsz > 0)
+            .map(|ph| ph.p_vaddr as u32..ph.p_vaddr as u32 + ph.p_memsz as u32)
+            .collect()
+    }
+
+    /// Returns how much of each flash and RAM region of `memory_map` the image uses,
+    /// like the size summary of a linker.
+    ///
+    /// The flash usage is the data which is flashed, the RAM usage is the initialized
+    /// and zero initialized data, e.g. `.data` and `.bss`. The stack and heap are not included.
+    pub fn region_usage(&self, memory_map: &[MemoryRegion]) -> Vec<RegionUsage> {
+        let loadable = self.loadable_ranges();
+        let allocated = self.allocated_ranges();
+
+        memory_map
+            .iter()
+            .filter_map(|region| {
+                let ranges = match region {
+                    MemoryRegion::Flash(_) => &loadable,
+                    MemoryRegion::Ram(_) => &allocated,
+                    MemoryRegion::Generic(_) => return None,
+                };
+
+                Some(RegionUsage {
+                    region: region.clone(),
+                    used: used_bytes(region.range(), ranges),
+                })
+            })
+            .collect()
+    }
+
+    fn elf(&self) -> Elf<'_> {
+        // The data was validated when the image was created.
+        Elf::parse(&self.data).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dump_segments, elf_header, parse_build_id_note, used_bytes, ElfImage};
+    use crate::config::memory::{FlashRegion, GenericRegion, MemoryRegion, RamRegion};
+    use goblin::elf::program_header::{PF_R, PF_W, PF_X};
+
+    #[test]
+    fn used_bytes_of_region() {
+        let ranges = [0x0..0x100, 0x800..0x1100, 0x2000..0x2010];
+
+        assert_eq!(used_bytes(&(0x1000..0x2000), &ranges), 0x100);
+        assert_eq!(used_bytes(&(0x0..0x1000), &ranges), 0x100 + 0x800);
+        assert_eq!(used_bytes(&(0x3000..0x4000), &ranges), 0);
+    }
+
+    #[test]
+    fn dump_is_split_at_regions() {
+        let memory_map = vec![
+            MemoryRegion::Flash(FlashRegion {
+                range: 0x0..0x100,
+                is_boot_memory: true,
+                sector_size: 0x100,
+                page_size: 0x80,
+                erased_byte_value: 0xFF,
+                erase_sector_time_ms: None,
+                program_page_time_ms: None,
+                erase_page_size: None,
+            }),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x100..0x200,
+                is_boot_memory: false,
+            }),
+            MemoryRegion::Generic(GenericRegion {
+                range: 0x1000..0x2000,
+            }),
+        ];
+
+        let segments = dump_segments(&memory_map, 0x80..0x280);
+
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].range, 0x80..0x100);
+        assert_eq!(segments[0].flags, PF_R | PF_X);
+        assert_eq!(segments[1].range, 0x100..0x200);
+        assert_eq!(segments[1].flags, PF_R | PF_W);
+        assert_eq!(segments[2].range, 0x200..0x280);
+        assert_eq!(segments[2].flags, PF_R);
+
+        let mut elf = elf_header(&segments);
+        elf.resize(elf.len() + 0x200, 0xAB);
+        let image = ElfImage::new(elf).unwrap();
+        assert_eq!(
+            image.loadable_ranges(),
+            vec![0x80..0x100, 0x100..0x200, 0x200..0x280]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn symbol_lookup() {
+        // The test binary itself is an ELF file with a symbol table.
+        let image = ElfImage::from_file(&std::env::current_exe().unwrap()).unwrap();
+
+        assert!(image.symbol_address("main").is_some());
+        assert_eq!(image.symbol_address("this_symbol_does_not_exist"), None);
+    }
+
+    #[test]
+    fn build_id_note() {
+        let mut note = vec![4, 0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0];
+        note.extend_from_slice(b"GNU\0");
+        note.extend_from_slice(&[0xAB, 0xCD, 0xEF]);
+
+        assert_eq!(
+            parse_build_id_note(&note),
+            Some((16, &[0xAB, 0xCD, 0xEF][..]))
+        );
+
+        // Another type of note
+        note[8] = 1;
+        assert_eq!(parse_build_id_note(&note), None);
+        note[8] = 3;
+
+        // A truncated note
+        assert_eq!(parse_build_id_note(&note[..18]), None);
+    }
+}
@@ -0,0 +1,151 @@
+use super::builder::FlashBuilderError;
+use super::flasher::{Flasher, FlasherError};
+use super::loader::{FlashLoader, FlashLoaderError};
+use crate::config::memory::{MemoryRegion, SectorInfo};
+use crate::session::Session;
+
+/// The result of an endurance test.
+#[derive(Debug)]
+pub struct EnduranceReport {
+    /// The sector which was cycled.
+    pub sector: SectorInfo,
+    /// How many erase/program/verify cycles completed successfully.
+    pub cycles_completed: usize,
+    /// The first failure, if the sector wore out before all cycles were done.
+    pub failure: Option<EnduranceFailure>,
+}
+
+/// A failed cycle of an endurance test.
+#[derive(Debug)]
+pub struct EnduranceFailure {
+    /// The number of the failed cycle, starting at 1.
+    pub cycle: usize,
+    /// What went wrong in this cycle.
+    pub error: FlasherError,
+}
+
+/// Returns the byte which is programmed at `offset` of the sector in `cycle`.
+///
+/// The pattern is inverted every cycle, such that every bit is programmed every other cycle.
+fn endurance_pattern(cycle: usize, offset: usize) -> u8 {
+    let pattern = if offset & 1 == 0 { 0x55 } else { 0xAA };
+    if cycle & 1 == 0 {
+        pattern
+    } else {
+        !pattern
+    }
+}
+
+/// Repeatedly erases, programs and verifies the flash sector containing `address`, to qualify the flash.
+///
+/// Each cycle erases the sector, checks that it is blank, programs all of its pages with a test pattern
+/// and reads them back. The test stops at the first cycle which fails, or after `cycles` cycles.
+/// `on_cycle` is called with the number of each successfully completed cycle.
+///
+/// Failures of the flash are reported in the returned `EnduranceReport`,
+/// while errors of the probe are returned as an error.
+pub fn endurance_test(
+    session: &mut Session,
+    address: u32,
+    cycles: usize,
+    mut on_cycle: impl FnMut(usize),
+) -> Result<EnduranceReport, FlashLoaderError> {
+    let region =
+        match FlashLoader::get_region_for_address(&session.target.memory_map, address).cloned() {
+            Some(MemoryRegion::Flash(region)) => region,
+            Some(_) => return Err(FlashLoaderError::MemoryRegionNotFlash(address)),
+            None => return Err(FlashLoaderError::NoSuitableFlash(address)),
+        };
+    let flash_algorithm = session
+        .target
+        .flash_algorithm
+        .clone()
+        .ok_or(FlashLoaderError::NoFlashLoaderAlgorithmAttached)?;
+
+    // The address is within the region, so there always is a sector.
+    let sector = region.sector_info(address).unwrap();
+    let page_size = sector.page_size as usize;
+
+    let mut report = EnduranceReport {
+        sector,
+        cycles_completed: 0,
+        failure: None,
+    };
+
+    for cycle in 1..=cycles {
+        let mut flasher = Flasher::new(
+            &session.target,
+            &mut session.probe,
+            &flash_algorithm,
+            &region,
+        );
+
+        let result = flasher
+            .run_erase(|active| {
+                active.erase_sector(sector.base_address)?;
+
+                let mut data = vec![0u8; sector.size as usize];
+                active.read_block8(sector.base_address, &mut data)?;
+                if !region.is_erased(&data) {
+                    return Err(FlasherError::NotErased(sector.base_address));
+                }
+                Ok(())
+            })
+            .and_then(|()| {
+                flasher.run_program(|active| {
+                    let data: Vec<u8> = (0..sector.size as usize)
+                        .map(|offset| endurance_pattern(cycle, offset))
+                        .collect();
+                    let mut read_back = vec![0u8; page_size];
+
+                    for (i, page) in data.chunks(page_size).enumerate() {
+                        let page_address = sector.base_address + (i * page_size) as u32;
+                        active.program_page(page_address, page)?;
+
+                        active.read_block8(page_address, &mut read_back[..page.len()])?;
+                        if read_back[..page.len()] != *page {
+                            return Err(FlasherError::VerifyPage(page_address));
+                        }
+                    }
+                    Ok(())
+                })
+            });
+
+        match result {
+            Ok(()) => {
+                report.cycles_completed = cycle;
+                on_cycle(cycle);
+            }
+            // These errors mean that the flash itself failed, as opposed to the connection to the target.
+            Err(error @ FlasherError::EraseSector(..))
+            | Err(error @ FlasherError::ProgramPage(..))
+            | Err(error @ FlasherError::VerifyPage(_))
+            | Err(error @ FlasherError::NotErased(_)) => {
+                log::warn!("Endurance cycle {} failed: {}", cycle, error);
+                report.failure = Some(EnduranceFailure { cycle, error });
+                break;
+            }
+            Err(error) => {
+                return Err(FlashLoaderError::FlashBuilder(FlashBuilderError::from(
+                    error,
+                )))
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::endurance_pattern;
+
+    #[test]
+    fn pattern_is_inverted_every_cycle() {
+        for offset in 0..4 {
+            assert_eq!(endurance_pattern(1, offset), !endurance_pattern(2, offset));
+            assert_eq!(endurance_pattern(1, offset), endurance_pattern(3, offset));
+        }
+        assert_ne!(endurance_pattern(1, 0), endurance_pattern(1, 1));
+    }
+}
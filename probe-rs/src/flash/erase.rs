@@ -0,0 +1,138 @@
+use super::builder::FlashBuilderError;
+use super::flasher::{Flasher, FlasherError};
+use super::loader::FlashLoaderError;
+use super::progress::FlashProgress;
+use crate::config::memory::{FlashRegion, MemoryRegion, SectorInfo};
+use crate::session::Session;
+
+use std::time::Instant;
+
+/// Returns all erase units of `region`, see `FlashRegion::sector_info`.
+fn region_sectors(region: &FlashRegion) -> Vec<SectorInfo> {
+    let mut sectors = vec![];
+    let mut address = region.range.start;
+
+    while let Some(sector) = region.sector_info(address) {
+        sectors.push(sector);
+        address = match sector.base_address.checked_add(sector.size) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+
+    sectors
+}
+
+/// Erases all flash regions of `memory_map`, without having to download an image.
+///
+/// The `EraseAll` function of the flash algorithm is called once, as it erases the whole chip.
+/// If the algorithm has none, every sector of every flash region is erased one by one instead.
+/// The progress reports each erased sector, it reports no pages.
+pub fn erase_all(
+    session: &mut Session,
+    memory_map: &[MemoryRegion],
+    progress: &FlashProgress,
+) -> Result<(), FlashLoaderError> {
+    let flash_algorithm = session
+        .target
+        .flash_algorithm
+        .clone()
+        .ok_or(FlashLoaderError::NoFlashLoaderAlgorithmAttached)?;
+
+    let regions: Vec<FlashRegion> = memory_map
+        .iter()
+        .filter_map(|region| match region {
+            MemoryRegion::Flash(region) => Some(region.clone()),
+            _ => None,
+        })
+        .collect();
+    let sectors: Vec<Vec<SectorInfo>> = regions.iter().map(region_sectors).collect();
+
+    let total_sectors = sectors.iter().map(Vec::len).sum();
+    let sizes = sectors
+        .iter()
+        .flatten()
+        .next()
+        .map(|s| (s.size, s.page_size));
+    let (sector_size, page_size) = sizes.unwrap_or((0, 0));
+    progress.initialized(total_sectors, 0, sector_size, page_size);
+
+    progress.started_erasing();
+
+    let result = if flash_algorithm.pc_erase_all.is_some() {
+        match regions.first() {
+            Some(region) => {
+                // The algorithm erases the whole chip, no matter which region it is started for.
+                let mut flasher = Flasher::new(
+                    &session.target,
+                    &mut session.probe,
+                    &flash_algorithm,
+                    region,
+                );
+
+                let mut t = Instant::now();
+                let result = flasher.run_erase(|active| active.erase_all());
+                for sector in sectors.iter().flatten() {
+                    progress.sector_erased(sector.size, t.elapsed().as_millis());
+                    t = Instant::now();
+                }
+                result
+            }
+            None => Ok(()),
+        }
+    } else {
+        log::info!("The flash algorithm can not erase the whole chip, erasing each sector.");
+
+        regions
+            .iter()
+            .zip(&sectors)
+            .try_for_each(|(region, sectors)| {
+                let mut flasher = Flasher::new(
+                    &session.target,
+                    &mut session.probe,
+                    &flash_algorithm,
+                    region,
+                );
+
+                flasher.run_erase(|active| {
+                    for sector in sectors {
+                        let t = Instant::now();
+                        active.erase_sector(sector.base_address)?;
+                        progress.sector_erased(sector.size, t.elapsed().as_millis());
+                    }
+                    Ok::<(), FlasherError>(())
+                })
+            })
+    };
+
+    progress.finished_erasing();
+
+    result.map_err(|e| FlashLoaderError::FlashBuilder(FlashBuilderError::from(e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::region_sectors;
+    use crate::config::memory::FlashRegion;
+
+    #[test]
+    fn sectors_cover_the_region() {
+        let region = FlashRegion {
+            range: 0x0800_0000..0x0800_1000,
+            is_boot_memory: true,
+            sector_size: 0x400,
+            page_size: 0x100,
+            erased_byte_value: 0xFF,
+            erase_sector_time_ms: None,
+            program_page_time_ms: None,
+            erase_page_size: None,
+        };
+
+        let sectors = region_sectors(&region);
+
+        assert_eq!(sectors.len(), 4);
+        assert_eq!(sectors[0].base_address, 0x0800_0000);
+        assert_eq!(sectors[3].base_address, 0x0800_0C00);
+        assert!(sectors.iter().all(|s| s.size == 0x400));
+    }
+}
@@ -1,12 +1,21 @@
-use super::builder::FlashBuilder;
+use super::builder::{EraseStrategy, FlashBuilder};
+use super::session::FlashSession;
 use super::FlashProgress;
 use crate::config::{
     flash_algorithm::FlashAlgorithm,
     memory::{FlashRegion, MemoryRange},
     target::Target,
 };
+// The DHCSR is the same on all Cortex-M cores.
+use crate::cores::m0::Dhcsr;
 use crate::coresight::{access_ports::AccessPortError, memory::MI};
 use crate::probe::{DebugProbeError, MasterProbe};
+use crate::target::{enable_fpu, CoreRegister};
+use std::error::Error;
+use std::fmt;
+
+/// The pattern the stack of the flash algorithm is painted with to detect overflows.
+const STACK_CANARY: u32 = 0xC0DE_CAFE;
 
 pub trait Operation {
     fn operation() -> u32;
@@ -52,6 +61,8 @@ pub enum FlasherError {
     EraseAllNotSupported,
     EraseSector(u32, u32),
     ProgramPage(u32, u32),
+    VerifyPage(u32), // Contains the address of the page.
+    NotErased(u32),  // Contains the address of the sector.
     InvalidBufferNumber(u32, u32),
     UnalignedFlashWriteAddress,
     UnalignedPhraseLength,
@@ -62,6 +73,103 @@ pub enum FlasherError {
     AccessPort(AccessPortError),
     DebugProbe(DebugProbeError),
     AddressNotInRegion(u32, FlashRegion),
+    /// The core locked up while running the routine of the flash algorithm at `entry_point`.
+    AlgorithmLockedUp {
+        entry_point: u32,
+    },
+    /// The routine of the flash algorithm at `entry_point` overwrote the canary at the stack limit.
+    AlgorithmStackOverflow {
+        entry_point: u32,
+        stack_size: u32,
+    },
+}
+
+impl Error for FlasherError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use FlasherError::*;
+
+        match self {
+            AccessPort(ref e) => Some(e),
+            DebugProbe(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FlasherError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use FlasherError::*;
+
+        match self {
+            Init(code) => write!(
+                f,
+                "The init routine of the flash algorithm failed with code {}.",
+                code
+            ),
+            Uninit(code) => write!(
+                f,
+                "The uninit routine of the flash algorithm failed with code {}.",
+                code
+            ),
+            EraseAll(code) => write!(f, "Erasing the entire chip failed with code {}.", code),
+            EraseAllNotSupported => write!(
+                f,
+                "The flash algorithm does not support erasing the entire chip."
+            ),
+            EraseSector(code, address) => write!(
+                f,
+                "Erasing the sector at address {:#010x} failed with code {}.",
+                address, code
+            ),
+            ProgramPage(code, address) => write!(
+                f,
+                "Programming the page at address {:#010x} failed with code {}.",
+                address, code
+            ),
+            VerifyPage(address) => write!(
+                f,
+                "The page at address {:#010x} does not contain the programmed data.",
+                address
+            ),
+            NotErased(address) => write!(
+                f,
+                "The sector at address {:#010x} is not blank after erasing it.",
+                address
+            ),
+            InvalidBufferNumber(n, max) => write!(
+                f,
+                "Buffer {} does not exist, the flash algorithm only has {} buffers.",
+                n, max
+            ),
+            UnalignedFlashWriteAddress => write!(f, "The flash write address is not aligned."),
+            UnalignedPhraseLength => write!(f, "The phrase length is not aligned."),
+            ProgramPhrase(code, address) => write!(
+                f,
+                "Programming the phrase at address {:#010x} failed with code {}.",
+                address, code
+            ),
+            AnalyzerNotSupported => write!(f, "The flash analyzer is not supported."),
+            SizeNotPowerOf2 => write!(f, "The size is not a power of 2."),
+            AddressNotMultipleOfSize => write!(f, "The address is not a multiple of the size."),
+            AlgorithmLockedUp { entry_point } => write!(
+                f,
+                "The core locked up while running the flash algorithm routine at {:#010x}. The flash algorithm crashed, e.g. because it does not fit into the RAM of the target.",
+                entry_point
+            ),
+            AlgorithmStackOverflow { entry_point, stack_size } => write!(
+                f,
+                "The flash algorithm routine at {:#010x} overflowed its stack of {} bytes and may have corrupted its code or data.",
+                entry_point, stack_size
+            ),
+            AccessPort(ref e) => e.fmt(f),
+            DebugProbe(ref e) => e.fmt(f),
+            AddressNotInRegion(address, region) => write!(
+                f,
+                "Address {:#010x} is not in the flash region {:#010x}..{:#010x}.",
+                address, region.range.start, region.range.end
+            ),
+        }
+    }
 }
 
 impl From<DebugProbeError> for FlasherError {
@@ -76,12 +184,44 @@ impl From<AccessPortError> for FlasherError {
     }
 }
 
+/// Options for how the `Flasher` prepares the target and runs the flash algorithm.
+#[derive(Debug, Clone, Copy)]
+pub struct FlasherOptions {
+    /// Apply the watchdog disable register writes of the target after the core was reset,
+    /// before the flash algorithm is loaded. The default is `false`.
+    pub disable_watchdog: bool,
+    /// Reset the core before the flash algorithm is loaded. The default is `true`.
+    ///
+    /// Without a reset, the running firmware is only halted, which allows to update
+    /// a part of the flash which is not in use without restarting the application.
+    /// Beware that the flash controller and clocks are left in whatever state the firmware
+    /// configured, which some flash algorithms do not expect. The core registers are used
+    /// to run the flash algorithm, so the firmware can not simply be resumed afterwards.
+    pub reset_before_flash: bool,
+    /// Check the stack of the flash algorithm for overflows. The default is `false`.
+    ///
+    /// The stack is painted with a known pattern when the algorithm is loaded, and after each
+    /// routine the word at the stack limit is checked. This reads back the whole stack after each routine.
+    pub check_stack_overflow: bool,
+}
+
+impl Default for FlasherOptions {
+    fn default() -> Self {
+        Self {
+            disable_watchdog: false,
+            reset_before_flash: true,
+            check_stack_overflow: false,
+        }
+    }
+}
+
 pub struct Flasher<'a> {
     target: &'a Target,
     probe: &'a mut MasterProbe,
     flash_algorithm: &'a FlashAlgorithm,
     region: &'a FlashRegion,
     double_buffering_supported: bool,
+    options: FlasherOptions,
 }
 
 impl<'a> Flasher<'a> {
@@ -97,9 +237,15 @@ impl<'a> Flasher<'a> {
             flash_algorithm,
             region,
             double_buffering_supported: false,
+            options: FlasherOptions::default(),
         }
     }
 
+    /// Sets how the target is prepared and the flash algorithm is run, see `FlasherOptions`.
+    pub fn set_options(&mut self, options: FlasherOptions) {
+        self.options = options;
+    }
+
     pub fn region(&self) -> &FlashRegion {
         &self.region
     }
@@ -112,11 +258,33 @@ impl<'a> Flasher<'a> {
         self.double_buffering_supported
     }
 
+    pub fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), FlasherError> {
+        self.probe.read_block8(address, data)?;
+        Ok(())
+    }
+
     pub fn init<'b, 's: 'b, O: Operation>(
         &'s mut self,
         mut address: Option<u32>,
         clock: Option<u32>,
     ) -> Result<ActiveFlasher<'b, O>, FlasherError> {
+        self.load_algorithm()?;
+
+        if address.is_none() {
+            address = Some(self.region.flash_info().rom_start);
+        }
+
+        let mut flasher = self.activate();
+
+        flasher.init(address, clock)?;
+
+        Ok(flasher)
+    }
+
+    /// Resets and halts the core, and loads the flash algorithm into RAM.
+    ///
+    /// This does not run the init routine of the algorithm yet.
+    pub(crate) fn load_algorithm(&mut self) -> Result<(), FlasherError> {
         log::debug!("Initializing the flash algorithm.");
         let flasher = self;
         let algo = flasher.flash_algorithm;
@@ -153,10 +321,6 @@ impl<'a> Flasher<'a> {
             log::debug!("{}", instruction);
         }
 
-        if address.is_none() {
-            address = Some(flasher.region.flash_info().rom_start);
-        }
-
         // TODO: Halt & reset target.
         log::debug!("Halting core.");
         let cpu_info = flasher.target.core.halt(&mut flasher.probe);
@@ -165,8 +329,25 @@ impl<'a> Flasher<'a> {
             .target
             .core
             .wait_for_core_halted(&mut flasher.probe)?;
-        log::debug!("Reset and halt");
-        flasher.target.core.reset_and_halt(&mut flasher.probe)?;
+        if flasher.options.reset_before_flash {
+            log::debug!("Reset and halt");
+            flasher.target.reset_and_halt(flasher.probe)?;
+        } else {
+            log::warn!("Flashing without resetting the core first.");
+        }
+
+        if flasher.options.disable_watchdog {
+            log::debug!("Disabling the watchdog.");
+            for write in &flasher.target.watchdog_disable {
+                write.apply(flasher.probe)?;
+            }
+        }
+
+        // The reset disabled the FPU, which faults algorithms that were compiled with hardware float.
+        if flasher.target.has_fpu {
+            log::debug!("Enabling the FPU.");
+            enable_fpu(flasher.probe)?;
+        }
 
         // TODO: Possible special preparation of the target such as enabling faster clocks for the flash e.g.
 
@@ -201,24 +382,40 @@ impl<'a> Flasher<'a> {
 
         log::debug!("RAM contents match flashing algo blob.");
 
+        if flasher.options.check_stack_overflow {
+            log::debug!(
+                "Painting the stack at 0x{:08x} with the canary.",
+                algo.begin_stack - algo.stack_size
+            );
+            let canary = vec![STACK_CANARY; algo.stack_size as usize / 4];
+            flasher
+                .probe
+                .write_block32(algo.begin_stack - algo.stack_size, &canary)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates an `ActiveFlasher` for an algorithm which was already loaded with `load_algorithm`.
+    ///
+    /// The init routine for the operation is not run.
+    pub(crate) fn activate<O: Operation>(&mut self) -> ActiveFlasher<'_, O> {
         log::debug!("Preparing Flasher for region:");
-        log::debug!("{:#?}", &flasher.region);
+        log::debug!("{:#?}", &self.region);
         log::debug!(
             "Double buffering enabled: {}",
-            flasher.double_buffering_supported
+            self.double_buffering_supported
         );
-        let mut flasher = ActiveFlasher {
-            target: flasher.target,
-            probe: flasher.probe,
-            flash_algorithm: flasher.flash_algorithm,
-            region: flasher.region,
-            double_buffering_supported: flasher.double_buffering_supported,
+        ActiveFlasher {
+            target: self.target,
+            probe: self.probe,
+            flash_algorithm: self.flash_algorithm,
+            region: self.region,
+            double_buffering_supported: self.double_buffering_supported,
+            options: self.options,
+            current_routine: 0,
             _operation: core::marker::PhantomData,
-        };
-
-        flasher.init(address, clock)?;
-
-        Ok(flasher)
+        }
     }
 
     pub fn run_erase<T, E: From<FlasherError>>(
@@ -254,6 +451,13 @@ impl<'a> Flasher<'a> {
         Ok(r)
     }
 
+    /// Loads the flash algorithm once and returns a `FlashSession`, which can erase and program
+    /// the region multiple times without loading the algorithm again.
+    pub fn start_session(mut self) -> Result<FlashSession<'a>, FlasherError> {
+        self.load_algorithm()?;
+        Ok(FlashSession::new(self))
+    }
+
     pub fn flash_block(
         self,
         address: u32,
@@ -275,8 +479,16 @@ impl<'a> Flasher<'a> {
 
         let mut fb = FlashBuilder::new();
         fb.add_data(address, data).expect("Add Data failed");
-        fb.program(self, do_chip_erase, true, progress)
-            .expect("Add Data failed");
+        fb.program(
+            self,
+            do_chip_erase,
+            true,
+            None,
+            0,
+            EraseStrategy::AllThenProgram,
+            progress,
+        )
+        .expect("Add Data failed");
 
         Ok(())
     }
@@ -288,6 +500,9 @@ pub struct ActiveFlasher<'a, O: Operation> {
     flash_algorithm: &'a FlashAlgorithm,
     region: &'a FlashRegion,
     double_buffering_supported: bool,
+    options: FlasherOptions,
+    /// The entry point of the routine which was called last.
+    current_routine: u32,
     _operation: core::marker::PhantomData<O>,
 }
 
@@ -303,7 +518,7 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
                 address,
                 clock.or(Some(0)),
                 Some(O::operation()),
-                None,
+                algo.init_extra_argument,
                 true,
             )?;
 
@@ -328,31 +543,33 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
     // }
 
     pub fn uninit<'b, 's: 'b>(&'s mut self) -> Result<Flasher<'b>, FlasherError> {
+        self.uninit_operation(O::operation())?;
+
+        Ok(Flasher {
+            target: self.target,
+            probe: self.probe,
+            flash_algorithm: self.flash_algorithm,
+            region: self.region,
+            double_buffering_supported: self.double_buffering_supported,
+            options: self.options,
+        })
+    }
+
+    /// Runs the uninit routine of the algorithm for the given operation code.
+    pub(crate) fn uninit_operation(&mut self, operation: u32) -> Result<(), FlasherError> {
         log::debug!("Running uninit routine.");
         let algo = &self.flash_algorithm;
 
         if let Some(pc_uninit) = algo.pc_uninit {
-            let result = self.call_function_and_wait(
-                pc_uninit,
-                Some(O::operation()),
-                None,
-                None,
-                None,
-                false,
-            )?;
+            let result =
+                self.call_function_and_wait(pc_uninit, Some(operation), None, None, None, false)?;
 
             if result != 0 {
                 return Err(FlasherError::Uninit(result));
             }
         }
 
-        Ok(Flasher {
-            target: self.target,
-            probe: self.probe,
-            flash_algorithm: self.flash_algorithm,
-            region: self.region,
-            double_buffering_supported: self.double_buffering_supported,
-        })
+        Ok(())
     }
 
     fn call_function_and_wait(
@@ -387,6 +604,8 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
             init
         );
 
+        self.current_routine = pc;
+
         let algo = &self.flash_algorithm;
         let regs = self.target.core.registers();
 
@@ -434,12 +653,47 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
             .core
             .wait_for_core_halted(&mut self.probe)
             .is_err()
-        {}
+        {
+            // A locked up core never halts on its own, so waiting longer is pointless.
+            if Dhcsr::from(self.probe.read32(Dhcsr::ADDRESS)?).s_lockup() {
+                return Err(FlasherError::AlgorithmLockedUp {
+                    entry_point: self.current_routine,
+                });
+            }
+        }
+
+        if self.options.check_stack_overflow {
+            self.check_stack()?;
+        }
 
         let r = self.target.core.read_core_reg(&mut self.probe, regs.R0)?;
         Ok(r)
     }
 
+    /// Checks the canary at the stack limit, which was painted by `Flasher::load_algorithm`.
+    fn check_stack(&mut self) -> Result<(), FlasherError> {
+        let algo = &self.flash_algorithm;
+        let mut stack = vec![0; algo.stack_size as usize / 4];
+        self.probe
+            .read_block32(algo.begin_stack - algo.stack_size, &mut stack)?;
+
+        let unused = stack.iter().take_while(|&&w| w == STACK_CANARY).count();
+        log::debug!(
+            "The flash algorithm used at most {} of {} bytes of stack.",
+            (stack.len() - unused) * 4,
+            algo.stack_size
+        );
+
+        if !stack.is_empty() && unused == 0 {
+            return Err(FlasherError::AlgorithmStackOverflow {
+                entry_point: self.current_routine,
+                stack_size: algo.stack_size,
+            });
+        }
+
+        Ok(())
+    }
+
     pub fn read_block32(&mut self, address: u32, data: &mut [u32]) -> Result<(), FlasherError> {
         self.probe.read_block32(address, data)?;
         Ok(())
@@ -514,7 +768,7 @@ impl<'a> ActiveFlasher<'a, Program> {
             Some(address),
             Some(bytes.len() as u32),
             Some(algo.begin_data),
-            None,
+            algo.program_page_extra_argument,
             false,
         )?;
         log::info!("Flashing took: {:?}", t1.elapsed());
@@ -547,7 +801,7 @@ impl<'a> ActiveFlasher<'a, Program> {
             Some(address),
             Some(flasher.region.page_size),
             Some(algo.page_buffers[buffer_number as usize]),
-            None,
+            algo.program_page_extra_argument,
             false,
         )?;
 
@@ -1,13 +1,25 @@
+use crate::probe::DebugProbeError;
 use crate::session::Session;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::time::Duration;
 
-use super::builder::FlashBuilder;
-use super::flasher::Flasher;
+use super::builder::{EraseStrategy, FlashBuilder, FlashBuilderError};
+use super::flasher::{Flasher, FlasherOptions};
+use super::manifest::FlashManifest;
 use super::FlashProgress;
 use crate::config::memory::{FlashRegion, MemoryRegion};
 
+/// How many times the loader reconnects to a lost probe during one commit, if it is resilient.
+const MAX_RECONNECTS: usize = 5;
+
+/// How long the loader waits for a lost probe to show up again.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest gap between two chunks of data which `FlashLoader::contiguous_image` pads.
+const MAX_IMAGE_GAP: u32 = 1024 * 1024;
+
 /// `FlashLoader` is a struct which manages the flashing of any chunks of data onto any sections of flash.
 /// Use `add_data()` to add a chunks of data.
 /// Once you are done adding all your data, use `commit()` to flash the data.
@@ -17,6 +29,13 @@ pub struct FlashLoader<'a, 'b> {
     memory_map: &'a [MemoryRegion],
     builders: HashMap<FlashRegion, FlashBuilder<'b>>,
     keep_unwritten: bool,
+    pad_value: Option<u8>,
+    page_retries: usize,
+    flasher_options: FlasherOptions,
+    resilient: bool,
+    erase_strategy: EraseStrategy,
+    previous_manifest: Option<FlashManifest>,
+    blank_check: bool,
 }
 
 #[derive(Debug)]
@@ -24,9 +43,23 @@ pub enum FlashLoaderError {
     NoSuitableFlash(u32),      // Contains the faulty address.
     MemoryRegionNotFlash(u32), // Contains the faulty address.
     NoFlashLoaderAlgorithmAttached,
+    FlashBuilder(FlashBuilderError),
+    Reconnect(DebugProbeError, FlashBuilderError), // Contains the reconnect error and the error which caused it.
+    AddressNotInFlashRegion { address: u32, segment: String },
+    GapTooLarge { start: u32, end: u32 }, // Contains the bounds of the gap.
 }
 
-impl Error for FlashLoaderError {}
+impl Error for FlashLoaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use FlashLoaderError::*;
+
+        match self {
+            FlashBuilder(ref e) => Some(e),
+            Reconnect(ref e, _) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for FlashLoaderError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -36,6 +69,10 @@ impl fmt::Display for FlashLoaderError {
             NoSuitableFlash(addr) => write!(f, "No flash memory was found at address {:#08x}.", addr),
             MemoryRegionNotFlash(addr) => write!(f, "Trying to access flash at address {:#08x}, which is not inside any defined flash region.", addr),
             NoFlashLoaderAlgorithmAttached => write!(f, "Trying to write flash, but no flash loader algorithm is attached."),
+            FlashBuilder(ref e) => e.fmt(f),
+            Reconnect(ref e, ref cause) => write!(f, "Lost the connection to the probe ({}) and could not reconnect: {:?}", cause, e),
+            AddressNotInFlashRegion { address, segment } => write!(f, "The data of segment {} at address {:#010x} is not inside any flash region of the target.", segment, address),
+            GapTooLarge { start, end } => write!(f, "The gap between the data at {:#010x} and {:#010x} is too large to be padded into one image.", start, end),
         }
     }
 }
@@ -46,8 +83,128 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
             memory_map,
             builders: HashMap::new(),
             keep_unwritten,
+            pad_value: None,
+            page_retries: 1,
+            flasher_options: FlasherOptions::default(),
+            resilient: false,
+            erase_strategy: EraseStrategy::AllThenProgram,
+            previous_manifest: None,
+            blank_check: false,
+        }
+    }
+
+    /// Sets the value used to pad partially written pages.
+    ///
+    /// This has no effect if unwritten bytes are kept. By default, pages are padded with the erased byte value of their region.
+    pub fn set_pad_value(&mut self, pad_value: u8) {
+        self.pad_value = Some(pad_value);
+    }
+
+    /// Returns the value which pads unwritten bytes at `address`.
+    ///
+    /// This is the value set with `set_pad_value`, or the erased byte value of the flash region at `address`.
+    /// Addresses outside of all flash regions are padded with `0xFF`.
+    pub fn pad_value_at(&self, address: u32) -> u8 {
+        self.pad_value.unwrap_or_else(|| {
+            self.memory_map
+                .iter()
+                .find_map(|region| match region {
+                    MemoryRegion::Flash(region) if region.range.contains(&address) => {
+                        Some(region.erased_byte_value)
+                    }
+                    _ => None,
+                })
+                .unwrap_or(0xFF)
+        })
+    }
+
+    /// Sets how many times programming a page is retried before giving up.
+    ///
+    /// Each retry erases the sector containing the page and reprograms it. The default is `1`.
+    pub fn set_page_retries(&mut self, page_retries: usize) {
+        self.page_retries = page_retries;
+    }
+
+    /// Sets the options of the `Flasher` of each region, see `FlasherOptions`.
+    pub fn set_flasher_options(&mut self, flasher_options: FlasherOptions) {
+        self.flasher_options = flasher_options;
+    }
+
+    /// Sets whether the loader reconnects to the probe if the connection is lost while flashing.
+    ///
+    /// After reconnecting, the flash algorithm is loaded again and programming resumes
+    /// after the last completely programmed sector. The default is `false`.
+    pub fn set_resilient(&mut self, resilient: bool) {
+        self.resilient = resilient;
+    }
+
+    /// Sets whether all sectors are erased before programming or each sector right before it is programmed.
+    ///
+    /// The default is `EraseStrategy::AllThenProgram`.
+    pub fn set_erase_strategy(&mut self, erase_strategy: EraseStrategy) {
+        self.erase_strategy = erase_strategy;
+    }
+
+    /// Sets the manifest of the last flash operation, sectors which did not change since are skipped.
+    ///
+    /// The manifest must describe the current contents of the flash, see [`FlashManifest`].
+    ///
+    /// [`FlashManifest`]: ../manifest/struct.FlashManifest.html
+    pub fn set_previous_manifest(&mut self, manifest: FlashManifest) {
+        self.previous_manifest = Some(manifest);
+    }
+
+    /// Sets whether sectors which are blank already are not erased again. The default is `false`.
+    ///
+    /// See [`FlashBuilder::set_blank_check`] for how sectors are checked.
+    ///
+    /// [`FlashBuilder::set_blank_check`]: ../builder/struct.FlashBuilder.html#method.set_blank_check
+    pub fn set_blank_check(&mut self, blank_check: bool) {
+        self.blank_check = blank_check;
+    }
+
+    /// Returns the manifest of the last commit, which can be passed to the next flash operation.
+    pub fn manifest(&self) -> FlashManifest {
+        let mut manifest = FlashManifest::new();
+
+        for builder in self.builders.values() {
+            manifest.merge(&builder.manifest());
+        }
+
+        manifest
+    }
+
+    /// Returns all staged data as one contiguous image and its start address,
+    /// with the gaps between the chunks filled with the pad value.
+    ///
+    /// This is what transports without addresses need, e.g. XMODEM. Returns `None` if no data was staged.
+    /// Gaps larger than 1 MiB are refused, as they usually mean the data is spread over
+    /// several memories, e.g. the flash and the option bytes, which cannot be sent as one image.
+    pub fn contiguous_image(&self) -> Result<Option<(u32, Vec<u8>)>, FlashLoaderError> {
+        let mut chunks: Vec<_> = self.builders.values().flat_map(|b| b.data()).collect();
+        chunks.sort_by_key(|(address, _)| *address);
+
+        let start = match chunks.first() {
+            Some((address, _)) => *address,
+            None => return Ok(None),
+        };
+        let mut image = vec![];
+
+        for (address, data) in chunks {
+            let end = start + image.len() as u32;
+            if address.saturating_sub(end) > MAX_IMAGE_GAP {
+                return Err(FlashLoaderError::GapTooLarge {
+                    start: end,
+                    end: address,
+                });
+            }
+            image.resize((address - start) as usize, self.pad_value_at(address));
+            image.extend_from_slice(data);
         }
+
+        Ok(Some((start, image)))
     }
+
     /// Stages a junk of data to be programmed.
     ///
     /// The chunk can cross flash boundaries as long as one flash region connects to another flash region.
@@ -65,17 +222,19 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
                 };
 
                 // Determine how much more data can be contained by this region.
-                let program_length =
-                    usize::min(remaining, (region.range.end - address + 1) as usize);
+                let program_length = usize::min(remaining, (region.range.end - address) as usize);
 
                 // Add as much data to the builder as can be contained by this region.
+                let start = size - remaining;
                 self.builders
                     .get_mut(&region)
-                    .map(|r| r.add_data(address, &data[size - remaining..program_length]));
+                    .map(|r| r.add_data(address, &data[start..start + program_length]));
 
                 // Advance the cursors.
                 remaining -= program_length;
                 address += program_length as u32;
+            } else if possible_region.is_some() {
+                return Err(FlashLoaderError::MemoryRegionNotFlash(address));
             } else {
                 return Err(FlashLoaderError::NoSuitableFlash(address));
             }
@@ -87,17 +246,27 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
         memory_map: &[MemoryRegion],
         address: u32,
     ) -> Option<&MemoryRegion> {
-        for region in memory_map {
-            let r = match region {
-                MemoryRegion::Ram(r) => r.range.clone(),
-                MemoryRegion::Flash(r) => r.range.clone(),
-                MemoryRegion::Generic(r) => r.range.clone(),
-            };
-            if r.contains(&address) {
-                return Some(region);
+        memory_map.iter().find(|region| region.contains(address))
+    }
+
+    /// Compares all the stored data chunks with the contents of the flash.
+    ///
+    /// The flash is only read, nothing is erased or programmed.
+    /// Returns the lowest address which differs, or `None` if all data matches.
+    pub fn verify(&self, session: &mut Session) -> Result<Option<u32>, FlashLoaderError> {
+        let mut first_mismatch = None;
+
+        for builder in self.builders.values() {
+            if let Some(address) = builder
+                .verify(&mut session.probe)
+                .map_err(FlashLoaderError::FlashBuilder)?
+            {
+                first_mismatch =
+                    Some(first_mismatch.map_or(address, |first: u32| first.min(address)));
             }
         }
-        None
+
+        Ok(first_mismatch)
     }
 
     /// Writes all the stored data chunks to flash.
@@ -117,21 +286,55 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
         // If the session target has a flash algorithm attached, initiate the download.
         if let Some(flash_algorithm) = target.flash_algorithm.as_ref() {
             // Iterate over builders we've created and program the data.
-            for (region, builder) in &self.builders {
+            for (region, builder) in self.builders.iter_mut() {
+                if let Some(manifest) = &self.previous_manifest {
+                    builder.set_previous_manifest(manifest.clone());
+                }
+                builder.set_blank_check(self.blank_check);
+
                 log::debug!(
                     "Using builder for region (0x{:08x}..0x{:08x})",
                     region.range.start,
                     region.range.end
                 );
-                // Program the data.
-                builder
-                    .program(
-                        Flasher::new(target, probe, flash_algorithm, region),
+                let mut reconnects = 0;
+
+                loop {
+                    let mut flasher = Flasher::new(target, probe, flash_algorithm, region);
+                    flasher.set_options(self.flasher_options);
+
+                    // Program the data.
+                    let error = match builder.program(
+                        flasher,
                         do_chip_erase,
                         self.keep_unwritten,
+                        self.pad_value,
+                        self.page_retries,
+                        self.erase_strategy,
                         progress,
-                    )
-                    .unwrap();
+                    ) {
+                        Ok(()) => break,
+                        Err(error) => error,
+                    };
+
+                    if !self.resilient || reconnects >= MAX_RECONNECTS || probe.is_connected() {
+                        // The failure is not resumed, so the next commit starts over.
+                        builder.forget_completed_sectors();
+                        return Err(FlashLoaderError::FlashBuilder(error));
+                    }
+
+                    reconnects += 1;
+                    log::warn!(
+                        "Lost the connection to the probe: {}. Reconnecting ({}/{}).",
+                        error,
+                        reconnects,
+                        MAX_RECONNECTS
+                    );
+                    if let Err(e) = probe.reconnect(RECONNECT_TIMEOUT) {
+                        builder.forget_completed_sectors();
+                        return Err(FlashLoaderError::Reconnect(e, error));
+                    }
+                }
             }
 
             Ok(())
@@ -140,3 +343,113 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{FlashLoader, FlashLoaderError};
+    use crate::config::memory::{FlashRegion, MemoryRegion, RamRegion};
+
+    fn memory_map() -> Vec<MemoryRegion> {
+        vec![
+            MemoryRegion::Flash(FlashRegion {
+                range: 0x0800_0000..0x0800_1000,
+                is_boot_memory: true,
+                sector_size: 0x400,
+                page_size: 0x400,
+                erased_byte_value: 0xFF,
+                erase_sector_time_ms: None,
+                program_page_time_ms: None,
+                erase_page_size: None,
+            }),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2000_1000,
+                is_boot_memory: false,
+            }),
+        ]
+    }
+
+    #[test]
+    fn data_reaching_into_a_gap_is_refused() {
+        let memory_map = memory_map();
+        let mut loader = FlashLoader::new(&memory_map, false);
+        let data = [0u8; 0x20];
+
+        match loader.add_data(0x0800_0ff0, &data) {
+            Err(FlashLoaderError::NoSuitableFlash(address)) => assert_eq!(address, 0x0800_1000),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn contiguous_image_fills_gaps() {
+        let memory_map = memory_map();
+        let mut loader = FlashLoader::new(&memory_map, false);
+        loader.set_pad_value(0xA5);
+        let first = [0x11u8; 4];
+        let second = [0x22u8; 4];
+
+        assert_eq!(loader.contiguous_image().unwrap(), None);
+
+        loader.add_data(0x0800_0408, &second).unwrap();
+        loader.add_data(0x0800_0400, &first).unwrap();
+
+        let (start, image) = loader.contiguous_image().unwrap().unwrap();
+        assert_eq!(start, 0x0800_0400);
+        assert_eq!(
+            image,
+            vec![0x11, 0x11, 0x11, 0x11, 0xA5, 0xA5, 0xA5, 0xA5, 0x22, 0x22, 0x22, 0x22]
+        );
+    }
+
+    #[test]
+    fn contiguous_image_refuses_large_gaps() {
+        let memory_map = vec![
+            MemoryRegion::Flash(FlashRegion {
+                range: 0x0800_0000..0x0810_0000,
+                is_boot_memory: false,
+                sector_size: 0x10,
+                page_size: 0x10,
+                erased_byte_value: 0xFF,
+                erase_sector_time_ms: None,
+                program_page_time_ms: None,
+                erase_page_size: None,
+            }),
+            MemoryRegion::Flash(FlashRegion {
+                range: 0x1FFF_C000..0x1FFF_C010,
+                is_boot_memory: false,
+                sector_size: 0x10,
+                page_size: 0x10,
+                erased_byte_value: 0xFF,
+                erase_sector_time_ms: None,
+                program_page_time_ms: None,
+                erase_page_size: None,
+            }),
+        ];
+        let mut loader = FlashLoader::new(&memory_map, false);
+        let data = [0u8; 4];
+
+        loader.add_data(0x0800_0000, &data).unwrap();
+        loader.add_data(0x1FFF_C000, &data).unwrap();
+
+        match loader.contiguous_image() {
+            Err(FlashLoaderError::GapTooLarge { start, end }) => {
+                assert_eq!((start, end), (0x0800_0004, 0x1FFF_C000))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn data_in_ram_is_refused() {
+        let memory_map = memory_map();
+        let mut loader = FlashLoader::new(&memory_map, false);
+        let data = [0u8; 0x20];
+
+        match loader.add_data(0x2000_0000, &data) {
+            Err(FlashLoaderError::MemoryRegionNotFlash(address)) => {
+                assert_eq!(address, 0x2000_0000)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
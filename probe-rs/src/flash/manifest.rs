@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+/// The hashes of the contents of all sectors that were written by a successful flash operation.
+///
+/// Passing the manifest of the last flash operation to the next one skips erasing and
+/// programming all sectors whose contents did not change. This is only correct as long as
+/// nothing else wrote to the flash in between, so a manifest should be discarded as soon as
+/// the flash is touched without it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlashManifest {
+    /// The hash of the contents of each sector, by the address of the sector.
+    pub sectors: BTreeMap<u32, u64>,
+}
+
+impl FlashManifest {
+    /// Creates an empty manifest, which does not skip any sector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if the sector at `address` was last written with contents of the given hash.
+    pub fn contains(&self, address: u32, hash: u64) -> bool {
+        self.sectors.get(&address) == Some(&hash)
+    }
+
+    /// Adds all sectors of `other`, replacing the hashes of sectors which are in both manifests.
+    pub fn merge(&mut self, other: &FlashManifest) {
+        self.sectors.extend(other.sectors.iter());
+    }
+}
+
+/// Hashes the contents of a sector with 64 bit FNV-1a.
+///
+/// The hash has to be stable across runs and versions, so the hasher of the standard library can not be used.
+pub(crate) fn hash_sector<'a>(size: u32, pages: impl IntoIterator<Item = (u32, &'a [u8])>) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash = OFFSET_BASIS;
+    let mut add = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    };
+
+    add(&size.to_le_bytes());
+    for (address, data) in pages {
+        add(&address.to_le_bytes());
+        add(data);
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::hash_sector;
+
+    #[test]
+    fn hash_covers_addresses_and_data() {
+        let data = [1u8, 2, 3, 4];
+        let hash = hash_sector(0x400, vec![(0x0, &data[..])]);
+
+        assert_eq!(hash, hash_sector(0x400, vec![(0x0, &data[..])]));
+        assert_ne!(hash, hash_sector(0x400, vec![(0x100, &data[..])]));
+        assert_ne!(hash, hash_sector(0x800, vec![(0x0, &data[..])]));
+        assert_ne!(hash, hash_sector(0x400, vec![(0x0, &data[..3])]));
+    }
+}
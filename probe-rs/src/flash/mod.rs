@@ -4,12 +4,26 @@
 
 pub mod builder;
 pub mod download;
+pub mod dump;
+pub mod elf;
+pub mod endurance;
+pub mod erase;
 pub mod flasher;
 pub mod loader;
+pub mod manifest;
 pub mod progress;
+pub mod session;
+pub mod xmodem;
 
 pub use builder::*;
 pub use download::*;
+pub use dump::*;
+pub use elf::*;
+pub use endurance::*;
+pub use erase::*;
 pub use flasher::*;
 pub use loader::*;
+pub use manifest::*;
 pub use progress::*;
+pub use session::*;
+pub use xmodem::*;
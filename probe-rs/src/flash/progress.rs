@@ -44,6 +44,10 @@ impl FlashProgress {
         self.emit(ProgressEvent::SectorErased { size, time });
     }
 
+    pub fn sector_blank_skipped(&self, size: u32) {
+        self.emit(ProgressEvent::SectorBlankSkipped { size });
+    }
+
     pub fn finished_programming(&self) {
         self.emit(ProgressEvent::FinishedProgramming);
     }
@@ -51,6 +55,18 @@ impl FlashProgress {
     pub fn finished_erasing(&self) {
         self.emit(ProgressEvent::FinishedErasing);
     }
+
+    pub fn started_reading(&self, total_bytes: u32) {
+        self.emit(ProgressEvent::StartedReading { total_bytes });
+    }
+
+    pub fn chunk_read(&self, size: u32, time: u128) {
+        self.emit(ProgressEvent::ChunkRead { size, time });
+    }
+
+    pub fn finished_reading(&self) {
+        self.emit(ProgressEvent::FinishedReading);
+    }
 }
 
 pub enum ProgressEvent {
@@ -70,6 +86,19 @@ pub enum ProgressEvent {
         size: u32,
         time: u128,
     },
+    /// A sector was not erased, as it was blank already, see `FlashLoader::set_blank_check`.
+    SectorBlankSkipped {
+        size: u32,
+    },
     FinishedProgramming,
     FinishedErasing,
+    /// A memory dump started, see `read_memory_to_writer`.
+    StartedReading {
+        total_bytes: u32,
+    },
+    ChunkRead {
+        size: u32,
+        time: u128,
+    },
+    FinishedReading,
 }
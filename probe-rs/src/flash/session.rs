@@ -0,0 +1,155 @@
+use std::convert::TryFrom;
+use std::ops::Range;
+
+use super::flasher::{Erase, Flasher, FlasherError, Operation, Program};
+use crate::config::memory::{FlashRegion, MemoryRange};
+
+/// A flash algorithm which stays loaded in RAM across multiple erase and program operations.
+///
+/// Created with [`Flasher::start_session`]. The algorithm is loaded only once, and its init routine
+/// only runs again when switching between erasing and programming. Call `finish` when done,
+/// which runs the uninit routine.
+///
+/// This is useful to program several separate images, e.g. a bootloader, an application and its
+/// configuration, into the same region in one go.
+///
+/// [`Flasher::start_session`]: ../flasher/struct.Flasher.html#method.start_session
+pub struct FlashSession<'a> {
+    flasher: Flasher<'a>,
+    /// The operation the algorithm is currently initialized for.
+    operation: Option<u32>,
+}
+
+impl<'a> FlashSession<'a> {
+    pub(crate) fn new(flasher: Flasher<'a>) -> Self {
+        Self {
+            flasher,
+            operation: None,
+        }
+    }
+
+    pub fn region(&self) -> &FlashRegion {
+        self.flasher.region()
+    }
+
+    /// Erases all sectors which overlap with `range`.
+    pub fn erase_region(&mut self, range: Range<u32>) -> Result<(), FlasherError> {
+        self.check_range(&range)?;
+
+        let region = self.flasher.region().clone();
+
+        let mut address = range.start;
+        while address < range.end {
+            // The range is within the region, so there always is a sector.
+            let sector = region.sector_info(address).unwrap();
+            self.erase_sector(sector.base_address)?;
+            address = sector.base_address + sector.size;
+        }
+
+        Ok(())
+    }
+
+    /// Erases the sector which starts at `address`.
+    pub fn erase_sector(&mut self, address: u32) -> Result<(), FlasherError> {
+        self.check_range(&(address..address.saturating_add(1)))?;
+        self.prepare::<Erase>()?;
+
+        self.flasher.activate::<Erase>().erase_sector(address)
+    }
+
+    /// Programs the page which starts at `address` with `data`.
+    ///
+    /// The sector of the page has to be erased beforehand.
+    pub fn program_page(&mut self, address: u32, data: &[u8]) -> Result<(), FlasherError> {
+        self.check_range(&(address..address.saturating_add(data.len() as u32)))?;
+        self.prepare::<Program>()?;
+
+        self.flasher
+            .activate::<Program>()
+            .program_page(address, data)
+    }
+
+    /// Reads a block of bytes at `address`, e.g. to check programmed data.
+    pub fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), FlasherError> {
+        self.flasher.read_block8(address, data)
+    }
+
+    /// Programs `data` at `address`.
+    ///
+    /// The affected sectors have to be erased beforehand. Bytes of partially written pages
+    /// which are not part of `data` keep their current contents.
+    pub fn program_data(&mut self, address: u32, data: &[u8]) -> Result<(), FlasherError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // Data which runs past the end of the address space cannot be within the region.
+        let end = u32::try_from(data.len())
+            .ok()
+            .and_then(|len| address.checked_add(len))
+            .ok_or_else(|| FlasherError::AddressNotInRegion(address, self.region().clone()))?;
+        self.check_range(&(address..end))?;
+        self.prepare::<Program>()?;
+
+        let region = self.flasher.region().clone();
+        let mut active = self.flasher.activate::<Program>();
+
+        let mut page_address = region.page_info(address).unwrap().base_address;
+        let mut page = vec![0u8; region.page_size as usize];
+
+        while page_address < end {
+            let page_end = page_address + region.page_size;
+            let start = address.max(page_address);
+            let stop = end.min(page_end);
+
+            if start != page_address || stop != page_end {
+                active.read_block8(page_address, &mut page)?;
+            }
+            page[(start - page_address) as usize..(stop - page_address) as usize]
+                .copy_from_slice(&data[(start - address) as usize..(stop - address) as usize]);
+
+            active.program_page(page_address, &page)?;
+            page_address = page_end;
+        }
+
+        Ok(())
+    }
+
+    /// Runs the uninit routine of the algorithm and ends the session.
+    pub fn finish(mut self) -> Result<(), FlasherError> {
+        if let Some(operation) = self.operation.take() {
+            self.flasher
+                .activate::<Erase>()
+                .uninit_operation(operation)?;
+        }
+        Ok(())
+    }
+
+    fn check_range(&self, range: &Range<u32>) -> Result<(), FlasherError> {
+        if self.region().range.contains_range(range) {
+            Ok(())
+        } else {
+            Err(FlasherError::AddressNotInRegion(
+                range.start,
+                self.region().clone(),
+            ))
+        }
+    }
+
+    /// Makes sure the algorithm is initialized for the operation `O`.
+    fn prepare<O: Operation>(&mut self) -> Result<(), FlasherError> {
+        if self.operation == Some(O::operation()) {
+            return Ok(());
+        }
+
+        let rom_start = self.flasher.region().flash_info().rom_start;
+        let mut active = self.flasher.activate::<O>();
+        if let Some(operation) = self.operation.take() {
+            active.uninit_operation(operation)?;
+        }
+        active.init(Some(rom_start), None)?;
+        self.operation = Some(O::operation());
+
+        Ok(())
+    }
+}
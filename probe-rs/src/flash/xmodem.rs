@@ -0,0 +1,301 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SOH: u8 = 0x01;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Sent by a receiver which wants checksums as CRC-16.
+const CRC_MODE: u8 = b'C';
+
+/// The number of data bytes in each block.
+pub const BLOCK_SIZE: usize = 128;
+
+/// How many bytes the receiver may send before asking for the first block.
+/// Bootloaders often print a banner first.
+const MAX_GARBAGE: usize = 1024;
+
+#[derive(Debug)]
+pub enum XmodemError {
+    Io(io::Error),
+    /// The receiver cancelled the transfer.
+    Cancelled,
+    /// The receiver did not ask for a transfer in CRC mode.
+    NotReady,
+    /// The receiver rejected the block with the given number too often.
+    RetriesExhausted(u8),
+    /// The receiver answered a block with an unknown byte.
+    UnexpectedResponse(u8),
+    /// The receiver did not send anything within the timeout.
+    Timeout,
+}
+
+impl Error for XmodemError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            XmodemError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for XmodemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use XmodemError::*;
+
+        match self {
+            Io(ref e) => e.fmt(f),
+            Cancelled => write!(f, "The bootloader cancelled the transfer."),
+            NotReady => write!(f, "The bootloader did not ask for an XMODEM-CRC transfer."),
+            RetriesExhausted(block) => write!(
+                f,
+                "The bootloader rejected block {} after all retries.",
+                block
+            ),
+            UnexpectedResponse(byte) => write!(
+                f,
+                "The bootloader answered with the unexpected byte {:#04x}.",
+                byte
+            ),
+            Timeout => write!(f, "The bootloader did not answer in time."),
+        }
+    }
+}
+
+impl From<io::Error> for XmodemError {
+    fn from(error: io::Error) -> Self {
+        XmodemError::Io(error)
+    }
+}
+
+/// Sends images to a serial bootloader with the XMODEM-CRC protocol.
+///
+/// XMODEM transfers no addresses, the bootloader writes the image to its own application start address.
+/// The port has to be set up already, e.g. the baud rate of a serial port.
+///
+/// Reads of the port have to return when there is no data, e.g. because the port is non-blocking
+/// or has a read timeout, such that a receiver which stopped answering is detected.
+pub struct Xmodem<P: Read + Write> {
+    port: P,
+    retries: usize,
+    timeout: Duration,
+}
+
+impl<P: Read + Write> Xmodem<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            retries: 10,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets how many times a rejected block is sent again. The default is `10`.
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    /// Sets how long to wait for each answer of the receiver. The default is 10 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sends `data` in blocks of `BLOCK_SIZE` bytes, the last block is padded with `pad_value`.
+    ///
+    /// `block_sent` is called with the number of data bytes of each acknowledged block.
+    pub fn send(
+        &mut self,
+        data: &[u8],
+        pad_value: u8,
+        mut block_sent: impl FnMut(usize),
+    ) -> Result<(), XmodemError> {
+        self.wait_for_receiver()?;
+
+        for (index, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+            // Block numbers start at 1 and wrap around.
+            let number = (index + 1) as u8;
+
+            let mut block = Vec::with_capacity(BLOCK_SIZE + 5);
+            block.extend_from_slice(&[SOH, number, !number]);
+            block.extend_from_slice(chunk);
+            block.resize(BLOCK_SIZE + 3, pad_value);
+            let crc = crc16(&block[3..]);
+            block.extend_from_slice(&crc.to_be_bytes());
+
+            self.send_until_acknowledged(&block, number)?;
+            block_sent(chunk.len());
+        }
+
+        self.send_until_acknowledged(&[EOT], 0)
+    }
+
+    /// Waits for the receiver to ask for the first block.
+    fn wait_for_receiver(&mut self) -> Result<(), XmodemError> {
+        for _ in 0..MAX_GARBAGE {
+            match self.read_byte()? {
+                CRC_MODE => return Ok(()),
+                CAN => return Err(XmodemError::Cancelled),
+                _ => (),
+            }
+        }
+
+        Err(XmodemError::NotReady)
+    }
+
+    fn send_until_acknowledged(&mut self, packet: &[u8], number: u8) -> Result<(), XmodemError> {
+        for _ in 0..=self.retries {
+            self.port.write_all(packet)?;
+            self.port.flush()?;
+
+            match self.read_byte()? {
+                ACK => return Ok(()),
+                NAK => log::debug!("The bootloader rejected block {}, retrying.", number),
+                CAN => return Err(XmodemError::Cancelled),
+                byte => return Err(XmodemError::UnexpectedResponse(byte)),
+            }
+        }
+
+        Err(XmodemError::RetriesExhausted(number))
+    }
+
+    fn read_byte(&mut self) -> Result<u8, XmodemError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut byte = [0];
+
+        loop {
+            match self.port.read(&mut byte) {
+                Ok(1) => return Ok(byte[0]),
+                Ok(_) => (),
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            if Instant::now() >= deadline {
+                return Err(XmodemError::Timeout);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// The CRC-16 of XMODEM, with the polynomial 0x1021 and an initial value of 0.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::VecDeque;
+
+    /// A receiver which answers with the given bytes and records everything sent to it.
+    struct Receiver {
+        responses: VecDeque<u8>,
+        received: Vec<u8>,
+    }
+
+    impl Read for Receiver {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.responses.pop_front() {
+                Some(byte) => {
+                    buf[0] = byte;
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    impl Write for Receiver {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.received.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn rejected_block_is_sent_again() {
+        let receiver = Receiver {
+            responses: vec![b'>', CRC_MODE, NAK, ACK, ACK].into(),
+            received: vec![],
+        };
+        let mut xmodem = Xmodem::new(receiver);
+        let mut sent = vec![];
+
+        xmodem
+            .send(&[0x11; 4], 0xFF, |size| sent.push(size))
+            .unwrap();
+
+        assert_eq!(sent, vec![4]);
+
+        let received = &xmodem.port.received;
+        let block_length = BLOCK_SIZE + 5;
+        assert_eq!(received.len(), 2 * block_length + 1);
+        assert_eq!(
+            received[..block_length],
+            received[block_length..2 * block_length]
+        );
+        assert_eq!(&received[..3], &[SOH, 1, 0xFE]);
+        assert_eq!(&received[3..7], &[0x11; 4]);
+        assert!(received[7..BLOCK_SIZE + 3].iter().all(|b| *b == 0xFF));
+        assert_eq!(received[2 * block_length], EOT);
+    }
+
+    #[test]
+    fn silent_receiver_times_out() {
+        let receiver = Receiver {
+            responses: vec![CRC_MODE].into(),
+            received: vec![],
+        };
+        let mut xmodem = Xmodem::new(receiver);
+        xmodem.set_timeout(Duration::from_millis(10));
+
+        match xmodem.send(&[0; 4], 0xFF, |_| ()) {
+            Err(XmodemError::Timeout) => (),
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cancel_aborts_the_transfer() {
+        let receiver = Receiver {
+            responses: vec![CRC_MODE, CAN].into(),
+            received: vec![],
+        };
+
+        match Xmodem::new(receiver).send(&[0; 4], 0xFF, |_| ()) {
+            Err(XmodemError::Cancelled) => (),
+            other => panic!("Expected a cancelled transfer, got {:?}", other),
+        }
+    }
+}
@@ -17,5 +17,6 @@ pub mod coresight;
 pub mod debug;
 pub mod flash;
 pub mod probe;
+pub mod rtt;
 pub mod session;
 pub mod target;
@@ -54,7 +54,7 @@ impl Response for SerialNumber {
 }
 
 #[derive(Clone, Default, Debug)]
-pub struct FirmwareVersion(String);
+pub struct FirmwareVersion(pub String);
 
 impl Response for FirmwareVersion {
     fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
@@ -3,6 +3,7 @@ pub mod swd;
 pub mod swj;
 pub mod transfer;
 
+use super::usb_interface::DAPLinkDevice;
 use crate::probe::DebugProbeError;
 use core::ops::Deref;
 
@@ -78,22 +79,16 @@ impl From<hidapi::HidError> for Error {
 }
 
 pub(crate) fn send_command<Req: Request, Res: Response>(
-    device: &hidapi::HidDevice,
+    device: &DAPLinkDevice,
     request: Req,
 ) -> Result<Res> {
     const BUFFER_LEN: usize = 100;
     // Write the command & request to the buffer.
-    // TODO: Error handling & real USB writing.
-    // TODO: Use proper buffer size based on the HID
-    //       report count.
+    // The device adds the HID report ID and padding if needed.
     let mut write_buffer = [0; BUFFER_LEN];
-    write_buffer[1] = *Req::CATEGORY;
-    let mut size = request.to_bytes(&mut write_buffer, 1 + 1)?;
-    size += 2;
-
-    // ensure size of packet is at least 64
-    // this should be read from the USB HID Record
-    size = std::cmp::max(size, 64);
+    write_buffer[0] = *Req::CATEGORY;
+    let mut size = request.to_bytes(&mut write_buffer, 1)?;
+    size += 1;
 
     device.write(&write_buffer[..size])?;
     log::trace!("Send buffer: {:02X?}", &write_buffer[..size]);
@@ -54,6 +54,19 @@ impl SequenceRequest {
             data: owned_data,
         })
     }
+
+    /// Creates a sequence of `bit_count` bits, which are taken LSB first from `data`.
+    pub(crate) fn with_bit_count(data: &[u8], bit_count: u8) -> Result<SequenceRequest> {
+        let mut request = Self::new(data)?;
+
+        if usize::from(bit_count) > data.len() * 8 {
+            return Err(Error::TooMuchData);
+        }
+
+        request.bit_count = bit_count;
+
+        Ok(request)
+    }
 }
 
 pub struct SequenceResponse(pub(crate) Status);
@@ -1,21 +1,28 @@
 pub mod commands;
 pub mod tools;
+pub mod usb_interface;
+
+pub use usb_interface::{DAPLinkDevice, DapInterface};
 
 use crate::{
     coresight::{
         debug_port::DPRegister,
         dp_access::{DPAccess, DebugPort},
     },
-    probe::{DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo, Port, WireProtocol},
+    probe::{
+        DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo, Port, SelfTestCheck,
+        SelfTestOutcome, WireProtocol,
+    },
 };
 
 use log::{debug, error, info};
+use std::any::Any;
 
 use commands::{
     general::{
         connect::{ConnectRequest, ConnectResponse},
         disconnect::{DisconnectRequest, DisconnectResponse},
-        info::{Command, PacketCount, PacketSize},
+        info::{Command, FirmwareVersion, PacketCount, PacketSize},
         reset::{ResetRequest, ResetResponse},
     },
     swd,
@@ -32,17 +39,46 @@ use commands::{
 };
 
 pub struct DAPLink {
-    pub device: hidapi::HidDevice,
+    pub device: DAPLinkDevice,
     _hw_version: u8,
     _jtag_version: u8,
     _protocol: WireProtocol,
 
     packet_size: Option<u16>,
     packet_count: Option<u8>,
+
+    /// The TARGETSEL value of the target on an SWD multidrop bus.
+    target_sel: Option<u32>,
+
+    /// The SWJ clock speed in kHz.
+    speed_khz: u32,
+}
+
+/// The SWJ clock speed used if no other speed is set.
+const DEFAULT_SPEED_KHZ: u32 = 1_000;
+
+/// Builds the SWD packet which writes `target_sel` to the TARGETSEL register.
+///
+/// The target does not drive the ACK of this write, so the packet is sent as a plain
+/// SWJ sequence with the line driven low during the turnaround and ACK phases.
+/// Returns the packet LSB first and its length in bits.
+fn target_sel_sequence(target_sel: u32) -> ([u8; 6], u8) {
+    // Start, DP write to address 0xC, parity, stop and park bits.
+    const REQUEST: u64 = 0x99;
+
+    let parity = u64::from(target_sel.count_ones() & 1);
+
+    // 8 request bits, 5 turnaround and ACK bits, 32 data bits and a parity bit.
+    let packet = REQUEST | u64::from(target_sel) << 13 | parity << 45;
+
+    let mut bytes = [0; 6];
+    bytes.copy_from_slice(&packet.to_le_bytes()[..6]);
+
+    (bytes, 46)
 }
 
 impl DAPLink {
-    pub fn new_from_device(device: hidapi::HidDevice) -> Self {
+    pub fn new_from_device(device: DAPLinkDevice) -> Self {
         Self {
             device,
             _hw_version: 0,
@@ -50,6 +86,8 @@ impl DAPLink {
             _protocol: WireProtocol::Swd,
             packet_count: None,
             packet_size: None,
+            target_sel: None,
+            speed_khz: DEFAULT_SPEED_KHZ,
         }
     }
 
@@ -134,27 +172,62 @@ impl DebugProbe for DAPLink {
     where
         Self: Sized,
     {
-        if let Some(serial_number) = &info.serial_number {
-            Ok(Box::new(Self::new_from_device(
-                hidapi::HidApi::new()
-                    .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?
-                    .open_serial(info.vendor_id, info.product_id, &serial_number)
-                    .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?,
-            )))
-        } else {
-            Ok(Box::new(Self::new_from_device(
-                hidapi::HidApi::new()
-                    .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?
-                    .open(info.vendor_id, info.product_id)
-                    .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?,
-            )))
-        }
+        Ok(Box::new(Self::new_from_device(DAPLinkDevice::open(
+            info,
+            info.dap_interface,
+        )?)))
     }
 
     fn get_name(&self) -> &str {
         "DAPLink"
     }
 
+    fn self_test(&mut self) -> Vec<SelfTestCheck> {
+        let version = commands::send_command(&self.device, Command::FirmwareVersion)
+            .map(|FirmwareVersion(version)| version)
+            .map_err(DebugProbeError::from);
+
+        // CMSIS-DAP has no command to measure the target voltage.
+        let voltage = SelfTestCheck {
+            name: "target voltage",
+            outcome: SelfTestOutcome::Skipped("not supported by CMSIS-DAP".to_owned()),
+        };
+
+        let clock = self
+            .set_swj_clock(self.speed_khz * 1_000)
+            .map(|_| format!("set to {} kHz", self.speed_khz));
+
+        let status = commands::send_command(&self.device, Command::PacketCount)
+            .and_then(|PacketCount(count)| {
+                commands::send_command(&self.device, Command::PacketSize)
+                    .map(|PacketSize(size)| format!("{} packets of {} bytes", count, size))
+            })
+            .map_err(DebugProbeError::from);
+
+        vec![
+            SelfTestCheck::from_result("firmware version", version),
+            voltage,
+            SelfTestCheck::from_result("SWJ clock", clock),
+            SelfTestCheck::from_result("status", status),
+        ]
+    }
+
+    fn select_target(&mut self, target_sel: u32) -> Result<(), DebugProbeError> {
+        self.target_sel = Some(target_sel);
+        Ok(())
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.speed_khz = speed_khz;
+
+        // Before attaching, the speed is only stored and set by `attach`.
+        if self.packet_count.is_some() {
+            self.set_swj_clock(speed_khz * 1_000)?;
+        }
+
+        Ok(speed_khz)
+    }
+
     /// Enters debug mode.
     fn attach(&mut self, protocol: Option<WireProtocol>) -> Result<WireProtocol, DebugProbeError> {
         use commands::Error;
@@ -166,7 +239,7 @@ impl DebugProbe for DAPLink {
         self.packet_count = Some(packet_count);
         self.packet_size = Some(packet_size);
 
-        let clock = 1_000_000;
+        let clock = self.speed_khz * 1_000;
 
         info!("Attaching to target system (clock = {})", clock);
         self.set_swj_clock(clock)?;
@@ -208,6 +281,15 @@ impl DebugProbe for DAPLink {
 
         self.send_swj_sequences(SequenceRequest::new(&[0x00]).unwrap())?;
 
+        // On a multidrop bus, the line reset deselected all targets, select ours before
+        // the DPIDR is read.
+        if let Some(target_sel) = self.target_sel {
+            info!("Selecting multidrop target {:#010x}", target_sel);
+
+            let (packet, bit_count) = target_sel_sequence(target_sel);
+            self.send_swj_sequences(SequenceRequest::with_bit_count(&packet, bit_count).unwrap())?;
+        }
+
         use crate::coresight::debug_port::{Abort, Ctrl, DPv1, DebugPortId, Select, DPIDR};
 
         // assume a dpv1 port for now
@@ -276,6 +358,14 @@ impl DebugProbe for DAPLink {
         })?;
         Ok(())
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 impl DAPAccess for DAPLink {
@@ -298,7 +388,7 @@ impl DAPAccess for DAPLink {
                 } else {
                     match v.transfer_response.ack {
                         Ack::Ok => Ok(v.transfer_data),
-                        _ => Err(DebugProbeError::UnknownError),
+                        ack => Err(DebugProbeError::UnexpectedAck(ack as u8)),
                     }
                 }
             } else {
@@ -326,7 +416,7 @@ impl DAPAccess for DAPLink {
                 } else {
                     match v.transfer_response.ack {
                         Ack::Ok => Ok(()),
-                        _ => Err(DebugProbeError::UnknownError),
+                        ack => Err(DebugProbeError::UnexpectedAck(ack as u8)),
                     }
                 }
             } else {
@@ -428,3 +518,26 @@ impl Drop for DAPLink {
         let _ = self.detach();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::target_sel_sequence;
+
+    #[test]
+    fn target_sel_packet() {
+        // TARGETSEL of the second core of an RP2040, which has an odd number of set bits.
+        let (packet, bit_count) = target_sel_sequence(0x1100_2927);
+
+        assert_eq!(bit_count, 46);
+        assert_eq!(packet[0], 0x99);
+
+        let packet = packet
+            .iter()
+            .rev()
+            .fold(0u64, |value, byte| value << 8 | u64::from(*byte));
+
+        assert_eq!((packet >> 8) & 0x1F, 0);
+        assert_eq!((packet >> 13) as u32, 0x1100_2927);
+        assert_eq!(packet >> 45, 1);
+    }
+}
@@ -1,6 +1,25 @@
 use crate::probe::{DebugProbeInfo, DebugProbeType};
 
+/// Lists the CMSIS-DAP probes with a HID interface, and the ones which only have a bulk interface.
 pub fn list_daplink_devices() -> Vec<DebugProbeInfo> {
+    let mut probes = list_hid_devices();
+
+    for probe in super::usb_interface::list_v2_devices() {
+        let listed = probes.iter().any(|p| {
+            p.vendor_id == probe.vendor_id
+                && p.product_id == probe.product_id
+                && p.serial_number == probe.serial_number
+        });
+
+        if !listed {
+            probes.push(probe);
+        }
+    }
+
+    probes
+}
+
+fn list_hid_devices() -> Vec<DebugProbeInfo> {
     match hidapi::HidApi::new() {
         Ok(api) => api
             .devices()
@@ -30,7 +49,7 @@ pub fn is_daplink_device(device: &hidapi::HidDeviceInfo) -> bool {
     }
 }
 
-pub fn read_status(device: &hidapi::HidDevice) {
+pub fn read_status(device: &super::DAPLinkDevice) {
     let vendor_id: super::commands::general::info::VendorID =
         super::commands::send_command(device, super::commands::general::info::Command::VendorID)
             .unwrap();
@@ -0,0 +1,227 @@
+use super::commands::Error;
+use crate::probe::{DebugProbeError, DebugProbeInfo};
+
+use rusb::{Context, Device, DeviceHandle, Direction, TransferType, UsbContext};
+use serde::Serialize;
+
+use std::time::Duration;
+
+/// The timeout of a single bulk transfer.
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// The minimum size of a HID report of CMSIS-DAP v1, including the report ID.
+const HID_REPORT_SIZE: usize = 64;
+
+/// The USB interface which is used to talk to a CMSIS-DAP probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DapInterface {
+    /// Use the bulk interface if the probe has one, and the HID interface otherwise.
+    Auto,
+    /// Always use the HID interface of CMSIS-DAP v1.
+    Hid,
+    /// Always use the bulk interface of CMSIS-DAP v2, which is a lot faster.
+    Bulk,
+}
+
+impl std::str::FromStr for DapInterface {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "auto" => Ok(DapInterface::Auto),
+            "hid" => Ok(DapInterface::Hid),
+            "bulk" => Ok(DapInterface::Bulk),
+            _ => Err(format!(
+                "'{}' is not a CMSIS-DAP interface, use 'auto', 'hid' or 'bulk'.",
+                s
+            )),
+        }
+    }
+}
+
+/// An opened CMSIS-DAP probe.
+pub enum DAPLinkDevice {
+    /// CMSIS-DAP v1, which exchanges HID reports.
+    V1(hidapi::HidDevice),
+    /// CMSIS-DAP v2, which uses a pair of bulk endpoints.
+    V2 {
+        handle: DeviceHandle<Context>,
+        out_ep: u8,
+        in_ep: u8,
+    },
+}
+
+impl DAPLinkDevice {
+    /// Opens the probe described by `info` through the given interface.
+    pub fn open(info: &DebugProbeInfo, interface: DapInterface) -> Result<Self, DebugProbeError> {
+        match interface {
+            DapInterface::Hid => Self::open_v1(info),
+            DapInterface::Bulk => Self::open_v2(info),
+            DapInterface::Auto => Self::open_v2(info).or_else(|e| {
+                log::debug!(
+                    "Using the HID interface, as the bulk interface failed: {:?}",
+                    e
+                );
+                Self::open_v1(info)
+            }),
+        }
+    }
+
+    fn open_v1(info: &DebugProbeInfo) -> Result<Self, DebugProbeError> {
+        let api = hidapi::HidApi::new().map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)?;
+
+        let device = match &info.serial_number {
+            Some(serial_number) => api.open_serial(info.vendor_id, info.product_id, serial_number),
+            None => api.open(info.vendor_id, info.product_id),
+        };
+
+        device
+            .map(DAPLinkDevice::V1)
+            .map_err(|_| DebugProbeError::ProbeCouldNotBeCreated)
+    }
+
+    fn open_v2(info: &DebugProbeInfo) -> Result<Self, DebugProbeError> {
+        let context = Context::new().map_err(|_| DebugProbeError::USBError)?;
+        let devices = context.devices().map_err(|_| DebugProbeError::USBError)?;
+
+        for device in devices.iter() {
+            let descriptor = match device.device_descriptor() {
+                Ok(descriptor) => descriptor,
+                Err(_) => continue,
+            };
+
+            if descriptor.vendor_id() != info.vendor_id
+                || descriptor.product_id() != info.product_id
+            {
+                continue;
+            }
+
+            let mut handle = match device.open() {
+                Ok(handle) => handle,
+                Err(_) => continue,
+            };
+
+            if info.serial_number.is_some()
+                && handle.read_serial_number_string_ascii(&descriptor).ok() != info.serial_number
+            {
+                continue;
+            }
+
+            let (interface, out_ep, in_ep) =
+                bulk_interface(&device, &handle).ok_or(DebugProbeError::EndpointNotFound)?;
+
+            handle
+                .claim_interface(interface)
+                .map_err(|_| DebugProbeError::USBError)?;
+
+            return Ok(DAPLinkDevice::V2 {
+                handle,
+                out_ep,
+                in_ep,
+            });
+        }
+
+        Err(DebugProbeError::ProbeCouldNotBeCreated)
+    }
+
+    /// Sends a command, which starts with the command ID.
+    pub(crate) fn write(&self, command: &[u8]) -> Result<usize, Error> {
+        match self {
+            DAPLinkDevice::V1(device) => {
+                // The report ID comes first, and the report is sent with at least 64 bytes.
+                let mut report = vec![0];
+                report.extend_from_slice(command);
+                if report.len() < HID_REPORT_SIZE {
+                    report.resize(HID_REPORT_SIZE, 0);
+                }
+                Ok(device.write(&report)?)
+            }
+            DAPLinkDevice::V2 { handle, out_ep, .. } => handle
+                .write_bulk(*out_ep, command, TIMEOUT)
+                .map_err(|_| Error::USB),
+        }
+    }
+
+    /// Reads a response, which starts with the ID of the command.
+    pub(crate) fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            DAPLinkDevice::V1(device) => Ok(device.read(buffer)?),
+            DAPLinkDevice::V2 { handle, in_ep, .. } => handle
+                .read_bulk(*in_ep, buffer, TIMEOUT)
+                .map_err(|_| Error::USB),
+        }
+    }
+}
+
+/// Finds the CMSIS-DAP v2 interface of a device and returns its number and its bulk out and in endpoints.
+///
+/// The interface is recognized by `CMSIS-DAP` in its name, as the specification demands.
+fn bulk_interface(
+    device: &Device<Context>,
+    handle: &DeviceHandle<Context>,
+) -> Option<(u8, u8, u8)> {
+    let config = device.active_config_descriptor().ok()?;
+
+    for interface in config.interfaces() {
+        for descriptor in interface.descriptors() {
+            let is_cmsis_dap = descriptor
+                .description_string_index()
+                .and_then(|index| handle.read_string_descriptor_ascii(index).ok())
+                .filter(|name| name.contains("CMSIS-DAP"))
+                .is_some();
+
+            if !is_cmsis_dap {
+                continue;
+            }
+
+            let bulk_endpoint = |direction| {
+                descriptor
+                    .endpoint_descriptors()
+                    .find(|ep| {
+                        ep.transfer_type() == TransferType::Bulk && ep.direction() == direction
+                    })
+                    .map(|ep| ep.address())
+            };
+
+            if let (Some(out_ep), Some(in_ep)) =
+                (bulk_endpoint(Direction::Out), bulk_endpoint(Direction::In))
+            {
+                return Some((descriptor.interface_number(), out_ep, in_ep));
+            }
+        }
+    }
+
+    None
+}
+
+/// Lists the probes which have a CMSIS-DAP v2 interface, with their product names.
+pub(crate) fn list_v2_devices() -> Vec<DebugProbeInfo> {
+    let context = match Context::new() {
+        Ok(context) => context,
+        Err(_) => return vec![],
+    };
+    let devices = match context.devices() {
+        Ok(devices) => devices,
+        Err(_) => return vec![],
+    };
+
+    devices
+        .iter()
+        .filter_map(|device| {
+            let descriptor = device.device_descriptor().ok()?;
+            let handle = device.open().ok()?;
+
+            bulk_interface(&device, &handle)?;
+
+            Some(DebugProbeInfo::new(
+                handle
+                    .read_product_string_ascii(&descriptor)
+                    .unwrap_or_else(|_| "Unknown CMSIS-DAP Probe".to_owned()),
+                descriptor.vendor_id(),
+                descriptor.product_id(),
+                handle.read_serial_number_string_ascii(&descriptor).ok(),
+                crate::probe::DebugProbeType::DAPLink,
+            ))
+        })
+        .collect()
+}
@@ -3,38 +3,83 @@ pub mod stlink;
 
 use crate::coresight::{
     access_ports::{
-        custom_ap::{CtrlAP, ERASEALL, ERASEALLSTATUS, RESET},
-        generic_ap::{APClass, APType, GenericAP, IDR},
+        custom_ap::{ControlApKind, DMCSW, ERASEALL, ERASEALLSTATUS, REQUEST, RESET, RETURN},
+        generic_ap::GenericAP,
         memory_ap::MemoryAP,
         APRegister, AccessPortError,
     },
-    ap_access::{get_ap_by_idr, APAccess, AccessPort},
+    ap_access::{get_ap_by_idr, valid_access_ports, APAccess, AccessPort},
     common::Register,
     memory::{adi_v5_memory_interface::ADIMemoryInterface, MI},
+    tpiu::TraceConfig,
 };
 
 use log::debug;
+use serde::{Deserialize, Serialize};
 
 use colored::*;
+use std::any::Any;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
-use std::time::Instant;
+use std::ops::Range;
+use std::time::{Duration, Instant};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum WireProtocol {
     Swd,
     Jtag,
 }
 
+impl std::str::FromStr for WireProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "swd" => Ok(WireProtocol::Swd),
+            "jtag" => Ok(WireProtocol::Jtag),
+            _ => Err(format!(
+                "'{}' is not a wire protocol, use 'swd' or 'jtag'.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for WireProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireProtocol::Swd => write!(f, "SWD"),
+            WireProtocol::Jtag => write!(f, "JTAG"),
+        }
+    }
+}
+
+/// How often and how fast a memory read which faulted is retried, see `MasterProbe::set_fault_retry`.
+///
+/// This is meant for peripherals which fault until their clock is stable, e.g. right after ungating it.
+/// It is independent of the WAIT retries of the probe, which only cover a busy bus.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultRetry {
+    /// How many times a faulting read is retried.
+    pub retries: usize,
+    /// How long to wait before each retry.
+    pub delay: Duration,
+}
+
+impl Default for FaultRetry {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            delay: Duration::from_millis(1),
+        }
+    }
+}
+
 const UNLOCK_TIMEOUT: u64 = 15;
-const CTRL_AP_IDR: IDR = IDR {
-    REVISION: 0,
-    DESIGNER: 0x0144,
-    CLASS: APClass::Undefined,
-    _RES0: 0,
-    VARIANT: 0,
-    TYPE: APType::JTAG_COM_AP,
-};
+/// How long a request to the NXP debug mailbox may take, including the reset of the chip.
+const DEBUG_MAILBOX_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Debug)]
 pub enum DebugProbeError {
@@ -51,12 +96,20 @@ pub enum DebugProbeError {
     BlanksNotAllowedOnDPRegister,
     RegisterAddressMustBe16Bit,
     NotEnoughBytesRead,
+    NotEnoughBytesWritten,
     EndpointNotFound,
     RentalInitError,
     ProbeCouldNotBeCreated,
     TargetPowerUpFailed,
     Timeout,
     AccessPortError(AccessPortError),
+    NotSupportedByProbe,
+    /// The target answered a transfer with the given ACK instead of OK.
+    UnexpectedAck(u8),
+    /// The DPIDR of the debug port read the given value, which is no valid ID.
+    InvalidDpidr(u32),
+    /// No wire protocol worked, see `DebugProbeInfo::open_autodetect`.
+    ProtocolDetectionFailed(Vec<AttachAttempt>),
 }
 
 impl Error for DebugProbeError {
@@ -71,7 +124,69 @@ impl Error for DebugProbeError {
 impl fmt::Display for DebugProbeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // TODO: Cleanup of Debug Probe Errors
-        write!(f, "{:?}", self)
+        match self {
+            DebugProbeError::UnexpectedAck(ack) => match ack {
+                2 => write!(f, "the target answered WAIT"),
+                4 => write!(f, "the target answered FAULT"),
+                7 => write!(f, "no response (no ACK)"),
+                ack => write!(f, "the target answered with the invalid ACK {:#05b}", ack),
+            },
+            DebugProbeError::InvalidDpidr(value) => {
+                write!(f, "no valid debug port (DPIDR read {:#010x})", value)
+            }
+            DebugProbeError::ProtocolDetectionFailed(attempts) => {
+                for (index, attempt) in attempts.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}: {}", attempt.protocol, attempt.error)?;
+                }
+                write!(f, ". Check the wiring and the power of the target.")
+            }
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// A wire protocol which failed to attach to the target, see `DebugProbeInfo::open_autodetect`.
+#[derive(Debug)]
+pub struct AttachAttempt {
+    pub protocol: WireProtocol,
+    pub error: DebugProbeError,
+}
+
+/// The outcome of one check of a probe self-test.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelfTestOutcome {
+    Passed(String),
+    Failed(String),
+    /// The probe does not support the check.
+    Skipped(String),
+}
+
+/// One check of a probe self-test, see [`DebugProbeInfo::self_test`].
+///
+/// [`DebugProbeInfo::self_test`]: struct.DebugProbeInfo.html#method.self_test
+#[derive(Debug, Clone)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    pub outcome: SelfTestOutcome,
+}
+
+impl SelfTestCheck {
+    /// Creates a check from the result of a probe command.
+    ///
+    /// `DebugProbeError::NotSupportedByProbe` skips the check, all other errors fail it.
+    pub fn from_result(name: &'static str, result: Result<String, DebugProbeError>) -> Self {
+        let outcome = match result {
+            Ok(details) => SelfTestOutcome::Passed(details),
+            Err(DebugProbeError::NotSupportedByProbe) => {
+                SelfTestOutcome::Skipped("not supported by the probe".to_owned())
+            }
+            Err(e) => SelfTestOutcome::Failed(e.to_string()),
+        };
+
+        Self { name, outcome }
     }
 }
 
@@ -133,6 +248,25 @@ pub struct MasterProbe {
     actual_probe: Box<dyn DebugProbe>,
     current_apsel: u8,
     current_apbanksel: u8,
+    /// The probe info, the protocol and the multidrop target the probe was opened with, if it was
+    /// opened with `DebugProbeInfo::open`. This is needed to find the probe again after it was lost.
+    origin: Option<(DebugProbeInfo, WireProtocol, Option<u32>)>,
+    /// Whether a memory AP only supports 32 bit accesses, per AP number, once it was detected.
+    only_32bit_data_size: HashMap<u8, bool>,
+    /// The speed which was set with `set_speed`, which is set again after reconnecting.
+    speed_khz: Option<u32>,
+    /// The memory AP and ROM table address which were set with `force_access_port`.
+    forced_access_port: Option<(u8, u64)>,
+    /// The time to wait before each transfer, see `set_inter_transfer_delay`.
+    inter_transfer_delay: Duration,
+    /// The kind and the number of the control AP, once it was found.
+    control_ap: Option<(ControlApKind, u8)>,
+    /// Whether `access_ports` scans for all APs, see `set_single_access_port`.
+    single_access_port: bool,
+    /// How memory reads are retried if they fault, see `set_fault_retry`.
+    fault_retry: Option<FaultRetry>,
+    /// The address ranges in which 8 bit accesses are real 8 bit accesses, see `enable_byte_access`.
+    byte_access_ranges: Vec<Range<u32>>,
 }
 
 impl MasterProbe {
@@ -141,13 +275,407 @@ impl MasterProbe {
             actual_probe: probe,
             current_apbanksel: 0,
             current_apsel: 0,
+            origin: None,
+            only_32bit_data_size: HashMap::new(),
+            speed_khz: None,
+            forced_access_port: None,
+            inter_transfer_delay: Duration::from_secs(0),
+            control_ap: None,
+            single_access_port: false,
+            fault_retry: None,
+            byte_access_ranges: Vec::new(),
         }
     }
 
+    /// Returns the info of the probe, if it was opened with `DebugProbeInfo::open`.
+    pub fn probe_info(&self) -> Option<&DebugProbeInfo> {
+        self.origin.as_ref().map(|(info, _, _)| info)
+    }
+
+    /// Returns `true` if the probe still responds.
+    ///
+    /// This reads the ID register of the debug port, so it also fails if the target is gone.
+    pub fn is_connected(&mut self) -> bool {
+        self.actual_probe.read_register(Port::DebugPort, 0).is_ok()
+    }
+
+    /// Opens the probe again after the USB connection was lost, e.g. by a glitch.
+    ///
+    /// The probe is searched by its USB IDs and serial number until it shows up again,
+    /// or until `timeout` expires. It is attached with the same protocol, speed
+    /// and multidrop target as before.
+    /// This only works for probes which were opened with `DebugProbeInfo::open`.
+    ///
+    /// As with `reset_probe`, the state of the target connection is lost.
+    pub fn reconnect(&mut self, timeout: Duration) -> Result<(), DebugProbeError> {
+        let (info, protocol, target_sel) = self
+            .origin
+            .clone()
+            .ok_or(DebugProbeError::NotSupportedByProbe)?;
+        let speed_khz = self.speed_khz;
+        let forced_access_port = self.forced_access_port;
+        let inter_transfer_delay = self.inter_transfer_delay;
+        let single_access_port = self.single_access_port;
+        let fault_retry = self.fault_retry;
+        let start = Instant::now();
+
+        loop {
+            let found = list_all().into_iter().find(|candidate| {
+                candidate.vendor_id == info.vendor_id
+                    && candidate.product_id == info.product_id
+                    && candidate.serial_number == info.serial_number
+            });
+
+            if let Some(candidate) = found {
+                match candidate.open_with_target_sel(Some(protocol), target_sel) {
+                    Ok(probe) => {
+                        *self = probe;
+                        self.forced_access_port = forced_access_port;
+                        self.inter_transfer_delay = inter_transfer_delay;
+                        self.single_access_port = single_access_port;
+                        self.fault_retry = fault_retry;
+                        if let Some(speed_khz) = speed_khz {
+                            self.set_speed(speed_khz)?;
+                        }
+                        return Ok(());
+                    }
+                    Err(e) => log::debug!("Opening the probe again failed: {:?}", e),
+                }
+            }
+
+            if start.elapsed() > timeout {
+                return Err(DebugProbeError::Timeout);
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+
+    /// Resets the target device.
+    ///
+    /// Pending transfers are flushed first.
     pub fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.flush()?;
         self.actual_probe.target_reset()
     }
 
+    /// Completes all transfers which the probe has queued.
+    ///
+    /// Errors of queued transfers might only be reported here instead of by the
+    /// call which issued the transfer.
+    pub fn flush(&mut self) -> Result<(), DebugProbeError> {
+        self.actual_probe.flush()
+    }
+
+    /// Sets the clock speed of the wire protocol to at most `speed_khz`.
+    ///
+    /// Returns the speed which was actually set, as probes only support some speeds.
+    pub fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        let actual_speed_khz = self.actual_probe.set_speed(speed_khz)?;
+        self.speed_khz = Some(speed_khz);
+        Ok(actual_speed_khz)
+    }
+
+    /// Waits for `delay` before each transfer of the probe, i.e. each register access and
+    /// each block transfer.
+    ///
+    /// This makes transfers a lot slower and should normally be left at zero, which is the default.
+    /// It can make the connection reliable where the signal integrity is marginal, e.g. with long
+    /// flying leads, and back-to-back transfers get corrupted even at a low clock speed.
+    pub fn set_inter_transfer_delay(&mut self, delay: Duration) {
+        self.inter_transfer_delay = delay;
+    }
+
+    /// Retries all memory reads which fault, as configured by `retry`. `None` disables the retries,
+    /// which is the default.
+    ///
+    /// To retry only some reads, use `with_fault_retry` instead.
+    pub fn set_fault_retry(&mut self, retry: Option<FaultRetry>) {
+        self.fault_retry = retry;
+    }
+
+    /// Runs the memory access `access` and retries it as configured by `retry` if it faults.
+    ///
+    /// The sticky error flags of the debug port are cleared before each retry, as the
+    /// debug port ignores further transfers until then.
+    ///
+    /// ```no_run
+    /// # use probe_rs::{coresight::memory::MI, probe::{FaultRetry, MasterProbe}};
+    /// # fn f(probe: &mut MasterProbe) -> Result<u32, Box<dyn std::error::Error>> {
+    /// let value = probe.with_fault_retry(FaultRetry::default(), |probe| probe.read32(0x4002_1000))?;
+    /// # Ok(value)
+    /// # }
+    /// ```
+    pub fn with_fault_retry<T>(
+        &mut self,
+        retry: FaultRetry,
+        mut access: impl FnMut(&mut Self) -> Result<T, AccessPortError>,
+    ) -> Result<T, AccessPortError> {
+        let mut attempt = 0;
+
+        loop {
+            match access(self) {
+                Err(AccessPortError::RegisterReadError { addr, name })
+                    if attempt < retry.retries =>
+                {
+                    attempt += 1;
+                    log::debug!(
+                        "Reading {} ({:#04x}) faulted, retrying ({}/{}).",
+                        name,
+                        addr,
+                        attempt,
+                        retry.retries
+                    );
+
+                    self.clear_sticky_errors().map_err(|e| {
+                        log::warn!(
+                            "Clearing the sticky error flags after the fault failed: {}",
+                            e
+                        );
+                        AccessPortError::RegisterReadError { addr, name }
+                    })?;
+                    std::thread::sleep(retry.delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Clears the sticky error flags of the debug port after a faulted transfer.
+    fn clear_sticky_errors(&mut self) -> Result<(), DebugProbeError> {
+        use crate::coresight::debug_port::Abort;
+
+        let mut abort = Abort(0);
+        abort.set_orunerrclr(true);
+        abort.set_wderrclr(true);
+        abort.set_stkerrclr(true);
+        abort.set_stkcmpclr(true);
+
+        self.actual_probe
+            .write_register(Port::DebugPort, u16::from(Abort::ADDRESS), abort.into())
+    }
+
+    /// Runs the memory read `read`, with the retries which were set with `set_fault_retry`.
+    fn read_memory<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> Result<T, AccessPortError>,
+    ) -> Result<T, AccessPortError> {
+        match self.fault_retry {
+            Some(retry) => self.with_fault_retry(retry, read),
+            None => read(self),
+        }
+    }
+
+    /// Waits for the delay which was set with `set_inter_transfer_delay`, if any.
+    fn delay_transfer(&self) {
+        if self.inter_transfer_delay > Duration::from_secs(0) {
+            std::thread::sleep(self.inter_transfer_delay);
+        }
+    }
+
+    /// Returns the maximum number of bytes the probe transfers to or from memory at once.
+    pub fn max_memory_transfer_size(&self) -> usize {
+        self.actual_probe.max_memory_transfer_size()
+    }
+
+    /// Uses the memory AP `ap` for all memory accesses instead of AP 0, and the ROM table at
+    /// `rom_table_base` when the chip is detected, instead of discovering the APs and reading the
+    /// ROM table address from the BASE register.
+    ///
+    /// This is an escape hatch for chips with an AP topology the automatic discovery can not handle.
+    pub fn force_access_port(&mut self, ap: u8, rom_table_base: u64) {
+        self.forced_access_port = Some((ap, rom_table_base));
+    }
+
+    /// Returns the memory AP and ROM table address which were set with `force_access_port`.
+    pub fn forced_access_port(&self) -> Option<(u8, u64)> {
+        self.forced_access_port
+    }
+
+    /// Uses AP 0 as the only AP when the chip is detected, instead of scanning all 256 AP numbers.
+    ///
+    /// The scan reads the IDR of every AP number, which are hundreds of failing transfers on most
+    /// chips. This saves them on chips which are known to have everything behind AP 0.
+    pub fn set_single_access_port(&mut self, single_access_port: bool) {
+        self.single_access_port = single_access_port;
+    }
+
+    /// Uses real 8 bit accesses for `read8` and `write8` in the address range `range`.
+    ///
+    /// Outside of these ranges, 8 bit accesses read and write the containing word, which works with
+    /// every AP. Byte wide peripheral registers, which have side effects on accesses to their
+    /// neighbours, need real 8 bit accesses. They are only done if the memory AP supports them.
+    pub fn enable_byte_access(&mut self, range: Range<u32>) {
+        self.byte_access_ranges.push(range);
+    }
+
+    /// Returns `true` if `address` is in a range given to `enable_byte_access`.
+    fn has_byte_access(&self, address: u32) -> bool {
+        self.byte_access_ranges
+            .iter()
+            .any(|range| range.contains(&address))
+    }
+
+    /// Returns the APs of the target, which are searched for the ROM table when the chip is detected.
+    ///
+    /// This scans all AP numbers, unless `set_single_access_port` was used.
+    pub fn access_ports(&mut self) -> Vec<GenericAP> {
+        if self.single_access_port {
+            vec![GenericAP::new(0)]
+        } else {
+            valid_access_ports(self)
+        }
+    }
+
+    /// Returns the number of the memory AP which memory accesses go through.
+    ///
+    /// This is AP 0, unless another AP was forced with `force_access_port`.
+    pub fn memory_access_port(&self) -> u8 {
+        self.forced_access_port.map_or(0, |(ap, _)| ap)
+    }
+
+    /// Returns the memory interface of the default memory AP, with transfers sized for the probe.
+    fn memory_interface(&mut self) -> ADIMemoryInterface {
+        let port = self.memory_access_port();
+        self.memory_interface_for(port)
+    }
+
+    /// Returns the memory interface of the memory AP `port`, with transfers sized for the probe.
+    ///
+    /// The access sizes supported by the AP are detected on first use.
+    fn memory_interface_for(&mut self, port: u8) -> ADIMemoryInterface {
+        let mut interface =
+            ADIMemoryInterface::new(port).with_max_transfer_size(self.max_memory_transfer_size());
+
+        match self.only_32bit_data_size.get(&port) {
+            Some(&only_32bit_data_size) => {
+                interface = interface.with_only_32bit_data_size(only_32bit_data_size)
+            }
+            None => match interface.detect_data_sizes(self) {
+                Ok(()) => {
+                    self.only_32bit_data_size
+                        .insert(port, interface.only_32bit_data_size());
+                }
+                Err(e) => log::warn!(
+                    "Detecting the supported access sizes of AP {} failed, using 32 bit accesses: {}",
+                    port,
+                    e
+                ),
+            },
+        }
+
+        interface
+    }
+
+    /// Reads a block of bytes at `address` through the memory AP `ap`.
+    ///
+    /// All other memory accesses go through AP 0, or the AP set with `force_access_port`.
+    /// This reaches memory which is only mapped
+    /// behind another AP, e.g. debug registers behind an APB-AP.
+    pub fn read_block8_via(
+        &mut self,
+        ap: GenericAP,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<(), AccessPortError> {
+        self.memory_interface_for(ap.get_port_number())
+            .read_block8(self, address, data)
+    }
+
+    /// Writes a block of bytes at `address` through the memory AP `ap`.
+    ///
+    /// See `read_block8_via` for when this is needed.
+    pub fn write_block8_via(
+        &mut self,
+        ap: GenericAP,
+        address: u32,
+        data: &[u8],
+    ) -> Result<(), AccessPortError> {
+        self.memory_interface_for(ap.get_port_number())
+            .write_block8(self, address, data)
+    }
+
+    /// Resets the probe itself on the USB level and initializes it again.
+    ///
+    /// This can be used to recover a probe which got stuck, without having to unplug it.
+    ///
+    /// Any state of the target connection is lost. A `Session` using this probe becomes
+    /// invalid and has to be rebuilt, as halted cores, breakpoints or loaded flash
+    /// algorithms can not be assumed to be in the same state anymore.
+    pub fn reset_probe(&mut self) -> Result<(), DebugProbeError> {
+        use crate::coresight::debug_port::Select;
+
+        self.actual_probe.reset_probe()?;
+
+        // The AP selection of the target is unknown after the reset, so select AP 0 explicitly.
+        self.actual_probe
+            .write_register(Port::DebugPort, u16::from(Select::ADDRESS), 0)?;
+        self.current_apsel = 0;
+        self.current_apbanksel = 0;
+        self.only_32bit_data_size.clear();
+
+        Ok(())
+    }
+
+    /// Attaches to the debug port again with the protocol the probe was opened with,
+    /// e.g. after a reset of the target which dropped the debug connection.
+    ///
+    /// As with `reset_probe`, the AP selection is reset.
+    pub fn reattach(&mut self) -> Result<(), DebugProbeError> {
+        use crate::coresight::debug_port::Select;
+
+        let protocol = self.origin.as_ref().map(|(_, protocol, _)| *protocol);
+        self.actual_probe.attach(protocol)?;
+
+        self.actual_probe
+            .write_register(Port::DebugPort, u16::from(Select::ADDRESS), 0)?;
+        self.current_apsel = 0;
+        self.current_apbanksel = 0;
+
+        Ok(())
+    }
+
+    /// Starts capturing trace data on the probe.
+    ///
+    /// The TPIU of the target has to be configured with the same configuration
+    /// using `coresight::tpiu::configure_tpiu`.
+    pub fn start_trace(&mut self, config: &TraceConfig) -> Result<(), DebugProbeError> {
+        self.actual_probe.start_trace(config)
+    }
+
+    /// Stops capturing trace data on the probe.
+    pub fn stop_trace(&mut self) -> Result<(), DebugProbeError> {
+        self.actual_probe.stop_trace()
+    }
+
+    /// Returns the raw trace data which the probe captured since the last call.
+    pub fn read_trace(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        self.actual_probe.read_trace()
+    }
+
+    /// Returns the concrete probe if it is of type `P`.
+    ///
+    /// This gives access to probe specific functionality which is not part of the
+    /// `DebugProbe` trait. Changing the AP selection of the probe directly is not
+    /// tracked by the `MasterProbe` and should be avoided.
+    pub fn downcast_mut<P: DebugProbe + 'static>(&mut self) -> Option<&mut P> {
+        self.actual_probe.as_any_mut().downcast_mut()
+    }
+
+    /// Returns the concrete probe if it is of type `P`.
+    pub fn downcast_ref<P: DebugProbe + 'static>(&self) -> Option<&P> {
+        self.actual_probe.as_any().downcast_ref()
+    }
+
+    /// Returns the underlying ST-Link, if the probe is one.
+    pub fn as_stlink_mut(&mut self) -> Option<&mut stlink::STLink> {
+        self.downcast_mut()
+    }
+
+    /// Returns the underlying DAPLink, if the probe is one.
+    pub fn as_daplink_mut(&mut self) -> Option<&mut daplink::DAPLink> {
+        self.downcast_mut()
+    }
+
     fn select_ap_and_ap_bank(&mut self, port: u8, ap_bank: u8) -> Result<(), DebugProbeError> {
         let mut cache_changed = if self.current_apsel != port {
             self.current_apsel = port;
@@ -174,6 +702,7 @@ impl MasterProbe {
             select.set_ap_sel(self.current_apsel);
             select.set_ap_bank_sel(self.current_apbanksel);
 
+            self.delay_transfer();
             self.actual_probe.write_register(
                 Port::DebugPort,
                 u16::from(Select::ADDRESS),
@@ -203,6 +732,7 @@ impl MasterProbe {
 
         self.select_ap_and_ap_bank(port.get_port_number(), REGISTER::APBANKSEL)?;
 
+        self.delay_transfer();
         let link = &mut self.actual_probe;
         link.write_register(
             Port::AccessPort(u16::from(self.current_apsel)),
@@ -230,6 +760,7 @@ impl MasterProbe {
 
         self.select_ap_and_ap_bank(port.get_port_number(), REGISTER::APBANKSEL)?;
 
+        self.delay_transfer();
         let link = &mut self.actual_probe;
         link.write_block(
             Port::AccessPort(u16::from(self.current_apsel)),
@@ -251,6 +782,7 @@ impl MasterProbe {
         debug!("Reading register {}", REGISTER::NAME);
         self.select_ap_and_ap_bank(port.get_port_number(), REGISTER::APBANKSEL)?;
 
+        self.delay_transfer();
         let link = &mut self.actual_probe;
         //log::debug!("{:?}, {:08X}", link.current_apsel, REGISTER::ADDRESS);
         let result = link.read_register(
@@ -285,6 +817,7 @@ impl MasterProbe {
 
         self.select_ap_and_ap_bank(port.get_port_number(), REGISTER::APBANKSEL)?;
 
+        self.delay_transfer();
         let link = &mut self.actual_probe;
         link.read_block(
             Port::AccessPort(u16::from(self.current_apsel)),
@@ -295,10 +828,12 @@ impl MasterProbe {
     }
 
     pub fn read_register_dp(&mut self, offset: u16) -> Result<u32, DebugProbeError> {
+        self.delay_transfer();
         self.actual_probe.read_register(Port::DebugPort, offset)
     }
 
     pub fn write_register_dp(&mut self, offset: u16, val: u32) -> Result<(), DebugProbeError> {
+        self.delay_transfer();
         self.actual_probe
             .write_register(Port::DebugPort, offset, val)
     }
@@ -306,45 +841,35 @@ impl MasterProbe {
     /// Tries to mass erase a locked nRF52 chip, this process may timeout, if it does, the chip
     /// might be unlocked or not, it is advised to try again if flashing fails
     pub fn nrf_recover(&mut self) -> Result<(), DebugProbeError> {
-        let ctrl_port = match get_ap_by_idr(self, |idr| idr == CTRL_AP_IDR) {
-            Some(port) => CtrlAP::from(port),
-            None => {
-                return Err(DebugProbeError::AccessPortError(
-                    AccessPortError::CtrlAPNotFound,
-                ));
-            }
-        };
+        if self.control_ap()?.0 != ControlApKind::NordicCtrlAp {
+            return Err(DebugProbeError::AccessPortError(
+                AccessPortError::CtrlAPNotFound,
+            ));
+        }
+
         log::info!("Starting mass erase...");
-        let mut erase_reg = ERASEALL::from(1);
-        let status_reg = ERASEALLSTATUS::from(0);
-        let mut reset_reg = RESET::from(1);
 
         // Reset first
-        self.write_ap_register(ctrl_port, reset_reg)?;
-        reset_reg.RESET = false;
-        self.write_ap_register(ctrl_port, reset_reg)?;
+        self.ctrl_ap_write(RESET::ADDRESS, 1)?;
+        self.ctrl_ap_write(RESET::ADDRESS, 0)?;
 
-        self.write_ap_register(ctrl_port, erase_reg)?;
+        self.ctrl_ap_write(ERASEALL::ADDRESS, 1)?;
 
         // Prepare timeout
         let now = Instant::now();
-        let status = self.read_ap_register(ctrl_port, status_reg)?;
-        log::info!("Erase status: {:?}", status.ERASEALLSTATUS);
+        let status = self.ctrl_ap_read(ERASEALLSTATUS::ADDRESS)?;
+        log::info!("Erase status: {:?}", status != 0);
         let timeout = loop {
-            let status = self.read_ap_register(ctrl_port, status_reg)?;
-            if !status.ERASEALLSTATUS {
+            if self.ctrl_ap_read(ERASEALLSTATUS::ADDRESS)? == 0 {
                 break false;
             }
             if now.elapsed().as_secs() >= UNLOCK_TIMEOUT {
                 break true;
             }
         };
-        reset_reg.RESET = true;
-        self.write_ap_register(ctrl_port, reset_reg)?;
-        reset_reg.RESET = false;
-        self.write_ap_register(ctrl_port, reset_reg)?;
-        erase_reg.ERASEALL = false;
-        self.write_ap_register(ctrl_port, erase_reg)?;
+        self.ctrl_ap_write(RESET::ADDRESS, 1)?;
+        self.ctrl_ap_write(RESET::ADDRESS, 0)?;
+        self.ctrl_ap_write(ERASEALL::ADDRESS, 0)?;
         if timeout {
             log::error!(
                 "    {} Mass erase process timeout, the chip might still be locked.",
@@ -355,6 +880,101 @@ impl MasterProbe {
         }
         Ok(())
     }
+
+    /// Sends a request to the debug mailbox of an NXP LPC55xx, e.g. `DEBUG_MAILBOX_START_DEBUG_SESSION`.
+    ///
+    /// The chip is reset and the debug mailbox resynchronized first, as the boot ROM only reads
+    /// requests during boot. Returns the value of the `RETURN` register, whose lower 16 bits are
+    /// the status of the request, `0` on success.
+    pub fn debug_mailbox_request(&mut self, command: u16) -> Result<u32, DebugProbeError> {
+        if self.control_ap()?.0 != ControlApKind::NxpDebugMailbox {
+            return Err(DebugProbeError::AccessPortError(
+                AccessPortError::CtrlAPNotFound,
+            ));
+        }
+
+        let resynch = DMCSW {
+            RESYNCH_REQ: true,
+            CHIP_RESET_REQ: true,
+            ..Default::default()
+        };
+        self.ctrl_ap_write(DMCSW::ADDRESS, resynch.into())?;
+
+        // The AP does not answer while the chip resets.
+        self.wait_for_debug_mailbox(|csw| !csw.RESYNCH_REQ)?;
+
+        self.ctrl_ap_write(REQUEST::ADDRESS, u32::from(command))?;
+        self.wait_for_debug_mailbox(|csw| !csw.REQ_PENDING)?;
+
+        self.ctrl_ap_read(RETURN::ADDRESS)
+    }
+
+    /// Polls the `CSW` register of the debug mailbox until `done` returns `true`.
+    fn wait_for_debug_mailbox(
+        &mut self,
+        done: impl Fn(DMCSW) -> bool,
+    ) -> Result<(), DebugProbeError> {
+        let start = Instant::now();
+
+        loop {
+            match self.ctrl_ap_read(DMCSW::ADDRESS) {
+                Ok(csw) if done(DMCSW::from(csw)) => return Ok(()),
+                Ok(_) => (),
+                Err(e) => log::debug!("Reading the debug mailbox failed: {:?}", e),
+            }
+
+            if start.elapsed() > DEBUG_MAILBOX_TIMEOUT {
+                return Err(DebugProbeError::Timeout);
+            }
+        }
+    }
+
+    /// Returns the kind and the number of the control AP of the chip, e.g. the CTRL-AP of the nRF52.
+    ///
+    /// The APs are searched for the IDR of each `ControlApKind` on the first call.
+    pub fn control_ap(&mut self) -> Result<(ControlApKind, u8), DebugProbeError> {
+        if let Some(control_ap) = self.control_ap {
+            return Ok(control_ap);
+        }
+
+        for kind in &ControlApKind::ALL {
+            let idr = kind.idr();
+            if let Some(port) = get_ap_by_idr(self, |candidate| candidate == idr) {
+                let control_ap = (*kind, port.get_port_number());
+                self.control_ap = Some(control_ap);
+                return Ok(control_ap);
+            }
+        }
+
+        Err(DebugProbeError::AccessPortError(
+            AccessPortError::CtrlAPNotFound,
+        ))
+    }
+
+    /// Reads the register at `address` of the control AP, see `control_ap`.
+    pub fn ctrl_ap_read(&mut self, address: u8) -> Result<u32, DebugProbeError> {
+        let (_, port) = self.control_ap()?;
+        self.select_ap_and_ap_bank(port, address >> 4)?;
+
+        self.delay_transfer();
+        self.actual_probe
+            .read_register(Port::AccessPort(u16::from(port)), u16::from(address))
+    }
+
+    /// Writes `value` to the register at `address` of the control AP, see `control_ap`.
+    ///
+    /// This is a low level escape hatch, e.g. for unlock sequences probe-rs does not know.
+    pub fn ctrl_ap_write(&mut self, address: u8, value: u32) -> Result<(), DebugProbeError> {
+        let (_, port) = self.control_ap()?;
+        self.select_ap_and_ap_bank(port, address >> 4)?;
+
+        self.delay_transfer();
+        self.actual_probe.write_register(
+            Port::AccessPort(u16::from(port)),
+            u16::from(address),
+            value,
+        )
+    }
 }
 
 impl<REGISTER> APAccess<MemoryAP, REGISTER> for MasterProbe
@@ -437,35 +1057,44 @@ where
 
 impl MI for MasterProbe {
     fn read32(&mut self, address: u32) -> Result<u32, AccessPortError> {
-        ADIMemoryInterface::new(0).read32(self, address)
+        self.read_memory(|probe| probe.memory_interface().read32(probe, address))
     }
 
     fn read8(&mut self, address: u32) -> Result<u8, AccessPortError> {
-        ADIMemoryInterface::new(0).read8(self, address)
+        let byte_access = self.has_byte_access(address);
+        self.read_memory(|probe| {
+            probe
+                .memory_interface()
+                .with_byte_access(byte_access)
+                .read8(probe, address)
+        })
     }
 
     fn read_block32(&mut self, address: u32, data: &mut [u32]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).read_block32(self, address, data)
+        self.read_memory(|probe| probe.memory_interface().read_block32(probe, address, data))
     }
 
     fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).read_block8(self, address, data)
+        self.read_memory(|probe| probe.memory_interface().read_block8(probe, address, data))
     }
 
     fn write32(&mut self, addr: u32, data: u32) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write32(self, addr, data)
+        self.memory_interface().write32(self, addr, data)
     }
 
     fn write8(&mut self, addr: u32, data: u8) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write8(self, addr, data)
+        let byte_access = self.has_byte_access(addr);
+        self.memory_interface()
+            .with_byte_access(byte_access)
+            .write8(self, addr, data)
     }
 
     fn write_block32(&mut self, addr: u32, data: &[u32]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write_block32(self, addr, data)
+        self.memory_interface().write_block32(self, addr, data)
     }
 
     fn write_block8(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write_block8(self, addr, data)
+        self.memory_interface().write_block8(self, addr, data)
     }
 }
 
@@ -483,26 +1112,115 @@ pub trait DebugProbe: DAPAccess {
     /// Leave debug mode
     fn detach(&mut self) -> Result<(), DebugProbeError>;
 
+    /// Selects the target with the given TARGETSEL value on an SWD multidrop bus.
+    ///
+    /// This has to be called before `attach`, which writes the TARGETSEL register after the line reset
+    /// of the SWD connect sequence. Returns `DebugProbeError::NotSupportedByProbe` if the probe
+    /// can not select targets.
+    fn select_target(&mut self, _target_sel: u32) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::NotSupportedByProbe)
+    }
+
+    /// Sets the clock speed of the wire protocol to the fastest supported speed which does not
+    /// exceed `speed_khz`, and returns it.
+    ///
+    /// Returns `DebugProbeError::NotSupportedByProbe` if the probe can not change its speed.
+    fn set_speed(&mut self, _speed_khz: u32) -> Result<u32, DebugProbeError> {
+        Err(DebugProbeError::NotSupportedByProbe)
+    }
+
     /// Resets the target device.
     fn target_reset(&mut self) -> Result<(), DebugProbeError>;
+
+    /// Resets the probe on the USB level, initializes it again and reattaches to the target.
+    ///
+    /// Returns `DebugProbeError::NotSupportedByProbe` if the probe can not be reset.
+    fn reset_probe(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::NotSupportedByProbe)
+    }
+
+    /// Completes all queued transfers and returns any error which was deferred while queueing.
+    ///
+    /// Probes which execute every transfer immediately do not have to implement this.
+    fn flush(&mut self) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    /// Returns the maximum number of bytes the probe can transfer to or from memory at once.
+    ///
+    /// Block transfers are split into chunks of at most this size. Probes which split
+    /// transfers into packets themselves do not have to implement this.
+    fn max_memory_transfer_size(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Starts capturing trace data with the given configuration.
+    ///
+    /// Returns `DebugProbeError::NotSupportedByProbe` if the probe can not capture trace data
+    /// with the configured protocol.
+    fn start_trace(&mut self, _config: &TraceConfig) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::NotSupportedByProbe)
+    }
+
+    /// Stops capturing trace data.
+    fn stop_trace(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::NotSupportedByProbe)
+    }
+
+    /// Returns the raw trace data which was captured since the last call.
+    ///
+    /// The data is not decoded in any way. Returns an empty buffer if no new data is available.
+    fn read_trace(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        Err(DebugProbeError::NotSupportedByProbe)
+    }
+
+    /// Checks the probe itself without attaching to a target, e.g. its firmware version.
+    ///
+    /// All checks are run, even if an earlier one failed. Probes without checks return an empty list.
+    fn self_test(&mut self) -> Vec<SelfTestCheck> {
+        vec![]
+    }
+
+    /// Returns details about the probe which are not known before it is opened,
+    /// e.g. the firmware version and the features which depend on it.
+    fn details(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the probe as `Any`, such that it can be downcast to the concrete probe type.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Returns the probe as `Any`, such that it can be downcast to the concrete probe type.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
-#[derive(Debug, Clone)]
+/// Lists all connected probes of all supported types.
+///
+/// This only enumerates the USB devices, none of the probes is opened.
+pub fn list_all() -> Vec<DebugProbeInfo> {
+    let mut list = daplink::tools::list_daplink_devices();
+    list.extend(stlink::tools::list_stlink_devices());
+    list
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum DebugProbeType {
     DAPLink,
     STLink,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DebugProbeInfo {
     pub identifier: String,
     pub vendor_id: u16,
     pub product_id: u16,
     pub serial_number: Option<String>,
     pub probe_type: DebugProbeType,
+    /// The USB interface which is used if this is a CMSIS-DAP probe. The default is `DapInterface::Auto`.
+    pub dap_interface: daplink::DapInterface,
 }
 
-impl std::fmt::Debug for DebugProbeInfo {
+impl std::fmt::Display for DebugProbeInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
@@ -532,8 +1250,109 @@ impl DebugProbeInfo {
             product_id,
             serial_number,
             probe_type,
+            dap_interface: daplink::DapInterface::Auto,
         }
     }
+
+    /// Opens the probe described by this info and attaches to the target
+    /// using the given `protocol`.
+    ///
+    /// Returns a `MasterProbe` which is ready to be used.
+    pub fn open(&self, protocol: Option<WireProtocol>) -> Result<MasterProbe, DebugProbeError> {
+        self.open_with_target_sel(protocol, None)
+    }
+
+    /// Opens the probe without attaching to a target and runs its self-test.
+    ///
+    /// This tells whether a probe works at all, independent of the target. See `DebugProbe::self_test`.
+    pub fn self_test(&self) -> Result<Vec<SelfTestCheck>, DebugProbeError> {
+        let mut probe = self.create_probe()?;
+
+        Ok(probe.self_test())
+    }
+
+    /// Opens the probe without attaching to a target and returns its details, see `DebugProbe::details`.
+    pub fn details(&self) -> Result<Option<String>, DebugProbeError> {
+        let probe = self.create_probe()?;
+
+        Ok(probe.details())
+    }
+
+    /// Opens the probe like `open`, and selects the target with the given TARGETSEL value
+    /// if the target is on an SWD multidrop bus.
+    pub fn open_with_target_sel(
+        &self,
+        protocol: Option<WireProtocol>,
+        target_sel: Option<u32>,
+    ) -> Result<MasterProbe, DebugProbeError> {
+        let probe = self.create_probe()?;
+
+        self.attach(probe, protocol, target_sel)
+    }
+
+    /// Opens the probe like `open_with_target_sel`, trying SWD first and JTAG second.
+    ///
+    /// A protocol is only used if it reads a valid DPIDR afterwards. If none of them does,
+    /// the error tells what went wrong with each protocol.
+    pub fn open_autodetect(&self, target_sel: Option<u32>) -> Result<MasterProbe, DebugProbeError> {
+        let mut attempts = vec![];
+
+        for protocol in &[WireProtocol::Swd, WireProtocol::Jtag] {
+            // The probe is opened again for each protocol, as a failed attach can leave it in any state.
+            let probe = self.create_probe()?;
+
+            let result = self
+                .attach(probe, Some(*protocol), target_sel)
+                .and_then(|mut probe| {
+                    let dpidr = probe.actual_probe.read_register(Port::DebugPort, 0)?;
+                    if dpidr == 0 || dpidr == 0xFFFF_FFFF {
+                        return Err(DebugProbeError::InvalidDpidr(dpidr));
+                    }
+                    debug!("{} works, the DPIDR is {:#010x}", protocol, dpidr);
+                    Ok(probe)
+                });
+
+            match result {
+                Ok(probe) => return Ok(probe),
+                Err(error) => {
+                    debug!("{} failed: {}", protocol, error);
+                    attempts.push(AttachAttempt {
+                        protocol: *protocol,
+                        error,
+                    });
+                }
+            }
+        }
+
+        Err(DebugProbeError::ProtocolDetectionFailed(attempts))
+    }
+
+    /// Opens the probe described by this info, without attaching to a target.
+    fn create_probe(&self) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+        let probe: Box<dyn DebugProbe> = match self.probe_type {
+            DebugProbeType::DAPLink => daplink::DAPLink::new_from_probe_info(self)?,
+            DebugProbeType::STLink => stlink::STLink::new_from_probe_info(self)?,
+        };
+
+        Ok(probe)
+    }
+
+    fn attach(
+        &self,
+        mut probe: Box<dyn DebugProbe>,
+        protocol: Option<WireProtocol>,
+        target_sel: Option<u32>,
+    ) -> Result<MasterProbe, DebugProbeError> {
+        if let Some(target_sel) = target_sel {
+            probe.select_target(target_sel)?;
+        }
+
+        let protocol = probe.attach(protocol)?;
+
+        let mut probe = MasterProbe::from_specific_probe(probe);
+        probe.origin = Some((self.clone(), protocol, target_sel));
+        Ok(probe)
+    }
 }
 
 #[derive(Default)]
@@ -573,6 +1392,14 @@ impl DebugProbe for FakeProbe {
     fn target_reset(&mut self) -> Result<(), DebugProbeError> {
         Err(DebugProbeError::UnknownError)
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 impl DAPAccess for FakeProbe {
@@ -591,3 +1418,120 @@ impl DAPAccess for FakeProbe {
         Err(DebugProbeError::UnknownError)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::coresight::debug_port::Abort;
+
+    /// The address of the CSW register of a memory AP.
+    const CSW_ADDRESS: u16 = 0x00;
+    /// The address of the DRW register of a memory AP.
+    const DRW_ADDRESS: u16 = 0x0C;
+
+    /// A probe whose memory reads fault the given number of times before they succeed.
+    #[derive(Default)]
+    struct FaultingProbe {
+        faults: usize,
+        aborts: usize,
+        csw: u32,
+    }
+
+    impl DebugProbe for FaultingProbe {
+        fn new_from_probe_info(_info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+        where
+            Self: Sized,
+        {
+            Err(DebugProbeError::ProbeCouldNotBeCreated)
+        }
+
+        fn get_name(&self) -> &str {
+            "Faulting probe for testing"
+        }
+
+        fn attach(
+            &mut self,
+            protocol: Option<WireProtocol>,
+        ) -> Result<WireProtocol, DebugProbeError> {
+            Ok(protocol.unwrap_or(WireProtocol::Swd))
+        }
+
+        fn detach(&mut self) -> Result<(), DebugProbeError> {
+            Ok(())
+        }
+
+        fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+            Ok(())
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    impl DAPAccess for FaultingProbe {
+        fn read_register(&mut self, port: Port, addr: u16) -> Result<u32, DebugProbeError> {
+            match (port, addr) {
+                (Port::AccessPort(_), CSW_ADDRESS) => Ok(self.csw),
+                (Port::AccessPort(_), DRW_ADDRESS) if self.faults > 0 => {
+                    self.faults -= 1;
+                    Err(DebugProbeError::UnknownError)
+                }
+                (Port::AccessPort(_), DRW_ADDRESS) => Ok(0x1234_5678),
+                _ => Ok(0),
+            }
+        }
+
+        fn write_register(
+            &mut self,
+            port: Port,
+            addr: u16,
+            value: u32,
+        ) -> Result<(), DebugProbeError> {
+            match port {
+                Port::DebugPort if addr == u16::from(Abort::ADDRESS) => self.aborts += 1,
+                Port::AccessPort(_) if addr == CSW_ADDRESS => self.csw = value,
+                _ => (),
+            }
+            Ok(())
+        }
+    }
+
+    fn faulting_probe(faults: usize, retries: usize) -> MasterProbe {
+        let mut probe = MasterProbe::from_specific_probe(Box::new(FaultingProbe {
+            faults,
+            ..FaultingProbe::default()
+        }));
+        probe.set_fault_retry(Some(FaultRetry {
+            retries,
+            delay: Duration::from_millis(0),
+        }));
+        probe
+    }
+
+    #[test]
+    fn faulting_read_is_retried() {
+        let mut probe = faulting_probe(2, 3);
+
+        assert_eq!(probe.read32(0x4002_1000).unwrap(), 0x1234_5678);
+        assert_eq!(probe.downcast_ref::<FaultingProbe>().unwrap().aborts, 2);
+    }
+
+    #[test]
+    fn fault_is_returned_after_all_retries() {
+        let mut probe = faulting_probe(3, 2);
+
+        match probe.read32(0x4002_1000) {
+            Err(AccessPortError::RegisterReadError { name, .. }) => assert_eq!(name, "DRW"),
+            other => panic!("Expected a register read error, got {:?}", other),
+        }
+        assert_eq!(probe.downcast_ref::<FaultingProbe>().unwrap().aborts, 2);
+
+        // The last fault is used up by now, so the next read succeeds.
+        assert_eq!(probe.read32(0x4002_1000).unwrap(), 0x1234_5678);
+    }
+}
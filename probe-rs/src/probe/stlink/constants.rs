@@ -93,6 +93,7 @@ pub enum Status {
 }
 
 /// Map from SWD frequency in Hertz to delay loop count.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SwdFrequencyToDelayCount {
     Hz4600000 = 0,
     Hz1800000 = 1, // Default
@@ -109,6 +110,7 @@ pub enum SwdFrequencyToDelayCount {
 }
 
 /// Map from JTAG frequency in Hertz to frequency divider.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum JTagFrequencyToDivider {
     Hz18000000 = 2,
     Hz9000000 = 4,
@@ -119,3 +121,78 @@ pub enum JTagFrequencyToDivider {
     Hz280000 = 128,
     Hz140000 = 256,
 }
+
+impl SwdFrequencyToDelayCount {
+    /// All frequencies in kHz, from the fastest to the slowest.
+    const ALL: [(u32, SwdFrequencyToDelayCount); 12] = [
+        (4600, SwdFrequencyToDelayCount::Hz4600000),
+        (1800, SwdFrequencyToDelayCount::Hz1800000),
+        (1200, SwdFrequencyToDelayCount::Hz1200000),
+        (950, SwdFrequencyToDelayCount::Hz950000),
+        (650, SwdFrequencyToDelayCount::Hz650000),
+        (480, SwdFrequencyToDelayCount::Hz480000),
+        (400, SwdFrequencyToDelayCount::Hz400000),
+        (360, SwdFrequencyToDelayCount::Hz360000),
+        (240, SwdFrequencyToDelayCount::Hz240000),
+        (150, SwdFrequencyToDelayCount::Hz150000),
+        (125, SwdFrequencyToDelayCount::Hz125000),
+        (100, SwdFrequencyToDelayCount::Hz100000),
+    ];
+
+    /// Returns the fastest frequency which does not exceed `speed_khz` and its speed in kHz,
+    /// or the slowest frequency if all are too fast.
+    pub fn from_khz(speed_khz: u32) -> (u32, Self) {
+        *Self::ALL
+            .iter()
+            .find(|(khz, _)| *khz <= speed_khz)
+            .unwrap_or(&Self::ALL[Self::ALL.len() - 1])
+    }
+}
+
+impl JTagFrequencyToDivider {
+    /// All frequencies in kHz, from the fastest to the slowest.
+    const ALL: [(u32, JTagFrequencyToDivider); 8] = [
+        (18000, JTagFrequencyToDivider::Hz18000000),
+        (9000, JTagFrequencyToDivider::Hz9000000),
+        (4500, JTagFrequencyToDivider::Hz4500000),
+        (2250, JTagFrequencyToDivider::Hz2250000),
+        (1120, JTagFrequencyToDivider::Hz1120000),
+        (560, JTagFrequencyToDivider::Hz560000),
+        (280, JTagFrequencyToDivider::Hz280000),
+        (140, JTagFrequencyToDivider::Hz140000),
+    ];
+
+    /// Returns the fastest frequency which does not exceed `speed_khz` and its speed in kHz,
+    /// or the slowest frequency if all are too fast.
+    pub fn from_khz(speed_khz: u32) -> (u32, Self) {
+        *Self::ALL
+            .iter()
+            .find(|(khz, _)| *khz <= speed_khz)
+            .unwrap_or(&Self::ALL[Self::ALL.len() - 1])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JTagFrequencyToDivider, SwdFrequencyToDelayCount};
+
+    #[test]
+    fn frequency_from_khz() {
+        assert_eq!(
+            SwdFrequencyToDelayCount::from_khz(4000),
+            (1800, SwdFrequencyToDelayCount::Hz1800000)
+        );
+        assert_eq!(
+            SwdFrequencyToDelayCount::from_khz(10_000),
+            (4600, SwdFrequencyToDelayCount::Hz4600000)
+        );
+        assert_eq!(
+            SwdFrequencyToDelayCount::from_khz(50),
+            (100, SwdFrequencyToDelayCount::Hz100000)
+        );
+        assert_eq!(
+            JTagFrequencyToDivider::from_khz(1120),
+            (1120, JTagFrequencyToDivider::Hz1120000)
+        );
+    }
+}
@@ -5,18 +5,67 @@ mod usb_interface;
 
 pub use self::usb_interface::STLinkUSBDevice;
 
-use super::{DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo, Port, WireProtocol};
-use crate::coresight::{ap_access::AccessPort, common::Register, debug_port::Ctrl};
+use super::{
+    DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo, Port, SelfTestCheck, SelfTestOutcome,
+    WireProtocol,
+};
+use crate::coresight::{
+    access_ports::generic_ap::GenericAP,
+    ap_access::AccessPort,
+    common::Register,
+    debug_port::Ctrl,
+    tpiu::{TraceConfig, TraceProtocol},
+};
 use scroll::{Pread, BE};
+use std::any::Any;
 
 use constants::{commands, JTagFrequencyToDivider, Status, SwdFrequencyToDelayCount};
 use usb_interface::TIMEOUT;
 
+/// The versions of an ST-Link and the features which depend on them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct STLinkVersion {
+    /// The hardware version, e.g. 2 for the ST-Link V2 and V2-1.
+    pub hw_version: u8,
+    /// The version of the JTAG/SWD firmware, e.g. 38 for `J38`.
+    pub jtag_version: u8,
+    /// Whether other APs than AP0 can be accessed.
+    pub multi_ap: bool,
+    /// Whether the firmware supports 16 bit memory transfers.
+    pub transfers_16bit: bool,
+    /// Whether the hardware supports SWD clocks above 4 MHz, which is the case from the V3 on.
+    pub high_speed: bool,
+}
+
+impl std::fmt::Display for STLinkVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "V{} (fw J{}", self.hw_version, self.jtag_version)?;
+        if self.multi_ap {
+            write!(f, ", multi-AP")?;
+        }
+        if self.transfers_16bit {
+            write!(f, ", 16-bit xfer")?;
+        }
+        if self.high_speed {
+            write!(f, ", high-speed")?;
+        }
+        write!(f, ")")
+    }
+}
+
 pub struct STLink {
     device: STLinkUSBDevice,
     hw_version: u8,
     jtag_version: u8,
     protocol: WireProtocol,
+    /// The APs which have been opened on the probe so far.
+    opened_aps: Vec<u8>,
+    /// Whether the user was told that the firmware only supports AP0.
+    single_ap_warned: bool,
+    /// The USB product ID the ST-Link reports in its version information.
+    reported_pid: u16,
+    /// The serial number of the USB device.
+    serial_number: Option<String>,
 }
 
 impl DebugProbe for STLink {
@@ -29,6 +78,10 @@ impl DebugProbe for STLink {
             hw_version: 0,
             jtag_version: 0,
             protocol: WireProtocol::Swd,
+            opened_aps: vec![],
+            single_ap_warned: false,
+            reported_pid: 0,
+            serial_number: info.serial_number.clone(),
         };
 
         stlink.init()?;
@@ -40,10 +93,72 @@ impl DebugProbe for STLink {
         "ST-Link"
     }
 
+    fn details(&self) -> Option<String> {
+        Some(self.version().to_string())
+    }
+
+    fn self_test(&mut self) -> Vec<SelfTestCheck> {
+        let version = self.get_version().map(|(hw_version, jtag_version)| {
+            format!("V{} with JTAG/SWD firmware v{}", hw_version, jtag_version)
+        });
+
+        let voltage = match self.get_target_voltage() {
+            Ok(voltage)
+                if (Self::MIN_TARGET_VOLTAGE..=Self::MAX_TARGET_VOLTAGE).contains(&voltage) =>
+            {
+                SelfTestCheck::from_result("target voltage", Ok(format!("{:.2} V", voltage)))
+            }
+            Ok(voltage) => SelfTestCheck {
+                name: "target voltage",
+                outcome: SelfTestOutcome::Failed(format!(
+                    "{:.2} V is out of range, is the target powered?",
+                    voltage
+                )),
+            },
+            Err(e) => SelfTestCheck::from_result("target voltage", Err(e)),
+        };
+
+        let clock = self
+            .set_speed(1_000)
+            .map(|speed_khz| format!("set to {} kHz", speed_khz));
+
+        let status = self
+            .enter_idle()
+            .map(|_| "the probe answers commands".to_owned());
+
+        vec![
+            SelfTestCheck::from_result("firmware version", version),
+            voltage,
+            SelfTestCheck::from_result("SWD clock", clock),
+            SelfTestCheck::from_result("status", status),
+        ]
+    }
+
+    /// Only ST-Link V2 and older are supported, V3 uses a different command to set the speed.
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        if self.hw_version >= 3 {
+            return Err(DebugProbeError::NotSupportedByProbe);
+        }
+
+        match self.protocol {
+            WireProtocol::Swd => {
+                let (actual_khz, frequency) = SwdFrequencyToDelayCount::from_khz(speed_khz);
+                self.set_swd_frequency(frequency)?;
+                Ok(actual_khz)
+            }
+            WireProtocol::Jtag => {
+                let (actual_khz, frequency) = JTagFrequencyToDivider::from_khz(speed_khz);
+                self.set_jtag_frequency(frequency)?;
+                Ok(actual_khz)
+            }
+        }
+    }
+
     /// Enters debug mode.
     fn attach(&mut self, protocol: Option<WireProtocol>) -> Result<WireProtocol, DebugProbeError> {
         log::debug!("attach({:?})", protocol);
         self.enter_idle()?;
+        self.opened_aps.clear();
 
         let (param, protocol) = if let Some(protocol) = protocol {
             (
@@ -65,6 +180,14 @@ impl DebugProbe for STLink {
             TIMEOUT,
         )?;
         Self::check_status(&buf)?;
+
+        if self.is_clone() {
+            log::warn!(
+                "This ST-Link looks like a clone. Only AP0 will be used, as clones are known to fail accessing other APs. \
+                 If you run into problems, please try again with a genuine ST-Link before reporting them."
+            );
+        }
+
         let mut ctrl_reg = Ctrl::default();
         ctrl_reg.set_csyspwrupreq(true);
         ctrl_reg.set_cdbgpwrupreq(true);
@@ -76,6 +199,7 @@ impl DebugProbe for STLink {
 
     /// Leave debug mode.
     fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.opened_aps.clear();
         self.enter_idle()
     }
 
@@ -94,6 +218,73 @@ impl DebugProbe for STLink {
         )?;
         Self::check_status(&buf)
     }
+
+    fn reset_probe(&mut self) -> Result<(), DebugProbeError> {
+        log::info!("Resetting the ST-Link.");
+        self.device.reset()?;
+        self.init()?;
+        self.attach(Some(self.protocol)).map(|_| ())
+    }
+
+    /// Starts the SWV trace reception of the ST-Link.
+    ///
+    /// The ST-Link can only capture NRZ encoded SWO data, parallel trace is not supported.
+    fn start_trace(&mut self, config: &TraceConfig) -> Result<(), DebugProbeError> {
+        if config.protocol != TraceProtocol::SwoNrz {
+            return Err(DebugProbeError::NotSupportedByProbe);
+        }
+
+        let mut command = vec![commands::JTAG_COMMAND, commands::SWV_START_TRACE_RECEPTION];
+        command.extend_from_slice(&Self::TRACE_BUFFER_SIZE.to_le_bytes());
+        command.extend_from_slice(&config.swo_baud_rate.to_le_bytes());
+
+        let mut buf = [0; 2];
+        self.device.write(command, &[], &mut buf, TIMEOUT)?;
+        Self::check_status(&buf)
+    }
+
+    fn stop_trace(&mut self) -> Result<(), DebugProbeError> {
+        let mut buf = [0; 2];
+        self.device.write(
+            vec![commands::JTAG_COMMAND, commands::SWV_STOP_TRACE_RECEPTION],
+            &[],
+            &mut buf,
+            TIMEOUT,
+        )?;
+        Self::check_status(&buf)
+    }
+
+    fn read_trace(&mut self) -> Result<Vec<u8>, DebugProbeError> {
+        let mut buf = [0; 2];
+        self.device.write(
+            vec![
+                commands::JTAG_COMMAND,
+                commands::SWV_GET_TRACE_NEW_RECORD_NB,
+            ],
+            &[],
+            &mut buf,
+            TIMEOUT,
+        )?;
+        let available = usize::from(u16::from_le_bytes(buf));
+
+        if available == 0 {
+            Ok(vec![])
+        } else {
+            self.device.read_swv(available, TIMEOUT)
+        }
+    }
+
+    fn max_memory_transfer_size(&self) -> usize {
+        Self::MAXIMUM_TRANSFER_SIZE
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 impl DAPAccess for STLink {
@@ -102,7 +293,10 @@ impl DAPAccess for STLink {
         if (addr & 0xf0) == 0 || port != Port::DebugPort {
             let port = match port {
                 Port::DebugPort => 0xffff,
-                Port::AccessPort(p) => p,
+                Port::AccessPort(p) => {
+                    self.select_ap(p as u8)?;
+                    p
+                }
             };
 
             let cmd = vec![
@@ -116,7 +310,7 @@ impl DAPAccess for STLink {
             let mut buf = [0; 8];
             self.device.write(cmd, &[], &mut buf, TIMEOUT)?;
             Self::check_status(&buf)?;
-            // Unwrap is ok!
+            // Unwrap is ok, `write` fails if the probe sent fewer than 8 bytes.
             Ok((&buf[4..8]).pread(0).unwrap())
         } else {
             Err(DebugProbeError::BlanksNotAllowedOnDPRegister)
@@ -128,7 +322,10 @@ impl DAPAccess for STLink {
         if (addr & 0xf0) == 0 || port != Port::DebugPort {
             let port = match port {
                 Port::DebugPort => 0xffff,
-                Port::AccessPort(p) => p,
+                Port::AccessPort(p) => {
+                    self.select_ap(p as u8)?;
+                    p
+                }
             };
 
             let cmd = vec![
@@ -164,17 +361,28 @@ impl STLink {
     /// Maximum number of bytes to send or receive for 32- and 16- bit transfers.
     ///
     /// 8-bit transfers have a maximum size of the maximum USB packet size (64 bytes for full speed).
-    const _MAXIMUM_TRANSFER_SIZE: u32 = 1024;
+    const MAXIMUM_TRANSFER_SIZE: usize = 1024;
 
     /// Minimum required STLink firmware version.
     const MIN_JTAG_VERSION: u8 = 24;
 
     /// Firmware version that adds 16-bit transfers.
-    const _MIN_JTAG_VERSION_16BIT_XFER: u8 = 26;
+    const MIN_JTAG_VERSION_16BIT_XFER: u8 = 26;
+
+    /// The size of the trace buffer of the ST-Link in bytes.
+    const TRACE_BUFFER_SIZE: u16 = 4096;
 
     /// Firmware version that adds multiple AP support.
     const MIN_JTAG_VERSION_MULTI_AP: u8 = 28;
 
+    /// Firmware version of the ST-Link V3 that has all the features of `J28` of the V2.
+    /// The V3 counts its firmware versions from 1 again.
+    const MIN_JTAG_VERSION_V3_FEATURES: u8 = 2;
+
+    /// The range of target voltages the self-test accepts, in volts.
+    const MIN_TARGET_VOLTAGE: f32 = 1.6;
+    const MAX_TARGET_VOLTAGE: f32 = 5.5;
+
     /// Reads the target voltage.
     /// For the china fake variants this will always read a nonzero value!
     pub fn get_target_voltage(&mut self) -> Result<f32, DebugProbeError> {
@@ -184,7 +392,7 @@ impl STLink {
             .write(vec![commands::GET_TARGET_VOLTAGE], &[], &mut buf, TIMEOUT)
         {
             Ok(_) => {
-                // The next two unwraps are safe!
+                // The next two unwraps are safe, `write` fails if the probe sent fewer than 8 bytes.
                 let a0 = (&buf[0..4]).pread::<u32>(0).unwrap() as f32;
                 let a1 = (&buf[4..8]).pread::<u32>(0).unwrap() as f32;
                 if a0 != 0.0 {
@@ -262,6 +470,7 @@ impl STLink {
                 let version: u16 = (&buf[0..2]).pread_with(0, BE).unwrap();
                 self.hw_version = (version >> HW_VERSION_SHIFT) as u8 & HW_VERSION_MASK;
                 self.jtag_version = (version >> JTAG_VERSION_SHIFT) as u8 & JTAG_VERSION_MASK;
+                self.reported_pid = u16::from_le_bytes([buf[4], buf[5]]);
             }
             Err(e) => return Err(e),
         }
@@ -285,6 +494,7 @@ impl STLink {
                 Ok(_) => {
                     let version: u8 = (&buf[2..3]).pread(0).unwrap();
                     self.jtag_version = version;
+                    self.reported_pid = u16::from_le_bytes([buf[10], buf[11]]);
                 }
                 Err(e) => return Err(e),
             }
@@ -347,43 +557,132 @@ impl STLink {
         Self::check_status(&buf)
     }
 
-    pub fn open_ap(&mut self, apsel: impl AccessPort) -> Result<(), DebugProbeError> {
-        if self.jtag_version < Self::MIN_JTAG_VERSION_MULTI_AP {
-            Err(DebugProbeError::JTagDoesNotSupportMultipleAP)
+    /// Returns `true` if the firmware of the ST-Link is recent enough
+    /// to access other APs than AP0.
+    ///
+    /// This is always `false` for ST-Links which look like clones, see `is_clone`.
+    pub fn supports_multiple_aps(&self) -> bool {
+        self.has_firmware_feature(Self::MIN_JTAG_VERSION_MULTI_AP) && !self.is_clone()
+    }
+
+    /// Returns `true` if the firmware is at least `min_v2_version` on an ST-Link V2,
+    /// or has the same feature on an ST-Link V3.
+    fn has_firmware_feature(&self, min_v2_version: u8) -> bool {
+        if self.hw_version >= 3 {
+            self.jtag_version >= Self::MIN_JTAG_VERSION_V3_FEATURES
         } else {
-            let mut buf = [0; 2];
-            self.device.write(
-                vec![
-                    commands::JTAG_COMMAND,
-                    commands::JTAG_INIT_AP,
-                    apsel.get_port_number(),
-                    commands::JTAG_AP_NO_CORE,
-                ],
-                &[],
-                &mut buf,
-                TIMEOUT,
-            )?;
-            Self::check_status(&buf)
+            self.jtag_version >= min_v2_version
         }
     }
 
+    /// Returns the versions of the ST-Link and the features which depend on them.
+    pub fn version(&self) -> STLinkVersion {
+        STLinkVersion {
+            hw_version: self.hw_version,
+            jtag_version: self.jtag_version,
+            multi_ap: self.supports_multiple_aps(),
+            transfers_16bit: self.has_firmware_feature(Self::MIN_JTAG_VERSION_16BIT_XFER),
+            high_speed: self.hw_version >= 3,
+        }
+    }
+
+    /// Returns `true` if the ST-Link is likely a clone.
+    ///
+    /// This is a best effort guess. Clones pretend to be an ST-Link V2 but often
+    /// do not report a serial number, or report a different product ID in their
+    /// version information than on the USB level.
+    /// Genuine ST-Links can not be told apart by the target voltage, as clones
+    /// simply report a fixed nonzero value.
+    pub fn is_clone(&self) -> bool {
+        let missing_serial = self
+            .serial_number
+            .as_deref()
+            .unwrap_or("")
+            .trim()
+            .is_empty();
+        let pid_mismatch = self.reported_pid != self.device.usb_pid();
+
+        self.hw_version == 2 && (missing_serial || pid_mismatch)
+    }
+
+    /// Opens the given AP if this has not been done before.
+    ///
+    /// Firmware without multi-AP support can only ever access AP0,
+    /// which does not have to be opened explicitly.
+    fn select_ap(&mut self, apsel: u8) -> Result<(), DebugProbeError> {
+        if !self.opened_aps.contains(&apsel) {
+            self.open_ap(GenericAP::new(apsel))?;
+            self.opened_aps.push(apsel);
+        }
+        Ok(())
+    }
+
+    /// Opens the given AP on the probe.
+    ///
+    /// If the firmware does not support multiple APs, AP0 is still usable implicitly,
+    /// so opening it succeeds. Any other AP results in an error.
+    pub fn open_ap(&mut self, apsel: impl AccessPort) -> Result<(), DebugProbeError> {
+        if !self.supports_multiple_aps() {
+            return self.single_ap_fallback(apsel);
+        }
+
+        let mut buf = [0; 2];
+        self.device.write(
+            vec![
+                commands::JTAG_COMMAND,
+                commands::JTAG_INIT_AP,
+                apsel.get_port_number(),
+                commands::JTAG_AP_NO_CORE,
+            ],
+            &[],
+            &mut buf,
+            TIMEOUT,
+        )?;
+        Self::check_status(&buf)
+    }
+
+    /// Closes the given AP on the probe.
+    ///
+    /// See `open_ap` for the behavior on firmware without multi-AP support.
     pub fn close_ap(&mut self, apsel: impl AccessPort) -> Result<(), DebugProbeError> {
-        if self.jtag_version < Self::MIN_JTAG_VERSION_MULTI_AP {
-            Err(DebugProbeError::JTagDoesNotSupportMultipleAP)
-        } else {
-            let mut buf = [0; 2];
-            self.device.write(
-                vec![
-                    commands::JTAG_COMMAND,
-                    commands::JTAG_CLOSE_AP_DBG,
-                    apsel.get_port_number(),
-                ],
-                &[],
-                &mut buf,
-                TIMEOUT,
-            )?;
-            Self::check_status(&buf)
+        if !self.supports_multiple_aps() {
+            return self.single_ap_fallback(apsel);
         }
+
+        let mut buf = [0; 2];
+        self.device.write(
+            vec![
+                commands::JTAG_COMMAND,
+                commands::JTAG_CLOSE_AP_DBG,
+                apsel.get_port_number(),
+            ],
+            &[],
+            &mut buf,
+            TIMEOUT,
+        )?;
+        Self::check_status(&buf)
+    }
+
+    /// Handles AP selection on firmware without multi-AP support, where only AP0 can be used.
+    ///
+    /// The user is only warned once, when an AP other than AP0 is requested for the first time.
+    fn single_ap_fallback(&mut self, apsel: impl AccessPort) -> Result<(), DebugProbeError> {
+        if apsel.get_port_number() == 0 {
+            log::debug!(
+                "The ST-Link firmware does not support multiple APs, using AP0 implicitly."
+            );
+            return Ok(());
+        }
+
+        if !self.single_ap_warned {
+            log::warn!(
+                "The ST-Link firmware does not support multiple APs, only AP0 can be used. Please update the firmware to version J{} or newer to access other APs.",
+                Self::MIN_JTAG_VERSION_MULTI_AP
+            );
+            self.single_ap_warned = true;
+        }
+
+        Err(DebugProbeError::JTagDoesNotSupportMultipleAP)
     }
 
     /// Drives the nRESET pin.
@@ -162,13 +162,19 @@ impl STLinkUSBDevice {
         Ok(usb_stlink)
     }
 
-    /// Writes to the out EP.
+    /// Reads `size` bytes from the in EP.
+    ///
+    /// Returns `DebugProbeError::NotEnoughBytesRead` if the probe sent fewer bytes.
     pub fn read(&mut self, size: u16, timeout: Duration) -> Result<Vec<u8>, DebugProbeError> {
         let mut buf = vec![0; size as usize];
         let ep_in = self.info.ep_in;
-        self.renter
+        let read_bytes = self
+            .renter
             .rent(|dh| dh.read_bulk(ep_in, buf.as_mut_slice(), timeout))
             .map_err(|_| DebugProbeError::USBError)?;
+        if read_bytes != buf.len() {
+            return Err(DebugProbeError::NotEnoughBytesRead);
+        }
         Ok(buf)
     }
 
@@ -176,6 +182,10 @@ impl STLinkUSBDevice {
     /// First the `cmd` is sent.
     /// In a second step `write_data` is transmitted.
     /// And lastly, data will be read back until `read_data` is filled.
+    ///
+    /// A truncated response fails with `DebugProbeError::NotEnoughBytesRead`, so on success
+    /// `read_data` is completely filled by the probe and can be parsed without further checks.
+    /// A truncated command or data phase fails with `DebugProbeError::NotEnoughBytesWritten`.
     pub fn write(
         &mut self,
         mut cmd: Vec<u8>,
@@ -197,7 +207,7 @@ impl STLinkUSBDevice {
             .map_err(|_| DebugProbeError::USBError)?;
 
         if written_bytes != CMD_LEN {
-            return Err(DebugProbeError::NotEnoughBytesRead);
+            return Err(DebugProbeError::NotEnoughBytesWritten);
         }
         // Optional data out phase.
         if !write_data.is_empty() {
@@ -206,7 +216,7 @@ impl STLinkUSBDevice {
                 .rent(|dh| dh.write_bulk(ep_out, write_data, timeout))
                 .map_err(|_| DebugProbeError::USBError)?;
             if written_bytes != write_data.len() {
-                return Err(DebugProbeError::NotEnoughBytesRead);
+                return Err(DebugProbeError::NotEnoughBytesWritten);
             }
         }
         // Optional data in phase.
@@ -222,10 +232,15 @@ impl STLinkUSBDevice {
         Ok(())
     }
 
-    /// Special read, TODO: for later.
+    /// Returns the USB product ID of the device.
+    pub fn usb_pid(&self) -> u16 {
+        self.info.usb_pid
+    }
+
+    /// Reads `size` bytes of trace data from the SWV endpoint.
     pub fn read_swv(&mut self, size: usize, timeout: Duration) -> Result<Vec<u8>, DebugProbeError> {
         let ep_swv = self.info.ep_swv;
-        let mut buf = Vec::with_capacity(size as usize);
+        let mut buf = vec![0; size];
         let read_bytes = self
             .renter
             .rent(|dh| dh.read_bulk(ep_swv, buf.as_mut_slice(), timeout))
@@ -237,6 +252,13 @@ impl STLinkUSBDevice {
         }
     }
 
+    /// Performs a USB port reset of the device.
+    pub fn reset(&mut self) -> Result<(), DebugProbeError> {
+        self.renter
+            .rent_mut(|dh| dh.reset())
+            .map_err(|_| DebugProbeError::USBError)
+    }
+
     /// Closes the USB interface gracefully.
     /// Internal helper.
     fn close(&mut self) -> Result<(), Error> {
@@ -0,0 +1,217 @@
+//! Reading of the SEGGER RTT (Real Time Transfer) up channels of a target.
+//!
+//! The firmware places a control block in RAM, which describes ring buffers for each channel.
+//! The debugger polls the write offset of a buffer, reads the new data and advances the read offset.
+
+use crate::coresight::access_ports::AccessPortError;
+use crate::coresight::memory::MI;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// The ID at the start of every RTT control block.
+const CONTROL_BLOCK_ID: &[u8; 16] = b"SEGGER RTT\0\0\0\0\0\0";
+
+/// The size of the ID and the two channel counts at the start of the control block.
+const CONTROL_BLOCK_HEADER_SIZE: u32 = 24;
+
+/// The size of a channel descriptor: name, buffer, size, write offset, read offset and flags.
+const CHANNEL_SIZE: u32 = 24;
+
+#[derive(Debug)]
+pub enum RttError {
+    AccessPort(AccessPortError),
+    ControlBlockNotFound,
+    NoSuchChannel(usize),
+    CorruptChannel(usize),
+}
+
+impl Error for RttError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use RttError::*;
+
+        match self {
+            AccessPort(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RttError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RttError::*;
+
+        match self {
+            AccessPort(ref e) => e.fmt(f),
+            ControlBlockNotFound => write!(f, "No RTT control block was found."),
+            NoSuchChannel(channel) => write!(f, "The RTT up channel {} does not exist.", channel),
+            CorruptChannel(channel) => write!(
+                f,
+                "The descriptor of the RTT up channel {} is corrupt.",
+                channel
+            ),
+        }
+    }
+}
+
+impl From<AccessPortError> for RttError {
+    fn from(error: AccessPortError) -> Self {
+        RttError::AccessPort(error)
+    }
+}
+
+/// An RTT control block in the memory of the target.
+#[derive(Debug, Clone)]
+pub struct Rtt {
+    control_block: u32,
+    up_channels: usize,
+}
+
+impl Rtt {
+    /// Attaches to the control block at `address`, e.g. the address of the `_SEGGER_RTT` symbol.
+    ///
+    /// Returns `RttError::ControlBlockNotFound` if the firmware did not initialize it yet.
+    pub fn attach(mi: &mut impl MI, address: u32) -> Result<Self, RttError> {
+        let mut header = [0u8; CONTROL_BLOCK_HEADER_SIZE as usize];
+        mi.read_block8(address, &mut header)?;
+
+        if header[..16] != CONTROL_BLOCK_ID[..] {
+            return Err(RttError::ControlBlockNotFound);
+        }
+
+        let up_channels = u32::from_le_bytes([header[16], header[17], header[18], header[19]]);
+
+        Ok(Self {
+            control_block: address,
+            up_channels: up_channels as usize,
+        })
+    }
+
+    /// Searches the memory `ranges`, usually the RAM of the target, for a control block.
+    pub fn scan(mi: &mut impl MI, ranges: &[Range<u32>]) -> Result<Self, RttError> {
+        for range in ranges {
+            let mut memory = vec![0u8; (range.end - range.start) as usize];
+            mi.read_block8(range.start, &mut memory)?;
+
+            let offset = memory
+                .windows(CONTROL_BLOCK_ID.len())
+                .position(|window| window == &CONTROL_BLOCK_ID[..]);
+
+            if let Some(offset) = offset {
+                return Self::attach(mi, range.start + offset as u32);
+            }
+        }
+
+        Err(RttError::ControlBlockNotFound)
+    }
+
+    /// Returns the address of the control block.
+    pub fn control_block(&self) -> u32 {
+        self.control_block
+    }
+
+    /// Returns the number of up channels, which transfer data from the target to the host.
+    pub fn up_channels(&self) -> usize {
+        self.up_channels
+    }
+
+    /// Reads all data which is available in the up `channel` and appends it to `data`.
+    ///
+    /// The data is consumed, such that the firmware can reuse the buffer.
+    /// Returns the number of bytes read.
+    pub fn read(
+        &self,
+        mi: &mut impl MI,
+        channel: usize,
+        data: &mut Vec<u8>,
+    ) -> Result<usize, RttError> {
+        if channel >= self.up_channels {
+            return Err(RttError::NoSuchChannel(channel));
+        }
+
+        let descriptor =
+            self.control_block + CONTROL_BLOCK_HEADER_SIZE + channel as u32 * CHANNEL_SIZE;
+        let mut words = [0u32; 6];
+        mi.read_block32(descriptor, &mut words)?;
+        let [_name, buffer, size, write, read, _flags] = words;
+
+        if size == 0 || write >= size || read >= size {
+            return Err(RttError::CorruptChannel(channel));
+        }
+
+        let start = data.len();
+        let mut read_range = |mi: &mut dyn MI, range: Range<u32>| -> Result<(), RttError> {
+            let offset = data.len();
+            data.resize(offset + (range.end - range.start) as usize, 0);
+            mi.read_block8(buffer + range.start, &mut data[offset..])?;
+            Ok(())
+        };
+
+        if write >= read {
+            read_range(mi, read..write)?;
+        } else {
+            // The data wraps around at the end of the buffer.
+            read_range(mi, read..size)?;
+            read_range(mi, 0..write)?;
+        }
+
+        let count = data.len() - start;
+        if count > 0 {
+            // Advance the read offset.
+            mi.write32(descriptor + 16, write)?;
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rtt, RttError};
+    use crate::coresight::memory::{mock::MockMemory, MI};
+
+    /// Creates RAM with a control block at 0x2000_0100 and a 16 byte buffer for up channel 0.
+    fn ram_with_control_block(write: u32, read: u32) -> MockMemory {
+        let mut ram = MockMemory::default();
+        ram.write_block8(0x2000_0100, b"SEGGER RTT\0\0\0\0\0\0")
+            .unwrap();
+        ram.write_block32(0x2000_0110, &[1, 0, 0, 0x2000_0200, 16, write, read, 0])
+            .unwrap();
+        ram.write_block8(0x2000_0200, b"0123456789abcdef").unwrap();
+        ram
+    }
+
+    #[test]
+    fn scan_and_read() {
+        let mut ram = ram_with_control_block(10, 4);
+
+        let rtt = Rtt::scan(
+            &mut ram,
+            &[0x2000_0000..0x2000_0080, 0x2000_0080..0x2000_0400],
+        )
+        .unwrap();
+        assert_eq!(rtt.control_block(), 0x2000_0100);
+        assert_eq!(rtt.up_channels(), 1);
+
+        let mut data = vec![];
+        assert_eq!(rtt.read(&mut ram, 0, &mut data).unwrap(), 6);
+        assert_eq!(data, b"456789");
+        // The read offset was advanced, so nothing is read twice.
+        assert_eq!(rtt.read(&mut ram, 0, &mut data).unwrap(), 0);
+
+        match rtt.read(&mut ram, 1, &mut data) {
+            Err(RttError::NoSuchChannel(1)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_wrapping_buffer() {
+        let mut ram = ram_with_control_block(2, 13);
+        let rtt = Rtt::attach(&mut ram, 0x2000_0100).unwrap();
+
+        let mut data = vec![];
+        assert_eq!(rtt.read(&mut ram, 0, &mut data).unwrap(), 5);
+        assert_eq!(data, b"def01");
+    }
+}
@@ -1,5 +1,18 @@
+use crate::config::memory::MemoryRegion;
+use crate::config::reset_cause::ResetReason;
 use crate::config::target::Target;
+use crate::coresight::access_ports::AccessPortError;
+use crate::coresight::memory::MI;
+use crate::flash::ElfImage;
 use crate::probe::{DebugProbeError, MasterProbe};
+use crate::target::fault::{FaultReport, BFAR_ADDRESS, CFSR_ADDRESS, HFSR_ADDRESS, MMFAR_ADDRESS};
+use crate::target::{CoreEvent, HaltReason, RegisterSnapshot};
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+/// How long to wait for the core to resume after `run`.
+const RESUME_TIMEOUT: Duration = Duration::from_millis(100);
 
 pub struct Session {
     pub target: Target,
@@ -7,6 +20,9 @@ pub struct Session {
 
     hw_breakpoint_enabled: bool,
     active_breakpoints: Vec<Breakpoint>,
+
+    event_subscribers: Vec<Sender<CoreEvent>>,
+    core_was_halted: Option<bool>,
 }
 
 impl Session {
@@ -17,11 +33,16 @@ impl Session {
             probe,
             hw_breakpoint_enabled: false,
             active_breakpoints: Vec::new(),
+            event_subscribers: Vec::new(),
+            core_was_halted: None,
         }
     }
 
     /// Set a hardware breakpoint
+    ///
+    /// The thumb bit of `address` is ignored, so the address of a function symbol can be used as is.
     pub fn set_hw_breakpoint(&mut self, address: u32) -> Result<(), DebugProbeError> {
+        let address = address & !1;
         log::debug!("Trying to set HW breakpoint at address {:#08x}", address);
 
         // Get the number of HW breakpoints available
@@ -62,6 +83,7 @@ impl Session {
     }
 
     pub fn clear_hw_breakpoint(&mut self, address: u32) -> Result<(), DebugProbeError> {
+        let address = address & !1;
         let bp_position = self
             .active_breakpoints
             .iter()
@@ -82,6 +104,328 @@ impl Session {
         }
     }
 
+    /// Subscribe to changes of the execution state of the core.
+    ///
+    /// Events are only emitted from [`poll_core_state`], so somebody has to call it
+    /// periodically, e.g. the main loop of a debugger. The receiver can be dropped at any time.
+    ///
+    /// [`poll_core_state`]: #method.poll_core_state
+    pub fn subscribe(&mut self) -> Receiver<CoreEvent> {
+        let (sender, receiver) = channel();
+        self.event_subscribers.push(sender);
+        receiver
+    }
+
+    /// Checks whether the core is halted, and emits a [`CoreEvent`] to all subscribers
+    /// if the state changed since the last poll.
+    ///
+    /// The first poll after opening the session always emits an event for the current state.
+    /// Returns whether the core is halted.
+    ///
+    /// [`CoreEvent`]: ../target/enum.CoreEvent.html
+    pub fn poll_core_state(&mut self) -> Result<bool, DebugProbeError> {
+        let halted = self.target.core.core_halted(&mut self.probe)?;
+
+        if self.core_was_halted == Some(halted) {
+            return Ok(halted);
+        }
+        self.core_was_halted = Some(halted);
+
+        let event = if halted {
+            let pc = self
+                .target
+                .core
+                .read_core_reg(&mut self.probe, self.target.core.registers().PC)?;
+
+            let dfsr = self.probe.read32(HaltReason::DFSR_ADDRESS)?;
+            // The DFSR bits are sticky, clear them so the next halt is reported correctly.
+            self.probe.write32(HaltReason::DFSR_ADDRESS, dfsr)?;
+
+            CoreEvent::Halted {
+                pc,
+                reason: HaltReason::from_dfsr(dfsr),
+            }
+        } else {
+            CoreEvent::Running
+        };
+
+        log::debug!("Core state changed: {:?}", event);
+
+        // Subscribers which dropped their receiver are removed.
+        self.event_subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+
+        Ok(halted)
+    }
+
+    /// Reads and decodes the fault status registers of the core.
+    ///
+    /// This is most useful when the core halted in a fault handler, e.g. because of a vector catch.
+    /// The registers are not available on ARMv6-M cores like the Cortex-M0.
+    pub fn read_fault_status(&mut self) -> Result<FaultReport, AccessPortError> {
+        let cfsr = self.probe.read32(CFSR_ADDRESS)?;
+        let hfsr = self.probe.read32(HFSR_ADDRESS)?;
+        let mmfar = self.probe.read32(MMFAR_ADDRESS)?;
+        let bfar = self.probe.read32(BFAR_ADDRESS)?;
+
+        log::debug!(
+            "CFSR = {:#010x}, HFSR = {:#010x}, MMFAR = {:#010x}, BFAR = {:#010x}",
+            cfsr,
+            hfsr,
+            mmfar,
+            bfar
+        );
+
+        Ok(FaultReport::new(cfsr, hfsr, mmfar, bfar))
+    }
+
+    /// Reads all general purpose and special registers of the core at once.
+    ///
+    /// A running core is halted for the reads and resumed afterwards,
+    /// a halted core is left halted.
+    pub fn core_registers_snapshot(&mut self) -> Result<RegisterSnapshot, DebugProbeError> {
+        let was_running = !self.target.core.core_halted(&mut self.probe)?;
+
+        if was_running {
+            self.target.core.halt(&mut self.probe)?;
+            self.target.core.wait_for_core_halted(&mut self.probe)?;
+        }
+
+        let snapshot = RegisterSnapshot::read(self.target.core.as_ref(), &mut self.probe);
+
+        // The core is resumed even if a read failed, to leave it as it was found.
+        if was_running {
+            self.target.core.run(&mut self.probe)?;
+        }
+
+        snapshot
+    }
+
+    /// Resets the core and runs it until it reaches `address`, e.g. the address of `main`.
+    ///
+    /// A hardware breakpoint is set at `address` while the core runs, and removed again afterwards.
+    /// If the core does not halt within `timeout`, it is halted and a timeout is returned.
+    /// Returns the PC the core stopped at, which differs from `address` if the core halted for another reason.
+    pub fn reset_and_run_to(
+        &mut self,
+        address: u32,
+        timeout: Duration,
+    ) -> Result<u32, DebugProbeError> {
+        self.target.reset_and_halt(&mut self.probe)?;
+        self.set_hw_breakpoint(address)?;
+        self.target.core.run(&mut self.probe)?;
+
+        // Otherwise the core might still be halted from the reset when it is polled below.
+        // It is never seen running if it halts on `address` right away, which is fine.
+        match self
+            .target
+            .core
+            .wait_for_core_running(&mut self.probe, RESUME_TIMEOUT)
+        {
+            Ok(()) | Err(DebugProbeError::Timeout) => (),
+            Err(e) => return Err(e),
+        }
+
+        let start = Instant::now();
+        let mut halted = false;
+        let result = loop {
+            match self.target.core.core_halted(&mut self.probe) {
+                Ok(true) => {
+                    halted = true;
+                    break Ok(());
+                }
+                Ok(false) if start.elapsed() > timeout => break Err(DebugProbeError::Timeout),
+                Ok(false) => std::thread::sleep(Duration::from_millis(1)),
+                Err(e) => break Err(e),
+            }
+        };
+
+        // The breakpoint is removed and the core left halted in any case.
+        if !halted {
+            self.target.core.halt(&mut self.probe)?;
+        }
+        self.clear_hw_breakpoint(address)?;
+        result?;
+
+        let pc = self.target.core.registers().PC;
+        self.target.core.read_core_reg(&mut self.probe, pc)
+    }
+
+    /// Completes all transfers which the probe has queued.
+    ///
+    /// Call this to make sure preceding writes took effect, e.g. before reading back
+    /// memory through another path, and to get errors which were deferred while queueing.
+    /// Running and resetting the core flush implicitly.
+    pub fn flush(&mut self) -> Result<(), DebugProbeError> {
+        self.probe.flush()
+    }
+
+    /// Estimates how long erasing and programming `image` takes,
+    /// based on the timing hints of the flash regions of the target.
+    ///
+    /// Flash regions without timing hints are not accounted for,
+    /// so the estimate is zero if the target has no hints at all.
+    pub fn estimate_flash_time(&self, image: &ElfImage) -> Duration {
+        let ranges = image.loadable_ranges();
+
+        self.target
+            .memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Flash(region) => Some(region.estimate_time(&ranges)),
+                _ => None,
+            })
+            .sum()
+    }
+
+    /// Reads the flash size register of the target, if it defines one,
+    /// and validates the size of the boot flash region against it.
+    ///
+    /// If the chip reports less flash than the definition claims, the flash region
+    /// is shrunk to the detected size, such that nothing is written beyond the real flash.
+    ///
+    /// A register which cannot be read, or which reads as `0` or `0xFFFF` (an unprogrammed
+    /// or inaccessible value), is ignored with a warning and the size of the definition is kept.
+    ///
+    /// Returns the detected flash size in bytes, or `None` if the target has no flash size register
+    /// or no plausible size could be read from it.
+    pub fn detect_flash_size(&mut self) -> Option<u32> {
+        let register = self.target.flash_size_register?;
+
+        let mut buffer = [0u8; 2];
+        if let Err(e) = self.probe.read_block8(register, &mut buffer) {
+            log::warn!(
+                "Could not read the flash size register at {:#08x}: {}. The flash size of the target definition is used.",
+                register,
+                e
+            );
+            return None;
+        }
+
+        let raw_size = u16::from_le_bytes(buffer);
+        if raw_size == 0 || raw_size == 0xFFFF {
+            log::warn!(
+                "The flash size register at {:#08x} reads {:#06x}, which is not a plausible flash size. The flash size of the target definition is used.",
+                register,
+                raw_size
+            );
+            return None;
+        }
+
+        let detected_size = u32::from(raw_size) * 1024;
+
+        log::debug!(
+            "Flash size register at {:#08x} reports {} KiB of flash.",
+            register,
+            detected_size / 1024
+        );
+
+        let region = self.target.memory_map.iter_mut().find_map(|r| match r {
+            MemoryRegion::Flash(region) if region.is_boot_memory => Some(region),
+            _ => None,
+        });
+
+        if let Some(region) = region {
+            let defined_size = region.range.end - region.range.start;
+            if detected_size < defined_size {
+                log::warn!(
+                    "The chip reports {} KiB of flash, but the target definition claims {} KiB. Only the first {} KiB will be used.",
+                    detected_size / 1024,
+                    defined_size / 1024,
+                    detected_size / 1024
+                );
+                region.range.end = region.range.start + detected_size;
+            } else if detected_size > defined_size {
+                log::info!(
+                    "The chip reports {} KiB of flash, which is more than the {} KiB of the target definition.",
+                    detected_size / 1024,
+                    defined_size / 1024
+                );
+            }
+        }
+
+        Some(detected_size)
+    }
+
+    /// Applies the debug keep alive register writes of the target.
+    ///
+    /// This keeps the debug unit clocked while the target is in a low power mode,
+    /// so the connection to the core is not lost when it goes to sleep.
+    pub fn keep_debug_alive(&mut self) -> Result<(), AccessPortError> {
+        if self.target.debug_keep_alive.is_empty() {
+            log::warn!("The target does not define any registers to keep the debug unit alive.");
+        }
+
+        for write in &self.target.debug_keep_alive {
+            write.apply(&mut self.probe)?;
+        }
+
+        Ok(())
+    }
+
+    /// Enables the FPU, if the target has one.
+    ///
+    /// This has to be done after every reset before running code which uses the FPU, e.g. a RAM image.
+    pub fn enable_fpu(&mut self) -> Result<(), AccessPortError> {
+        if self.target.has_fpu {
+            crate::target::enable_fpu(&mut self.probe)
+        } else {
+            log::warn!("The target does not have an FPU.");
+            Ok(())
+        }
+    }
+
+    /// Applies the watchdog disable register writes of the target.
+    ///
+    /// Watchdogs which are started by hardware can usually not be stopped,
+    /// so the writes might only extend their timeout or freeze them while the core is halted.
+    /// The flash loader applies the writes itself after resetting the core if
+    /// `DownloadOptions::disable_watchdog` is set.
+    pub fn disable_watchdog(&mut self) -> Result<(), AccessPortError> {
+        if self.target.watchdog_disable.is_empty() {
+            log::warn!("The target does not define any registers to disable the watchdog.");
+        }
+
+        for write in &self.target.watchdog_disable {
+            write.apply(&mut self.probe)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads and decodes the reset cause register of the target.
+    ///
+    /// Returns `None` if the target does not define a reset cause register.
+    pub fn read_reset_reason(&mut self) -> Result<Option<ResetReason>, AccessPortError> {
+        let register = match &self.target.reset_cause {
+            Some(register) => register,
+            None => return Ok(None),
+        };
+
+        let value = self.probe.read32(register.address)?;
+        log::debug!(
+            "Reset cause register at {:#010x} reads {:#010x}",
+            register.address,
+            value
+        );
+
+        Ok(Some(register.decode(value)))
+    }
+
+    /// Clears all flags of the reset cause register of the target,
+    /// such that the next read only reports resets which happened afterwards.
+    ///
+    /// Does nothing if the target does not define a reset cause register.
+    pub fn clear_reset_reason(&mut self) -> Result<(), AccessPortError> {
+        if let Some(register) = &self.target.reset_cause {
+            let value = self.probe.read32(register.address)?;
+            self.probe
+                .write32(register.address, value | register.clear_value)?;
+        }
+
+        Ok(())
+    }
+
     fn find_free_breakpoint_unit(&self) -> usize {
         let mut used_bp: Vec<_> = self
             .active_breakpoints
@@ -0,0 +1,182 @@
+use std::fmt;
+
+/// Address of the Configurable Fault Status Register.
+pub const CFSR_ADDRESS: u32 = 0xE000_ED28;
+/// Address of the HardFault Status Register.
+pub const HFSR_ADDRESS: u32 = 0xE000_ED2C;
+/// Address of the MemManage Fault Address Register.
+pub const MMFAR_ADDRESS: u32 = 0xE000_ED34;
+/// Address of the BusFault Address Register.
+pub const BFAR_ADDRESS: u32 = 0xE000_ED38;
+
+const MMARVALID: u32 = 1 << 7;
+const BFARVALID: u32 = 1 << 15;
+
+/// The flags of the CFSR with their names and descriptions.
+const CFSR_FLAGS: &[(u32, &str, &str)] = &[
+    (
+        0,
+        "IACCVIOL",
+        "memory management fault on instruction fetch",
+    ),
+    (1, "DACCVIOL", "memory management fault on data access"),
+    (
+        3,
+        "MUNSTKERR",
+        "memory management fault on exception return unstacking",
+    ),
+    (
+        4,
+        "MSTKERR",
+        "memory management fault on exception entry stacking",
+    ),
+    (
+        5,
+        "MLSPERR",
+        "memory management fault during lazy FP state preservation",
+    ),
+    (8, "IBUSERR", "bus fault on instruction prefetch"),
+    (9, "PRECISERR", "precise data bus fault"),
+    (10, "IMPRECISERR", "imprecise data bus fault"),
+    (11, "UNSTKERR", "bus fault on exception return unstacking"),
+    (12, "STKERR", "bus fault on exception entry stacking"),
+    (13, "LSPERR", "bus fault during lazy FP state preservation"),
+    (16, "UNDEFINSTR", "undefined instruction"),
+    (
+        17,
+        "INVSTATE",
+        "invalid EPSR state, e.g. a branch to an address without the thumb bit",
+    ),
+    (18, "INVPC", "invalid EXC_RETURN value on exception return"),
+    (
+        19,
+        "NOCP",
+        "coprocessor access while it is disabled or not present",
+    ),
+    (20, "STKOF", "stack overflow"),
+    (24, "UNALIGNED", "unaligned memory access"),
+    (25, "DIVBYZERO", "division by zero"),
+];
+
+/// The flags of the HFSR with their names and descriptions.
+const HFSR_FLAGS: &[(u32, &str, &str)] = &[
+    (1, "VECTTBL", "bus fault on a vector table read"),
+    (30, "FORCED", "configurable fault escalated to a hard fault"),
+    (
+        31,
+        "DEBUGEVT",
+        "debug event while halting debug is disabled",
+    ),
+];
+
+/// The decoded fault status registers of a Cortex-M core.
+///
+/// The fault status registers are not available on ARMv6-M cores, e.g. the Cortex-M0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultReport {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    /// The address which caused the memory management fault, if it is valid.
+    pub mmfar: Option<u32>,
+    /// The address which caused the bus fault, if it is valid.
+    pub bfar: Option<u32>,
+}
+
+impl FaultReport {
+    /// Creates a report from the raw register values.
+    ///
+    /// The fault addresses are only kept if the CFSR marks them as valid.
+    pub fn new(cfsr: u32, hfsr: u32, mmfar: u32, bfar: u32) -> Self {
+        Self {
+            cfsr,
+            hfsr,
+            mmfar: if cfsr & MMARVALID != 0 {
+                Some(mmfar)
+            } else {
+                None
+            },
+            bfar: if cfsr & BFARVALID != 0 {
+                Some(bfar)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Returns the names and descriptions of all fault flags which are set.
+    pub fn faults(&self) -> Vec<(&'static str, &'static str)> {
+        let set = |value: u32, flags: &[(u32, &'static str, &'static str)]| {
+            flags
+                .iter()
+                .filter(|(bit, _, _)| value & (1 << bit) != 0)
+                .map(|(_, name, description)| (*name, *description))
+                .collect::<Vec<_>>()
+        };
+
+        let mut faults = set(self.hfsr, HFSR_FLAGS);
+        faults.extend(set(self.cfsr, CFSR_FLAGS));
+        faults
+    }
+
+    /// Returns true if any fault is flagged.
+    pub fn is_fault(&self) -> bool {
+        !self.faults().is_empty()
+    }
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let faults = self.faults();
+        if faults.is_empty() {
+            return write!(f, "No fault is flagged.");
+        }
+
+        for (i, (name, description)) in faults.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", name, description)?;
+        }
+        if let Some(address) = self.mmfar {
+            write!(f, "\nMemory management fault address {:#010x}", address)?;
+        }
+        if let Some(address) = self.bfar {
+            write!(f, "\nBus fault address {:#010x}", address)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FaultReport;
+
+    #[test]
+    fn decode_precise_bus_fault() {
+        // FORCED, PRECISERR and BFARVALID.
+        let report = FaultReport::new(0x0000_8200, 0x4000_0000, 0x1234_5678, 0x4000_0000);
+
+        assert_eq!(
+            report.faults(),
+            vec![
+                ("FORCED", "configurable fault escalated to a hard fault"),
+                ("PRECISERR", "precise data bus fault")
+            ]
+        );
+        assert_eq!(report.mmfar, None);
+        assert_eq!(report.bfar, Some(0x4000_0000));
+        assert_eq!(
+            report.to_string(),
+            "FORCED: configurable fault escalated to a hard fault\nPRECISERR: precise data bus fault\nBus fault address 0x40000000"
+        );
+    }
+
+    #[test]
+    fn no_fault() {
+        let report = FaultReport::new(0, 0, 0, 0);
+
+        assert!(!report.is_fault());
+        assert_eq!(report.to_string(), "No fault is flagged.");
+    }
+}
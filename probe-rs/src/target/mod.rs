@@ -0,0 +1,397 @@
+pub mod fault;
+pub mod info;
+
+use serde::de::{Error, Unexpected};
+use serde::Serialize;
+
+use crate::{
+    cores::get_core,
+    coresight::{access_ports::AccessPortError, memory::MI},
+    probe::{DebugProbeError, MasterProbe},
+};
+
+use std::time::{Duration, Instant};
+
+/// Address of the Coprocessor Access Control Register, which is the same on all Cortex-M cores.
+pub const CPACR_ADDRESS: u32 = 0xE000_ED88;
+
+/// Enables the FPU by granting full access to the coprocessors CP10 and CP11 in the CPACR.
+///
+/// Without this, the first floating point instruction faults. Cores without an FPU ignore the write.
+pub fn enable_fpu(mi: &mut impl MI) -> Result<(), AccessPortError> {
+    let cpacr = mi.read32(CPACR_ADDRESS)?;
+    mi.write32(CPACR_ADDRESS, cpacr | (0xF << 20))
+}
+
+pub trait CoreRegister: Clone + From<u32> + Into<u32> + Sized + std::fmt::Debug {
+    const ADDRESS: u32;
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CoreRegisterAddress(pub u8);
+
+impl From<CoreRegisterAddress> for u32 {
+    fn from(value: CoreRegisterAddress) -> Self {
+        u32::from(value.0)
+    }
+}
+
+impl From<u8> for CoreRegisterAddress {
+    fn from(value: u8) -> Self {
+        CoreRegisterAddress(value)
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Copy, Clone)]
+pub struct BasicRegisterAddresses {
+    pub R0: CoreRegisterAddress,
+    pub R1: CoreRegisterAddress,
+    pub R2: CoreRegisterAddress,
+    pub R3: CoreRegisterAddress,
+    pub R4: CoreRegisterAddress,
+    pub R9: CoreRegisterAddress,
+    pub PC: CoreRegisterAddress,
+    pub LR: CoreRegisterAddress,
+    pub SP: CoreRegisterAddress,
+    pub XPSR: CoreRegisterAddress,
+}
+
+#[derive(Debug, Clone)]
+pub struct CoreInformation {
+    pub pc: u32,
+}
+
+/// The core register number of the main stack pointer.
+pub const MSP: CoreRegisterAddress = CoreRegisterAddress(17);
+/// The core register number of the process stack pointer.
+pub const PSP: CoreRegisterAddress = CoreRegisterAddress(18);
+/// The core register number of CONTROL, FAULTMASK, BASEPRI and PRIMASK, which are read together.
+pub const SPECIAL: CoreRegisterAddress = CoreRegisterAddress(20);
+
+/// The general purpose and special registers of a core at one point in time,
+/// e.g. to attach to a crash report.
+///
+/// FAULTMASK and BASEPRI do not exist on ARMv6-M cores like the Cortex-M0 and are zero there.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RegisterSnapshot {
+    /// R0 to R12.
+    pub general: [u32; 13],
+    pub sp: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+    pub msp: u32,
+    pub psp: u32,
+    pub control: u8,
+    pub faultmask: u8,
+    pub basepri: u8,
+    pub primask: u8,
+}
+
+impl RegisterSnapshot {
+    /// Reads all registers of the halted core in one batch.
+    pub(crate) fn read(core: &dyn Core, mi: &mut MasterProbe) -> Result<Self, DebugProbeError> {
+        let regs = core.registers();
+
+        let mut addresses: Vec<CoreRegisterAddress> = (0..13).map(CoreRegisterAddress).collect();
+        addresses.extend(&[regs.SP, regs.LR, regs.PC, regs.XPSR, MSP, PSP, SPECIAL]);
+
+        let values = core.read_core_registers(mi, &addresses)?;
+
+        let mut general = [0; 13];
+        general.copy_from_slice(&values[..13]);
+        let special = values[19];
+
+        Ok(Self {
+            general,
+            sp: values[13],
+            lr: values[14],
+            pc: values[15],
+            xpsr: values[16],
+            msp: values[17],
+            psp: values[18],
+            control: (special >> 24) as u8,
+            faultmask: (special >> 16) as u8,
+            basepri: (special >> 8) as u8,
+            primask: special as u8,
+        })
+    }
+}
+
+/// The reason why a core entered halted state, as reported by the
+/// Debug Fault Status Register (DFSR).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HaltReason {
+    /// The core was halted by a debug request, or by stepping.
+    Request,
+    /// A breakpoint was hit.
+    Breakpoint,
+    /// A watchpoint (DWT comparator) triggered.
+    Watchpoint,
+    /// A vector catch triggered, e.g. on reset or a fault.
+    VectorCatch,
+    /// The external debug request signal was asserted.
+    External,
+    /// No reason is flagged in the DFSR.
+    Unknown,
+}
+
+impl HaltReason {
+    /// Address of the Debug Fault Status Register, which is the same on all Cortex-M cores.
+    pub const DFSR_ADDRESS: u32 = 0xE000_ED30;
+
+    /// Decodes the value of the DFSR.
+    ///
+    /// If several flags are set, the most specific one wins.
+    pub fn from_dfsr(value: u32) -> Self {
+        if value & (1 << 1) != 0 {
+            HaltReason::Breakpoint
+        } else if value & (1 << 2) != 0 {
+            HaltReason::Watchpoint
+        } else if value & (1 << 3) != 0 {
+            HaltReason::VectorCatch
+        } else if value & (1 << 4) != 0 {
+            HaltReason::External
+        } else if value & 1 != 0 {
+            HaltReason::Request
+        } else {
+            HaltReason::Unknown
+        }
+    }
+}
+
+/// A change of the execution state of a core, as emitted by [`Session::poll_core_state`].
+///
+/// [`Session::poll_core_state`]: ../session/struct.Session.html#method.poll_core_state
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoreEvent {
+    /// The core entered halted state.
+    Halted { pc: u32, reason: HaltReason },
+    /// The core started executing instructions again.
+    Running,
+}
+
+pub trait Core: std::fmt::Debug + dyn_clone::DynClone {
+    /// Wait until the core is halted. If the core does not halt on its own,
+    /// a [`DebugProbeError::Timeout`] error will be returned.
+    ///
+    /// [`DebugProbeError::Timeout`]: ../probe/debug_probe/enum.DebugProbeError.html#variant.Timeout
+    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Wait until the core is running, i.e. until S_HALT of DHCSR is cleared, or until `timeout` expires.
+    ///
+    /// Some chips only resume a short while after `run`, so operations which depend on a running core
+    /// should wait for it first. A core which halts again right away, e.g. on a breakpoint at the
+    /// current PC, is never seen running and results in a [`DebugProbeError::Timeout`] error.
+    ///
+    /// [`DebugProbeError::Timeout`]: ../probe/debug_probe/enum.DebugProbeError.html#variant.Timeout
+    fn wait_for_core_running(
+        &self,
+        mi: &mut MasterProbe,
+        timeout: Duration,
+    ) -> Result<(), DebugProbeError> {
+        let start = Instant::now();
+
+        while self.core_halted(mi)? {
+            if start.elapsed() > timeout {
+                return Err(DebugProbeError::Timeout);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if the core is currently halted, without waiting.
+    fn core_halted(&self, mi: &mut MasterProbe) -> Result<bool, DebugProbeError>;
+
+    /// Try to halt the core. This function ensures the core is actually halted, and
+    /// returns a [`DebugProbeError::Timeout`] otherwise.
+    ///
+    /// [`DebugProbeError::Timeout`]: ../probe/debug_probe/enum.DebugProbeError.html#variant.Timeout
+    fn halt(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError>;
+
+    /// Continue to execute instructions.
+    ///
+    /// Pending transfers of the probe are flushed first.
+    fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Reset the core, and then continue to execute instructions. If the core
+    /// should be halted after reset, use the [`reset_and_halt`] function.
+    ///
+    /// Pending transfers of the probe are flushed first.
+    ///
+    /// [`reset_and_halt`]: trait.Core.html#tymethod.reset_and_halt
+    fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Reset the core, and then immediately halt. To continue execution after
+    /// reset, use the [`reset`] function.
+    ///
+    /// Pending transfers of the probe are flushed first.
+    ///
+    /// [`reset`]: trait.Core.html#tymethod.reset
+    fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError>;
+
+    /// Makes the core halt right when it comes out of the next reset, before it executes
+    /// the first instruction, e.g. with the `VC_CORERESET` vector catch of the Cortex-M.
+    ///
+    /// Unlike halting after the reset, this does not race with the startup code.
+    /// The catch stays set until it is cleared with [`reset_catch_clear`].
+    ///
+    /// [`reset_catch_clear`]: trait.Core.html#tymethod.reset_catch_clear
+    fn reset_catch_set(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Lets the core run after a reset again, see [`reset_catch_set`].
+    ///
+    /// [`reset_catch_set`]: trait.Core.html#tymethod.reset_catch_set
+    fn reset_catch_clear(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Steps one instruction and then enters halted state again.
+    ///
+    /// Interrupts are masked while stepping, so the step never ends up in an interrupt handler.
+    /// Returns the state the core stopped in, e.g. the new program counter.
+    fn step(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError>;
+
+    /// Steps `count` instructions, one after the other as with [`step`], and returns the state
+    /// after the last one.
+    ///
+    /// With a `count` of zero, nothing is executed and the current state is returned.
+    ///
+    /// [`step`]: trait.Core.html#tymethod.step
+    fn step_n(
+        &self,
+        mi: &mut MasterProbe,
+        count: usize,
+    ) -> Result<CoreInformation, DebugProbeError> {
+        if count == 0 {
+            return Ok(CoreInformation {
+                pc: self.read_core_reg(mi, self.registers().PC)?,
+            });
+        }
+
+        let mut info = self.step(mi)?;
+        for _ in 1..count {
+            info = self.step(mi)?;
+        }
+
+        Ok(info)
+    }
+
+    fn read_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+    ) -> Result<u32, DebugProbeError>;
+
+    /// Reads multiple core registers at once, returning the values in the order of `addresses`.
+    ///
+    /// Cores may override this to batch the accesses, which is a lot faster than
+    /// reading the registers one by one with `read_core_reg`.
+    fn read_core_registers(
+        &self,
+        mi: &mut MasterProbe,
+        addresses: &[CoreRegisterAddress],
+    ) -> Result<Vec<u32>, DebugProbeError> {
+        addresses
+            .iter()
+            .map(|address| self.read_core_reg(mi, *address))
+            .collect()
+    }
+
+    fn write_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+        value: u32,
+    ) -> Result<(), DebugProbeError>;
+
+    /// Writes multiple core registers at once, in the order of `registers`.
+    ///
+    /// Cores may override this to batch the accesses, like `read_core_registers`.
+    fn write_core_registers(
+        &self,
+        mi: &mut MasterProbe,
+        registers: &[(CoreRegisterAddress, u32)],
+    ) -> Result<(), DebugProbeError> {
+        registers
+            .iter()
+            .try_for_each(|(address, value)| self.write_core_reg(mi, *address, *value))
+    }
+
+    fn get_available_breakpoint_units(&self, mi: &mut MasterProbe) -> Result<u32, DebugProbeError>;
+
+    fn enable_breakpoints(&self, mi: &mut MasterProbe, state: bool) -> Result<(), DebugProbeError>;
+
+    /// Programs the comparator `bp_unit_index` of the breakpoint unit to halt at `addr`.
+    ///
+    /// The thumb bit of `addr` is ignored.
+    fn set_breakpoint(
+        &self,
+        mi: &mut MasterProbe,
+        bp_unit_index: usize,
+        addr: u32,
+    ) -> Result<(), DebugProbeError>;
+
+    fn clear_breakpoint(
+        &self,
+        mi: &mut MasterProbe,
+        bp_unit_index: usize,
+    ) -> Result<(), DebugProbeError>;
+
+    fn read_block8(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<(), DebugProbeError>;
+
+    fn registers<'a>(&self) -> &'a BasicRegisterAddresses;
+}
+
+dyn_clone::clone_trait_object!(Core);
+
+struct CoreVisitor;
+
+impl<'de> serde::de::Visitor<'de> for CoreVisitor {
+    type Value = Box<dyn Core>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "an existing core name")
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        if let Some(core) = get_core(s) {
+            Ok(core)
+        } else {
+            Err(Error::invalid_value(
+                Unexpected::Other(&format!("Core {} does not exist.", s)),
+                &self,
+            ))
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Box<dyn Core> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_identifier(CoreVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HaltReason;
+
+    #[test]
+    fn halt_reason_from_dfsr() {
+        assert_eq!(HaltReason::from_dfsr(0b00001), HaltReason::Request);
+        assert_eq!(HaltReason::from_dfsr(0b00011), HaltReason::Breakpoint);
+        assert_eq!(HaltReason::from_dfsr(0b00101), HaltReason::Watchpoint);
+        assert_eq!(HaltReason::from_dfsr(0b01000), HaltReason::VectorCatch);
+        assert_eq!(HaltReason::from_dfsr(0b10000), HaltReason::External);
+        assert_eq!(HaltReason::from_dfsr(0), HaltReason::Unknown);
+    }
+}